@@ -1,62 +1,718 @@
 ﻿use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use crate::{ActionCommand, ExecutionState, HistoryEntry};
+use crate::safety::ActionFilter;
 
 const CLAUDE_API_URL: &str = "https://api.anthropic.com/v1/messages";
-const MODEL: &str = "claude-sonnet-4-20250514";
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+pub const DEFAULT_MAX_TOKENS: u32 = 1000;
+pub const MAX_TOKENS_LIMIT: u32 = 8192;
+// Cheap model used for housekeeping calls like condensing history
+const SUMMARY_MODEL: &str = "claude-3-5-haiku-20241022";
 
+// (model prefix, input, output) in $ per million tokens, most specific prefix first.
+// Cache reads bill at 10% of the input rate, cache writes at 125%.
+const PRICING: &[(&str, f64, f64)] = &[
+    ("claude-opus-4-5", 5.0, 25.0),
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-haiku-4-5", 1.0, 5.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+// Unknown models are costed at Sonnet rates
+const FALLBACK_PRICING: (f64, f64) = (3.0, 15.0);
+const CACHE_READ_FACTOR: f64 = 0.1;
+const CACHE_WRITE_FACTOR: f64 = 1.25;
+// Most nodes sent to the model per prompt, after relevance ranking
+const MAX_PROMPT_NODES: usize = 100;
+// Corrective re-prompts for an action that fails validation before the call gives up
+const MAX_INVALID_RETRIES: u32 = 2;
+// Filler words dropped from goal keywords when ranking the tree; replaceable per session
+pub const DEFAULT_STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "with", "from", "into", "onto", "over", "under", "about", "then", "than",
+    "that", "this", "these", "those", "its", "our", "your", "their", "some", "any", "all", "via", "per", "please",
+];
+// Actions whose output is shown to the model as LAST_READ on the next step
+const READ_ACTIONS: &[&str] = &["read_text", "get_page_text", "get_value", "get_toggle_state", "is_selected"];
+// Kept as keywords but weighted low: in "search for chrome extensions" the nouns pick the node, not the verb
+const ACTION_VERBS: &[&str] = &["search", "find", "look", "open", "click", "press", "type", "enter", "select", "check", "read", "show", "get"];
+
+// Why a call produced no action, so the loop can give up (Auth), wait (RateLimited, Overloaded,
+// Network) or just ask again (Parse, EmptyContent)
+#[derive(Debug, Clone)]
+pub enum LLMError {
+    // Missing, wrong or unauthorized API key
+    Auth(String),
+    // retry_after comes from the retry-after header when the API sends one
+    RateLimited { retry_after: Option<std::time::Duration> },
+    // The API is overloaded or failed on its side (5xx)
+    Overloaded,
+    // The reply (or, after the corrective re-prompts, the action in it) couldn't be used
+    Parse(String),
+    Network(String),
+    EmptyContent,
+    // Any other rejected request, e.g. an unknown model or a prompt too large
+    Api { status: u16, message: String },
+}
+
+impl std::fmt::Display for LLMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LLMError::Auth(m) => write!(f, "API key rejected: {}", m),
+            LLMError::RateLimited { retry_after: Some(d) } => write!(f, "Rate limited, retry after {} s", d.as_secs()),
+            LLMError::RateLimited { retry_after: None } => write!(f, "Rate limited"),
+            LLMError::Overloaded => write!(f, "The API is overloaded"),
+            LLMError::Parse(m) => write!(f, "Unusable reply: {}", m),
+            LLMError::Network(m) => write!(f, "Network error: {}", m),
+            LLMError::EmptyContent => write!(f, "The reply had no text"),
+            LLMError::Api { status, message } => write!(f, "API error {}: {}", status, message),
+        }
+    }
+}
+
+impl std::error::Error for LLMError {}
+
+impl LLMError {
+    // Maps a non-2xx reply using Anthropic's error.type, falling back to the status code
+    fn from_reply(status: u16, retry_after: Option<&str>, body: &serde_json::Value) -> Self {
+        let message = body["error"]["message"].as_str().unwrap_or("").to_string();
+        match (body["error"]["type"].as_str().unwrap_or(""), status) {
+            ("authentication_error" | "permission_error", _) | (_, 401 | 403) => LLMError::Auth(message),
+            ("rate_limit_error", _) | (_, 429) => LLMError::RateLimited {
+                retry_after: retry_after.and_then(|s| s.trim().parse::<f64>().ok()).map(std::time::Duration::from_secs_f64),
+            },
+            ("overloaded_error" | "api_error", _) | (_, 500..=599) => LLMError::Overloaded,
+            _ => LLMError::Api { status, message },
+        }
+    }
+}
+
+impl From<reqwest::Error> for LLMError {
+    fn from(e: reqwest::Error) -> Self {
+        LLMError::Network(e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct ClaudeRequest { model: String, max_tokens: u32, system: Vec<SystemBlock>, messages: Vec<Message> }
+#[derive(Clone, Serialize, Deserialize)]
+// content is a plain string, or an array of blocks when an image goes along
+struct Message { role: String, content: serde_json::Value }
+#[derive(Serialize)]
+struct SystemBlock { r#type: String, text: String, #[serde(skip_serializing_if = "Option::is_none")] cache_control: Option<CacheControl> }
 #[derive(Serialize)]
-struct ClaudeRequest { model: String, max_tokens: u32, system: String, messages: Vec<Message> }
-#[derive(Serialize, Deserialize)]
-struct Message { role: String, content: String }
-
-pub async fn get_next_action(api_key: &str, cmd: &str, state: &ExecutionState, history: &[HistoryEntry]) -> Result<ActionCommand, Box<dyn std::error::Error + Send + Sync>> {
-    let req = ClaudeRequest { model: MODEL.to_string(), max_tokens: 1000, system: system_prompt(), messages: vec![Message { role: "user".to_string(), content: user_msg(cmd, state, history) }] };
-    let res = Client::new().post(CLAUDE_API_URL).header("x-api-key", api_key).header("anthropic-version", "2023-06-01").header("content-type", "application/json").json(&req).send().await?;
-    parse_response(&res.json().await?)
-}
-
-pub async fn get_retry_action(api_key: &str, failed: &ActionCommand, error: &str, state: &ExecutionState, history: &[HistoryEntry]) -> Result<ActionCommand, Box<dyn std::error::Error + Send + Sync>> {
-    let req = ClaudeRequest { model: MODEL.to_string(), max_tokens: 1000, system: system_prompt(), messages: vec![Message { role: "user".to_string(), content: retry_msg(failed, error, state, history) }] };
-    let res = Client::new().post(CLAUDE_API_URL).header("x-api-key", api_key).header("anthropic-version", "2023-06-01").header("content-type", "application/json").json(&req).send().await?;
-    parse_response(&res.json().await?)
-}
-
-fn system_prompt() -> String {
-    r#"You are a browser automation assistant. ONE action at a time. Use CSS selectors for target (or "ax:nodeId" for accessibility tree nodes, or "xpath://..." for XPath).
-
-ACTIONS:
-- click: target=CSS selector|"ax:id"|"xpath:..."
-- double_click: target=CSS selector
-- right_click: target=CSS selector
-- hover: target=CSS selector
-- type: target=CSS selector (or empty for focused), params.text=string
-- clear: target=CSS selector (clears input field)
-- navigate: params.url=URL
-- scroll: params.direction="up"|"down", params.amount=pixels (default 300)
-- press_key: params.key=key name (Enter, Tab, Escape, ArrowDown, etc.)
-- focus_window: brings browser tab to front
-- select: target=CSS selector, params.value=option value (for <select>)
-- wait: target=CSS selector, params.timeout=ms (wait for element to appear)
-- go_back: navigate back in history
-- go_forward: navigate forward in history
-- reload: refresh the page
-- eval_js: params.code=JavaScript to execute
-
-OUTPUT JSON ONLY: {"action_type":"...","target":"...","params":{...},"reasoning":"..."}"#.to_string()
-}
-
-fn user_msg(cmd: &str, state: &ExecutionState, history: &[HistoryEntry]) -> String {
+struct CacheControl { r#type: String }
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)] pub input_tokens: u32,
+    #[serde(default)] pub output_tokens: u32,
+    #[serde(default)] pub cache_creation_input_tokens: u32,
+    #[serde(default)] pub cache_read_input_tokens: u32,
+}
+
+// What the call that proposed an action cost; attached to the action so history can be costed later
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LlmCost {
+    pub model: String,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cost_usd: f64,
+    // Corrective re-prompts for actions that failed validation, included in the figures above
+    #[serde(default)]
+    pub invalid_retries: u32,
+}
+
+// Per-request settings, snapshotted from AppState so a call isn't affected by mid-flight changes
+#[derive(Debug, Clone)]
+// extra_actions are registered custom actions (name, description), appended to the action table;
+// vision lets screenshots go along where a call supports them
+pub struct LlmConfig { pub api_key: String, pub model: String, pub max_tokens: u32, pub extra_actions: Vec<(String, String)>, pub vision: bool, pub action_filter: ActionFilter, pub stop_words: Vec<String> }
+
+// notes are extra sections (user feedback, hints, ...) placed ahead of the goal
+pub async fn get_next_action(llm: &LlmConfig, cmd: &str, state: &ExecutionState, history: &[HistoryEntry], notes: &[String]) -> Result<ActionCommand, LLMError> {
+    call_claude(llm, user_msg(cmd, state, history, notes, &llm.stop_words), None).await
+}
+
+// screenshot is a PNG of the page after the failure, with the attempted target boxed where it could be
+// located; it is only sent in vision mode
+pub async fn get_retry_action(llm: &LlmConfig, failed: &ActionCommand, error: &str, state: &ExecutionState, history: &[HistoryEntry], screenshot: Option<&str>) -> Result<ActionCommand, LLMError> {
+    let (content, image) = retry_content(llm, failed, error, state, history, screenshot);
+    call_claude(llm, content, image).await
+}
+
+fn retry_content<'a>(llm: &LlmConfig, failed: &ActionCommand, error: &str, state: &ExecutionState, history: &[HistoryEntry], screenshot: Option<&'a str>) -> (String, Option<&'a str>) {
+    let image = screenshot.filter(|_| llm.vision);
+    (retry_msg(failed, error, state, history, image.is_some(), &llm.stop_words), image)
+}
+
+// The image block goes first, as the API recommends for image-then-question prompts
+fn message_content(text: String, png_base64: Option<&str>) -> serde_json::Value {
+    match png_base64 {
+        Some(data) => serde_json::json!([
+            { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": data } },
+            { "type": "text", "text": text },
+        ]),
+        None => serde_json::Value::String(text),
+    }
+}
+
+// Sends one request; the prompt-caching beta header goes along only when a block asks for the cache
+async fn post(api_key: &str, req: &ClaudeRequest) -> Result<serde_json::Value, LLMError> {
+    let mut builder = Client::new().post(CLAUDE_API_URL).header("x-api-key", api_key).header("anthropic-version", "2023-06-01").header("content-type", "application/json");
+    if req.system.iter().any(|b| b.cache_control.is_some()) { builder = builder.header("anthropic-beta", "prompt-caching-2024-07-31"); }
+    let res = builder.json(req).send().await?;
+    let status = res.status();
+    let retry_after = res.headers().get("retry-after").and_then(|v| v.to_str().ok()).map(|s| s.to_string());
+    let text = res.text().await?;
+    let body: serde_json::Value = serde_json::from_str(&text).unwrap_or(serde_json::Value::Null);
+    if !status.is_success() { return Err(LLMError::from_reply(status.as_u16(), retry_after.as_deref(), &body)); }
+    if body.is_null() { return Err(LLMError::Parse(format!("response body is not JSON: {}", text.chars().take(200).collect::<String>()))); }
+    Ok(body)
+}
+
+// The system prompt is identical on every call, so it is marked for Anthropic's prompt cache.
+// An action that fails validate_action, or a reply that isn't an action at all, is sent back with the
+// reason in the same conversation, up to MAX_INVALID_RETRIES times; those calls are counted apart
+// from execution retries.
+async fn call_claude(llm: &LlmConfig, content: String, png_base64: Option<&str>) -> Result<ActionCommand, LLMError> {
+    let system = system_prompt(&llm.extra_actions, &llm.action_filter);
+    let mut messages = vec![Message { role: "user".to_string(), content: message_content(content, png_base64) }];
+    let mut cost = LlmCost { model: llm.model.clone(), ..Default::default() };
+    loop {
+        let prompt_chars = system.len() + messages.iter().map(|m| m.content.to_string().len()).sum::<usize>();
+        let req = ClaudeRequest {
+            model: llm.model.clone(),
+            max_tokens: llm.max_tokens,
+            system: vec![SystemBlock { r#type: "text".to_string(), text: system.clone(), cache_control: Some(CacheControl { r#type: "ephemeral".to_string() }) }],
+            messages: messages.clone(),
+        };
+        let body = post(&llm.api_key, &req).await?;
+        let usage: Usage = serde_json::from_value(body["usage"].clone()).unwrap_or_default();
+        log_llm_call(&llm.model, &usage, prompt_chars);
+        cost.input_tokens += usage.input_tokens + usage.cache_creation_input_tokens + usage.cache_read_input_tokens;
+        cost.output_tokens += usage.output_tokens;
+        cost.cost_usd += estimate_cost(&llm.model, &usage);
+
+        let checked = parse_response(&body).and_then(|action| match validate_action(&action, &llm.extra_actions, &llm.action_filter) {
+            Ok(()) => Ok(action),
+            Err(why) => Err(LLMError::Parse(why)),
+        });
+        match checked {
+            Ok(mut action) => {
+                action.llm_cost = Some(cost);
+                return Ok(action);
+            }
+            Err(LLMError::Parse(why)) => {
+                if cost.invalid_retries >= MAX_INVALID_RETRIES {
+                    return Err(LLMError::Parse(format!("invalid action after {} corrections: {}", cost.invalid_retries, why)));
+                }
+                cost.invalid_retries += 1;
+                crate::log_warn!("llm", "invalid_action retry {}/{}: {}", cost.invalid_retries, MAX_INVALID_RETRIES, why);
+                let reply = body["content"][0]["text"].as_str().unwrap_or("").to_string();
+                messages.push(Message { role: "assistant".to_string(), content: reply.into() });
+                messages.push(Message { role: "user".to_string(), content: format!("Your action was invalid because {}. Reply with a corrected action. JSON only.", why).into() });
+            }
+            // EmptyContent has nothing to correct; the caller decides whether to ask again
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Parameters an action can't run without. "target" is the action's target field; "a|b" means either.
+const REQUIRED_PARAMS: &[(&str, &[&str])] = &[
+    ("click", &["target"]),
+    ("double_click", &["target"]),
+    ("right_click", &["target"]),
+    ("hover", &["target"]),
+    ("clear", &["target"]),
+    ("read_text", &["target"]),
+    ("type", &["text"]),
+    ("navigate", &["url"]),
+    ("open_url", &["url"]),
+    ("select", &["target", "label|value"]),
+    ("press_key", &["key"]),
+    ("fill_credential", &["label"]),
+    ("run_skill", &["name"]),
+    ("eval_js", &["code"]),
+    ("switch_tab", &["index|title"]),
+    ("move_window", &["x", "y"]),
+    ("resize_window", &["width", "height"]),
+    ("snap_window", &["side"]),
+    ("get_value", &["target"]),
+    ("get_toggle_state", &["target"]),
+    ("is_selected", &["target"]),
+];
+
+// Checks the action against what the system prompt offers, so a misspelled action or a missing
+// parameter is corrected by the model instead of failing deep inside the executor
+fn validate_action(action: &ActionCommand, extra: &[(String, String)], filter: &ActionFilter) -> Result<(), String> {
+    let name = action.action_type.as_str();
+    let offered = is_builtin_action(name) || extra.iter().any(|(n, _)| n == name);
+    if !offered || !filter.permits(name) { return Err(format!("\"{}\" is not one of the listed actions", name)); }
+    let params = action.params.clone().unwrap_or_default();
+    let present = |key: &str| match key {
+        "target" => action.target.as_str().is_some_and(|t| !t.trim().is_empty()),
+        _ => !params[key].is_null(),
+    };
+    let required = REQUIRED_PARAMS.iter().find(|(n, _)| *n == name).map_or(&[][..], |(_, r)| *r);
+    for need in required {
+        if need.split('|').any(present) { continue; }
+        let what = if *need == "target" { "a target".to_string() } else { format!("params.{}", need.replace('|', " or params.")) };
+        return Err(format!("{} needs {}", name, what));
+    }
+    Ok(())
+}
+
+// Condense older history into a short "progress so far" paragraph
+pub async fn summarize_history(api_key: &str, goal: &str, entries: &[HistoryEntry]) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let steps = entries.iter().enumerate().map(|(i, h)| format!("{}. {} {} -> {}{}", i + 1, h.action.action_type, h.action.target, if h.success {"ok"} else {"FAILED"}, h.error.as_deref().map(|e| format!(" ({})", e)).unwrap_or_default())).collect::<Vec<_>>().join("\n");
+    let req = ClaudeRequest {
+        model: SUMMARY_MODEL.to_string(),
+        max_tokens: 300,
+        system: vec![SystemBlock { r#type: "text".to_string(), text: "Summarize an automation agent's past steps in 2-4 sentences: what has been achieved, where it is now, and what failed. Plain text only.".to_string(), cache_control: None }],
+        messages: vec![Message { role: "user".to_string(), content: format!("GOAL: {}\n\nSTEPS:\n{}", goal, steps).into() }],
+    };
+    let body = post(api_key, &req).await?;
+    let usage: Usage = serde_json::from_value(body["usage"].clone()).unwrap_or_default();
+    log_llm_call(SUMMARY_MODEL, &usage, steps.len());
+    Ok(body["content"][0]["text"].as_str().ok_or("No text")?.trim().to_string())
+}
+
+// Planning mode: one call that outlines the goal as a few high-level steps
+pub async fn make_plan(llm: &LlmConfig, goal: &str, state: &ExecutionState) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let content = format!("GOAL: {}\n\nCURRENT PAGE: {} ({})", goal, state.active_window, state.url.as_deref().unwrap_or("N/A"));
+    let req = ClaudeRequest {
+        model: llm.model.clone(),
+        max_tokens: 500,
+        system: vec![SystemBlock { r#type: "text".to_string(), text: "Break a browser automation goal into 2-8 short, ordered, high-level steps (e.g. \"Open the login page\", \"Sign in\"). Output a JSON array of strings only.".to_string(), cache_control: None }],
+        messages: vec![Message { role: "user".to_string(), content: content.clone().into() }],
+    };
+    let body = post(&llm.api_key, &req).await?;
+    let usage: Usage = serde_json::from_value(body["usage"].clone()).unwrap_or_default();
+    log_llm_call(&llm.model, &usage, content.len());
+    let text = body["content"][0]["text"].as_str().ok_or("No text")?;
+    let start = text.find('[').ok_or("No JSON array in plan")?;
+    let end = text.rfind(']').ok_or("No JSON array in plan")?;
+    let steps: Vec<String> = serde_json::from_str(&text[start..=end])?;
+    if steps.is_empty() { return Err("Empty plan".into()); }
+    Ok(steps)
+}
+
+// Fallback when the summary call fails: counts plus the pages visited and failures seen
+pub fn local_history_summary(entries: &[HistoryEntry]) -> String {
+    let ok = entries.iter().filter(|h| h.success).count();
+    let urls = entries.iter().filter(|h| h.success && matches!(h.action.action_type.as_str(), "navigate" | "open_url")).filter_map(|h| h.action.params.as_ref()?["url"].as_str().map(|s| s.to_string())).collect::<Vec<_>>();
+    let failures = entries.iter().filter(|h| !h.success).map(|h| format!("{} on {}", h.action.action_type, h.action.target)).collect::<Vec<_>>();
+    let mut s = format!("{} earlier actions ({} ok, {} failed).", entries.len(), ok, entries.len() - ok);
+    if !urls.is_empty() { s.push_str(&format!(" Visited: {}.", urls.join(", "))); }
+    if !failures.is_empty() { s.push_str(&format!(" Failed: {}.", failures.join("; "))); }
+    s
+}
+
+pub fn model_pricing(model: &str) -> (f64, f64) {
+    PRICING.iter().find(|(prefix, _, _)| model.starts_with(prefix)).map(|(_, i, o)| (*i, *o)).unwrap_or(FALLBACK_PRICING)
+}
+
+pub fn estimate_cost(model: &str, usage: &Usage) -> f64 {
+    let (input, output) = model_pricing(model);
+    (usage.input_tokens as f64 * input
+        + usage.cache_creation_input_tokens as f64 * input * CACHE_WRITE_FACTOR
+        + usage.cache_read_input_tokens as f64 * input * CACHE_READ_FACTOR
+        + usage.output_tokens as f64 * output) / 1_000_000.0
+}
+
+fn log_llm_call(model: &str, usage: &Usage, prompt_chars: usize) {
+    let cost = estimate_cost(model, usage);
+    let saved = usage.cache_read_input_tokens as f64 * model_pricing(model).0 * (1.0 - CACHE_READ_FACTOR) / 1_000_000.0;
+    crate::log_info!(
+        "llm",
+        "LLM call: model={} prompt_chars={} input={} output={} cache_write={} cache_read={} cost=${:.4} cache_saved=${:.4}",
+        model, prompt_chars, usage.input_tokens, usage.output_tokens, usage.cache_creation_input_tokens, usage.cache_read_input_tokens, cost, saved
+    );
+}
+
+// Shared core: role and output contract, independent of the backend executing actions
+const CORE_PROMPT: &str = "You are a browser automation assistant. ONE action at a time.";
+const OUTPUT_FORMAT: &str = r#"OUTPUT JSON ONLY: {"action_type":"...","target":"...","params":{...},"reasoning":"...","confidence":0.0-1.0}. If more than one element could be the right one, add "alternatives":[the other candidate actions, same shape]."#;
+
+// Browser (CDP) environment notes and action table
+const BROWSER_TARGETING: &str = r#"Use CSS selectors for target (or "role:button|name:Save" for the node with that role and exact name in the accessibility tree, preferred when several elements share a name, or "ax:nodeId" for accessibility tree nodes, or "xpath://..." for XPath, or "coords:x,y" for nodes that only have bounds, like "ocr:" text nodes and unnamed icon controls that list a "coords" target, or "pixel:x,y" for a point you can only locate in the screenshot image, in that image's pixels). Nodes with "enabled": false do nothing when clicked; "offscreen": true nodes need a scroll before they can be used."#;
+pub const BROWSER_ACTIONS: &[(&str, &str)] = &[
+    ("click", r#"target=CSS selector|"ax:id"|"xpath:..."|"coords:x,y""#),
+    ("double_click", "target=CSS selector"),
+    ("right_click", "target=CSS selector"),
+    ("hover", "target=CSS selector"),
+    ("mouse_down", r#"target=any element target, or "screen:x,y" for a point on the real screen, params.button=left|right|middle; holds the button until mouse_up"#),
+    ("mouse_up", "target=where to release (omit to release in place; a different point drags there); must follow a mouse_down, only one button can be held"),
+    ("type", "target=CSS selector (or empty for focused), params.text=string, params.clear_first=false to append instead of replacing, params.delay_ms=N to type key by key for fields that react to each keystroke"),
+    ("clear", "target=CSS selector (clears input field)"),
+    ("navigate", "params.url=URL"),
+    ("open_url", "params.url=URL; like navigate, but also starts Chrome first if it isn't running (use it for \"go to X\" when no page is open)"),
+    ("scroll", r#"params.direction="up"|"down"|"left"|"right", params.amount=pixels (default 300), params.axis="x"|"y" (default y), params.target=element whose container scrolls (optional), params.to="top"|"bottom" jumps to the edge instead"#),
+    ("copy", "target=element to copy from (optional, else the focused one); copies the current selection"),
+    ("cut", "target=element (optional); cuts the current selection"),
+    ("paste", "target=element to paste into (optional), params.text=text to put on the clipboard first (optional)"),
+    ("select_all", "target=element (optional); selects all of its text, or the page with no target"),
+    ("press_key", r#"params.key=key name (Enter, Tab, Escape, ArrowDown, etc.), params.modifiers=["ctrl"|"shift"|"alt"|"meta"] (optional). For clipboard shortcuts use copy/cut/paste/select_all instead"#),
+    ("focus_window", "brings browser tab to front"),
+    ("switch_tab", "params.index=tab number from the STATE tab list, or params.title=part of its title or URL; later actions go to that tab"),
+    ("new_tab", "params.url=page to open (default about:blank); later actions go to the new tab"),
+    ("close_tab", "params.index=tab number to close (default the active tab); the last tab can't be closed"),
+    ("zoom", r#"params.direction="in"|"out"|"reset" (25% steps) or params.level=absolute page zoom (e.g. 1.5); clicks still land on the zoomed page. On Windows, params.title=window to zoom instead, with params.steps=wheel notches (default 1)"#),
+    ("select", "target=CSS selector, params.label=visible option text (preferred) or params.value=option value (for <select>)"),
+    ("wait", r#"target=CSS selector, params.timeout=ms (wait for element to appear); or params.until="network_idle" (params.idle_ms, default 500) to wait for XHR/fetch to finish"#),
+    ("go_back", "navigate back in history"),
+    ("go_forward", "navigate forward in history"),
+    ("reload", "refresh the page"),
+    ("fill_credential", r#"target=CSS selector of the input, params.label=stored credential label, params.field="username"|"password" (the app types the secret for you)"#),
+    ("run_skill", r#"params.name=saved skill to replay, params.args={placeholder: value} filling the skill's {placeholders}"#),
+    ("eval_js", "params.code=JavaScript to execute"),
+    ("read_text", r#"target=CSS selector|"ax:id"|"xpath:...", params.attribute=optional attribute name (the text is shown to you as LAST_READ next step)"#),
+    ("get_page_text", "reads the page's main text (article or main region, else the whole body); it is shown to you as LAST_READ next step. Use it to summarise or answer questions about content. On Windows, params.title=window whose document and text controls to read instead"),
+    ("assert", "params.condition=what should be true, params.target=element (optional), params.expected_text=text it should contain (optional). Use to verify the goal is reached; fails with the mismatch otherwise"),
+    ("save_pdf", r#"save the current page as a PDF in Downloads: params.filename (optional), params.landscape=bool, params.paper="a4"|"letter"|"legal"|"a3" (default A4 portrait), params.print_background=bool (default true)"#),
+    ("complete", "params.summary=what was achieved (emit once the goal is done); params.result=the requested data as JSON when the goal asks for information (e.g. {\"price\": \"$129\"})"),
+];
+
+// Window switching, placement and control reads outside the browser; only offered where it works
+const DESKTOP_ACTIONS: &[(&str, &str)] = &[
+    ("alt_tab", "params.count=how many times to tap Tab while holding Alt (default 1, the previous window)"),
+    ("switch_to_app", "params.title=part of the window title to bring to the front"),
+    ("move_window", "params.x, params.y=new top-left corner in screen pixels; params.title=window to move (default the foreground one)"),
+    ("resize_window", "params.width, params.height=new size in pixels (at least 100x50); params.title as for move_window"),
+    ("snap_window", "params.side=\"left\"|\"right\" half or \"maximize\", on the monitor the window is on; params.title as for move_window"),
+    ("get_value", "target=AutomationId or name of a control in the window (see get_screen_a11y_tree), or \"role:Button|name:Save\" when the name is shared, params.title as for move_window; its text value is shown to you as LAST_READ"),
+    ("get_toggle_state", "target, params.title as for get_value; checkbox/toggle state on|off|indeterminate as LAST_READ"),
+    ("is_selected", "target, params.title as for get_value; true|false for a list item, tab or radio button as LAST_READ"),
+];
+
+// The built-in actions the prompt offers on this platform; custom actions come from the registry
+pub fn is_builtin_action(name: &str) -> bool {
+    BROWSER_ACTIONS.iter().any(|(n, _)| *n == name) || (cfg!(windows) && DESKTOP_ACTIONS.iter().any(|(n, _)| *n == name))
+}
+
+// Actions the deployment doesn't permit are left out, so the model never proposes them
+fn system_prompt(extra: &[(String, String)], filter: &ActionFilter) -> String {
+    let permitted = |table: &[(&'static str, &'static str)]| table.iter().filter(|(n, _)| filter.permits(n)).copied().collect::<Vec<_>>();
+    let mut actions = action_table(&permitted(BROWSER_ACTIONS));
+    if cfg!(windows) { actions.push_str(&format!("\n{}", action_table(&permitted(DESKTOP_ACTIONS)))); }
+    for (name, desc) in extra.iter().filter(|(n, _)| filter.permits(n)) { actions.push_str(&format!("\n- {}: {}", name, desc)); }
+    format!("{} {}\n\nACTIONS:\n{}\n\n{}", CORE_PROMPT, BROWSER_TARGETING, actions, OUTPUT_FORMAT)
+}
+
+fn action_table(actions: &[(&str, &str)]) -> String {
+    actions.iter().map(|(name, desc)| format!("- {}: {}", name, desc)).collect::<Vec<_>>().join("\n")
+}
+
+fn user_msg(cmd: &str, state: &ExecutionState, history: &[HistoryEntry], notes: &[String], stop_words: &[String]) -> String {
     let h = history.iter().map(|h| format!("- {}: {} ({})", h.action.action_type, h.action.reasoning.as_deref().unwrap_or(""), if h.success {"ok"} else {"fail"})).collect::<Vec<_>>().join("\n");
-    format!("GOAL: {}\n\nSTATE:\n- Window: {}\n- URL: {}\n- A11y Tree:\n{}\n\nHISTORY:\n{}\n\nNext action? JSON only.", cmd, state.active_window, state.url.as_deref().unwrap_or("N/A"), serde_json::to_string_pretty(&state.accessibility_tree).unwrap_or_default(), if h.is_empty() {"(none)".to_string()} else {h})
+    let last_read = last_read(history).map(|t| format!("\n\nLAST_READ:\n{}", t)).unwrap_or_default();
+    let notes = notes.iter().map(|n| format!("{}\n\n", n)).collect::<String>();
+    format!("{}GOAL: {}\n\nSTATE:\n- Window: {}\n- URL: {}{}\n- A11y Tree:\n{}\n\nHISTORY:\n{}{}\n\nNext action? JSON only.", notes, cmd, state.active_window, state.url.as_deref().unwrap_or("N/A"), tab_list(state), serde_json::to_string_pretty(&ranked_tree(&state.accessibility_tree, cmd, stop_words)).unwrap_or_default(), if h.is_empty() {"(none)".to_string()} else {h}, last_read)
+}
+
+// Output of the latest step when it was a read or a plugin action; history arrives newest-first. Older
+// reads and other built-ins' output (assert, eval_js, ...) stay out, so the model never acts on a stale value.
+fn last_read(history: &[HistoryEntry]) -> Option<&str> {
+    let latest = history.first()?;
+    let name = latest.action.action_type.as_str();
+    let builtin = BROWSER_ACTIONS.iter().chain(DESKTOP_ACTIONS).any(|(n, _)| *n == name);
+    if builtin && !READ_ACTIONS.contains(&name) { return None; }
+    latest.output.as_deref()
+}
+
+// One line per tab; nothing when only one is open
+fn tab_list(state: &ExecutionState) -> String {
+    if state.tabs.len() < 2 { return String::new(); }
+    state.tabs.iter().enumerate().map(|(i, t)| {
+        let title: String = t.title.chars().take(60).collect();
+        format!("\n  [{}] {} | {}{}", i, title, t.url, if state.active_tab == Some(i) {" (active)"} else {""})
+    }).fold("\n- Tabs:".to_string(), |acc, line| acc + &line)
 }
 
-fn retry_msg(action: &ActionCommand, error: &str, state: &ExecutionState, _: &[HistoryEntry]) -> String {
-    format!("FAILED: {} on {:?}\nError: {}\n\nCURRENT STATE:\n- Window: {}\n- URL: {}\n- A11y:\n{}\n\nSuggest alternative. JSON only.", action.action_type, action.target, error, state.active_window, state.url.as_deref().unwrap_or("N/A"), serde_json::to_string_pretty(&state.accessibility_tree).unwrap_or_default())
+fn retry_msg(action: &ActionCommand, error: &str, state: &ExecutionState, _: &[HistoryEntry], with_screenshot: bool, stop_words: &[String]) -> String {
+    let screenshot = if with_screenshot { "\nThe screenshot shows the page now; a red box marks where the failed action aimed, if it could be located.\n" } else { "" };
+    format!("FAILED: {} on {:?}\nError: {}\n{}\nCURRENT STATE:\n- Window: {}\n- URL: {}\n- A11y:\n{}\n\nSuggest alternative. JSON only.", action.action_type, action.target, error, screenshot, state.active_window, state.url.as_deref().unwrap_or("N/A"), serde_json::to_string_pretty(&ranked_tree(&state.accessibility_tree, action.reasoning.as_deref().unwrap_or(""), stop_words)).unwrap_or_default())
 }
 
-fn parse_response(res: &serde_json::Value) -> Result<ActionCommand, Box<dyn std::error::Error + Send + Sync>> {
-    let t = res["content"][0]["text"].as_str().ok_or("No text")?;
+#[derive(Debug, PartialEq)]
+struct GoalKeywords {
+    content: Vec<String>,
+    verbs: Vec<String>,
+}
+
+impl GoalKeywords {
+    // Words of 3+ letters, lowercased and deduplicated, minus stop words; action verbs go in their own list
+    fn new(goal: &str, stop_words: &[String]) -> Self {
+        let mut keywords = GoalKeywords { content: Vec::new(), verbs: Vec::new() };
+        for word in goal.split(|c: char| !c.is_alphanumeric()).filter(|w| w.len() >= 3).map(str::to_lowercase) {
+            if stop_words.iter().any(|s| s.eq_ignore_ascii_case(&word)) { continue; }
+            let list = if ACTION_VERBS.contains(&word.as_str()) { &mut keywords.verbs } else { &mut keywords.content };
+            if !list.contains(&word) { list.push(word); }
+        }
+        keywords
+    }
+
+    // A node matches when it holds at least half the content keywords (rounded up), or any verb when
+    // the goal has nothing else
+    fn matches(&self, text: &str) -> bool {
+        if self.content.is_empty() { return self.verbs.iter().any(|v| text.contains(v.as_str())); }
+        let hits = self.content.iter().filter(|k| text.contains(k.as_str())).count();
+        hits * 2 >= self.content.len()
+    }
+}
+
+// Orders nodes by how likely they matter for the goal and keeps the top MAX_PROMPT_NODES. Nodes matching
+// the goal's keywords come first, then content hits, verb hits and usability (enabled, on screen);
+// ties keep page order.
+fn ranked_tree(tree: &serde_json::Value, goal: &str, stop_words: &[String]) -> serde_json::Value {
+    let Some(nodes) = tree.as_array() else { return tree.clone() };
+    let keywords = GoalKeywords::new(goal, stop_words);
+    let score = |n: &serde_json::Value| {
+        let text = format!("{} {} {}", n["name"].as_str().unwrap_or(""), n["value"].as_str().unwrap_or(""), n["context"].as_str().unwrap_or("")).to_lowercase();
+        let hits = |words: &[String]| words.iter().filter(|k| text.contains(k.as_str())).count() as u32;
+        let usable = n["enabled"].as_bool().unwrap_or(true) && !n["offscreen"].as_bool().unwrap_or(false);
+        let matched = if keywords.matches(&text) { 100 } else { 0 };
+        matched + hits(&keywords.content) * 10 + hits(&keywords.verbs) * 3
+            + if usable { 5 } else { 0 } + if n["focusable"].as_bool().unwrap_or(false) { 1 } else { 0 }
+    };
+    let mut ranked: Vec<&serde_json::Value> = nodes.iter().collect();
+    ranked.sort_by_key(|n| std::cmp::Reverse(score(n)));
+    ranked.truncate(MAX_PROMPT_NODES);
+    serde_json::Value::Array(ranked.into_iter().cloned().collect())
+}
+
+fn parse_response(res: &serde_json::Value) -> Result<ActionCommand, LLMError> {
+    let t = res["content"][0]["text"].as_str().filter(|t| !t.trim().is_empty()).ok_or(LLMError::EmptyContent)?;
     let j = t.trim().trim_start_matches("```json").trim_start_matches("```").trim_end_matches("```").trim();
-    Ok(serde_json::from_str(j)?)
+    serde_json::from_str(j).map_err(|e| LLMError::Parse(format!("the reply is not a JSON action ({})", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn defaults() -> Vec<String> {
+        DEFAULT_STOP_WORDS.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn usage(input_tokens: u32, output_tokens: u32) -> Usage {
+        Usage { input_tokens, output_tokens, ..Default::default() }
+    }
+
+    #[test]
+    fn haiku_is_cheaper_than_sonnet_for_the_same_tokens() {
+        let u = usage(1_000_000, 100_000);
+        assert!((estimate_cost("claude-sonnet-4-20250514", &u) - 4.5).abs() < 1e-9);
+        assert!((estimate_cost("claude-3-5-haiku-20241022", &u) - 1.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn unknown_models_cost_sonnet_rates_and_cache_reads_are_discounted() {
+        assert_eq!(model_pricing("some-future-model"), FALLBACK_PRICING);
+        let cached = Usage { cache_read_input_tokens: 1_000_000, ..Default::default() };
+        assert!((estimate_cost("claude-sonnet-4-20250514", &cached) - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_matching_node_survives_the_node_cap() {
+        let mut nodes: Vec<serde_json::Value> = (0..200).map(|i| json!({"name": format!("Item {}", i), "focusable": true})).collect();
+        nodes[149] = json!({"name": "Checkout"});
+        let ranked = ranked_tree(&serde_json::Value::Array(nodes), "go to checkout", &defaults());
+        let ranked = ranked.as_array().unwrap();
+        assert_eq!(ranked.len(), MAX_PROMPT_NODES);
+        assert_eq!(ranked[0]["name"], "Checkout");
+    }
+
+    #[test]
+    fn search_goals_keep_their_nouns() {
+        let k = GoalKeywords::new("Search for chrome extensions", &defaults());
+        assert_eq!(k.content, vec!["chrome", "extensions"]);
+        assert_eq!(k.verbs, vec!["search"]);
+        let k = GoalKeywords::new("find the google docs link", &defaults());
+        assert_eq!(k.content, vec!["google", "docs", "link"]);
+        assert_eq!(k.verbs, vec!["find"]);
+    }
+
+    #[test]
+    fn custom_stop_words_replace_the_defaults() {
+        let k = GoalKeywords::new("search for chrome extensions", &["chrome".to_string()]);
+        assert_eq!(k.content, vec!["for", "extensions"]);
+        assert_eq!(GoalKeywords::new("search for chrome", &[]).content, vec!["for", "chrome"]);
+    }
+
+    #[test]
+    fn a_match_needs_half_the_content_keywords() {
+        let k = GoalKeywords::new("search for chrome extensions manager", &defaults());
+        assert!(k.matches("chrome extensions"));
+        assert!(k.matches("manage extensions and chrome apps"));
+        assert!(!k.matches("chrome"));
+        assert!(!k.matches("search"));
+        let only_verbs = GoalKeywords::new("search", &defaults());
+        assert!(only_verbs.matches("search google"));
+    }
+
+    #[test]
+    fn matching_nodes_outrank_verb_hits() {
+        let tree = json!([
+            {"name": "Search Google", "focusable": true},
+            {"name": "About"},
+            {"name": "Chrome Web Store - Extensions"},
+        ]);
+        let ranked = ranked_tree(&tree, "search for chrome extensions", &defaults());
+        let names: Vec<&str> = ranked.as_array().unwrap().iter().map(|n| n["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Chrome Web Store - Extensions", "Search Google", "About"]);
+    }
+
+    fn step(action_type: &str, output: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: String::new(),
+            user_input: None,
+            llm_reasoning: String::new(),
+            action: ActionCommand { action_type: action_type.to_string(), ..Default::default() },
+            success: true,
+            error: None,
+            screenshot_base64: None,
+            screenshot_path: None,
+            output: output.map(str::to_string),
+            target_screenshot_base64: None,
+            confidence: None,
+        }
+    }
+
+    #[test]
+    fn last_read_is_the_latest_read_step() {
+        assert_eq!(last_read(&[step("read_text", Some("Order #123")), step("get_value", Some("old"))]), Some("Order #123"));
+        assert_eq!(last_read(&[step("get_page_text", Some("Body"))]), Some("Body"));
+        assert_eq!(last_read(&[step("lookup_invoice", Some("Paid"))]), Some("Paid"));
+    }
+
+    #[test]
+    fn last_read_skips_stale_and_non_read_output() {
+        assert_eq!(last_read(&[step("click", None), step("read_text", Some("Order #123"))]), None);
+        assert_eq!(last_read(&[step("assert", Some("title contains Inbox")), step("read_text", Some("x"))]), None);
+        assert_eq!(last_read(&[]), None);
+    }
+
+    fn llm(vision: bool) -> LlmConfig {
+        LlmConfig { api_key: String::new(), model: String::new(), max_tokens: 1024, extra_actions: Vec::new(), vision, action_filter: ActionFilter::default(), stop_words: defaults() }
+    }
+
+    #[test]
+    fn retries_send_the_screenshot_only_in_vision_mode() {
+        let state: ExecutionState = serde_json::from_value(json!({
+            "screenshot_base64": "", "accessibility_tree": [], "active_window": "Shop",
+            "url": "https://shop.example/cart", "success": false, "error": "element not found"
+        })).unwrap();
+        let failed = ActionCommand { action_type: "click".to_string(), target: json!("ax:12"), ..Default::default() };
+
+        let (text, image) = retry_content(&llm(true), &failed, "element not found", &state, &[], Some("iVBOR"));
+        assert!(text.contains("red box"));
+        let content = message_content(text, image);
+        assert_eq!(content[0]["type"], "image");
+        assert_eq!(content[0]["source"]["data"], "iVBOR");
+        assert_eq!(content[1]["type"], "text");
+
+        let (text, image) = retry_content(&llm(false), &failed, "element not found", &state, &[], Some("iVBOR"));
+        assert!(!text.contains("red box"));
+        assert!(message_content(text, image).is_string());
+        let (text, image) = retry_content(&llm(true), &failed, "element not found", &state, &[], None);
+        assert!(message_content(text, image).is_string());
+    }
+
+    #[test]
+    fn the_prompt_lists_tabs_when_more_than_one_is_open() {
+        let mut state: ExecutionState = serde_json::from_value(json!({
+            "screenshot_base64": "", "accessibility_tree": [], "active_window": "GitHub", "url": null, "success": true, "error": null,
+            "tabs": [
+                {"id": "A", "title": "GitHub", "url": "https://github.com/", "ws_url": ""},
+                {"id": "B", "title": "docs.rs", "url": "https://docs.rs/", "ws_url": ""}
+            ],
+            "active_tab": 1
+        })).unwrap();
+        assert_eq!(tab_list(&state), "\n- Tabs:\n  [0] GitHub | https://github.com/\n  [1] docs.rs | https://docs.rs/ (active)");
+        assert!(user_msg("switch to the GitHub tab", &state, &[], &[], &defaults()).contains("[0] GitHub | https://github.com/"));
+        state.tabs.truncate(1);
+        assert_eq!(tab_list(&state), "");
+    }
+
+    fn act(action_type: &str, target: &str, params: serde_json::Value) -> ActionCommand {
+        ActionCommand { action_type: action_type.to_string(), target: json!(target), params: Some(params), ..Default::default() }
+    }
+
+    #[test]
+    fn malformed_actions_are_rejected_with_the_reason() {
+        let none = ActionFilter::default();
+        let check = |a: &ActionCommand| validate_action(a, &[], &none);
+        assert_eq!(check(&act("clik", "ax:3", json!({}))), Err("\"clik\" is not one of the listed actions".to_string()));
+        assert_eq!(check(&act("type", "ax:3", json!({"txt": "hi"}))), Err("type needs params.text".to_string()));
+        assert_eq!(check(&act("click", "  ", json!({}))), Err("click needs a target".to_string()));
+        assert_eq!(check(&act("select", "ax:9", json!({}))), Err("select needs params.label or params.value".to_string()));
+        assert_eq!(check(&act("select", "", json!({"value": "NL"}))), Err("select needs a target".to_string()));
+        let no_params = ActionCommand { action_type: "navigate".to_string(), ..Default::default() };
+        assert_eq!(check(&no_params), Err("navigate needs params.url".to_string()));
+    }
+
+    #[test]
+    fn well_formed_actions_pass() {
+        let none = ActionFilter::default();
+        assert!(validate_action(&act("type", "ax:3", json!({"text": "hi"})), &[], &none).is_ok());
+        assert!(validate_action(&act("select", "ax:9", json!({"value": "NL"})), &[], &none).is_ok());
+        assert!(validate_action(&act("scroll", "", json!({})), &[], &none).is_ok());
+        // Registered actions count as listed, and the filter can take any action off the list
+        let extra = vec![("lookup_invoice".to_string(), "Find an invoice".to_string())];
+        assert!(validate_action(&act("lookup_invoice", "", json!({})), &extra, &none).is_ok());
+        let filter = ActionFilter { allowed: Vec::new(), blocked: vec!["eval_js".to_string()] };
+        assert!(validate_action(&act("eval_js", "", json!({"code": "1"})), &[], &filter).is_err());
+    }
+
+    fn api_error(error_type: &str, message: &str) -> serde_json::Value {
+        json!({"type": "error", "error": {"type": error_type, "message": message}})
+    }
+
+    #[test]
+    fn anthropic_error_bodies_map_to_their_variant() {
+        let auth = LLMError::from_reply(401, None, &api_error("authentication_error", "invalid x-api-key"));
+        assert!(matches!(&auth, LLMError::Auth(m) if m == "invalid x-api-key"));
+        assert!(matches!(LLMError::from_reply(403, None, &api_error("permission_error", "Your API key does not have permission")), LLMError::Auth(_)));
+        let limited = LLMError::from_reply(429, Some("20"), &api_error("rate_limit_error", "Number of request tokens has exceeded your rate limit"));
+        assert!(matches!(limited, LLMError::RateLimited { retry_after: Some(d) } if d == std::time::Duration::from_secs(20)));
+        assert!(matches!(LLMError::from_reply(429, Some("soon"), &api_error("rate_limit_error", "")), LLMError::RateLimited { retry_after: None }));
+        assert!(matches!(LLMError::from_reply(529, None, &api_error("overloaded_error", "Overloaded")), LLMError::Overloaded));
+        assert!(matches!(LLMError::from_reply(500, None, &api_error("api_error", "Internal server error")), LLMError::Overloaded));
+        let invalid = LLMError::from_reply(400, None, &api_error("invalid_request_error", "model: claude-nope not found"));
+        assert!(matches!(&invalid, LLMError::Api { status: 400, message } if message == "model: claude-nope not found"));
+        assert!(matches!(LLMError::from_reply(404, None, &api_error("not_found_error", "Not found")), LLMError::Api { status: 404, .. }));
+    }
+
+    #[test]
+    fn bodies_without_an_error_type_fall_back_to_the_status() {
+        // e.g. an HTML page from a proxy in front of the API
+        assert!(matches!(LLMError::from_reply(401, None, &serde_json::Value::Null), LLMError::Auth(_)));
+        assert!(matches!(LLMError::from_reply(429, None, &serde_json::Value::Null), LLMError::RateLimited { retry_after: None }));
+        assert!(matches!(LLMError::from_reply(502, None, &serde_json::Value::Null), LLMError::Overloaded));
+        assert!(matches!(LLMError::from_reply(413, None, &serde_json::Value::Null), LLMError::Api { status: 413, .. }));
+    }
+
+    #[test]
+    fn unusable_replies_are_parse_or_empty_content() {
+        let reply = |text: &str| json!({"content": [{"type": "text", "text": text}]});
+        assert!(matches!(parse_response(&json!({"content": []})), Err(LLMError::EmptyContent)));
+        assert!(matches!(parse_response(&reply("  \n")), Err(LLMError::EmptyContent)));
+        assert!(matches!(parse_response(&reply("I will click the button.")), Err(LLMError::Parse(_))));
+        let fenced = parse_response(&reply("```json\n{\"action_type\": \"click\", \"target\": \"ax:4\"}\n```")).unwrap();
+        assert_eq!((fenced.action_type.as_str(), &fenced.target), ("click", &json!("ax:4")));
+    }
+
+    #[test]
+    fn non_array_trees_pass_through() {
+        let tree = json!({"name": "Desktop", "children": []});
+        assert_eq!(ranked_tree(&tree, "anything", &defaults()), tree);
+    }
 }
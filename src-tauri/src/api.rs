@@ -0,0 +1,115 @@
+// Local HTTP API for driving the agent from scripts. It runs the same command functions as the
+// webview against the same AppState, so the GUI and API share one session.
+//
+// Listens on 127.0.0.1 only. Every request needs `Authorization: Bearer <token>`.
+// Errors come back as 400 with {"error": "..."}; a missing or wrong token is a bare 401.
+//
+//   POST /command  {"goal": "..."}                      -> ActionCommand proposed as the first step
+//   POST /approve  {"approved": bool, "feedback": "..."} -> {"outcome": "executed", "data": ExecutionState}
+//                                                         | {"outcome": "revised", "data": ActionCommand}
+//   GET  /state                                          -> ExecutionState of the current page
+//   GET  /history                                        -> [HistoryEntry]
+//   GET  /result                                         -> params.result of the last complete step, or null
+//   POST /cancel                                         -> {}
+use std::net::SocketAddr;
+use axum::extract::State as ApiState;
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use tauri::{AppHandle, Manager, Window};
+use crate::AppState;
+
+// Short tokens are too easy to guess for something that can drive the browser
+const MIN_TOKEN_LEN: usize = 16;
+
+#[derive(Clone)]
+struct Api {
+    app: AppHandle,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct CommandBody {
+    goal: String,
+}
+
+#[derive(Deserialize)]
+struct ApproveBody {
+    approved: bool,
+    feedback: Option<String>,
+}
+
+// Binds before returning so a taken port is reported to the caller; requests are then served in the background
+pub fn start(app: AppHandle, port: u16, token: String) -> Result<(), String> {
+    if token.len() < MIN_TOKEN_LEN {
+        return Err(format!("API token must be at least {} characters", MIN_TOKEN_LEN));
+    }
+    let api = Api { app, token };
+    let router = Router::new()
+        .route("/command", post(command))
+        .route("/approve", post(approve))
+        .route("/state", get(current_state))
+        .route("/history", get(history))
+        .route("/result", get(last_result))
+        .route("/cancel", post(cancel))
+        .route_layer(middleware::from_fn_with_state(api.clone(), auth))
+        .with_state(api);
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let server = axum::Server::try_bind(&addr).map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = server.serve(router.into_make_service()).await {
+            log_error!("api", "server stopped: {}", e);
+        }
+    });
+    log_info!("api", "listening on http://{}", addr);
+    Ok(())
+}
+
+async fn auth<B>(ApiState(api): ApiState<Api>, req: Request<B>, next: Next<B>) -> Response {
+    let bearer = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+    if bearer != Some(api.token.as_str()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(req).await
+}
+
+fn reply<T: serde::Serialize>(result: Result<T, String>) -> Response {
+    match result {
+        Ok(v) => Json(v).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))).into_response(),
+    }
+}
+
+fn main_window(api: &Api) -> Result<Window, String> {
+    api.app.get_window("main").ok_or_else(|| "Main window is not open".to_string())
+}
+
+async fn command(ApiState(api): ApiState<Api>, Json(body): Json<CommandBody>) -> Response {
+    let window = match main_window(&api) { Ok(w) => w, Err(e) => return reply::<()>(Err(e)) };
+    reply(crate::execute_user_command(body.goal, window, api.app.state::<AppState>()).await)
+}
+
+async fn approve(ApiState(api): ApiState<Api>, Json(body): Json<ApproveBody>) -> Response {
+    let window = match main_window(&api) { Ok(w) => w, Err(e) => return reply::<()>(Err(e)) };
+    reply(crate::approve_action_with_feedback(body.approved, body.feedback, window, api.app.state::<AppState>()).await)
+}
+
+async fn current_state(ApiState(api): ApiState<Api>) -> Response {
+    reply(crate::get_current_state(api.app.state::<AppState>()).await)
+}
+
+async fn history(ApiState(api): ApiState<Api>) -> Response {
+    reply(crate::get_history(api.app.state::<AppState>()).await)
+}
+
+async fn last_result(ApiState(api): ApiState<Api>) -> Response {
+    reply(crate::get_last_result(api.app.state::<AppState>()).await)
+}
+
+async fn cancel(ApiState(api): ApiState<Api>) -> Response {
+    reply(crate::cancel_execution(api.app.state::<AppState>()).await.map(|_| serde_json::json!({})))
+}
@@ -1,9 +1,13 @@
-﻿use serde::{Deserialize, Serialize};
+﻿use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
 use std::process::Command;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,67 +18,406 @@ pub struct AXNode {
     pub value: Option<String>,
     pub bounds: Option<Bounds>,
     pub focusable: bool,
+    // Disabled and offscreen nodes stay in the tree, flagged, so scroll-to-reveal can still target them
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub offscreen: bool,
+    // Only set on unnamed nodes: nearest named ancestor or sibling, and a click target by position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coords: Option<String>,
+    // Set for nodes inside iframes; their node_id is "axId@frameId" so ax: targets resolve in that frame
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_id: Option<String>,
 }
 
+// Icon-only controls are kept but bounded so they can't crowd out named ones
+const MAX_UNNAMED_NODES: usize = 30;
+
+fn default_enabled() -> bool { true }
+
+fn default_dpr() -> f64 { 1.0 }
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bounds { pub x: f64, pub y: f64, pub width: f64, pub height: f64 }
 
+// An element the user clicked in pick mode. target is "ax:<id>" when the click resolved to an
+// accessibility node, otherwise the "coords:x,y" of the click.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PickedTarget { pub target: String, pub role: Option<String>, pub name: Option<String>, pub x: f64, pub y: f64 }
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TabInfo { pub id: String, pub title: String, pub url: String, pub ws_url: String }
 
+// What save_browser_session writes: every cookie as Network.getAllCookies returned it, and the URLs of
+// the open tabs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BrowserSession { pub cookies: Vec<Value>, pub tab_urls: Vec<String> }
+
+// Where Chrome's debugger listens. Only a local endpoint is ever launched; a remote one (container, VM)
+// must already be running with --remote-debugging-address=0.0.0.0 or behind a port forward.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CdpEndpoint {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for CdpEndpoint {
+    fn default() -> Self {
+        Self { host: "localhost".to_string(), port: 9222 }
+    }
+}
+
+impl CdpEndpoint {
+    pub fn is_local(&self) -> bool {
+        matches!(self.host.trim_matches(|c| c == '[' || c == ']'), "localhost" | "127.0.0.1" | "::1")
+    }
+
+    // host:port with IPv6 literals bracketed
+    fn authority(&self) -> String {
+        if self.host.contains(':') && !self.host.starts_with('[') { format!("[{}]:{}", self.host, self.port) } else { format!("{}:{}", self.host, self.port) }
+    }
+}
+
+// Chrome refuses HTTP and WebSocket requests whose Host header is a name rather than an IP or localhost
+// (DNS rebinding protection), so requests to a named host claim to be for localhost
+fn host_header_ok(host: &str) -> bool {
+    let host = host.trim_matches(|c| c == '[' || c == ']');
+    host == "localhost" || host.parse::<std::net::IpAddr>().is_ok()
+}
+
+// webSocketDebuggerUrl names whatever host Chrome thinks it is (localhost, the container's address,
+// or the spoofed Host header), which is unreachable from here; keep its path and use the endpoint we reached
+fn rewrite_ws_host(ws_url: &str, authority: &str) -> String {
+    let Some((scheme, rest)) = ws_url.split_once("://") else { return ws_url.to_string() };
+    let path = rest.find('/').map_or("", |i| &rest[i..]);
+    format!("{}://{}{}", scheme, authority, path)
+}
+
+// Device emulation applied to every CDP session (overrides are dropped when a session closes)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmulationConfig {
+    pub width: u32,
+    pub height: u32,
+    pub device_scale_factor: f64,
+    pub mobile: bool,
+    pub user_agent: Option<String>,
+}
+
+impl EmulationConfig {
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "iphone" | "iphone 14" => Some(Self { width: 390, height: 844, device_scale_factor: 3.0, mobile: true, user_agent: Some("Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Mobile/15E148 Safari/604.1".to_string()) }),
+            "pixel" | "pixel 7" => Some(Self { width: 412, height: 915, device_scale_factor: 2.625, mobile: true, user_agent: Some("Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Mobile Safari/537.36".to_string()) }),
+            "ipad" => Some(Self { width: 820, height: 1180, device_scale_factor: 2.0, mobile: true, user_agent: Some("Mozilla/5.0 (iPad; CPU OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Mobile/15E148 Safari/604.1".to_string()) }),
+            "desktop" | "desktop hd" => Some(Self { width: 1920, height: 1080, device_scale_factor: 1.0, mobile: false, user_agent: None }),
+            _ => None,
+        }
+    }
+}
+
+// Where and when the page thinks it is. Each part is optional; all are re-applied per CDP session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LocaleConfig {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // Metres; defaults to 100
+    pub accuracy: Option<f64>,
+    // IANA id, e.g. "Europe/Berlin"
+    pub timezone: Option<String>,
+    // ICU locale, e.g. "de-DE"
+    pub locale: Option<String>,
+}
+
+// Page.printToPDF options; defaults to A4 portrait with backgrounds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdfOptions {
+    #[serde(default)]
+    pub landscape: bool,
+    #[serde(default = "default_enabled")]
+    pub print_background: bool,
+    // "a4", "a3", "letter" or "legal"
+    #[serde(default = "default_paper")]
+    pub paper: String,
+}
+
+fn default_paper() -> String { "a4".to_string() }
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self { landscape: false, print_background: true, paper: default_paper() }
+    }
+}
+
+impl PdfOptions {
+    // Width and height in inches, which is what printToPDF takes
+    fn paper_size(&self) -> Result<(f64, f64), String> {
+        match self.paper.to_lowercase().as_str() {
+            "a4" => Ok((8.27, 11.69)),
+            "a3" => Ok((11.69, 16.54)),
+            "letter" => Ok((8.5, 11.0)),
+            "legal" => Ok((8.5, 14.0)),
+            other => Err(format!("Unknown paper size: {}", other)),
+        }
+    }
+}
+
+// Throttling re-applied to every CDP session like EmulationConfig. Throughputs are bytes per second,
+// as Network.emulateNetworkConditions takes them; -1 leaves that direction unthrottled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConditions {
+    pub offline: bool,
+    pub latency_ms: f64,
+    pub download_bps: f64,
+    pub upload_bps: f64,
+}
+
+impl NetworkConditions {
+    // Same numbers as the DevTools throttling presets
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "slow 3g" => Some(Self { offline: false, latency_ms: 2000.0, download_bps: 50_000.0, upload_bps: 50_000.0 }),
+            "fast 3g" => Some(Self { offline: false, latency_ms: 562.5, download_bps: 180_000.0, upload_bps: 84_375.0 }),
+            "offline" => Some(Self { offline: true, latency_ms: 0.0, download_bps: -1.0, upload_bps: -1.0 }),
+            _ => None,
+        }
+    }
+
+    pub fn cdp_params(&self) -> Value {
+        json!({"offline": self.offline, "latency": self.latency_ms, "downloadThroughput": self.download_bps, "uploadThroughput": self.upload_bps})
+    }
+}
+
+// How wait_for_stable_ui polls after an action: every interval_ms until two captures match, giving up
+// after timeout_ms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettleConfig {
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for SettleConfig {
+    fn default() -> Self {
+        Self { interval_ms: 200, timeout_ms: 3000 }
+    }
+}
+
+// tabs comes from the HTTP endpoint rather than this tab's socket, so the caller fills it in
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BrowserState { pub url: String, pub title: String, pub screenshot_base64: String, pub accessibility_tree: Vec<AXNode> }
+pub struct BrowserState { pub url: String, pub title: String, pub screenshot_base64: String, pub accessibility_tree: Vec<AXNode>, #[serde(default)] pub network_requests: Vec<String>, #[serde(default = "default_dpr")] pub device_pixel_ratio: f64, #[serde(default)] pub tabs: Vec<TabInfo> }
 
 pub struct ChromeConnection {
     ws_write: Arc<Mutex<futures_util::stream::SplitSink<tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>, Message>>>,
     ws_read: Arc<Mutex<futures_util::stream::SplitStream<tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>>>>,
     cmd_id: Arc<Mutex<u64>>,
+    // CDP events that arrived while waiting for command replies, oldest first
+    events: Arc<Mutex<VecDeque<Value>>>,
+    // Set by disconnect so Drop doesn't send a second Close
+    closed: bool,
+    // URL patterns whose requests are failed while this session is open (see block_urls)
+    blocked: Vec<String>,
+    // Default pause between typed characters (see type_text); zero inserts the whole string at once
+    typing_delay: std::time::Duration,
+    // Flash each click point first (see flash_point)
+    highlight: bool,
+    // The app's cancel flag, checked between typed characters
+    cancel: Arc<std::sync::atomic::AtomicBool>,
+    // nodeId of the document from DOM.getDocument, dropped when the main frame navigates (see document_root)
+    doc_root: Arc<Mutex<Option<i64>>>,
+}
+
+// Chrome keeps a debugger session per socket until it sees a Close frame, so a connection that is just
+// dropped sends one from a background task; disconnect does the same inline when the caller can await
+impl Drop for ChromeConnection {
+    fn drop(&mut self) {
+        if self.closed { return; }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else { return };
+        let ws_write = self.ws_write.clone();
+        runtime.spawn(async move {
+            let mut w = ws_write.lock().await;
+            let _ = w.send(Message::Close(None)).await;
+            let _ = w.flush().await;
+        });
+    }
+}
+
+const MAX_BUFFERED_EVENTS: usize = 1000;
+// Chrome fails or produces garbage past this many CSS pixels in one capture
+const MAX_FULL_PAGE_HEIGHT: f64 = 16384.0;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LaunchOptions {
+    pub headless: bool,
+    // Reuse a profile directory to keep cookies between sessions; defaults to a temp profile
+    pub user_data_dir: Option<String>,
+    // How long a freshly spawned Chrome gets to answer on the debugging port; None = DEFAULT_READY_TIMEOUT
+    #[serde(default)]
+    pub ready_timeout_secs: Option<u64>,
+}
+
+// Upper bound for the per-character typing delay, so a typo can't stall a step for minutes
+pub const MAX_TYPING_DELAY_MS: u64 = 1000;
+// How long a click point stays outlined when highlighting is on
+pub const HIGHLIGHT_MS: u64 = 300;
+const DEFAULT_READY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+const READY_POLL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Off by default: clicking consent buttons on the user's behalf should be a deliberate choice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BannerConfig {
+    pub enabled: bool,
+    // Button texts to click, matched case-insensitively against the whole trimmed label
+    pub phrases: Vec<String>,
+}
+
+impl Default for BannerConfig {
+    fn default() -> Self {
+        let phrases = ["accept all", "accept all cookies", "accept", "accept cookies", "allow all", "allow all cookies", "i agree", "agree", "agree and continue", "got it", "ok", "close", "no thanks", "dismiss"];
+        Self { enabled: false, phrases: phrases.iter().map(|p| p.to_string()).collect() }
+    }
+}
+
+// What makes the agent stop and wait for a person: challenge iframes, challenge wording, login forms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandoffConfig {
+    pub enabled: bool,
+    // Substrings of iframe src URLs
+    pub frame_patterns: Vec<String>,
+    // Case-insensitive substrings of the page text
+    pub text_patterns: Vec<String>,
+    pub pause_on_login: bool,
 }
 
-pub fn launch_chrome_with_debugging(port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+impl Default for HandoffConfig {
+    fn default() -> Self {
+        let frames = ["recaptcha", "hcaptcha.com", "challenges.cloudflare.com", "arkoselabs.com", "funcaptcha"];
+        let text = ["verify you are human", "are you a robot", "i'm not a robot", "complete the security check", "checking your browser"];
+        Self {
+            enabled: true,
+            frame_patterns: frames.iter().map(|p| p.to_string()).collect(),
+            text_patterns: text.iter().map(|p| p.to_string()).collect(),
+            pause_on_login: true,
+        }
+    }
+}
+
+fn chrome_binary() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     #[cfg(target_os = "windows")] {
         let paths = vec![
             r"C:\Program Files\Google\Chrome\Application\chrome.exe",
             r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe",
         ];
-        let chrome = paths.iter().find(|p| std::path::Path::new(p).exists()).ok_or("Chrome not found")?;
-        let data_dir = std::env::temp_dir().join("chrome-automation");
-        std::fs::create_dir_all(&data_dir)?;
-        Command::new(chrome).args(&[&format!("--remote-debugging-port={}", port), &format!("--user-data-dir={}", data_dir.display()), "--no-first-run"]).spawn()?;
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        Ok(paths.iter().find(|p| std::path::Path::new(p).exists()).ok_or("Chrome not found")?.to_string())
+    }
+    #[cfg(target_os = "macos")] {
+        Ok("/Applications/Google Chrome.app/Contents/MacOS/Google Chrome".to_string())
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))] {
+        Ok("google-chrome".to_string())
+    }
+}
+
+fn default_user_data_dir() -> std::path::PathBuf {
+    #[cfg(target_os = "windows")] { std::env::temp_dir().join("chrome-automation") }
+    #[cfg(not(target_os = "windows"))] { std::path::PathBuf::from("/tmp/chrome-auto") }
+}
+
+// True if something answers /json/version on the port like a Chrome debugger does
+pub fn debugger_available(port: u16) -> bool {
+    use std::io::{Read, Write};
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    let Ok(mut stream) = std::net::TcpStream::connect_timeout(&addr, std::time::Duration::from_millis(500)) else { return false; };
+    let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(2)));
+    if stream.write_all(b"GET /json/version HTTP/1.0\r\nHost: localhost\r\n\r\n").is_err() { return false; }
+    let mut body = String::new();
+    let _ = stream.read_to_string(&mut body);
+    body.contains("webSocketDebuggerUrl")
+}
+
+fn chrome_process_running() -> bool {
+    #[cfg(target_os = "windows")] {
+        Command::new("tasklist").args(["/FI", "IMAGENAME eq chrome.exe", "/NH"]).output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).to_lowercase().contains("chrome.exe"))
+            .unwrap_or(false)
     }
     #[cfg(target_os = "macos")] {
-        Command::new("/Applications/Google Chrome.app/Contents/MacOS/Google Chrome").args(&[&format!("--remote-debugging-port={}", port), "--user-data-dir=/tmp/chrome-auto", "--no-first-run"]).spawn()?;
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        Command::new("pgrep").args(["-x", "Google Chrome"]).output().map(|o| o.status.success()).unwrap_or(false)
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))] {
+        Command::new("pgrep").args(["-x", "chrome"]).output().map(|o| o.status.success()).unwrap_or(false)
     }
-    #[cfg(target_os = "linux")] {
-        Command::new("google-chrome").args(&[&format!("--remote-debugging-port={}", port), "--user-data-dir=/tmp/chrome-auto", "--no-first-run"]).spawn()?;
-        std::thread::sleep(std::time::Duration::from_secs(2));
+}
+
+// Attaches to a Chrome that already exposes the debugger instead of spawning a second, throwaway instance
+pub fn launch_chrome_with_debugging(port: u16, opts: &LaunchOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if debugger_available(port) { return Ok(()); }
+    if chrome_process_running() {
+        return Err(format!("Chrome is already running without remote debugging. Close all Chrome windows and start it with --remote-debugging-port={} (or let the agent launch it).", port).into());
+    }
+    let chrome = chrome_binary()?;
+    let data_dir = opts.user_data_dir.as_ref().map(std::path::PathBuf::from).unwrap_or_else(default_user_data_dir);
+    std::fs::create_dir_all(&data_dir)?;
+    let mut args = vec![format!("--remote-debugging-port={}", port), format!("--user-data-dir={}", data_dir.display()), "--no-first-run".to_string()];
+    // Screenshots still work headless because they come from CDP, not the OS
+    if opts.headless { args.push("--headless=new".to_string()); }
+    let previous = if cfg!(windows) && !opts.headless { crate::automation::desktop::foreground_title().ok() } else { None };
+    Command::new(chrome).args(&args).spawn()?;
+    wait_until_ready(port, opts.ready_timeout_secs.map_or(DEFAULT_READY_TIMEOUT, std::time::Duration::from_secs))?;
+    // The debugger can answer before the window is up; a visible window is only usable once it's in front
+    if let Some(prev) = previous {
+        if let Err(e) = crate::automation::desktop::wait_for_foreground_change(&prev, std::time::Duration::from_secs(3)) {
+            crate::log_warn!("chrome", "could not watch the foreground window: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Polls /json/version until Chrome's debugger answers, instead of guessing how long startup takes
+fn wait_until_ready(port: u16, timeout: std::time::Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let start = std::time::Instant::now();
+    while !debugger_available(port) {
+        if start.elapsed() >= timeout {
+            return Err(format!("Chrome did not open its debugging port {} within {}s", port, timeout.as_secs()).into());
+        }
+        std::thread::sleep(READY_POLL);
     }
+    crate::log_info!("chrome", "debugger ready on port {} after {} ms", port, start.elapsed().as_millis());
     Ok(())
 }
 
-pub async fn get_tabs(port: u16) -> Result<Vec<TabInfo>, Box<dyn std::error::Error + Send + Sync>> {
-    let resp: Vec<Value> = reqwest::Client::new().get(format!("http://localhost:{}/json", port)).send().await?.json().await?;
+pub async fn get_tabs(endpoint: &CdpEndpoint) -> Result<Vec<TabInfo>, Box<dyn std::error::Error + Send + Sync>> {
+    let authority = endpoint.authority();
+    let mut req = reqwest::Client::new().get(format!("http://{}/json", authority)).timeout(std::time::Duration::from_secs(5));
+    if !host_header_ok(&endpoint.host) { req = req.header(reqwest::header::HOST, "localhost"); }
+    let resp: Vec<Value> = req.send().await?.json().await?;
     Ok(resp.iter().filter(|t| t["type"] == "page").map(|t| TabInfo {
         id: t["id"].as_str().unwrap_or("").to_string(),
         title: t["title"].as_str().unwrap_or("").to_string(),
         url: t["url"].as_str().unwrap_or("").to_string(),
-        ws_url: t["webSocketDebuggerUrl"].as_str().unwrap_or("").to_string(),
+        ws_url: rewrite_ws_host(t["webSocketDebuggerUrl"].as_str().unwrap_or(""), &authority),
     }).collect())
 }
 
 impl ChromeConnection {
     pub async fn connect(ws_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let (ws, _) = connect_async(ws_url).await?;
+        let mut request = ws_url.into_client_request()?;
+        if request.uri().host().is_some_and(|h| !host_header_ok(h)) {
+            request.headers_mut().insert("Host", HeaderValue::from_static("localhost"));
+        }
+        let (ws, _) = connect_async(request).await?;
         let (w, r) = ws.split();
-        Ok(Self { ws_write: Arc::new(Mutex::new(w)), ws_read: Arc::new(Mutex::new(r)), cmd_id: Arc::new(Mutex::new(0)) })
+        Ok(Self { ws_write: Arc::new(Mutex::new(w)), ws_read: Arc::new(Mutex::new(r)), cmd_id: Arc::new(Mutex::new(0)), events: Arc::new(Mutex::new(VecDeque::new())), closed: false, blocked: Vec::new(), typing_delay: std::time::Duration::ZERO, highlight: false, cancel: Arc::new(std::sync::atomic::AtomicBool::new(false)), doc_root: Arc::new(Mutex::new(None)) })
     }
 
-    pub async fn connect_to_first_tab(port: u16) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let tabs = get_tabs(port).await?;
-        let tab = tabs.first().ok_or("No tabs")?;
-        Self::connect(&tab.ws_url).await
+    // Ends the debugger session with a Close frame and waits for it to be written
+    pub async fn disconnect(mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.closed = true;
+        let mut w = self.ws_write.lock().await;
+        w.send(Message::Close(None)).await?;
+        w.flush().await?;
+        Ok(())
     }
 
     async fn send(&self, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
@@ -92,18 +435,152 @@ impl ChromeConnection {
                         if let Some(e) = r.get("error") { return Err(format!("CDP: {:?}", e).into()); }
                         return Ok(r["result"].clone());
                     }
+                    if r.get("method").is_some() { self.buffer_event(r).await; }
+                }
+            }
+        }
+    }
+
+    // Sends a command without waiting for its reply, which send() later skips as an unknown id
+    async fn fire(&self, method: &str, params: Value) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut id = self.cmd_id.lock().await;
+        *id += 1;
+        let cmd = json!({"id": *id, "method": method, "params": params});
+        drop(id);
+        self.ws_write.lock().await.send(Message::Text(cmd.to_string())).await?;
+        Ok(())
+    }
+
+    // Requests paused by block_urls hang the page until answered, so they're answered as soon as
+    // they're read instead of waiting in the buffer
+    async fn buffer_event(&self, event: Value) {
+        if event["method"] == "Fetch.requestPaused" {
+            let id = event["params"]["requestId"].clone();
+            // Only blocklisted URLs are paused, but anything else Fetch hands us must keep loading
+            let blocked = event["params"]["request"]["url"].as_str().is_some_and(|url| self.blocked.iter().any(|p| url_matches(p, url)));
+            let reply = if blocked {
+                self.fire("Fetch.failRequest", json!({"requestId": id, "errorReason": "BlockedByClient"})).await
+            } else {
+                self.fire("Fetch.continueRequest", json!({"requestId": id})).await
+            };
+            if let Err(e) = reply { crate::log_warn!("cdp", "could not answer paused request: {}", e); }
+            return;
+        }
+        let mut events = self.events.lock().await;
+        if events.len() >= MAX_BUFFERED_EVENTS { events.pop_front(); }
+        events.push_back(event);
+    }
+
+    // Reads events that arrive within `wait` into the buffer, for when no command is in flight
+    async fn pump_events(&self, wait: std::time::Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let deadline = tokio::time::Instant::now() + wait;
+        let mut read = self.ws_read.lock().await;
+        while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, read.next()).await {
+            if let Message::Text(txt) = msg? {
+                let r: Value = serde_json::from_str(&txt)?;
+                if r.get("method").is_some() { self.buffer_event(r).await; }
+            }
+        }
+        Ok(())
+    }
+
+    // Removes and returns buffered events whose method starts with `prefix` (e.g. "Network.")
+    async fn drain_events(&self, prefix: &str) -> Vec<Value> {
+        let mut events = self.events.lock().await;
+        let (matched, rest): (VecDeque<Value>, VecDeque<Value>) = events.drain(..).partition(|e| e["method"].as_str().map_or(false, |m| m.starts_with(prefix)));
+        *events = rest;
+        matched.into()
+    }
+
+    // Tracks requests seen since Network.enable until none are in flight for idle_ms.
+    // Returns whether idle was reached before timeout_ms, plus every request URL seen.
+    async fn network_settle(&self, idle_ms: u64, timeout_ms: u64) -> Result<(bool, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Network.enable", json!({})).await?;
+        let start = std::time::Instant::now();
+        let mut idle_since = std::time::Instant::now();
+        let mut in_flight = HashSet::new();
+        let mut urls = Vec::new();
+        loop {
+            for e in self.drain_events("Network.").await {
+                let id = e["params"]["requestId"].as_str().unwrap_or("").to_string();
+                match e["method"].as_str() {
+                    Some("Network.requestWillBeSent") => {
+                        urls.push(e["params"]["request"]["url"].as_str().unwrap_or("").to_string());
+                        in_flight.insert(id);
+                    }
+                    Some("Network.loadingFinished") | Some("Network.loadingFailed") => { in_flight.remove(&id); }
+                    _ => {}
                 }
             }
+            if !in_flight.is_empty() {
+                idle_since = std::time::Instant::now();
+            } else if idle_since.elapsed().as_millis() as u64 >= idle_ms {
+                return Ok((true, urls));
+            }
+            if start.elapsed().as_millis() as u64 >= timeout_ms {
+                return Ok((false, urls));
+            }
+            self.pump_events(std::time::Duration::from_millis(50)).await?;
+        }
+    }
+
+    pub async fn wait_for_network_idle(&self, idle_ms: u64, timeout_ms: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.network_settle(idle_ms, timeout_ms).await? {
+            (true, _) => Ok(()),
+            (false, _) => Err(format!("Network not idle after {}ms", timeout_ms).into()),
         }
     }
 
     pub async fn navigate(&self, url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Network.enable", json!({})).await?;
         self.send("Page.navigate", json!({"url": url})).await?;
         self.send("Page.enable", json!({})).await?;
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        // Pages with long-polling never go idle; the timeout just caps the wait
+        self.network_settle(500, 10000).await?;
         Ok(())
     }
 
+    // Polls a fingerprint of the accessibility tree until two captures in a row match and nothing is busy
+    // (aria-busy, a progressbar), so a spinner or a list still filling in isn't mistaken for the result.
+    // Ok(false) means the timeout ran out first; the caller goes on with whatever is there.
+    pub async fn wait_for_stable_ui(&self, cfg: &SettleConfig) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let start = std::time::Instant::now();
+        let timeout = std::time::Duration::from_millis(cfg.timeout_ms);
+        let interval = std::time::Duration::from_millis(cfg.interval_ms.max(50));
+        self.send("Accessibility.enable", json!({})).await?;
+        let mut last = self.ui_fingerprint().await;
+        loop {
+            if start.elapsed() >= timeout {
+                crate::log_info!("chrome", "UI still changing after {} ms", cfg.timeout_ms);
+                return Ok(false);
+            }
+            tokio::time::sleep(interval).await;
+            let next = self.ui_fingerprint().await;
+            if next.is_some() && next == last {
+                crate::log_info!("chrome", "UI settled after {} ms", start.elapsed().as_millis());
+                return Ok(true);
+            }
+            last = next;
+        }
+    }
+
+    // Hash of role, name and value of every node; None while the page is busy or mid-navigation
+    async fn ui_fingerprint(&self) -> Option<u64> {
+        use std::hash::{Hash, Hasher};
+
+        let r = self.send("Accessibility.getFullAXTree", json!({})).await.ok()?;
+        let nodes = r["nodes"].as_array()?;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        for n in nodes.iter().filter(|n| n["ignored"].as_bool() != Some(true)) {
+            let role = n["role"]["value"].as_str().unwrap_or("");
+            if role == "progressbar" || ax_property(n, "busy") { return None; }
+            role.hash(&mut hasher);
+            n["name"]["value"].as_str().hash(&mut hasher);
+            n["value"]["value"].to_string().hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+
     pub async fn get_url(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         let r = self.send("Runtime.evaluate", json!({"expression": "window.location.href"})).await?;
         Ok(r["result"]["value"].as_str().unwrap_or("").to_string())
@@ -112,38 +589,173 @@ impl ChromeConnection {
     pub async fn get_a11y_tree(&self) -> Result<Vec<AXNode>, Box<dyn std::error::Error + Send + Sync>> {
         self.send("Accessibility.enable", json!({})).await?;
         let r = self.send("Accessibility.getFullAXTree", json!({})).await?;
-        let nodes = r["nodes"].as_array().ok_or("No nodes")?;
+        let mut trees = vec![(None, r["nodes"].as_array().ok_or("No nodes")?.clone())];
+        for frame in self.child_frame_ids().await? {
+            // Out-of-process (cross-site) frames live in another target and fail here; skip them
+            if let Ok(r) = self.send("Accessibility.getFullAXTree", json!({"frameId": frame})).await {
+                trees.push((Some(frame), r["nodes"].as_array().cloned().unwrap_or_default()));
+            }
+        }
         let roles = vec!["button", "link", "textbox", "searchbox", "combobox", "checkbox", "radio", "menuitem", "tab", "listitem"];
-        Ok(nodes.iter().filter(|n| {
-            let role = n["role"]["value"].as_str().unwrap_or("");
-            roles.contains(&role) || n["focusable"]["value"].as_bool().unwrap_or(false)
-        }).filter_map(|n| {
-            Some(AXNode {
-                node_id: n["nodeId"].as_str()?.to_string(),
-                role: n["role"]["value"].as_str().unwrap_or("").to_string(),
-                name: n["name"]["value"].as_str().map(|s| s.to_string()),
-                value: n["value"]["value"].as_str().map(|s| s.to_string()),
-                bounds: n["boundingBox"].as_object().map(|b| Bounds { x: b["x"].as_f64().unwrap_or(0.0), y: b["y"].as_f64().unwrap_or(0.0), width: b["width"].as_f64().unwrap_or(0.0), height: b["height"].as_f64().unwrap_or(0.0) }),
-                focusable: n["focusable"]["value"].as_bool().unwrap_or(false),
-            })
-        }).collect())
+        let metrics = self.send("Page.getLayoutMetrics", json!({})).await?;
+        let (vw, vh) = (metrics["cssLayoutViewport"]["clientWidth"].as_f64().unwrap_or(f64::MAX), metrics["cssLayoutViewport"]["clientHeight"].as_f64().unwrap_or(f64::MAX));
+        let mut out = Vec::new();
+        let mut unnamed = 0;
+        for (frame_id, nodes) in &trees {
+            let by_id: std::collections::HashMap<&str, &Value> = nodes.iter().filter_map(|n| Some((n["nodeId"].as_str()?, n))).collect();
+            for n in nodes {
+                let role = n["role"]["value"].as_str().unwrap_or("");
+                if !roles.contains(&role) && !ax_property(n, "focusable") { continue; }
+                let Some(node_id) = n["nodeId"].as_str() else { continue };
+                let name = n["name"]["value"].as_str().filter(|s| !s.trim().is_empty()).map(|s| s.to_string());
+                // AX nodes carry no geometry, so ask the DOM; display:none nodes have no box model
+                let bounds = match n["backendDOMNodeId"].as_i64() {
+                    Some(backend) => self.send("DOM.getBoxModel", json!({"backendNodeId": backend})).await.ok().and_then(|r| {
+                        let c = r["model"]["border"].as_array()?;
+                        let (x1, y1, x2, y2) = (c[0].as_f64()?, c[1].as_f64()?, c[4].as_f64()?, c[5].as_f64()?);
+                        Some(Bounds { x: x1, y: y1, width: x2 - x1, height: y2 - y1 })
+                    }),
+                    None => None,
+                };
+                let offscreen = ax_property(n, "hidden") || match &bounds {
+                    Some(b) => b.width <= 0.0 || b.height <= 0.0 || b.x + b.width <= 0.0 || b.y + b.height <= 0.0 || b.x >= vw || b.y >= vh,
+                    None => true,
+                };
+                let (context, coords) = if name.is_none() {
+                    // Without a name or a box the model has nothing to go on
+                    let Some(b) = bounds.as_ref().filter(|_| unnamed < MAX_UNNAMED_NODES) else { continue };
+                    unnamed += 1;
+                    (ax_context(n, &by_id), Some(format!("coords:{:.0},{:.0}", b.x + b.width / 2.0, b.y + b.height / 2.0)))
+                } else {
+                    (None, None)
+                };
+                out.push(AXNode {
+                    node_id: match frame_id { Some(f) => format!("{}@{}", node_id, f), None => node_id.to_string() },
+                    role: role.to_string(),
+                    name,
+                    value: n["value"]["value"].as_str().map(|s| s.to_string()),
+                    bounds,
+                    focusable: ax_property(n, "focusable"),
+                    enabled: !ax_property(n, "disabled"),
+                    offscreen,
+                    context,
+                    coords,
+                    frame_id: frame_id.clone(),
+                });
+            }
+        }
+        let disabled = out.iter().filter(|n| !n.enabled).count();
+        let offscreen = out.iter().filter(|n| n.offscreen).count();
+        if disabled + offscreen > 0 {
+            crate::log_info!("a11y", "{} nodes, {} disabled, {} offscreen", out.len(), disabled, offscreen);
+        }
+        Ok(out)
     }
 
-    pub async fn find_element(&self, selector: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    // The document's nodeId, fetched once per document. A navigation of the main frame (or the DOM
+    // domain reporting a new document) invalidates every nodeId, so the cached root is dropped and
+    // refetched; without this a lookup after a form submit fails with "Could not find node with given id".
+    async fn document_root(&self) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        // Picks up events that arrived since the last command
+        self.pump_events(std::time::Duration::ZERO).await?;
+        let navigated = self.drain_events("Page.frameNavigated").await.iter().any(|e| e["params"]["frame"]["parentId"].is_null());
+        let replaced = !self.drain_events("DOM.documentUpdated").await.is_empty();
+        let mut cached = self.doc_root.lock().await;
+        if navigated || replaced {
+            if cached.is_some() { crate::log_info!("cdp", "page navigated, refetching the document"); }
+            *cached = None;
+        }
+        if let Some(root) = *cached { return Ok(root); }
         self.send("DOM.enable", json!({})).await?;
+        // Page events are what report the navigation
+        self.send("Page.enable", json!({})).await?;
         let doc = self.send("DOM.getDocument", json!({})).await?;
         let root = doc["root"]["nodeId"].as_i64().ok_or("No root")?;
-        let r = self.send("DOM.querySelector", json!({"nodeId": root, "selector": selector})).await?;
-        r["nodeId"].as_i64().ok_or("Not found".into())
+        *cached = Some(root);
+        Ok(root)
+    }
+
+    async fn forget_document(&self) {
+        *self.doc_root.lock().await = None;
+    }
+
+    pub async fn find_element(&self, selector: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let root = self.document_root().await?;
+        let r = match self.send("DOM.querySelector", json!({"nodeId": root, "selector": selector})).await {
+            Ok(r) => r,
+            // The navigation event may not have arrived yet; one retry against a fresh document
+            Err(_) => {
+                self.forget_document().await;
+                let root = self.document_root().await?;
+                self.send("DOM.querySelector", json!({"nodeId": root, "selector": selector})).await?
+            }
+        };
+        // querySelector reports a miss as nodeId 0; web components may still hold it in a shadow root
+        match r["nodeId"].as_i64() {
+            Some(id) if id != 0 => Ok(id),
+            _ => self.find_in_shadow_roots(selector).await,
+        }
     }
 
-    pub async fn get_bounds(&self, node_id: i64) -> Result<Bounds, Box<dyn std::error::Error + Send + Sync>> {
-        let r = self.send("DOM.getBoxModel", json!({"nodeId": node_id})).await?;
-        let c = r["model"]["content"].as_array().ok_or("No box")?;
-        Ok(Bounds { x: c[0].as_f64().unwrap_or(0.0), y: c[1].as_f64().unwrap_or(0.0), width: c[4].as_f64().unwrap_or(0.0) - c[0].as_f64().unwrap_or(0.0), height: c[5].as_f64().unwrap_or(0.0) - c[1].as_f64().unwrap_or(0.0) })
+    // Recursively searches open shadow roots and hands the match back to the DOM domain as a nodeId
+    async fn find_in_shadow_roots(&self, selector: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let js = format!(r#"
+            (function deepQuery(root, sel) {{
+                const hit = root.querySelector(sel);
+                if (hit) return hit;
+                for (const el of root.querySelectorAll('*')) {{
+                    if (el.shadowRoot) {{
+                        const found = deepQuery(el.shadowRoot, sel);
+                        if (found) return found;
+                    }}
+                }}
+                return null;
+            }})(document, '{}')
+        "#, selector.replace('\'', "\\'"));
+        let r = self.send("Runtime.evaluate", json!({"expression": js})).await?;
+        let object_id = r["result"]["objectId"].as_str().ok_or("Not found")?;
+        let node = self.send("DOM.requestNode", json!({"objectId": object_id})).await?;
+        node["nodeId"].as_i64().filter(|id| *id != 0).ok_or("Not found".into())
+    }
+
+    // `what` names the target in the error, e.g. the selector
+    pub async fn get_bounds(&self, node_id: i64, what: &str) -> Result<Bounds, Box<dyn std::error::Error + Send + Sync>> {
+        self.node_bounds(json!({"nodeId": node_id}), what).await
+    }
+
+    // Viewport box of a node (DOM.* params, see target_node). getBoxModel fails for display:none and
+    // returns empty or degenerate quads for zero-size boxes and some SVG children, which would click
+    // (0,0); getBoundingClientRect covers some of those. If neither gives an area the node is scrolled
+    // into view and both are tried once more.
+    async fn node_bounds(&self, node: Value, what: &str) -> Result<Bounds, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(b) = self.quick_bounds(&node).await { return Ok(b); }
+        if self.send("DOM.scrollIntoViewIfNeeded", node.clone()).await.is_ok() {
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            if let Some(b) = self.quick_bounds(&node).await { return Ok(b); }
+        }
+        Err(format!("{} has no clickable area (hidden, zero-size or not rendered)", what).into())
+    }
+
+    // Box model content quad, else the client rect; None unless the area is non-zero
+    async fn quick_bounds(&self, node: &Value) -> Option<Bounds> {
+        let from_model = match self.send("DOM.getBoxModel", node.clone()).await {
+            Ok(r) => quad_bounds(&r["model"]["content"]),
+            Err(_) => None,
+        };
+        if from_model.is_some() { return from_model; }
+        let object = self.send("DOM.resolveNode", node.clone()).await.ok()?["object"]["objectId"].as_str()?.to_string();
+        let r = self.send("Runtime.callFunctionOn", json!({
+            "objectId": object,
+            "functionDeclaration": "function() { const el = this.nodeType === 1 ? this : this.parentElement; if (!el) return null; const r = el.getBoundingClientRect(); return [r.x, r.y, r.width, r.height]; }",
+            "returnByValue": true,
+        })).await.ok()?;
+        let v = r["result"]["value"].as_array()?;
+        let n = |i: usize| v.get(i).and_then(Value::as_f64).unwrap_or(0.0);
+        Some(Bounds { x: n(0), y: n(1), width: n(2), height: n(3) }).filter(|b| b.width > 0.0 && b.height > 0.0)
     }
 
     pub async fn click_at(&self, x: f64, y: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.flash_point(x, y).await;
         self.send("Input.dispatchMouseEvent", json!({"type": "mousePressed", "x": x, "y": y, "button": "left", "clickCount": 1})).await?;
         self.send("Input.dispatchMouseEvent", json!({"type": "mouseReleased", "x": x, "y": y, "button": "left", "clickCount": 1})).await?;
         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -152,51 +764,363 @@ impl ChromeConnection {
 
     pub async fn click_element(&self, selector: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let id = self.find_element(selector).await?;
-        let b = self.get_bounds(id).await?;
+        let b = self.get_bounds(id, selector).await?;
         self.click_at(b.x + b.width / 2.0, b.y + b.height / 2.0).await
     }
 
-    pub async fn click_ax(&self, ax_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let tree = self.send("Accessibility.getFullAXTree", json!({})).await?;
+    // All descendant frame ids of the main frame, depth-first
+    async fn child_frame_ids(&self) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let r = self.send("Page.getFrameTree", json!({})).await?;
+        let mut ids = Vec::new();
+        let mut stack: Vec<&Value> = r["frameTree"]["childFrames"].as_array().map(|c| c.iter().collect()).unwrap_or_default();
+        while let Some(f) = stack.pop() {
+            if let Some(id) = f["frame"]["id"].as_str() { ids.push(id.to_string()); }
+            stack.extend(f["childFrames"].as_array().into_iter().flatten());
+        }
+        Ok(ids)
+    }
+
+    // Box models of iframe nodes come back in main-frame coordinates, so callers can click them directly
+    async fn ax_backend_id(&self, ax_id: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let (ax_id, params) = match ax_id.split_once('@') {
+            Some((id, frame)) => (id, json!({"frameId": frame})),
+            None => (ax_id, json!({})),
+        };
+        let tree = self.send("Accessibility.getFullAXTree", params).await?;
         let nodes = tree["nodes"].as_array().ok_or("No nodes")?;
         let node = nodes.iter().find(|n| n["nodeId"].as_str() == Some(ax_id)).ok_or("AX not found")?;
-        let backend = node["backendDOMNodeId"].as_i64().ok_or("No backend")?;
-        let r = self.send("DOM.getBoxModel", json!({"backendNodeId": backend})).await?;
-        let c = r["model"]["content"].as_array().ok_or("No box")?;
-        let cx = (c[0].as_f64().unwrap_or(0.0) + c[4].as_f64().unwrap_or(0.0)) / 2.0;
-        let cy = (c[1].as_f64().unwrap_or(0.0) + c[5].as_f64().unwrap_or(0.0)) / 2.0;
-        self.click_at(cx, cy).await
+        node["backendDOMNodeId"].as_i64().ok_or("No backend".into())
+    }
+
+    // "role:button|name:Save": the first unignored AX node with that role and accessible name, both
+    // compared case-insensitively and the name exactly, so a same-named link and button can't be mixed
+    // up. Returns its AX node id and backend DOM node id.
+    async fn find_node_by_role_and_name(&self, target: &str) -> Result<(String, i64), Box<dyn std::error::Error + Send + Sync>> {
+        let (role, name) = parse_role_target(target).ok_or(format!("Role target must look like role:button|name:Save, got \"{}\"", target))?;
+        let tree = self.send("Accessibility.getFullAXTree", json!({})).await?;
+        let nodes = tree["nodes"].as_array().ok_or("No nodes")?;
+        let same = |v: &Value, want: &str| v["value"].as_str().is_some_and(|s| s.trim().eq_ignore_ascii_case(want));
+        let node = nodes.iter()
+            .find(|n| n["ignored"] != true && same(&n["role"], role) && same(&n["name"], name) && n["backendDOMNodeId"].is_i64())
+            .ok_or(format!("No {} named \"{}\"", role, name))?;
+        Ok((node["nodeId"].as_str().unwrap_or("").to_string(), node["backendDOMNodeId"].as_i64().unwrap_or(0)))
+    }
+
+    pub async fn click_ax(&self, ax_id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let backend = self.ax_backend_id(ax_id).await?;
+        let b = self.node_bounds(json!({"backendNodeId": backend}), &format!("ax:{}", ax_id)).await?;
+        self.click_at(b.x + b.width / 2.0, b.y + b.height / 2.0).await
+    }
+
+    pub fn set_highlight(&mut self, on: bool) {
+        self.highlight = on;
+    }
+
+    // Debug aid: outlines the point about to be pressed for HIGHLIGHT_MS. Overlay highlights are drawn by
+    // the browser rather than the page, so CSS zoom doesn't shift them and no DOM is left behind; the
+    // highlight is hidden again before the press, so it never reaches a screenshot. Failures only log.
+    async fn flash_point(&self, x: f64, y: f64) {
+        if !self.highlight { return; }
+        const HALF: f64 = 12.0;
+        let rect = json!({
+            "x": (x - HALF).round() as i64, "y": (y - HALF).round() as i64, "width": (HALF * 2.0) as i64, "height": (HALF * 2.0) as i64,
+            "color": {"r": 255, "g": 0, "b": 0, "a": 0.3}, "outlineColor": {"r": 255, "g": 0, "b": 0, "a": 1.0},
+        });
+        let shown = async {
+            self.send("DOM.enable", json!({})).await?;
+            self.send("Overlay.enable", json!({})).await?;
+            self.send("Overlay.highlightRect", rect).await
+        }.await;
+        if let Err(e) = shown { crate::log_warn!("cdp", "click highlight failed: {}", e); return; }
+        tokio::time::sleep(std::time::Duration::from_millis(HIGHLIGHT_MS)).await;
+        if let Err(e) = self.send("Overlay.hideHighlight", json!({})).await { crate::log_warn!("cdp", "click highlight not cleared: {}", e); }
+    }
+
+    pub fn set_cancel_flag(&mut self, cancel: Arc<std::sync::atomic::AtomicBool>) {
+        self.cancel = cancel;
+    }
+
+    pub fn set_typing_delay(&mut self, ms: u64) {
+        self.typing_delay = std::time::Duration::from_millis(ms.min(MAX_TYPING_DELAY_MS));
+    }
+
+    // insertText is one event, which key handlers never see. With a delay each character is a real
+    // key press instead: much slower, but autocomplete, debouncing and per-keystroke validation fire.
+    pub async fn type_text(&self, text: &str, delay: std::time::Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if delay.is_zero() {
+            self.send("Input.insertText", json!({"text": text})).await?;
+            return Ok(());
+        }
+        for (i, c) in text.chars().enumerate() {
+            if i > 0 { tokio::time::sleep(delay).await; }
+            if self.cancel.load(std::sync::atomic::Ordering::SeqCst) { return Err("Cancelled".into()); }
+            self.type_char(c).await?;
+        }
+        Ok(())
     }
 
-    pub async fn type_text(&self, text: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.send("Input.insertText", json!({"text": text})).await?;
+    // Characters off the US layout (accents, emoji) have no key, so they go out as a bare char event
+    async fn type_char(&self, c: char) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let name = if c == '\n' { "Enter".to_string() } else { c.to_string() };
+        if key_definition(&name).is_some_and(|d| d.key_code != 0) {
+            return self.press_key(&name, 0).await;
+        }
+        self.send("Input.dispatchKeyEvent", json!({"type": "char", "key": name, "text": name, "unmodifiedText": name})).await?;
         Ok(())
     }
 
-    pub async fn type_into(&self, selector: &str, text: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn type_into(&self, selector: &str, text: &str, clear_first: bool, delay: std::time::Duration) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.click_element(selector).await?;
         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyDown", "key": "a", "modifiers": 2})).await?;
-        self.type_text(text).await
+        if clear_first { self.select_all_and_delete().await?; }
+        self.type_text(text, delay).await
+    }
+
+    // Ctrl+A then Backspace in the focused element, so the old text is gone rather than just selected
+    pub async fn select_all_and_delete(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.press_key_with_commands("a", 2, &["selectAll"]).await?;
+        self.press_key("Backspace", 0).await
+    }
+
+    // modifiers is the CDP bitmask (Alt=1, Ctrl=2, Meta=4, Shift=8). Keys go out the way Chrome sends
+    // real ones (see key_definition): without code and key code, sites reading keyCode/which and
+    // shortcuts such as Ctrl+Z ignore them. Unknown names are sent as a bare key like before.
+    pub async fn press_key(&self, key: &str, modifiers: i64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.press_key_with_commands(key, modifiers, &[]).await
+    }
+
+    // Synthetic key events don't trigger Chrome's own editing shortcuts, so Ctrl+C copies nothing unless
+    // the keyDown also names the editor command ("copy", "cut", "paste", "selectAll")
+    pub async fn press_key_with_commands(&self, key: &str, modifiers: i64, commands: &[&str]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let def = key_definition(key).unwrap_or(KeyDef { key: key.to_string(), code: String::new(), key_code: 0, text: None });
+        let mut event = json!({"key": def.key, "modifiers": modifiers});
+        if !def.code.is_empty() { event["code"] = json!(def.code); }
+        if def.key_code != 0 {
+            event["windowsVirtualKeyCode"] = json!(def.key_code);
+            event["nativeVirtualKeyCode"] = json!(def.key_code);
+        }
+        // A shortcut inserts nothing; Shift alone still types
+        let text = def.text.filter(|_| modifiers & !8 == 0);
+        event["type"] = json!(if text.is_some() { "keyDown" } else { "rawKeyDown" });
+        if let Some(t) = &text {
+            event["text"] = json!(t);
+            event["unmodifiedText"] = json!(t);
+        }
+        let mut down = event.clone();
+        if !commands.is_empty() { down["commands"] = json!(commands); }
+        self.send("Input.dispatchKeyEvent", down).await?;
+        event["type"] = json!("keyUp");
+        if let Some(obj) = event.as_object_mut() {
+            obj.remove("text");
+            obj.remove("unmodifiedText");
+        }
+        self.send("Input.dispatchKeyEvent", event).await?;
+        Ok(())
     }
 
-    pub async fn press_key(&self, key: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyDown", "key": key})).await?;
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyUp", "key": key})).await?;
+    // Wheel at a point (the element under it scrolls), defaulting to the viewport centre
+    pub async fn scroll(&self, dx: f64, dy: f64, at: Option<(f64, f64)>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (x, y) = match at {
+            Some(p) => p,
+            None => {
+                let m = self.send("Page.getLayoutMetrics", json!({})).await?;
+                (m["cssLayoutViewport"]["clientWidth"].as_f64().unwrap_or(800.0) / 2.0, m["cssLayoutViewport"]["clientHeight"].as_f64().unwrap_or(600.0) / 2.0)
+            }
+        };
+        self.send("Input.dispatchMouseEvent", json!({"type": "mouseWheel", "x": x, "y": y, "deltaX": dx, "deltaY": dy})).await?;
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
         Ok(())
     }
 
-    pub async fn scroll(&self, dy: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.send("Input.dispatchMouseEvent", json!({"type": "mouseWheel", "x": 400, "y": 300, "deltaX": 0, "deltaY": dy})).await?;
+    // Jump the nearest scrollable container of target (or the page) to its top/bottom edge, or left/right for axis x
+    pub async fn scroll_to_edge(&self, target: Option<&str>, horizontal: bool, end: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let object_id = match target {
+            Some(t) => self.target_object(t).await?,
+            None => {
+                let r = self.send("Runtime.evaluate", json!({"expression": "document.scrollingElement"})).await?;
+                r["result"]["objectId"].as_str().ok_or("No scrolling element")?.to_string()
+            }
+        };
+        self.send("Runtime.callFunctionOn", json!({
+            "objectId": object_id,
+            "functionDeclaration": format!(r#"function() {{
+                const horiz = {}, end = {};
+                const scrollable = e => horiz ? e.scrollWidth > e.clientWidth : e.scrollHeight > e.clientHeight;
+                let el = this;
+                while (el && el !== document.documentElement && !scrollable(el)) el = el.parentElement;
+                el = el || document.scrollingElement;
+                if (horiz) el.scrollLeft = end ? el.scrollWidth : 0;
+                else el.scrollTop = end ? el.scrollHeight : 0;
+            }}"#, horizontal, end),
+        })).await?;
         tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
         Ok(())
     }
 
+    // DOM.* params addressing a target; coords: targets have no node
+    async fn target_node(&self, target: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ax) = target.strip_prefix("ax:") {
+            Ok(json!({"backendNodeId": self.ax_backend_id(ax).await?}))
+        } else if let Some(xpath) = target.strip_prefix("xpath:") {
+            Ok(json!({"nodeId": self.find_by_xpath(xpath).await?}))
+        } else if target.starts_with("role:") {
+            Ok(json!({"backendNodeId": self.find_node_by_role_and_name(target).await?.1}))
+        } else if target.starts_with("coords:") {
+            Err("coords: target does not address a DOM node".into())
+        } else {
+            Ok(json!({"nodeId": self.find_element(target).await?}))
+        }
+    }
+
+    // Centre point of any target syntax the LLM can emit
+    pub async fn target_center(&self, target: &str) -> Result<(f64, f64), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(c) = target.strip_prefix("coords:") {
+            return parse_coords(c);
+        }
+        if let Some(p) = target.strip_prefix("pixel:") {
+            return Ok(screenshot_to_css(parse_coords(p)?, self.device_pixel_ratio().await));
+        }
+        let b = self.node_bounds(self.target_node(target).await?, target).await?;
+        Ok((b.x + b.width / 2.0, b.y + b.height / 2.0))
+    }
+
+    // Runtime object for a target, for callFunctionOn; coords: resolve to the element under the point
+    async fn target_object(&self, target: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let r = if let Some(c) = target.strip_prefix("coords:") {
+            let (x, y) = parse_coords(c)?;
+            self.send("Runtime.evaluate", json!({"expression": format!("document.elementFromPoint({}, {})", x, y)})).await?["result"].clone()
+        } else {
+            self.send("DOM.resolveNode", self.target_node(target).await?).await?["object"].clone()
+        };
+        r["objectId"].as_str().map(|s| s.to_string()).ok_or("Could not resolve target".into())
+    }
+
     pub async fn screenshot(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         let r = self.send("Page.captureScreenshot", json!({"format": "png"})).await?;
         Ok(r["data"].as_str().unwrap_or("").to_string())
     }
 
+    // Pick mode: the next real (isTrusted) click on the page is swallowed and reported instead of acting.
+    // The listeners go away on that click or at the timeout, so later clicks behave normally again.
+    pub async fn pick_element(&self, timeout_ms: u64) -> Result<Option<PickedTarget>, Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Page.bringToFront", json!({})).await?;
+        let r = self.send("Runtime.evaluate", json!({"expression": PICK_SCRIPT.replace("__TIMEOUT__", &timeout_ms.to_string()), "awaitPromise": true, "returnByValue": true})).await?;
+        let point = &r["result"]["value"];
+        let (Some(x), Some(y)) = (point["x"].as_f64(), point["y"].as_f64()) else { return Ok(None) };
+        let coords = PickedTarget { target: format!("coords:{:.0},{:.0}", x, y), role: None, name: None, x, y };
+        let Ok(node) = self.send("DOM.getNodeForLocation", json!({"x": x as i64, "y": y as i64})).await else { return Ok(Some(coords)) };
+        let Some(backend) = node["backendNodeId"].as_i64() else { return Ok(Some(coords)) };
+        self.send("Accessibility.enable", json!({})).await?;
+        let tree = self.send("Accessibility.getPartialAXTree", json!({"backendNodeId": backend, "fetchRelatives": true})).await?;
+        let nodes = tree["nodes"].as_array().cloned().unwrap_or_default();
+        // The clicked node itself, or else its nearest ancestor that isn't ignored (a span inside a button)
+        let by_id: std::collections::HashMap<&str, &Value> = nodes.iter().filter_map(|n| Some((n["nodeId"].as_str()?, n))).collect();
+        let mut picked = nodes.iter().find(|n| n["backendDOMNodeId"].as_i64() == Some(backend));
+        while let Some(n) = picked.filter(|n| n["ignored"].as_bool() == Some(true)) {
+            picked = n["parentId"].as_str().and_then(|p| by_id.get(p).copied());
+        }
+        Ok(Some(match picked.and_then(|n| Some((n["nodeId"].as_str()?, n))) {
+            Some((id, n)) => PickedTarget {
+                target: format!("ax:{}", id),
+                role: n["role"]["value"].as_str().map(|s| s.to_string()),
+                name: n["name"]["value"].as_str().filter(|s| !s.is_empty()).map(|s| s.to_string()),
+                ..coords
+            },
+            None => coords,
+        }))
+    }
+
+    // PNG clipped to the target's border box plus a little padding; coords: targets get a 100px square
+    pub async fn screenshot_element(&self, target: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let (x, y, w, h) = if let Some(c) = target.strip_prefix("coords:") {
+            let (cx, cy) = parse_coords(c)?;
+            (cx - 50.0, cy - 50.0, 100.0, 100.0)
+        } else {
+            let r = self.send("DOM.getBoxModel", self.target_node(target).await?).await?;
+            let c = r["model"]["border"].as_array().ok_or("No box")?;
+            let (x1, y1) = (c[0].as_f64().unwrap_or(0.0), c[1].as_f64().unwrap_or(0.0));
+            (x1 - 8.0, y1 - 8.0, c[4].as_f64().unwrap_or(0.0) - x1 + 16.0, c[5].as_f64().unwrap_or(0.0) - y1 + 16.0)
+        };
+        // Box models are viewport-relative, clips are document-relative
+        let m = self.send("Page.getLayoutMetrics", json!({})).await?;
+        let (sx, sy) = (m["cssLayoutViewport"]["pageX"].as_f64().unwrap_or(0.0), m["cssLayoutViewport"]["pageY"].as_f64().unwrap_or(0.0));
+        let r = self.send("Page.captureScreenshot", json!({
+            "format": "png",
+            "clip": {"x": (x + sx).max(0.0), "y": (y + sy).max(0.0), "width": w.max(1.0), "height": h.max(1.0), "scale": 1}
+        })).await?;
+        Ok(r["data"].as_str().unwrap_or("").to_string())
+    }
+
+    // Clicks the first visible button matching a phrase inside something banner-like
+    // (dialog, aria-modal, fixed overlay, or cookie/consent/gdpr in id/class). Returns what was clicked.
+    pub async fn dismiss_banners(&self, phrases: &[String]) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let js = r#"(function(phrases) {
+                const wanted = phrases.map(p => p.trim().toLowerCase());
+                const bannerLike = el => {
+                    for (let e = el; e && e !== document.body; e = e.parentElement) {
+                        const tag = ((e.id || '') + ' ' + (typeof e.className === 'string' ? e.className : '')).toLowerCase();
+                        if (/cookie|consent|gdpr|privacy|banner/.test(tag)) return true;
+                        if (e.getAttribute('role') === 'dialog' || e.getAttribute('aria-modal') === 'true') return true;
+                        const pos = getComputedStyle(e).position;
+                        if (pos === 'fixed' || pos === 'sticky') return true;
+                    }
+                    return false;
+                };
+                const candidates = Array.from(document.querySelectorAll('button, a, [role=button], input[type=button], input[type=submit]'));
+                for (const phrase of wanted) {
+                    for (const el of candidates) {
+                        const label = (el.innerText || el.value || el.getAttribute('aria-label') || '').trim().toLowerCase();
+                        const r = el.getBoundingClientRect();
+                        if (label === phrase && r.width > 0 && r.height > 0 && bannerLike(el)) {
+                            el.click();
+                            return label;
+                        }
+                    }
+                }
+                return null;
+            })"#;
+        // A JSON array is a valid JS literal, so the phrases need no further escaping
+        let expression = format!("{}({})", js, serde_json::to_string(phrases)?);
+        let r = self.send("Runtime.evaluate", json!({"expression": expression, "returnByValue": true})).await?;
+        Ok(r["result"]["value"].as_str().map(|s| s.to_string()))
+    }
+
+    // Reason the page needs a human (CAPTCHA, login wall), or None
+    pub async fn detect_handoff(&self, cfg: &HandoffConfig) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let js = r#"(function(cfg) {
+                const frame = Array.from(document.querySelectorAll('iframe')).map(f => f.src || '').find(src => cfg.frame_patterns.some(p => src.includes(p)));
+                if (frame) return 'CAPTCHA frame: ' + frame.slice(0, 120);
+                const text = (document.body ? document.body.innerText : '').toLowerCase();
+                const phrase = cfg.text_patterns.find(p => text.includes(p.toLowerCase()));
+                if (phrase) return 'Challenge text: "' + phrase + '"';
+                if (cfg.pause_on_login) {
+                    const pw = Array.from(document.querySelectorAll('input[type=password]')).find(el => el.getBoundingClientRect().width > 0);
+                    if (pw) return 'Login form';
+                }
+                return null;
+            })"#;
+        let expression = format!("{}({})", js, serde_json::to_string(cfg)?);
+        let r = self.send("Runtime.evaluate", json!({"expression": expression, "returnByValue": true})).await?;
+        Ok(r["result"]["value"].as_str().map(|s| s.to_string()))
+    }
+
+    // Whole scrollable page in one PNG, capped at MAX_FULL_PAGE_HEIGHT
+    pub async fn screenshot_full_page(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let m = self.send("Page.getLayoutMetrics", json!({})).await?;
+        let width = m["cssContentSize"]["width"].as_f64().ok_or("No content size")?;
+        let full_height = m["cssContentSize"]["height"].as_f64().ok_or("No content size")?;
+        let height = full_height.min(MAX_FULL_PAGE_HEIGHT);
+        if height < full_height {
+            crate::log_warn!("screenshot", "full page truncated from {}px to {}px", full_height, height);
+        }
+        let r = self.send("Page.captureScreenshot", json!({
+            "format": "png",
+            "captureBeyondViewport": true,
+            "clip": {"x": 0, "y": 0, "width": width, "height": height, "scale": 1}
+        })).await?;
+        Ok(r["data"].as_str().unwrap_or("").to_string())
+    }
+
     // Bring page/tab to front (activate tab)
     pub async fn focus_window(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.send("Page.bringToFront", json!({})).await?;
@@ -205,37 +1129,60 @@ impl ChromeConnection {
 
     // Find element by XPath and return node ID
     pub async fn find_by_xpath(&self, xpath: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
-        self.send("DOM.enable", json!({})).await?;
-        let _ = self.send("DOM.getDocument", json!({})).await?;
+        // performSearch only returns nodes of a document the client has fetched
+        self.document_root().await?;
 
         // Use DOM.performSearch for XPath
-        let search = self.send("DOM.performSearch", json!({"query": xpath})).await?;
+        let search = self.send("DOM.performSearch", json!({"query": xpath, "includeUserAgentShadowDOM": false})).await?;
         let search_id = search["searchId"].as_str().ok_or("No searchId")?;
         let count = search["resultCount"].as_i64().unwrap_or(0);
 
+        // Discard on every path, including errors from the lookup itself
+        let found = self.first_clickable_result(search_id, count).await;
+        let _ = self.send("DOM.discardSearchResults", json!({"searchId": search_id})).await;
+        found
+    }
+
+    // First search result that is an element with a box model; text and hidden nodes are skipped
+    async fn first_clickable_result(&self, search_id: &str, count: i64) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
         if count == 0 {
-            self.send("DOM.discardSearchResults", json!({"searchId": search_id})).await?;
             return Err("XPath not found".into());
         }
-
         let results = self.send("DOM.getSearchResults", json!({
             "searchId": search_id,
             "fromIndex": 0,
-            "toIndex": 1
+            "toIndex": count.min(50)
         })).await?;
-
-        self.send("DOM.discardSearchResults", json!({"searchId": search_id})).await?;
-
-        results["nodeIds"][0].as_i64().ok_or("No node found".into())
+        for id in results["nodeIds"].as_array().into_iter().flatten().filter_map(|v| v.as_i64()) {
+            let node = self.send("DOM.describeNode", json!({"nodeId": id})).await?;
+            if node["node"]["nodeType"].as_i64() == Some(1) && self.quick_bounds(&json!({"nodeId": id})).await.is_some() {
+                return Ok(id);
+            }
+        }
+        Err(format!("XPath matched {} node(s) but none is a visible element", count).into())
     }
 
     // Click element by XPath
     pub async fn click_xpath(&self, xpath: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let node_id = self.find_by_xpath(xpath).await?;
-        let b = self.get_bounds(node_id).await?;
+        let b = self.get_bounds(node_id, &format!("xpath:{}", xpath)).await?;
         self.click_at(b.x + b.width / 2.0, b.y + b.height / 2.0).await
     }
 
+    // The halves of a click, for gestures the model assembles itself. Releasing away from the press
+    // point first moves there with the button still down, which is what pages see as a drag.
+    pub async fn mouse_press(&self, x: f64, y: f64, button: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.flash_point(x, y).await;
+        self.send("Input.dispatchMouseEvent", json!({"type": "mousePressed", "x": x, "y": y, "button": button, "buttons": button_mask(button), "clickCount": 1})).await?;
+        Ok(())
+    }
+
+    pub async fn mouse_release(&self, x: f64, y: f64, button: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Input.dispatchMouseEvent", json!({"type": "mouseMoved", "x": x, "y": y, "button": button, "buttons": button_mask(button)})).await?;
+        self.send("Input.dispatchMouseEvent", json!({"type": "mouseReleased", "x": x, "y": y, "button": button, "buttons": 0, "clickCount": 1})).await?;
+        Ok(())
+    }
+
     // Hover over element (move mouse without clicking)
     pub async fn hover_at(&self, x: f64, y: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.send("Input.dispatchMouseEvent", json!({"type": "mouseMoved", "x": x, "y": y})).await?;
@@ -245,7 +1192,7 @@ impl ChromeConnection {
 
     pub async fn hover_element(&self, selector: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let id = self.find_element(selector).await?;
-        let b = self.get_bounds(id).await?;
+        let b = self.get_bounds(id, selector).await?;
         self.hover_at(b.x + b.width / 2.0, b.y + b.height / 2.0).await
     }
 
@@ -256,6 +1203,58 @@ impl ChromeConnection {
         Ok(r["result"]["value"].as_str().unwrap_or("").to_string())
     }
 
+    // Get innerText of a resolved DOM node ({"nodeId": ..} or {"backendNodeId": ..})
+    async fn node_text(&self, node: Value) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let obj = self.send("DOM.resolveNode", node).await?;
+        let object_id = obj["object"]["objectId"].as_str().ok_or("Could not resolve node")?;
+        let r = self.send("Runtime.callFunctionOn", json!({
+            "objectId": object_id,
+            "functionDeclaration": "function() { return this.innerText || this.value || this.textContent || ''; }",
+            "returnByValue": true
+        })).await?;
+        Ok(r["result"]["value"].as_str().unwrap_or("").trim().to_string())
+    }
+
+    // Read text for any target syntax the LLM can emit
+    pub async fn read_text(&self, target: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ax) = target.strip_prefix("ax:") {
+            let backend = self.ax_backend_id(ax).await?;
+            self.node_text(json!({"backendNodeId": backend})).await
+        } else if let Some(xpath) = target.strip_prefix("xpath:") {
+            let node_id = self.find_by_xpath(xpath).await?;
+            self.node_text(json!({"nodeId": node_id})).await
+        } else {
+            self.get_text(target).await
+        }
+    }
+
+    // Readable text of the page: the largest article/main region, else the body. innerText already skips
+    // hidden elements, scripts and styles; runs of blank lines are collapsed. Capping is up to the caller.
+    pub async fn page_text(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let text = self.eval_js(PAGE_TEXT_SCRIPT).await?;
+        Ok(text.as_str().unwrap_or("").to_string())
+    }
+
+    // Verify a target exists and/or contains the expected text; Err carries the mismatch
+    pub async fn assert_condition(&self, condition: &str, target: Option<&str>, expected_text: Option<&str>, timeout_ms: u64) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let actual = match (target, expected_text) {
+            (Some(t), _) if !t.starts_with("ax:") && !t.starts_with("xpath:") => {
+                self.wait_for_element(t, timeout_ms).await.map_err(|_| format!("Assertion failed ({}): {} not found", condition, t))?;
+                self.get_text(t).await?
+            }
+            (Some(t), _) => self.read_text(t).await.map_err(|e| format!("Assertion failed ({}): {} not readable: {}", condition, t, e))?,
+            (None, Some(_)) => self.eval_js("document.body ? document.body.innerText : ''").await?.as_str().unwrap_or("").to_string(),
+            (None, None) => return Err("assert needs params.target and/or params.expected_text".into()),
+        };
+        if let Some(expected) = expected_text {
+            if !actual.to_lowercase().contains(&expected.to_lowercase()) {
+                let found: String = actual.chars().take(200).collect();
+                return Err(format!("Assertion failed ({}): expected \"{}\" in {}, found \"{}\"", condition, expected, target.unwrap_or("page"), found).into());
+            }
+        }
+        Ok(format!("Assertion passed: {}", condition))
+    }
+
     // Get attribute value
     pub async fn get_attribute(&self, selector: &str, attr: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         let js = format!(r#"document.querySelector('{}')?.getAttribute('{}') || ''"#,
@@ -264,19 +1263,33 @@ impl ChromeConnection {
         Ok(r["result"]["value"].as_str().unwrap_or("").to_string())
     }
 
-    // Select option from dropdown
-    pub async fn select_option(&self, selector: &str, value: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let js = format!(r#"
-            (function() {{
-                const el = document.querySelector('{}');
-                if (!el) return false;
-                el.value = '{}';
-                el.dispatchEvent(new Event('change', {{ bubbles: true }}));
-                return true;
-            }})()
-        "#, selector.replace('\'', "\\'"), value.replace('\'', "\\'"));
-        self.send("Runtime.evaluate", json!({"expression": js})).await?;
-        Ok(())
+    // Select option from dropdown by value attribute or by visible label (exact, then substring, case-insensitive)
+    pub async fn select_option(&self, target: &str, value: Option<&str>, label: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let object_id = self.target_object(target).await?;
+        let r = self.send("Runtime.callFunctionOn", json!({
+            "objectId": object_id,
+            "functionDeclaration": r#"function(value, label) {
+                if (!this.options) return 'not a <select>';
+                const opts = Array.from(this.options);
+                const text = o => o.text.trim().toLowerCase();
+                const want = label == null ? null : label.trim().toLowerCase();
+                const idx = want == null
+                    ? opts.findIndex(o => o.value === value)
+                    : [opts.findIndex(o => text(o) === want), opts.findIndex(o => text(o).includes(want))].find(i => i >= 0) ?? -1;
+                if (idx < 0) return 'no option ' + JSON.stringify(label ?? value) + '; options: ' + opts.map(o => o.text.trim()).slice(0, 20).join(', ');
+                this.selectedIndex = idx;
+                this.dispatchEvent(new Event('input', { bubbles: true }));
+                this.dispatchEvent(new Event('change', { bubbles: true }));
+                return '';
+            }"#,
+            "arguments": [{"value": value}, {"value": label}],
+            "returnByValue": true
+        })).await?;
+        match r["result"]["value"].as_str() {
+            Some("") => Ok(()),
+            Some(err) => Err(format!("select failed: {}", err).into()),
+            None => Err("select failed".into()),
+        }
     }
 
     // Wait for element to appear (polling)
@@ -303,6 +1316,7 @@ impl ChromeConnection {
 
     // Double click
     pub async fn double_click_at(&self, x: f64, y: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.flash_point(x, y).await;
         self.send("Input.dispatchMouseEvent", json!({"type": "mousePressed", "x": x, "y": y, "button": "left", "clickCount": 2})).await?;
         self.send("Input.dispatchMouseEvent", json!({"type": "mouseReleased", "x": x, "y": y, "button": "left", "clickCount": 2})).await?;
         Ok(())
@@ -310,6 +1324,7 @@ impl ChromeConnection {
 
     // Right click
     pub async fn right_click_at(&self, x: f64, y: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.flash_point(x, y).await;
         self.send("Input.dispatchMouseEvent", json!({"type": "mousePressed", "x": x, "y": y, "button": "right", "clickCount": 1})).await?;
         self.send("Input.dispatchMouseEvent", json!({"type": "mouseReleased", "x": x, "y": y, "button": "right", "clickCount": 1})).await?;
         Ok(())
@@ -318,12 +1333,7 @@ impl ChromeConnection {
     // Clear input field
     pub async fn clear_input(&self, selector: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.click_element(selector).await?;
-        // Select all and delete
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyDown", "key": "a", "modifiers": 2})).await?; // Ctrl+A
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyUp", "key": "a", "modifiers": 2})).await?;
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyDown", "key": "Backspace"})).await?;
-        self.send("Input.dispatchKeyEvent", json!({"type": "keyUp", "key": "Backspace"})).await?;
-        Ok(())
+        self.select_all_and_delete().await
     }
 
     // Go back in history
@@ -361,34 +1371,194 @@ impl ChromeConnection {
         Ok(())
     }
 
-    pub async fn get_browser_state(&self) -> Result<BrowserState, Box<dyn std::error::Error + Send + Sync>> {
+    // Fixed viewport and DPR. Box-model/AX coordinates are CSS pixels, which is also what
+    // Input.dispatchMouseEvent takes, so clicks stay correct for any deviceScaleFactor.
+    pub async fn set_device_metrics(&self, width: u32, height: u32, device_scale_factor: f64, mobile: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Emulation.setDeviceMetricsOverride", json!({"width": width, "height": height, "deviceScaleFactor": device_scale_factor, "mobile": mobile})).await?;
+        self.send("Emulation.setTouchEmulationEnabled", json!({"enabled": mobile})).await?;
+        Ok(())
+    }
+
+    pub async fn set_user_agent(&self, user_agent: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Emulation.setUserAgentOverride", json!({"userAgent": user_agent})).await?;
+        Ok(())
+    }
+
+    pub async fn apply_emulation(&self, cfg: &EmulationConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.set_device_metrics(cfg.width, cfg.height, cfg.device_scale_factor, cfg.mobile).await?;
+        if let Some(ua) = &cfg.user_agent { self.set_user_agent(ua).await?; }
+        Ok(())
+    }
+
+    // Fetch pauses only requests matching one of the patterns, so the rest of the page loads untouched;
+    // the paused ones are failed as they're read (buffer_event). Chrome resumes anything still paused
+    // when the session closes, so blocking only covers the time a connection is open.
+    pub async fn block_urls(&mut self, patterns: &[String]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let fetch_patterns: Vec<Value> = patterns.iter().map(|p| json!({"urlPattern": p, "requestStage": "Request"})).collect();
+        self.send("Fetch.enable", json!({"patterns": fetch_patterns})).await?;
+        self.blocked = patterns.to_vec();
+        Ok(())
+    }
+
+    // Base64 PDF of the current page
+    pub async fn print_to_pdf(&self, opts: &PdfOptions) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let (width, height) = opts.paper_size()?;
+        let r = self.send("Page.printToPDF", json!({"landscape": opts.landscape, "printBackground": opts.print_background, "paperWidth": width, "paperHeight": height})).await?;
+        Ok(r["data"].as_str().ok_or("printToPDF returned no data")?.to_string())
+    }
+
+    pub async fn save_pdf(&self, path: &std::path::Path, opts: &PdfOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = STANDARD.decode(self.print_to_pdf(opts).await?)?;
+        if !bytes.starts_with(b"%PDF-") { return Err("printToPDF did not return a PDF".into()); }
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) { std::fs::create_dir_all(dir)?; }
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    // Also grants the geolocation permission, so the site's prompt can't block on a click nobody makes
+    pub async fn set_geolocation(&self, latitude: f64, longitude: f64, accuracy: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Err(e) = self.send("Browser.grantPermissions", json!({"permissions": ["geolocation"]})).await {
+            crate::log_warn!("cdp", "could not grant geolocation permission: {}", e);
+        }
+        self.send("Emulation.setGeolocationOverride", json!({"latitude": latitude, "longitude": longitude, "accuracy": accuracy})).await?;
+        Ok(())
+    }
+
+    pub async fn apply_locale(&self, cfg: &LocaleConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let (Some(lat), Some(lng)) = (cfg.latitude, cfg.longitude) {
+            self.set_geolocation(lat, lng, cfg.accuracy.unwrap_or(100.0)).await?;
+        }
+        if let Some(tz) = &cfg.timezone { self.send("Emulation.setTimezoneOverride", json!({"timezoneId": tz})).await?; }
+        if let Some(locale) = &cfg.locale { self.send("Emulation.setLocaleOverride", json!({"locale": locale})).await?; }
+        Ok(())
+    }
+
+    pub async fn set_network_conditions(&self, conditions: &NetworkConditions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Network.enable", json!({})).await?;
+        self.send("Network.emulateNetworkConditions", conditions.cdp_params()).await?;
+        Ok(())
+    }
+
+    // CSS zoom on the root element rather than Emulation.setPageScaleFactor: page scale is a pinch zoom
+    // that leaves box models unscaled while input lands on the scaled view, so clicks would miss. With
+    // CSS zoom, box models, screenshots and input coordinates agree. Navigation drops it.
+    pub async fn set_zoom(&self, level: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let value = if level == 1.0 { String::new() } else { level.to_string() };
+        self.send("Runtime.evaluate", json!({"expression": format!("document.documentElement.style.zoom = '{}'", value)})).await?;
+        Ok(())
+    }
+
+    // Every cookie in the browser, httpOnly ones included, not just those of this page
+    pub async fn all_cookies(&self) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
+        let r = self.send("Network.getAllCookies", json!({})).await?;
+        Ok(r["cookies"].as_array().cloned().unwrap_or_default())
+    }
+
+    // Sets cookies read by all_cookies; returns how many were set. Expired ones, and any without a name or
+    // domain, are skipped.
+    pub async fn set_cookies(&self, cookies: &[Value]) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        let params: Vec<Value> = cookies.iter().filter_map(|c| cookie_param(c, now)).collect();
+        if !params.is_empty() { self.send("Network.setCookies", json!({"cookies": params})).await?; }
+        Ok(params.len())
+    }
+
+    // Opens a tab in the same browser; returns its target id
+    pub async fn new_tab(&self, url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let r = self.send("Target.createTarget", json!({"url": url})).await?;
+        Ok(r["targetId"].as_str().ok_or("Target.createTarget returned no targetId")?.to_string())
+    }
+
+    pub async fn activate_tab(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Target.activateTarget", json!({"targetId": id})).await?;
+        Ok(())
+    }
+
+    pub async fn close_tab(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Target.closeTarget", json!({"targetId": id})).await?;
+        Ok(())
+    }
+
+    // Starts buffering Target.targetCreated events; returns the pages open now so new_tabs can tell them apart
+    pub async fn watch_new_tabs(&self) -> Result<HashSet<String>, Box<dyn std::error::Error + Send + Sync>> {
+        self.send("Target.setDiscoverTargets", json!({"discover": true})).await?;
+        let r = self.send("Target.getTargets", json!({})).await?;
+        Ok(r["targetInfos"].as_array().cloned().unwrap_or_default().iter()
+            .filter(|t| t["type"] == "page")
+            .filter_map(|t| t["targetId"].as_str().map(str::to_string))
+            .collect())
+    }
+
+    // Ids of pages created since watch_new_tabs, oldest first. Events are read briefly first, since a
+    // popup opened by the last input may not have been announced while commands were in flight.
+    pub async fn new_tabs(&self, known: &HashSet<String>) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        self.pump_events(std::time::Duration::from_millis(100)).await?;
+        let mut ids: Vec<String> = Vec::new();
+        for e in self.drain_events("Target.targetCreated").await {
+            let info = &e["params"]["targetInfo"];
+            let Some(id) = info["targetId"].as_str() else { continue };
+            if info["type"] == "page" && !known.contains(id) && !ids.iter().any(|i| i == id) { ids.push(id.to_string()); }
+        }
+        Ok(ids)
+    }
+
+    pub async fn get_browser_state(&self, full_page: bool) -> Result<BrowserState, Box<dyn std::error::Error + Send + Sync>> {
+        // Let late XHR/fetch settle so the tree isn't captured mid-load
+        let (_, network_requests) = self.network_settle(300, 1500).await?;
         let url = self.get_url().await?;
         let title = self.send("Runtime.evaluate", json!({"expression": "document.title"})).await?["result"]["value"].as_str().unwrap_or("").to_string();
-        let screenshot = self.screenshot().await?;
+        let screenshot = if full_page { self.screenshot_full_page().await? } else { self.screenshot().await? };
         let tree = self.get_a11y_tree().await?;
-        Ok(BrowserState { url, title, screenshot_base64: screenshot, accessibility_tree: tree })
+        let device_pixel_ratio = self.device_pixel_ratio().await;
+        Ok(BrowserState { url, title, screenshot_base64: screenshot, accessibility_tree: tree, network_requests, device_pixel_ratio, tabs: Vec::new() })
+    }
+
+    // Screenshot pixels per CSS pixel, including any Emulation override
+    pub async fn device_pixel_ratio(&self) -> f64 {
+        match self.send("Runtime.evaluate", json!({"expression": "window.devicePixelRatio", "returnByValue": true})).await {
+            Ok(r) => r["result"]["value"].as_f64().filter(|d| *d > 0.0).unwrap_or(1.0),
+            Err(_) => 1.0,
+        }
+    }
+
+    // Everything below dispatches in CSS pixels, so a "pixel:x,y" point read off the screenshot becomes
+    // the equivalent "coords:" target here, before any action sees it. A "role:" target becomes the
+    // "ax:" node it names.
+    async fn css_target(&self, target: &Value) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+        match target.as_str() {
+            Some(s) if s.starts_with("pixel:") => {
+                let (x, y) = screenshot_to_css(parse_coords(&s[6..])?, self.device_pixel_ratio().await);
+                Ok(json!(format!("coords:{:.0},{:.0}", x, y)))
+            }
+            Some(s) if s.starts_with("role:") => Ok(json!(format!("ax:{}", self.find_node_by_role_and_name(s).await?.0))),
+            _ => Ok(target.clone()),
+        }
     }
 
-    pub async fn execute_llm_action(&self, action: &str, target: &Value, params: Option<&Value>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Returns the action's output for actions that read something back (e.g. read_text)
+    pub async fn execute_llm_action(&self, action: &str, target: &Value, params: Option<&Value>) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let target = &self.css_target(target).await?;
+        let mut output = None;
         match action {
             "click" => {
                 if let Some(s) = target.as_str() {
                     if s.starts_with("ax:") { self.click_ax(&s[3..]).await? }
                     else if s.starts_with("xpath:") { self.click_xpath(&s[6..]).await? }
+                    else if s.starts_with("coords:") { let (x, y) = parse_coords(&s[7..])?; self.click_at(x, y).await? }
                     else { self.click_element(s).await? }
                 }
             }
             "double_click" => {
                 if let Some(s) = target.as_str() {
                     let id = self.find_element(s).await?;
-                    let b = self.get_bounds(id).await?;
+                    let b = self.get_bounds(id, s).await?;
                     self.double_click_at(b.x + b.width / 2.0, b.y + b.height / 2.0).await?;
                 }
             }
             "right_click" => {
                 if let Some(s) = target.as_str() {
                     let id = self.find_element(s).await?;
-                    let b = self.get_bounds(id).await?;
+                    let b = self.get_bounds(id, s).await?;
                     self.right_click_at(b.x + b.width / 2.0, b.y + b.height / 2.0).await?;
                 }
             }
@@ -397,28 +1567,53 @@ impl ChromeConnection {
                     self.hover_element(s).await?;
                 }
             }
+            "copy" | "cut" | "paste" | "select_all" => {
+                // An optional target is clicked first so the shortcut lands in it
+                if let Some(s) = target.as_str().filter(|s| !s.is_empty()) {
+                    let (x, y) = self.target_center(s).await?;
+                    self.click_at(x, y).await?;
+                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                }
+                // The clipboard is this machine's, so with a remote Chrome the text isn't what gets pasted
+                if let Some(text) = params.and_then(|p| p["text"].as_str()).filter(|_| action == "paste") {
+                    arboard::Clipboard::new()?.set_text(text)?;
+                }
+                let (key, command) = match action {
+                    "copy" => ("c", "copy"),
+                    "cut" => ("x", "cut"),
+                    "paste" => ("v", "paste"),
+                    _ => ("a", "selectAll"),
+                };
+                self.press_key_with_commands(key, 2, &[command]).await?;
+            }
             "type" => {
                 let text = params.and_then(|p| p["text"].as_str()).ok_or("No text")?;
+                // Replaces the field's text unless clear_first=false, which appends at the caret
+                let clear_first = params.and_then(|p| p["clear_first"].as_bool()).unwrap_or(true);
+                // params.delay_ms overrides the configured typing delay for this action
+                let delay = params.and_then(|p| p["delay_ms"].as_u64()).map_or(self.typing_delay, |ms| std::time::Duration::from_millis(ms.min(MAX_TYPING_DELAY_MS)));
                 if let Some(s) = target.as_str() {
                     if s.is_empty() {
                         // No target - type to currently focused element
-                        self.type_text(text).await?;
+                        self.type_text(text, delay).await?;
                     } else if s.starts_with("ax:") {
                         // Click accessibility node first to focus, then type
                         self.click_ax(&s[3..]).await?;
                         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                        self.type_text(text).await?;
+                        if clear_first { self.select_all_and_delete().await?; }
+                        self.type_text(text, delay).await?;
                     } else if s.starts_with("xpath:") {
                         // Click XPath element first to focus, then type
                         self.click_xpath(&s[6..]).await?;
                         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                        self.type_text(text).await?;
+                        if clear_first { self.select_all_and_delete().await?; }
+                        self.type_text(text, delay).await?;
                     } else {
                         // CSS selector
-                        self.type_into(s, text).await?;
+                        self.type_into(s, text, clear_first, delay).await?;
                     }
                 } else {
-                    self.type_text(text).await?;
+                    self.type_text(text, delay).await?;
                 }
             }
             "clear" => {
@@ -426,11 +1621,7 @@ impl ChromeConnection {
                     if s.starts_with("ax:") {
                         self.click_ax(&s[3..]).await?;
                         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                        // Select all and delete
-                        self.send("Input.dispatchKeyEvent", json!({"type": "keyDown", "key": "a", "modifiers": 2})).await?;
-                        self.send("Input.dispatchKeyEvent", json!({"type": "keyUp", "key": "a", "modifiers": 2})).await?;
-                        self.send("Input.dispatchKeyEvent", json!({"type": "keyDown", "key": "Backspace"})).await?;
-                        self.send("Input.dispatchKeyEvent", json!({"type": "keyUp", "key": "Backspace"})).await?;
+                        self.select_all_and_delete().await?;
                     } else {
                         self.clear_input(s).await?;
                     }
@@ -441,26 +1632,48 @@ impl ChromeConnection {
                 self.navigate(url).await?;
             }
             "scroll" => {
-                let dir = params.and_then(|p| p["direction"].as_str()).unwrap_or("down");
-                let amt = params.and_then(|p| p["amount"].as_f64()).unwrap_or(300.0);
-                self.scroll(if dir == "up" { -amt } else { amt }).await?;
+                let horizontal = params.and_then(|p| p["axis"].as_str()) == Some("x");
+                let scroll_target = params.and_then(|p| p["target"].as_str()).or(target.as_str());
+                if let Some(to) = params.and_then(|p| p["to"].as_str()) {
+                    let end = matches!(to, "bottom" | "end" | "right");
+                    self.scroll_to_edge(scroll_target, horizontal, end).await?;
+                } else {
+                    let dir = params.and_then(|p| p["direction"].as_str()).unwrap_or(if horizontal { "right" } else { "down" });
+                    let amt = params.and_then(|p| p["amount"].as_f64()).unwrap_or(300.0);
+                    let delta = if matches!(dir, "up" | "left") { -amt } else { amt };
+                    let at = match scroll_target {
+                        Some(t) => Some(self.target_center(t).await?),
+                        None => None,
+                    };
+                    if horizontal { self.scroll(delta, 0.0, at).await? } else { self.scroll(0.0, delta, at).await? }
+                }
             }
             "press_key" => {
                 let key = params.and_then(|p| p["key"].as_str()).ok_or("No key")?;
-                self.press_key(key).await?;
+                let modifiers = params.and_then(|p| p["modifiers"].as_array()).map_or(0, |m| {
+                    m.iter().filter_map(|v| v.as_str()).map(|name| match name.to_lowercase().as_str() {
+                        "alt" => 1, "ctrl" | "control" => 2, "meta" | "cmd" => 4, "shift" => 8, _ => 0,
+                    }).fold(0, |acc, bit| acc | bit)
+                });
+                self.press_key(key, modifiers).await?;
             }
             "focus_window" => {
                 self.focus_window().await?;
             }
             "select" => {
-                let value = params.and_then(|p| p["value"].as_str()).ok_or("No value")?;
+                let value = params.and_then(|p| p["value"].as_str());
+                let label = params.and_then(|p| p["label"].as_str());
+                if value.is_none() && label.is_none() { return Err("select needs params.value or params.label".into()); }
                 if let Some(s) = target.as_str() {
-                    self.select_option(s, value).await?;
+                    self.select_option(s, value, label).await?;
                 }
             }
             "wait" => {
                 let timeout = params.and_then(|p| p["timeout"].as_u64()).unwrap_or(5000);
-                if let Some(s) = target.as_str() {
+                if params.and_then(|p| p["until"].as_str()) == Some("network_idle") {
+                    let idle = params.and_then(|p| p["idle_ms"].as_u64()).unwrap_or(500);
+                    self.wait_for_network_idle(idle, timeout).await?;
+                } else if let Some(s) = target.as_str() {
                     self.wait_for_element(s, timeout).await?;
                 }
             }
@@ -477,9 +1690,1038 @@ impl ChromeConnection {
                 let js = params.and_then(|p| p["code"].as_str()).ok_or("No code")?;
                 self.eval_js(js).await?;
             }
+            "read_text" => {
+                let s = target.as_str().ok_or("No target")?;
+                let text = match params.and_then(|p| p["attribute"].as_str()) {
+                    Some(attr) => self.get_attribute(s, attr).await?,
+                    None => self.read_text(s).await?,
+                };
+                output = Some(text);
+            }
+            "assert" => {
+                let condition = params.and_then(|p| p["condition"].as_str()).unwrap_or("condition");
+                let t = params.and_then(|p| p["target"].as_str()).or(target.as_str()).filter(|s| !s.is_empty());
+                let expected = params.and_then(|p| p["expected_text"].as_str());
+                let timeout = params.and_then(|p| p["timeout"].as_u64()).unwrap_or(2000);
+                output = Some(self.assert_condition(condition, t, expected, timeout).await?);
+            }
+            "save_pdf" => {
+                let opts: PdfOptions = params.map(|p| serde_json::from_value(p.clone())).transpose()?.unwrap_or_default();
+                let path = pdf_path(params.and_then(|p| p["filename"].as_str()));
+                self.save_pdf(&path, &opts).await?;
+                output = Some(format!("Saved PDF to {}", path.display()));
+            }
+            "complete" => {
+                output = params.and_then(|p| p["summary"].as_str()).map(|s| s.to_string());
+            }
             _ => return Err(format!("Unknown action: {}", action).into()),
         }
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        Ok(())
+        Ok(output)
+    }
+}
+
+// The model only picks a file name; the PDF always lands in the Downloads folder
+fn pdf_path(filename: Option<&str>) -> std::path::PathBuf {
+    let dir = dirs::download_dir().unwrap_or_else(std::env::temp_dir);
+    let name = filename
+        .and_then(|f| std::path::Path::new(f).file_name())
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| format!("page-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let name = if name.to_lowercase().ends_with(".pdf") { name } else { format!("{}.pdf", name) };
+    dir.join(name)
+}
+
+// Resolves to the viewport point of the next trusted click, or null at the timeout. Press and release
+// events are swallowed too while picking, so the page doesn't react to the teaching click.
+const PICK_SCRIPT: &str = r#"new Promise(resolve => {
+    const root = document.documentElement;
+    const cursor = root.style.cursor;
+    const block = e => { if (e.isTrusted) { e.preventDefault(); e.stopImmediatePropagation(); } };
+    const swallowed = ['pointerdown', 'mousedown', 'pointerup', 'mouseup'];
+    const done = value => {
+        removeEventListener('click', onClick, true);
+        swallowed.forEach(t => removeEventListener(t, block, true));
+        clearTimeout(timer);
+        root.style.cursor = cursor;
+        resolve(value);
+    };
+    const onClick = e => { if (!e.isTrusted) return; block(e); done({ x: e.clientX, y: e.clientY }); };
+    swallowed.forEach(t => addEventListener(t, block, true));
+    addEventListener('click', onClick, true);
+    root.style.cursor = 'crosshair';
+    const timer = setTimeout(() => done(null), __TIMEOUT__);
+})"#;
+
+// DOM key, physical code, Windows virtual key code and inserted text for one key
+struct KeyDef {
+    key: String,
+    code: String,
+    key_code: u32,
+    text: Option<String>,
+}
+
+// US layout, matching what Chrome dispatches for a real key press. Names are case-insensitive and accept
+// the usual aliases (Return, Esc, Del, Up, PgDn, ...); single characters map to their own key.
+fn key_definition(name: &str) -> Option<KeyDef> {
+    let def = |key: &str, code: &str, key_code: u32, text: Option<&str>| Some(KeyDef { key: key.to_string(), code: code.to_string(), key_code, text: text.map(str::to_string) });
+    let lower = name.to_lowercase();
+    match lower.as_str() {
+        "enter" | "return" => return def("Enter", "Enter", 13, Some("\r")),
+        "tab" => return def("Tab", "Tab", 9, None),
+        "backspace" => return def("Backspace", "Backspace", 8, None),
+        "escape" | "esc" => return def("Escape", "Escape", 27, None),
+        "delete" | "del" => return def("Delete", "Delete", 46, None),
+        "insert" | "ins" => return def("Insert", "Insert", 45, None),
+        "space" | "spacebar" | " " => return def(" ", "Space", 32, Some(" ")),
+        "arrowup" | "up" => return def("ArrowUp", "ArrowUp", 38, None),
+        "arrowdown" | "down" => return def("ArrowDown", "ArrowDown", 40, None),
+        "arrowleft" | "left" => return def("ArrowLeft", "ArrowLeft", 37, None),
+        "arrowright" | "right" => return def("ArrowRight", "ArrowRight", 39, None),
+        "home" => return def("Home", "Home", 36, None),
+        "end" => return def("End", "End", 35, None),
+        "pageup" | "pgup" => return def("PageUp", "PageUp", 33, None),
+        "pagedown" | "pgdn" => return def("PageDown", "PageDown", 34, None),
+        _ => {}
+    }
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()).filter(|n| (1..=12).contains(n)) {
+        let f = format!("F{}", n);
+        return def(&f, &f, 111 + n, None);
+    }
+    let mut chars = name.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else { return None };
+    let text = c.to_string();
+    if c.is_ascii_alphabetic() {
+        let upper = c.to_ascii_uppercase();
+        return def(&text, &format!("Key{}", upper), upper as u32, Some(&text));
+    }
+    if c.is_ascii_digit() {
+        return def(&text, &format!("Digit{}", c), c as u32, Some(&text));
+    }
+    let (code, key_code) = match c {
+        '-' => ("Minus", 189),
+        '=' => ("Equal", 187),
+        ',' => ("Comma", 188),
+        '.' => ("Period", 190),
+        '/' => ("Slash", 191),
+        ';' => ("Semicolon", 186),
+        '\'' => ("Quote", 222),
+        '[' => ("BracketLeft", 219),
+        ']' => ("BracketRight", 221),
+        '\\' => ("Backslash", 220),
+        '`' => ("Backquote", 192),
+        _ => ("", 0),
+    };
+    def(&text, code, key_code, Some(&text))
+}
+
+// Common ad and tracker hosts, in Fetch urlPattern syntax
+pub const AD_BLOCKLIST: &[&str] = &[
+    "*://*.doubleclick.net/*",
+    "*://*.googlesyndication.com/*",
+    "*://*.googleadservices.com/*",
+    "*://*.google-analytics.com/*",
+    "*://*.googletagmanager.com/*",
+    "*://*.amazon-adsystem.com/*",
+    "*://*.adnxs.com/*",
+    "*://*.criteo.com/*",
+    "*://*.taboola.com/*",
+    "*://*.outbrain.com/*",
+    "*://*.scorecardresearch.com/*",
+    "*://*.hotjar.com/*",
+    "*://connect.facebook.net/*",
+];
+
+// Fetch urlPattern semantics: `*` is any run of characters, `?` any one, and the pattern covers the whole URL
+fn url_matches(pattern: &str, url: &str) -> bool {
+    let (p, u): (Vec<char>, Vec<char>) = (pattern.chars().collect(), url.chars().collect());
+    let (mut pi, mut ui) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ui < u.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == u[ui]) {
+            pi += 1;
+            ui += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ui));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ui = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
     }
-}
\ No newline at end of file
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+// Main regions shorter than this are likely teasers or nav, so the whole body is read instead
+const PAGE_TEXT_SCRIPT: &str = r#"(() => {
+    const text = el => (el.innerText || '').replace(/[ \t]+\n/g, '\n').replace(/\n{3,}/g, '\n\n').trim();
+    const main = [...document.querySelectorAll('article, main, [role=main]')].map(text).sort((a, b) => b.length - a.length)[0] || '';
+    return main.length >= 500 ? main : text(document.body || document.documentElement);
+})()"#;
+
+// Axis-aligned bounds of a box model quad (four x,y corners); None for a malformed or empty quad
+fn quad_bounds(quad: &Value) -> Option<Bounds> {
+    let q: Vec<f64> = quad.as_array()?.iter().filter_map(Value::as_f64).collect();
+    if q.len() != 8 { return None; }
+    let xs = [q[0], q[2], q[4], q[6]];
+    let ys = [q[1], q[3], q[5], q[7]];
+    let (x, y) = (xs.iter().cloned().fold(f64::MAX, f64::min), ys.iter().cloned().fold(f64::MAX, f64::min));
+    let (w, h) = (xs.iter().cloned().fold(f64::MIN, f64::max) - x, ys.iter().cloned().fold(f64::MIN, f64::max) - y);
+    Some(Bounds { x, y, width: w, height: h }).filter(|b| b.width > 0.0 && b.height > 0.0)
+}
+
+// MouseEvent.buttons bit for a CDP button name
+fn button_mask(button: &str) -> i64 {
+    match button {
+        "right" => 2,
+        "middle" => 4,
+        _ => 1,
+    }
+}
+
+// Captures are taken at the device scale, so a screenshot is DPR times the CSS viewport
+pub fn screenshot_to_css((x, y): (f64, f64), device_pixel_ratio: f64) -> (f64, f64) {
+    let dpr = if device_pixel_ratio > 0.0 { device_pixel_ratio } else { 1.0 };
+    (x / dpr, y / dpr)
+}
+
+// Network.Cookie -> Network.CookieParam. A session cookie (no expiry) must go back without "expires" or
+// it would be stored as already expired; flags and partitioning are carried over as they were.
+fn cookie_param(c: &Value, now: f64) -> Option<Value> {
+    let expires = c["expires"].as_f64().unwrap_or(-1.0);
+    let session = c["session"].as_bool().unwrap_or(expires <= 0.0);
+    if !session && expires < now { return None; }
+    let mut p = json!({
+        "name": c["name"].as_str()?, "value": c["value"].as_str().unwrap_or(""),
+        "domain": c["domain"].as_str()?, "path": c["path"].as_str().unwrap_or("/"),
+        "secure": c["secure"].as_bool().unwrap_or(false), "httpOnly": c["httpOnly"].as_bool().unwrap_or(false),
+    });
+    if !session { p["expires"] = json!(expires); }
+    for key in ["sameSite", "priority", "sourceScheme", "sourcePort", "partitionKey"] {
+        if !c[key].is_null() { p[key] = c[key].clone(); }
+    }
+    Some(p)
+}
+
+// ("button", "Save") from "role:button|name:Save"; both parts must be non-empty
+pub fn parse_role_target(target: &str) -> Option<(&str, &str)> {
+    let (role, name) = target.strip_prefix("role:")?.split_once("|name:")?;
+    let (role, name) = (role.trim(), name.trim());
+    (!role.is_empty() && !name.is_empty()).then_some((role, name))
+}
+
+// Parse "x,y" from a coords: target (e.g. OCR text nodes, which have no DOM node behind them)
+pub fn parse_coords(s: &str) -> Result<(f64, f64), Box<dyn std::error::Error + Send + Sync>> {
+    let (x, y) = s.split_once(',').ok_or("Invalid coords, expected x,y")?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
+// Describes an unnamed node by the first named sibling, falling back to the nearest named ancestor
+fn ax_context(node: &Value, by_id: &std::collections::HashMap<&str, &Value>) -> Option<String> {
+    let name_of = |id: &Value| id.as_str().and_then(|id| by_id.get(id)).and_then(|n| n["name"]["value"].as_str()).filter(|s| !s.trim().is_empty());
+    let mut parent = node["parentId"].as_str().and_then(|id| by_id.get(id));
+    if let Some(p) = parent {
+        let siblings = p["childIds"].as_array().into_iter().flatten();
+        if let Some(text) = siblings.filter(|id| id.as_str() != node["nodeId"].as_str()).find_map(name_of) {
+            return Some(format!("next to \"{}\"", text));
+        }
+    }
+    for _ in 0..3 {
+        let p = parent?;
+        if let Some(text) = p["name"]["value"].as_str().filter(|s| !s.trim().is_empty()) {
+            return Some(format!("inside {} \"{}\"", p["role"]["value"].as_str().unwrap_or("node"), text));
+        }
+        parent = p["parentId"].as_str().and_then(|id| by_id.get(id));
+    }
+    None
+}
+
+// Boolean AX properties (focusable, disabled, hidden) live in the node's properties list
+fn ax_property(node: &Value, name: &str) -> bool {
+    node["properties"].as_array().map_or(false, |props| props.iter().any(|p| p["name"].as_str() == Some(name) && p["value"]["value"].as_bool() == Some(true)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    type Reply = Arc<dyn Fn(&str, &Value) -> Result<Value, String> + Send + Sync>;
+
+    // A scripted Chrome on a local port. Every command is logged and answered by `reply`, after any
+    // events queued with push_event, and a Close frame is logged as a "Close" call. Like Chrome's debugger
+    // port it also serves the tab list over HTTP, starting with one page whose socket is this mock, logging
+    // each request as a "GET" call with the path.
+    pub(crate) struct MockChrome {
+        url: String,
+        port: u16,
+        log: Arc<StdMutex<Vec<(String, Value)>>>,
+        events: Arc<StdMutex<Vec<Value>>>,
+        silent: Arc<StdMutex<Vec<String>>>,
+        pages: Arc<StdMutex<Vec<Value>>>,
+    }
+
+    impl MockChrome {
+        pub(crate) async fn start(reply: impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static) -> Self {
+            let reply: Reply = Arc::new(reply);
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let url = format!("ws://127.0.0.1:{}", port);
+            let log = Arc::new(StdMutex::new(Vec::new()));
+            let events = Arc::new(StdMutex::new(Vec::new()));
+            let silent = Arc::new(StdMutex::new(Vec::new()));
+            let pages = Arc::new(StdMutex::new(vec![page_info(port, "mock", "Mock", "about:blank")]));
+            let (seen, queued, ignored, listed) = (log.clone(), events.clone(), silent.clone(), pages.clone());
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let (reply, seen, queued, ignored, listed) = (reply.clone(), seen.clone(), queued.clone(), ignored.clone(), listed.clone());
+                    tokio::spawn(async move {
+                        if is_tab_list_request(&stream).await {
+                            serve_tab_list(stream, port, &seen, &listed).await;
+                        } else {
+                            serve_session(stream, reply, seen, queued, ignored).await;
+                        }
+                    });
+                }
+            });
+            MockChrome { url, port, log, events, silent, pages }
+        }
+
+        pub(crate) fn port(&self) -> u16 {
+            self.port
+        }
+
+        fn push_event(&self, method: &str, params: Value) {
+            self.events.lock().unwrap().push(json!({"method": method, "params": params}));
+        }
+
+        // A page the browser opened on its own, as a popup does: listed from now on and announced as a
+        // Target.targetCreated event with the next answer
+        pub(crate) fn open_tab(&self, id: &str, title: &str, url: &str) {
+            self.pages.lock().unwrap().push(page_info(self.port, id, title, url));
+            self.push_event("Target.targetCreated", json!({"targetInfo": {"targetId": id, "type": "page", "title": title, "url": url}}));
+        }
+
+        // Calls to `method` are still logged but never answered, like a page that hangs
+        pub(crate) fn ignore(&self, method: &str) {
+            self.silent.lock().unwrap().push(method.to_string());
+        }
+
+        pub(crate) async fn connect(&self) -> ChromeConnection {
+            ChromeConnection::connect(&self.url).await.unwrap()
+        }
+
+        // Params of every logged call to `method`, in order
+        pub(crate) fn calls(&self, method: &str) -> Vec<Value> {
+            self.log.lock().unwrap().iter().filter(|(m, _)| m == method).map(|(_, p)| p.clone()).collect()
+        }
+
+        // For calls sent from a background task, which may land after the test's own await returns
+        pub(crate) async fn wait_for_calls(&self, method: &str, count: usize) -> usize {
+            for _ in 0..100 {
+                if self.calls(method).len() >= count { break; }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            self.calls(method).len()
+        }
+
+        // Keyboard input in order: "<type> <key>" per key event, "insert <text>" per insertText
+        fn typed(&self) -> Vec<String> {
+            self.log.lock().unwrap().iter().filter_map(|(m, p)| match m.as_str() {
+                "Input.dispatchKeyEvent" => Some(format!("{} {}", p["type"].as_str().unwrap_or(""), p["key"].as_str().unwrap_or(""))),
+                "Input.insertText" => Some(format!("insert {}", p["text"].as_str().unwrap_or(""))),
+                _ => None,
+            }).collect()
+        }
+    }
+
+    // A WebSocket handshake is a GET too, so only the path tells the two apart
+    async fn is_tab_list_request(stream: &tokio::net::TcpStream) -> bool {
+        let mut head = [0; 9];
+        loop {
+            match stream.peek(&mut head).await {
+                Ok(n) if n < head.len() && n > 0 => tokio::task::yield_now().await,
+                Ok(n) => return &head[..n] == b"GET /json",
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn page_info(port: u16, id: &str, title: &str, url: &str) -> Value {
+        json!({"type": "page", "id": id, "title": title, "url": url, "webSocketDebuggerUrl": format!("ws://127.0.0.1:{}/devtools/page/{}", port, id)})
+    }
+
+    async fn serve_tab_list(mut stream: tokio::net::TcpStream, port: u16, seen: &StdMutex<Vec<(String, Value)>>, pages: &StdMutex<Vec<Value>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        let path = String::from_utf8_lossy(&request).split_whitespace().nth(1).unwrap_or("").to_string();
+        seen.lock().unwrap().push(("GET".to_string(), json!(path)));
+        let body = if path == "/json/version" {
+            json!({"webSocketDebuggerUrl": format!("ws://127.0.0.1:{}/devtools/browser/mock", port)})
+        } else {
+            json!(*pages.lock().unwrap())
+        };
+        let body = body.to_string();
+        let _ = stream.write_all(format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).as_bytes()).await;
+    }
+
+    async fn serve_session(stream: tokio::net::TcpStream, reply: Reply, seen: Arc<StdMutex<Vec<(String, Value)>>>, queued: Arc<StdMutex<Vec<Value>>>, ignored: Arc<StdMutex<Vec<String>>>) {
+        let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else { return };
+        while let Some(Ok(message)) = ws.next().await {
+            let txt = match message {
+                Message::Text(txt) => txt,
+                // Read on past it, so a second Close would be logged too
+                Message::Close(_) => {
+                    seen.lock().unwrap().push(("Close".to_string(), Value::Null));
+                    continue;
+                }
+                _ => continue,
+            };
+            let cmd: Value = serde_json::from_str(&txt).unwrap();
+            let method = cmd["method"].as_str().unwrap_or("").to_string();
+            seen.lock().unwrap().push((method.clone(), cmd["params"].clone()));
+            if ignored.lock().unwrap().contains(&method) { continue; }
+            let answer = match reply(&method, &cmd["params"]) {
+                Ok(result) => json!({"id": cmd["id"], "result": result}),
+                Err(message) => json!({"id": cmd["id"], "error": {"code": -32000, "message": message}}),
+            };
+            let pending: Vec<Value> = std::mem::take(&mut *queued.lock().unwrap());
+            for event in pending {
+                if ws.send(Message::Text(event.to_string())).await.is_err() { return; }
+            }
+            if ws.send(Message::Text(answer.to_string())).await.is_err() { return; }
+        }
+    }
+
+    fn ax_node(id: &str, role: &str, name: &str, backend: Option<i64>, parent: Option<&str>, children: &[&str]) -> Value {
+        json!({
+            "nodeId": id, "role": {"value": role}, "name": {"value": name}, "backendDOMNodeId": backend,
+            "parentId": parent, "childIds": children, "properties": [{"name": "focusable", "value": {"value": true}}],
+        })
+    }
+
+    // Every node with a backend id gets a 20x20 box at (10 * backend, 50)
+    fn page_reply(nodes: Vec<Value>) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        move |method, params| Ok(match method {
+            "Accessibility.getFullAXTree" => json!({"nodes": nodes}),
+            "Page.getFrameTree" => json!({"frameTree": {"frame": {"id": "main"}}}),
+            "Page.getLayoutMetrics" => json!({"cssLayoutViewport": {"clientWidth": 800, "clientHeight": 600}}),
+            "DOM.getBoxModel" => {
+                let x = params["backendNodeId"].as_f64().unwrap_or(0.0) * 10.0;
+                let quad = json!([x, 50, x + 20.0, 50, x + 20.0, 70, x, 70]);
+                json!({"model": {"content": quad, "border": quad}})
+            }
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn unnamed_icon_buttons_get_context_and_coords() {
+        let chrome = MockChrome::start(page_reply(vec![
+            ax_node("1", "toolbar", "Formatting", None, None, &["2", "3", "4"]),
+            ax_node("2", "button", "Bold", Some(10), Some("1"), &[]),
+            ax_node("3", "button", "", Some(11), Some("1"), &[]),
+            // Nothing to describe it by and no box to click
+            ax_node("4", "button", "", None, Some("1"), &[]),
+        ])).await;
+        let tree = chrome.connect().await.get_a11y_tree().await.unwrap();
+        let ids: Vec<&str> = tree.iter().map(|n| n.node_id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        let icon = &tree[2];
+        assert_eq!(icon.name, None);
+        assert_eq!(icon.context.as_deref(), Some("next to \"Bold\""));
+        assert_eq!(icon.coords.as_deref(), Some("coords:120,60"));
+        assert_eq!(tree[1].coords, None);
+    }
+
+    #[tokio::test]
+    async fn unnamed_nodes_are_capped() {
+        let nodes = (0..MAX_UNNAMED_NODES as i64 + 10).map(|i| ax_node(&i.to_string(), "button", "", Some(i), None, &[])).collect();
+        let chrome = MockChrome::start(page_reply(nodes)).await;
+        let tree = chrome.connect().await.get_a11y_tree().await.unwrap();
+        assert_eq!(tree.len(), MAX_UNNAMED_NODES);
+        assert_eq!(chrome.calls("Accessibility.enable").len(), 1);
+    }
+
+    // <shop-cart> keeps its button in a shadow root, so querySelector misses it; the deep query finds
+    // it when `open` is set and returns null otherwise
+    fn shadow_reply(open: bool) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        move |method, params| Ok(match method {
+            "DOM.getDocument" => json!({"root": {"nodeId": 1}}),
+            "DOM.querySelector" => json!({"nodeId": 0}),
+            "Runtime.evaluate" if open => json!({"result": {"type": "object", "subtype": "node", "objectId": "shadow-button"}}),
+            "Runtime.evaluate" => json!({"result": {"type": "object", "subtype": "null", "value": null}}),
+            "DOM.requestNode" if params["objectId"] == "shadow-button" => json!({"nodeId": 42}),
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn find_element_reaches_a_button_inside_a_shadow_root() {
+        let chrome = MockChrome::start(shadow_reply(true)).await;
+        assert_eq!(chrome.connect().await.find_element("button.buy").await.unwrap(), 42);
+        let query = chrome.calls("Runtime.evaluate")[0]["expression"].as_str().unwrap().to_string();
+        assert!(query.contains("el.shadowRoot") && query.contains("(document, 'button.buy')"));
+        assert_eq!(chrome.calls("DOM.requestNode"), vec![json!({"objectId": "shadow-button"})]);
+
+        let chrome = MockChrome::start(shadow_reply(false)).await;
+        assert_eq!(chrome.connect().await.find_element("button.buy").await.unwrap_err().to_string(), "Not found");
+        assert!(chrome.calls("DOM.requestNode").is_empty());
+    }
+
+    // An XPath search whose nodes 5 (text), 6 (hidden element) and 7 (visible element) all match
+    fn xpath_reply(visible: bool) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        move |method, params| match method {
+            "DOM.getDocument" => Ok(json!({"root": {"nodeId": 1}})),
+            "DOM.performSearch" => Ok(json!({"searchId": "s1", "resultCount": 3})),
+            "DOM.getSearchResults" => Ok(json!({"nodeIds": [5, 6, 7]})),
+            "DOM.describeNode" => Ok(json!({"node": {"nodeType": if params["nodeId"] == 5 { 3 } else { 1 }}})),
+            "DOM.getBoxModel" if visible && params["nodeId"] == 7 => Ok(json!({"model": {"content": [0, 0, 10, 0, 10, 10, 0, 10]}})),
+            "DOM.getBoxModel" | "DOM.resolveNode" => Err("Could not compute box model.".to_string()),
+            _ => Ok(json!({})),
+        }
+    }
+
+    #[tokio::test]
+    async fn xpath_picks_the_first_visible_element_of_several_matches() {
+        let chrome = MockChrome::start(xpath_reply(true)).await;
+        assert_eq!(chrome.connect().await.find_by_xpath("//button").await.unwrap(), 7);
+        assert_eq!(chrome.calls("DOM.getSearchResults")[0]["toIndex"], 3);
+        assert_eq!(chrome.calls("DOM.discardSearchResults"), vec![json!({"searchId": "s1"})]);
+    }
+
+    #[tokio::test]
+    async fn xpath_search_is_discarded_when_nothing_is_visible() {
+        let chrome = MockChrome::start(xpath_reply(false)).await;
+        let err = chrome.connect().await.find_by_xpath("//button").await.unwrap_err();
+        assert!(err.to_string().contains("matched 3 node(s)"));
+        assert_eq!(chrome.calls("DOM.discardSearchResults").len(), 1);
+    }
+
+    // A <select> of countries. callFunctionOn plays the page's side of select_option with the same
+    // rules: the value exactly, or a label exactly before a label containing the text.
+    fn select_reply() -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        |method, params| Ok(match method {
+            "DOM.getDocument" => json!({"root": {"nodeId": 1}}),
+            "DOM.querySelector" => json!({"nodeId": 5}),
+            "DOM.resolveNode" => json!({"object": {"objectId": "select-1"}}),
+            "Runtime.callFunctionOn" => {
+                let options = [("de", "Germany"), ("at", "Austria"), ("ch", "Switzerland"), ("de-sw", "Germany (South West)")];
+                let (value, label) = (params["arguments"][0]["value"].as_str(), params["arguments"][1]["value"].as_str());
+                let found = match label.map(|l| l.trim().to_lowercase()) {
+                    Some(want) => options.iter().position(|o| o.1.to_lowercase() == want).or(options.iter().position(|o| o.1.to_lowercase().contains(&want))),
+                    None => options.iter().position(|o| Some(o.0) == value),
+                };
+                let outcome = match found {
+                    Some(_) => String::new(),
+                    None => format!("no option \"{}\"; options: Germany, Austria, Switzerland, Germany (South West)", label.or(value).unwrap_or("")),
+                };
+                json!({"result": {"type": "string", "value": outcome}})
+            }
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn select_matches_the_visible_label() {
+        let chrome = MockChrome::start(select_reply()).await;
+        let conn = chrome.connect().await;
+        conn.execute_llm_action("select", &json!("#country"), Some(&json!({"label": " germany "}))).await.unwrap();
+        conn.execute_llm_action("select", &json!("#country"), Some(&json!({"label": "Swit"}))).await.unwrap();
+        let calls = chrome.calls("Runtime.callFunctionOn");
+        assert_eq!(calls[0]["objectId"], "select-1");
+        assert_eq!(calls[0]["arguments"], json!([{"value": null}, {"value": " germany "}]));
+        assert_eq!(calls[1]["arguments"][1]["value"], "Swit");
+        assert_eq!(chrome.calls("DOM.querySelector")[0]["selector"], "#country");
+    }
+
+    #[tokio::test]
+    async fn select_matches_the_option_value() {
+        let chrome = MockChrome::start(select_reply()).await;
+        let conn = chrome.connect().await;
+        conn.execute_llm_action("select", &json!("#country"), Some(&json!({"value": "at"}))).await.unwrap();
+        assert_eq!(chrome.calls("Runtime.callFunctionOn")[0]["arguments"], json!([{"value": "at"}, {"value": null}]));
+        // Values are matched exactly, never by label
+        let err = conn.execute_llm_action("select", &json!("#country"), Some(&json!({"value": "Austria"}))).await.unwrap_err();
+        assert!(err.to_string().starts_with("select failed: no option \"Austria\""));
+    }
+
+    #[tokio::test]
+    async fn select_without_a_match_lists_the_options() {
+        let chrome = MockChrome::start(select_reply()).await;
+        let conn = chrome.connect().await;
+        let err = conn.execute_llm_action("select", &json!("#country"), Some(&json!({"label": "Atlantis"}))).await.unwrap_err();
+        assert_eq!(err.to_string(), "select failed: no option \"Atlantis\"; options: Germany, Austria, Switzerland, Germany (South West)");
+        let err = conn.execute_llm_action("select", &json!("#country"), Some(&json!({}))).await.unwrap_err();
+        assert_eq!(err.to_string(), "select needs params.value or params.label");
+        assert_eq!(chrome.calls("Runtime.callFunctionOn").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn each_session_ends_with_exactly_one_close_frame() {
+        let chrome = MockChrome::start(|_, _| Ok(json!({}))).await;
+        for i in 0..50 {
+            let conn = chrome.connect().await;
+            conn.send("Runtime.evaluate", json!({"expression": i.to_string()})).await.unwrap();
+            conn.disconnect().await.unwrap();
+        }
+        assert_eq!(chrome.wait_for_calls("Close", 50).await, 50);
+        assert_eq!(chrome.calls("Runtime.evaluate").len(), 50);
+
+        // Dropped without disconnect(): Drop sends the frame instead
+        let conn = chrome.connect().await;
+        conn.send("Runtime.evaluate", json!({"expression": "1"})).await.unwrap();
+        drop(conn);
+        assert_eq!(chrome.wait_for_calls("Close", 51).await, 51);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(chrome.calls("Close").len(), 51);
+    }
+
+    #[test]
+    fn screenshot_pixels_divide_by_the_device_pixel_ratio() {
+        assert_eq!(screenshot_to_css((800.0, 450.0), 2.0), (400.0, 225.0));
+        assert_eq!(screenshot_to_css((300.0, 90.0), 1.5), (200.0, 60.0));
+        assert_eq!(screenshot_to_css((800.0, 450.0), 0.0), (800.0, 450.0));
+    }
+
+    #[tokio::test]
+    async fn pixel_targets_use_the_page_device_pixel_ratio() {
+        let chrome = MockChrome::start(|method, _| Ok(match method {
+            "Runtime.evaluate" => json!({"result": {"type": "number", "value": 2}}),
+            _ => json!({}),
+        })).await;
+        let conn = chrome.connect().await;
+        assert_eq!(conn.target_center("pixel:640,360").await.unwrap(), (320.0, 180.0));
+        assert_eq!(conn.target_center("coords:640,360").await.unwrap(), (640.0, 360.0));
+    }
+
+    #[test]
+    fn network_presets_match_devtools() {
+        for (name, params) in [
+            ("Slow 3G", json!({"offline": false, "latency": 2000.0, "downloadThroughput": 50_000.0, "uploadThroughput": 50_000.0})),
+            ("fast 3g", json!({"offline": false, "latency": 562.5, "downloadThroughput": 180_000.0, "uploadThroughput": 84_375.0})),
+            ("OFFLINE", json!({"offline": true, "latency": 0.0, "downloadThroughput": -1.0, "uploadThroughput": -1.0})),
+        ] {
+            assert_eq!(NetworkConditions::preset(name).unwrap().cdp_params(), params, "{}", name);
+        }
+        assert!(NetworkConditions::preset("4g").is_none());
+    }
+
+    #[tokio::test]
+    async fn network_conditions_are_sent_after_enabling_the_network_domain() {
+        let chrome = MockChrome::start(|_, _| Ok(json!({}))).await;
+        let conn = chrome.connect().await;
+        conn.set_network_conditions(&NetworkConditions::preset("offline").unwrap()).await.unwrap();
+        conn.set_network_conditions(&NetworkConditions::preset("slow 3g").unwrap()).await.unwrap();
+        let sent = chrome.calls("Network.emulateNetworkConditions");
+        assert_eq!(sent[0], json!({"offline": true, "latency": 0.0, "downloadThroughput": -1.0, "uploadThroughput": -1.0}));
+        assert_eq!((sent[1]["offline"].as_bool(), sent[1]["latency"].as_f64()), (Some(false), Some(2000.0)));
+        let order: Vec<String> = chrome.log.lock().unwrap().iter().map(|(m, _)| m.clone()).collect();
+        assert_eq!(order[..2], ["Network.enable".to_string(), "Network.emulateNetworkConditions".to_string()]);
+    }
+
+    #[test]
+    fn url_patterns_follow_fetch_wildcards() {
+        assert!(url_matches("*://*.doubleclick.net/*", "https://ad.doubleclick.net/pixel?id=1"));
+        assert!(!url_matches("*://*.doubleclick.net/*", "https://doubleclick.net.example.com/"));
+        assert!(url_matches("https://example.com/ad?.js", "https://example.com/ad1.js"));
+        assert!(!url_matches("https://example.com/ad?.js", "https://example.com/ad12.js"));
+        assert!(url_matches("*", ""));
+    }
+
+    #[tokio::test]
+    async fn blocked_requests_fail_and_others_continue() {
+        let chrome = MockChrome::start(|_, _| Ok(json!({}))).await;
+        let mut conn = chrome.connect().await;
+        conn.block_urls(&["*://*.ads.example/*".to_string()]).await.unwrap();
+        assert_eq!(chrome.calls("Fetch.enable")[0]["patterns"][0]["urlPattern"], "*://*.ads.example/*");
+        chrome.push_event("Fetch.requestPaused", json!({"requestId": "r1", "request": {"url": "https://cdn.ads.example/banner.js"}}));
+        chrome.push_event("Fetch.requestPaused", json!({"requestId": "r2", "request": {"url": "https://news.example/app.js"}}));
+        conn.send("Runtime.evaluate", json!({"expression": "1"})).await.unwrap();
+        // The mock handles messages in order, so the answers fired above are logged by the time this returns
+        conn.send("Runtime.evaluate", json!({"expression": "2"})).await.unwrap();
+        assert_eq!(chrome.calls("Fetch.failRequest"), vec![json!({"requestId": "r1", "errorReason": "BlockedByClient"})]);
+        assert_eq!(chrome.calls("Fetch.continueRequest"), vec![json!({"requestId": "r2"})]);
+        assert!(conn.events.lock().await.is_empty());
+    }
+
+    fn pdf_reply(bytes: &'static [u8]) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        move |method, _| Ok(match method {
+            "Page.printToPDF" => json!({"data": STANDARD.encode(bytes)}),
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn save_pdf_writes_an_a4_portrait_pdf_by_default() {
+        let chrome = MockChrome::start(pdf_reply(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n")).await;
+        let conn = chrome.connect().await;
+        let path = std::env::temp_dir().join(format!("pc-agent-pdf-{}", std::process::id())).join("page.pdf");
+        conn.save_pdf(&path, &PdfOptions::default()).await.unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(b"%PDF-"));
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+        assert_eq!(chrome.calls("Page.printToPDF")[0], json!({"landscape": false, "printBackground": true, "paperWidth": 8.27, "paperHeight": 11.69}));
+
+        let letter: PdfOptions = serde_json::from_value(json!({"paper": "Letter", "landscape": true})).unwrap();
+        conn.print_to_pdf(&letter).await.unwrap();
+        let params = &chrome.calls("Page.printToPDF")[1];
+        assert_eq!((params["paperWidth"].as_f64(), params["landscape"].as_bool(), params["printBackground"].as_bool()), (Some(8.5), Some(true), Some(true)));
+    }
+
+    #[tokio::test]
+    async fn save_pdf_rejects_data_that_is_not_a_pdf() {
+        let chrome = MockChrome::start(pdf_reply(b"<html>")).await;
+        let path = std::env::temp_dir().join(format!("pc-agent-not-pdf-{}.pdf", std::process::id()));
+        let err = chrome.connect().await.save_pdf(&path, &PdfOptions::default()).await.unwrap_err();
+        assert_eq!(err.to_string(), "printToPDF did not return a PDF");
+        assert!(!path.exists());
+        assert!(PdfOptions { paper: "tabloid".to_string(), ..PdfOptions::default() }.paper_size().is_err());
+    }
+
+    #[tokio::test]
+    async fn a_locale_override_sets_position_timezone_and_language() {
+        let chrome = MockChrome::start(|_, _| Ok(json!({}))).await;
+        let conn = chrome.connect().await;
+        let berlin = LocaleConfig { latitude: Some(52.52), longitude: Some(13.405), accuracy: None, timezone: Some("Europe/Berlin".to_string()), locale: Some("de-DE".to_string()) };
+        conn.apply_locale(&berlin).await.unwrap();
+        let sent: Vec<(String, Value)> = chrome.log.lock().unwrap().clone();
+        assert_eq!(sent, vec![
+            ("Browser.grantPermissions".to_string(), json!({"permissions": ["geolocation"]})),
+            ("Emulation.setGeolocationOverride".to_string(), json!({"latitude": 52.52, "longitude": 13.405, "accuracy": 100.0})),
+            ("Emulation.setTimezoneOverride".to_string(), json!({"timezoneId": "Europe/Berlin"})),
+            ("Emulation.setLocaleOverride".to_string(), json!({"locale": "de-DE"})),
+        ]);
+
+        // Only what is set is overridden
+        conn.apply_locale(&LocaleConfig { timezone: Some("Asia/Tokyo".to_string()), ..LocaleConfig::default() }).await.unwrap();
+        assert_eq!(chrome.log.lock().unwrap().len(), 5);
+        assert_eq!(chrome.calls("Emulation.setTimezoneOverride")[1], json!({"timezoneId": "Asia/Tokyo"}));
+    }
+
+    #[tokio::test]
+    async fn geolocation_is_still_set_when_the_permission_grant_fails() {
+        let chrome = MockChrome::start(|method, _| match method {
+            "Browser.grantPermissions" => Err("Browser context management is not supported.".to_string()),
+            _ => Ok(json!({})),
+        }).await;
+        chrome.connect().await.set_geolocation(35.68, 139.69, 10.0).await.unwrap();
+        assert_eq!(chrome.calls("Emulation.setGeolocationOverride"), vec![json!({"latitude": 35.68, "longitude": 139.69, "accuracy": 10.0})]);
+    }
+
+    #[test]
+    fn key_names_normalize_like_the_windows_backend() {
+        for (name, key, code, key_code) in [("Return", "Enter", "Enter", 13), ("esc", "Escape", "Escape", 27), ("Down", "ArrowDown", "ArrowDown", 40), ("f5", "F5", "F5", 116), ("a", "a", "KeyA", 65), ("7", "7", "Digit7", 55), ("/", "/", "Slash", 191)] {
+            let def = key_definition(name).unwrap();
+            assert_eq!((def.key.as_str(), def.code.as_str(), def.key_code), (key, code, key_code), "{}", name);
+        }
+        assert_eq!(key_definition("Enter").unwrap().text.as_deref(), Some("\r"));
+        assert_eq!(key_definition("Tab").unwrap().text, None);
+        assert!(key_definition("F13").is_none());
+        assert!(key_definition("MediaPlay").is_none());
+    }
+
+    #[tokio::test]
+    async fn press_key_sends_what_chrome_sends_for_enter_and_arrow_down() {
+        let chrome = MockChrome::start(|_, _| Ok(json!({}))).await;
+        let conn = chrome.connect().await;
+        conn.press_key("Return", 0).await.unwrap();
+        conn.press_key("ArrowDown", 8).await.unwrap();
+        let events = chrome.calls("Input.dispatchKeyEvent");
+        assert_eq!(events[0], json!({"type": "keyDown", "key": "Enter", "code": "Enter", "windowsVirtualKeyCode": 13, "nativeVirtualKeyCode": 13, "modifiers": 0, "text": "\r", "unmodifiedText": "\r"}));
+        assert_eq!(events[1], json!({"type": "keyUp", "key": "Enter", "code": "Enter", "windowsVirtualKeyCode": 13, "nativeVirtualKeyCode": 13, "modifiers": 0}));
+        assert_eq!(events[2], json!({"type": "rawKeyDown", "key": "ArrowDown", "code": "ArrowDown", "windowsVirtualKeyCode": 40, "nativeVirtualKeyCode": 40, "modifiers": 8}));
+        assert_eq!(events[3]["type"], "keyUp");
+        assert_eq!(events.len(), 4);
+    }
+
+    // A document whose querySelector always finds node 5, a 100x20 box at the origin
+    fn field_reply() -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        |method, _| Ok(match method {
+            "DOM.getDocument" => json!({"root": {"nodeId": 1}}),
+            "DOM.querySelector" => json!({"nodeId": 5}),
+            "DOM.getBoxModel" => json!({"model": {"content": [0, 0, 100, 0, 100, 20, 0, 20]}}),
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn type_replaces_the_field_text_unless_clear_first_is_off() {
+        let chrome = MockChrome::start(field_reply()).await;
+        let conn = chrome.connect().await;
+        conn.execute_llm_action("type", &json!("#q"), Some(&json!({"text": "new"}))).await.unwrap();
+        assert_eq!(chrome.typed(), ["rawKeyDown a", "keyUp a", "rawKeyDown Backspace", "keyUp Backspace", "insert new"]);
+        assert_eq!(chrome.calls("Input.dispatchKeyEvent")[0]["commands"], json!(["selectAll"]));
+        assert_eq!(chrome.calls("Input.dispatchKeyEvent")[0]["modifiers"], 2);
+        assert_eq!(chrome.calls("Input.dispatchMouseEvent")[0]["x"], 50.0);
+
+        let chrome = MockChrome::start(field_reply()).await;
+        let conn = chrome.connect().await;
+        conn.execute_llm_action("type", &json!("#q"), Some(&json!({"text": " more", "clear_first": false}))).await.unwrap();
+        assert_eq!(chrome.typed(), ["insert  more"]);
+        assert_eq!(chrome.calls("Input.dispatchMouseEvent").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_typing_delay_sends_one_key_press_per_character() {
+        let chrome = MockChrome::start(|_, _| Ok(json!({}))).await;
+        let conn = chrome.connect().await;
+        conn.type_text("hé1", std::time::Duration::from_millis(1)).await.unwrap();
+        // é has no US key, so it is a bare char event
+        assert_eq!(chrome.typed(), ["keyDown h", "keyUp h", "char é", "keyDown 1", "keyUp 1"]);
+        assert!(chrome.calls("Input.insertText").is_empty());
+
+        // delay_ms on the action overrides the connection's default of inserting at once
+        conn.execute_llm_action("type", &json!(""), Some(&json!({"text": "ok", "delay_ms": 1}))).await.unwrap();
+        assert_eq!(chrome.typed().len(), 9);
+        conn.execute_llm_action("type", &json!(""), Some(&json!({"text": "ok"}))).await.unwrap();
+        assert_eq!(chrome.calls("Input.insertText"), vec![json!({"text": "ok"})]);
+    }
+
+    #[tokio::test]
+    async fn clipboard_actions_send_ctrl_shortcuts_with_editor_commands() {
+        let chrome = MockChrome::start(field_reply()).await;
+        let conn = chrome.connect().await;
+        for action in ["copy", "cut", "paste", "select_all"] {
+            conn.execute_llm_action(action, &json!(""), None).await.unwrap();
+        }
+        let downs: Vec<Value> = chrome.calls("Input.dispatchKeyEvent").into_iter().filter(|e| e["type"] != "keyUp").collect();
+        let sent: Vec<(&str, &Value, &Value)> = downs.iter().map(|e| (e["key"].as_str().unwrap(), &e["modifiers"], &e["commands"])).collect();
+        assert_eq!(sent, [("c", &json!(2), &json!(["copy"])), ("x", &json!(2), &json!(["cut"])), ("v", &json!(2), &json!(["paste"])), ("a", &json!(2), &json!(["selectAll"]))]);
+        assert!(downs.iter().all(|e| e["type"] == "rawKeyDown" && e.get("text").is_none()));
+        assert!(chrome.calls("Input.dispatchMouseEvent").is_empty());
+
+        // A target is clicked first so the shortcut lands in it
+        conn.execute_llm_action("copy", &json!("#q"), None).await.unwrap();
+        assert_eq!(chrome.calls("Input.dispatchMouseEvent").len(), 2);
+        assert_eq!(chrome.typed()[8..], ["rawKeyDown c", "keyUp c"]);
+    }
+
+    // A page whose #buy button is a 40x20 box at (100, 40), scaled like CSS zoom scales it
+    fn zoomable_reply() -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        let zoom = Arc::new(StdMutex::new(1.0));
+        move |method, params| Ok(match method {
+            "Runtime.evaluate" => {
+                let expression = params["expression"].as_str().unwrap_or("");
+                if let Some(level) = expression.strip_prefix("document.documentElement.style.zoom = ") {
+                    *zoom.lock().unwrap() = level.trim_matches('\'').parse().unwrap_or(1.0);
+                }
+                json!({"result": {"type": "undefined"}})
+            }
+            "DOM.getDocument" => json!({"root": {"nodeId": 1}}),
+            "DOM.querySelector" => json!({"nodeId": 5}),
+            "DOM.getBoxModel" => {
+                let z = *zoom.lock().unwrap();
+                json!({"model": {"content": [100.0 * z, 40.0 * z, 140.0 * z, 40.0 * z, 140.0 * z, 60.0 * z, 100.0 * z, 60.0 * z]}})
+            }
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn zoom_sets_css_zoom_and_clicks_land_on_the_scaled_page() {
+        let chrome = MockChrome::start(zoomable_reply()).await;
+        let conn = chrome.connect().await;
+        conn.set_zoom(1.5).await.unwrap();
+        assert_eq!(chrome.calls("Runtime.evaluate")[0], json!({"expression": "document.documentElement.style.zoom = '1.5'"}));
+        conn.click_element("#buy").await.unwrap();
+        let press = &chrome.calls("Input.dispatchMouseEvent")[0];
+        assert_eq!((press["type"].as_str(), press["x"].as_f64(), press["y"].as_f64()), (Some("mousePressed"), Some(180.0), Some(75.0)));
+
+        // 100% clears the style rather than setting zoom: 1
+        conn.set_zoom(1.0).await.unwrap();
+        assert_eq!(chrome.calls("Runtime.evaluate")[1], json!({"expression": "document.documentElement.style.zoom = ''"}));
+        conn.click_element("#buy").await.unwrap();
+        assert_eq!(chrome.calls("Input.dispatchMouseEvent")[2]["x"], 120.0);
+    }
+
+    #[test]
+    fn browser_state_serializes_the_tab_list() {
+        let tab = |id: &str, url: &str| TabInfo { id: id.to_string(), title: format!("Tab {}", id), url: url.to_string(), ws_url: format!("ws://127.0.0.1:9222/devtools/page/{}", id) };
+        let state = BrowserState {
+            url: "https://github.com/".to_string(), title: "GitHub".to_string(), screenshot_base64: String::new(), accessibility_tree: Vec::new(),
+            network_requests: Vec::new(), device_pixel_ratio: 1.0, tabs: vec![tab("A", "https://github.com/"), tab("B", "https://docs.rs/")],
+        };
+        let value = serde_json::to_value(&state).unwrap();
+        assert_eq!(value["tabs"][1], json!({"id": "B", "title": "Tab B", "url": "https://docs.rs/", "ws_url": "ws://127.0.0.1:9222/devtools/page/B"}));
+        let back: BrowserState = serde_json::from_value(value).unwrap();
+        assert_eq!(back.tabs.len(), 2);
+        // States saved before tabs were tracked still load
+        let old: BrowserState = serde_json::from_value(json!({"url": "", "title": "", "screenshot_base64": "", "accessibility_tree": []})).unwrap();
+        assert!(old.tabs.is_empty());
+        assert_eq!(old.device_pixel_ratio, 1.0);
+    }
+
+    #[test]
+    fn role_targets_need_both_parts() {
+        assert_eq!(parse_role_target("role:button|name:Save"), Some(("button", "Save")));
+        assert_eq!(parse_role_target("role: Button|name: Save as… "), Some(("Button", "Save as…")));
+        assert_eq!(parse_role_target("role:button|name:a|b"), Some(("button", "a|b")));
+        assert_eq!(parse_role_target("role:button"), None);
+        assert_eq!(parse_role_target("role:|name:Save"), None);
+        assert_eq!(parse_role_target("name:Save"), None);
+    }
+
+    #[tokio::test]
+    async fn role_targets_pick_the_node_with_that_role_among_same_named_ones() {
+        let chrome = MockChrome::start(page_reply(vec![
+            ax_node("1", "link", "Save", Some(20), None, &[]),
+            ax_node("2", "button", "Save", Some(21), None, &[]),
+        ])).await;
+        let conn = chrome.connect().await;
+        assert_eq!(conn.find_node_by_role_and_name("role:Button|name:save").await.unwrap(), ("2".to_string(), 21));
+        assert_eq!(conn.target_center("role:button|name:Save").await.unwrap(), (220.0, 60.0));
+        assert_eq!(conn.target_center("role:link|name:Save").await.unwrap(), (210.0, 60.0));
+        let err = conn.find_node_by_role_and_name("role:checkbox|name:Save").await.unwrap_err();
+        assert_eq!(err.to_string(), "No checkbox named \"Save\"");
+    }
+
+    #[test]
+    fn cookie_params_keep_flags_and_drop_expired_cookies() {
+        let now = 1_700_000_000.0;
+        let session = json!({"name": "sid", "value": "abc", "domain": ".example.com", "path": "/", "expires": -1, "session": true, "secure": true, "httpOnly": true, "sameSite": "Lax", "size": 6});
+        assert_eq!(cookie_param(&session, now), Some(json!({"name": "sid", "value": "abc", "domain": ".example.com", "path": "/", "secure": true, "httpOnly": true, "sameSite": "Lax"})));
+        let lasting = json!({"name": "pref", "value": "dark", "domain": "example.com", "expires": now + 3600.0, "session": false});
+        let p = cookie_param(&lasting, now).unwrap();
+        assert_eq!((p["expires"].as_f64(), p["path"].as_str(), p["secure"].as_bool()), (Some(now + 3600.0), Some("/"), Some(false)));
+        let expired = json!({"name": "old", "value": "1", "domain": "example.com", "expires": now - 1.0, "session": false});
+        assert_eq!(cookie_param(&expired, now), None);
+        assert_eq!(cookie_param(&json!({"name": "nodomain", "value": "1"}), now), None);
+    }
+
+    // A cookie store: setCookies adds to it and getAllCookies reads it back in Network.Cookie form
+    fn cookie_jar(cookies: Vec<Value>) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        let jar = Arc::new(StdMutex::new(cookies));
+        move |method, params| Ok(match method {
+            "Network.getAllCookies" => json!({"cookies": *jar.lock().unwrap()}),
+            "Network.setCookies" => {
+                for c in params["cookies"].as_array().unwrap() {
+                    let mut cookie = c.clone();
+                    cookie["session"] = json!(c["expires"].is_null());
+                    if c["expires"].is_null() { cookie["expires"] = json!(-1); }
+                    jar.lock().unwrap().push(cookie);
+                }
+                json!({})
+            }
+            _ => json!({}),
+        })
+    }
+
+    #[tokio::test]
+    async fn a_saved_cookie_is_present_after_restore() {
+        let far = 4_000_000_000.0;
+        let source = MockChrome::start(cookie_jar(vec![
+            json!({"name": "sid", "value": "abc", "domain": ".example.com", "path": "/", "expires": -1, "session": true, "secure": true, "httpOnly": true}),
+            json!({"name": "pref", "value": "dark", "domain": "example.com", "path": "/", "expires": far, "session": false, "secure": false, "httpOnly": false}),
+            json!({"name": "old", "value": "1", "domain": "example.com", "path": "/", "expires": 1.0, "session": false}),
+        ])).await;
+        let saved = BrowserSession { cookies: source.connect().await.all_cookies().await.unwrap(), tab_urls: vec!["https://example.com/".to_string()] };
+        let file: BrowserSession = serde_json::from_str(&serde_json::to_string(&saved).unwrap()).unwrap();
+
+        let target = MockChrome::start(cookie_jar(Vec::new())).await;
+        let conn = target.connect().await;
+        assert_eq!(conn.set_cookies(&file.cookies).await.unwrap(), 2);
+        let restored = conn.all_cookies().await.unwrap();
+        let sid = restored.iter().find(|c| c["name"] == "sid").unwrap();
+        assert_eq!((sid["value"].as_str(), sid["httpOnly"].as_bool(), sid["secure"].as_bool(), sid["session"].as_bool()), (Some("abc"), Some(true), Some(true), Some(true)));
+        assert_eq!(restored.iter().find(|c| c["name"] == "pref").unwrap()["expires"].as_f64(), Some(far));
+        assert!(restored.iter().all(|c| c["name"] != "old"));
+        assert_eq!(file.tab_urls, ["https://example.com/"]);
+    }
+
+    // A page whose document is replaced whenever `root` changes; querySelector only accepts the live root
+    fn navigating_reply(root: Arc<std::sync::atomic::AtomicI64>) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        move |method, params| {
+            let live = root.load(std::sync::atomic::Ordering::SeqCst);
+            match method {
+                "DOM.getDocument" => Ok(json!({"root": {"nodeId": live}})),
+                "DOM.querySelector" if params["nodeId"] == live => Ok(json!({"nodeId": live * 100})),
+                "DOM.querySelector" => Err("Could not find node with given id".to_string()),
+                _ => Ok(json!({})),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn find_element_refetches_the_document_after_the_main_frame_navigates() {
+        let root = Arc::new(std::sync::atomic::AtomicI64::new(1));
+        let chrome = MockChrome::start(navigating_reply(root.clone())).await;
+        let conn = chrome.connect().await;
+        assert_eq!(conn.find_element("#email").await.unwrap(), 100);
+        assert_eq!(conn.find_element("#email").await.unwrap(), 100);
+        assert_eq!(chrome.calls("DOM.getDocument").len(), 1);
+
+        // A child frame navigating leaves the main document alone
+        chrome.push_event("Page.frameNavigated", json!({"frame": {"id": "ad", "parentId": "main"}}));
+        conn.send("Runtime.evaluate", json!({"expression": "1"})).await.unwrap();
+        assert_eq!(conn.find_element("#email").await.unwrap(), 100);
+        assert_eq!(chrome.calls("DOM.getDocument").len(), 1);
+
+        root.store(2, std::sync::atomic::Ordering::SeqCst);
+        chrome.push_event("Page.frameNavigated", json!({"frame": {"id": "main"}}));
+        conn.send("Runtime.evaluate", json!({"expression": "1"})).await.unwrap();
+        assert_eq!(conn.find_element("#email").await.unwrap(), 200);
+        assert_eq!(chrome.calls("DOM.getDocument").len(), 2);
+        let roots: Vec<Value> = chrome.calls("DOM.querySelector").iter().map(|p| p["nodeId"].clone()).collect();
+        assert_eq!(roots, [json!(1), json!(1), json!(1), json!(2)]);
+    }
+
+    #[tokio::test]
+    async fn a_stale_root_is_retried_once_against_a_fresh_document() {
+        let root = Arc::new(std::sync::atomic::AtomicI64::new(1));
+        let chrome = MockChrome::start(navigating_reply(root.clone())).await;
+        let conn = chrome.connect().await;
+        conn.find_element("#email").await.unwrap();
+        // The navigation event hasn't been read yet when the next lookup starts
+        root.store(2, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(conn.find_element("#email").await.unwrap(), 200);
+        assert_eq!(chrome.calls("DOM.getDocument").len(), 2);
+    }
+
+    // #icon is node 7, an SVG whose box model is a single point; `rect` is what getBoundingClientRect
+    // reports for it, and None makes resolveNode fail as it does for a detached node
+    fn flat_icon_reply(rect: Option<[f64; 4]>) -> impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static {
+        move |method, _| match method {
+            "DOM.getDocument" => Ok(json!({"root": {"nodeId": 1}})),
+            "DOM.querySelector" => Ok(json!({"nodeId": 7})),
+            "DOM.getBoxModel" => Ok(json!({"model": {"content": [30, 40, 30, 40, 30, 40, 30, 40]}})),
+            "DOM.resolveNode" if rect.is_some() => Ok(json!({"object": {"objectId": "icon"}})),
+            "DOM.resolveNode" => Err("No node with given id found".to_string()),
+            "Runtime.callFunctionOn" => Ok(json!({"result": {"type": "object", "value": rect}})),
+            _ => Ok(json!({})),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_degenerate_box_model_falls_back_to_the_client_rect() {
+        let chrome = MockChrome::start(flat_icon_reply(Some([30.0, 40.0, 20.0, 10.0]))).await;
+        chrome.connect().await.click_element("#icon").await.unwrap();
+        assert_eq!(chrome.calls("Runtime.callFunctionOn")[0]["objectId"], "icon");
+        let pressed = &chrome.calls("Input.dispatchMouseEvent")[0];
+        assert_eq!((pressed["x"].as_f64(), pressed["y"].as_f64()), (Some(40.0), Some(45.0)));
+        // The first look found an area, so nothing was scrolled
+        assert!(chrome.calls("DOM.scrollIntoViewIfNeeded").is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_element_without_any_area_names_the_selector() {
+        let chrome = MockChrome::start(flat_icon_reply(None)).await;
+        let err = chrome.connect().await.click_element("#icon").await.unwrap_err().to_string();
+        assert_eq!(err, "#icon has no clickable area (hidden, zero-size or not rendered)");
+        // Both strategies were tried again after scrolling, and nothing was clicked
+        assert_eq!(chrome.calls("DOM.scrollIntoViewIfNeeded"), vec![json!({"nodeId": 7})]);
+        assert_eq!(chrome.calls("DOM.getBoxModel").len(), 2);
+        assert_eq!(chrome.calls("DOM.resolveNode").len(), 2);
+        assert!(chrome.calls("Input.dispatchMouseEvent").is_empty());
+    }
+}
@@ -0,0 +1,531 @@
+// Window-level desktop actions (switching apps, placing windows), driven through PowerShell + user32
+// like get_screen_a11y_tree. Windows only; the browser itself is still automated over CDP.
+//
+// Window placement acts on the foreground window, or on the first window whose title contains
+// params.title:
+//   move_window   params.x, params.y       top-left corner in virtual-screen pixels
+//   resize_window params.width, params.height
+//   snap_window   params.side = "left" | "right" | "maximize", within the window's current monitor
+// A placement that would leave the title bar on no monitor, or a window smaller than 100x50, is rejected.
+// zoom with params.title sends Ctrl+wheel over that window (Ctrl+0 to reset), which most apps treat as zoom.
+//
+// Control reads find the first element of that window whose AutomationId or Name equals the target
+// and return the UI Automation property as LAST_READ: get_value (ValuePattern.Value), get_toggle_state
+// ("on" | "off" | "indeterminate") and is_selected ("true" | "false").
+use crate::ActionCommand;
+
+pub const ACTIONS: &[&str] = &["alt_tab", "switch_to_app", "move_window", "resize_window", "snap_window", "get_value", "get_toggle_state", "is_selected"];
+
+const DESKTOP_TYPES: &str = r#"
+Add-Type @"
+using System; using System.Text; using System.Runtime.InteropServices;
+using System.Collections.Generic;
+public class Desktop {
+    [DllImport("user32.dll")] static extern void keybd_event(byte vk, byte scan, uint flags, UIntPtr extra);
+    [DllImport("user32.dll")] public static extern IntPtr GetForegroundWindow();
+    [DllImport("user32.dll")] static extern bool SetForegroundWindow(IntPtr h);
+    [DllImport("user32.dll")] static extern bool BringWindowToTop(IntPtr h);
+    [DllImport("user32.dll")] static extern bool ShowWindow(IntPtr h, int cmd);
+    [DllImport("user32.dll")] static extern bool IsIconic(IntPtr h);
+    [DllImport("user32.dll")] static extern bool IsWindowVisible(IntPtr h);
+    [DllImport("user32.dll")] static extern uint GetWindowThreadProcessId(IntPtr h, IntPtr pid);
+    [DllImport("kernel32.dll")] static extern uint GetCurrentThreadId();
+    [DllImport("user32.dll")] static extern bool AttachThreadInput(uint a, uint b, bool attach);
+    [DllImport("user32.dll", CharSet = CharSet.Unicode)] static extern int GetWindowText(IntPtr h, StringBuilder s, int n);
+    public delegate bool EnumProc(IntPtr h, IntPtr l);
+    [DllImport("user32.dll")] static extern bool EnumWindows(EnumProc cb, IntPtr l);
+    public struct RECT { public int Left, Top, Right, Bottom; }
+    public struct MONITORINFO { public int cbSize; public RECT rcMonitor; public RECT rcWork; public uint dwFlags; }
+    [DllImport("user32.dll")] static extern bool GetWindowRect(IntPtr h, out RECT r);
+    [DllImport("user32.dll")] static extern IntPtr MonitorFromWindow(IntPtr h, uint flags);
+    [DllImport("user32.dll")] static extern IntPtr MonitorFromRect(ref RECT r, uint flags);
+    [DllImport("user32.dll")] static extern bool GetMonitorInfo(IntPtr m, ref MONITORINFO mi);
+    [DllImport("user32.dll")] static extern bool SetWindowPos(IntPtr h, IntPtr after, int x, int y, int cx, int cy, uint flags);
+    [DllImport("user32.dll")] static extern bool SetCursorPos(int x, int y);
+    [DllImport("user32.dll")] static extern void mouse_event(uint flags, int dx, int dy, uint data, UIntPtr extra);
+    [DllImport("user32.dll")] static extern short GetAsyncKeyState(int vk);
+
+    public static void KeyDown(byte vk) { keybd_event(vk, 0, 0, UIntPtr.Zero); }
+    public static void KeyUp(byte vk) { keybd_event(vk, 0, 2, UIntPtr.Zero); }
+    public static void Mouse(int x, int y, uint flags) { SetCursorPos(x, y); mouse_event(flags, 0, 0, 0, UIntPtr.Zero); }
+
+    // Key-up for every Ctrl/Shift/Alt/Win key the OS still sees as down; a bare Alt-up would open menus otherwise
+    public static string ReleaseModifiers() {
+        var released = new List<string>();
+        var keys = new Dictionary<byte, string> { {0x11, "Ctrl"}, {0x10, "Shift"}, {0x12, "Alt"}, {0x5B, "LWin"}, {0x5C, "RWin"} };
+        foreach (var k in keys) {
+            if ((GetAsyncKeyState(k.Key) & 0x8000) != 0) { KeyUp(k.Key); released.Add(k.Value); }
+        }
+        return String.Join(",", released);
+    }
+
+    public static string Title(IntPtr h) {
+        var sb = new StringBuilder(512);
+        GetWindowText(h, sb, sb.Capacity);
+        return sb.ToString();
+    }
+
+    // Visible, titled top-level windows in z-order
+    public static List<IntPtr> Windows() {
+        var list = new List<IntPtr>();
+        EnumWindows((h, l) => {
+            if (IsWindowVisible(h) && Title(h).Length > 0) list.Add(h);
+            return true;
+        }, IntPtr.Zero);
+        return list;
+    }
+
+    public static IntPtr Find(string part) {
+        foreach (var h in Windows()) {
+            if (Title(h).IndexOf(part, StringComparison.OrdinalIgnoreCase) >= 0) return h;
+        }
+        return IntPtr.Zero;
+    }
+
+    // Windows only lets the foreground thread hand focus away, so borrow its input state first,
+    // and fall back to tapping Alt (which also unlocks SetForegroundWindow) if that isn't enough
+    public static bool Focus(IntPtr h) {
+        if (IsIconic(h)) ShowWindow(h, 9);
+        uint fg = GetWindowThreadProcessId(GetForegroundWindow(), IntPtr.Zero);
+        uint me = GetCurrentThreadId();
+        bool attached = fg != me && AttachThreadInput(me, fg, true);
+        SetForegroundWindow(h);
+        BringWindowToTop(h);
+        if (attached) AttachThreadInput(me, fg, false);
+        if (GetForegroundWindow() != h) {
+            KeyDown(0x12); KeyUp(0x12);
+            SetForegroundWindow(h);
+        }
+        return GetForegroundWindow() == h;
+    }
+
+    // The grab strip of the title bar has to land on a monitor (MONITOR_DEFAULTTONULL) so the user can still reach it
+    static string Place(IntPtr h, int x, int y, int w, int ht) {
+        if (w < 100 || ht < 50) return "ERR:window would be smaller than 100x50";
+        var grab = new RECT { Left = x, Top = y, Right = x + Math.Min(w, 100), Bottom = y + 30 };
+        if (MonitorFromRect(ref grab, 0) == IntPtr.Zero) return "ERR:position is off-screen";
+        ShowWindow(h, 9);
+        SetWindowPos(h, IntPtr.Zero, x, y, w, ht, 0x0004 | 0x0010);
+        return Title(h);
+    }
+
+    public static string Move(IntPtr h, int x, int y) {
+        RECT r; GetWindowRect(h, out r);
+        return Place(h, x, y, r.Right - r.Left, r.Bottom - r.Top);
+    }
+
+    public static string Resize(IntPtr h, int w, int ht) {
+        RECT r; GetWindowRect(h, out r);
+        return Place(h, r.Left, r.Top, w, ht);
+    }
+
+    // Work area of the monitor the window is on (MONITOR_DEFAULTTONEAREST), minus the taskbar
+    public static string Snap(IntPtr h, string side) {
+        if (side == "maximize") { ShowWindow(h, 3); return Title(h); }
+        var mi = new MONITORINFO(); mi.cbSize = Marshal.SizeOf(mi);
+        GetMonitorInfo(MonitorFromWindow(h, 2), ref mi);
+        var w = mi.rcWork;
+        int half = (w.Right - w.Left) / 2;
+        return Place(h, side == "left" ? w.Left : w.Left + half, w.Top, half, w.Bottom - w.Top);
+    }
+
+    public static string Center(IntPtr h) {
+        RECT r; GetWindowRect(h, out r);
+        return ((r.Left + r.Right) / 2) + "," + ((r.Top + r.Bottom) / 2);
+    }
+
+    // Topmost at the given bounds without taking focus (HWND_TOPMOST, SWP_NOACTIVATE | SWP_SHOWWINDOW)
+    public static void ShowOverlay(IntPtr h, int x, int y, int w, int ht) {
+        SetWindowPos(h, new IntPtr(-1), x, y, w, ht, 0x0010 | 0x0040);
+    }
+
+    // Ctrl+wheel over the window's centre, one notch (120) per step; delta 0 sends Ctrl+0 instead
+    public static string Zoom(IntPtr h, int delta) {
+        Focus(h);
+        RECT r; GetWindowRect(h, out r);
+        SetCursorPos((r.Left + r.Right) / 2, (r.Top + r.Bottom) / 2);
+        KeyDown(0x11);
+        try {
+            if (delta == 0) { KeyDown(0x30); KeyUp(0x30); }
+            else mouse_event(0x0800, 0, 0, unchecked((uint)delta), UIntPtr.Zero);
+        } finally { KeyUp(0x11); }
+        return Title(h);
+    }
+}
+"@
+"#;
+
+fn run_ps(body: &str) -> Result<String, String> {
+    if !cfg!(windows) { return Err("Desktop actions are only available on Windows".to_string()); }
+    let script = format!("{}\n{}", DESKTOP_TYPES, body);
+    let output = std::process::Command::new("powershell")
+        .args(["-ExecutionPolicy", "Bypass", "-Command", &script])
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Single-quoted PowerShell literal
+fn ps_str(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+// Polls until the foreground title differs from $prev; prints "<changed 0|1>|<elapsed ms>|<title>"
+const WAIT_FOREGROUND: &str = r#"
+$sw = [System.Diagnostics.Stopwatch]::StartNew()
+do {
+    $t = [Desktop]::Title([Desktop]::GetForegroundWindow())
+    if ($t -ne $prev) { break }
+    Start-Sleep -Milliseconds 50
+} while ($sw.ElapsedMilliseconds -lt __TIMEOUT__)
+"$([int]($t -ne $prev))|$($sw.ElapsedMilliseconds)|$t"
+"#;
+
+// Logs the wait and returns the title now in front, which is still `previous` on timeout
+fn report_wait(what: &str, out: &str) -> String {
+    let mut parts = out.splitn(3, '|');
+    let (changed, ms, title) = (parts.next() == Some("1"), parts.next().unwrap_or("?"), parts.next().unwrap_or("").to_string());
+    if changed {
+        log_info!("desktop", "{}: foreground became \"{}\" after {} ms", what, title, ms);
+    } else {
+        log_warn!("desktop", "{}: foreground still \"{}\" after {} ms", what, title, ms);
+    }
+    title
+}
+
+pub fn foreground_title() -> Result<String, String> {
+    run_ps("[Desktop]::Title([Desktop]::GetForegroundWindow())")
+}
+
+// Centre of the foreground window in screen coordinates, to tell which monitor it's on
+pub fn foreground_center() -> Result<(i32, i32), String> {
+    let out = run_ps("[Desktop]::Center([Desktop]::GetForegroundWindow())")?;
+    out.split_once(',').and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?))).ok_or(format!("Unexpected window position \"{}\"", out))
+}
+
+// Waits for whatever was just launched to take focus, instead of sleeping a fixed time
+pub fn wait_for_foreground_change(previous: &str, timeout: std::time::Duration) -> Result<String, String> {
+    let script = format!("$prev = {}\n{}", ps_str(previous), WAIT_FOREGROUND.replace("__TIMEOUT__", &timeout.as_millis().to_string()));
+    Ok(report_wait("wait_for_foreground_change", &run_ps(&script)?))
+}
+
+// Sends key-up for any modifier left down, e.g. by a PowerShell run killed between KeyDown and KeyUp.
+// Called on cancel and shutdown; returns the keys it had to release.
+pub fn release_all_modifiers() -> Result<Vec<String>, String> {
+    let out = run_ps("[Desktop]::ReleaseModifiers()")?;
+    let released: Vec<String> = out.split(',').filter(|k| !k.is_empty()).map(str::to_string).collect();
+    if !released.is_empty() { log_warn!("desktop", "released stuck modifiers: {}", released.join(", ")); }
+    Ok(released)
+}
+
+// Held while a script presses modifiers; unless disarmed after a clean run, dropping it calls `release`
+// (release_all_modifiers outside tests), so an early return or panic between key-down and key-up can't
+// leave Alt pressed system-wide
+struct ModifierGuard<R: FnMut() = fn()> {
+    armed: bool,
+    release: R,
+}
+
+impl ModifierGuard {
+    fn new() -> Self {
+        Self::with_release(|| { let _ = release_all_modifiers(); })
+    }
+}
+
+impl<R: FnMut()> ModifierGuard<R> {
+    fn with_release(release: R) -> Self {
+        Self { armed: true, release }
+    }
+}
+
+impl<R: FnMut()> Drop for ModifierGuard<R> {
+    fn drop(&mut self) {
+        if self.armed {
+            (self.release)();
+        }
+    }
+}
+
+// Holds Alt, taps Tab `count` times, releases Alt. Returns the title that ended up in front.
+pub fn alt_tab(count: u32) -> Result<String, String> {
+    let count = count.clamp(1, 20);
+    let mut guard = ModifierGuard::new();
+    let out = run_ps(&format!(
+        "$prev = [Desktop]::Title([Desktop]::GetForegroundWindow())\n[Desktop]::KeyDown(0x12)\ntry {{ for ($i = 0; $i -lt {}; $i++) {{ [Desktop]::KeyDown(0x09); [Desktop]::KeyUp(0x09); Start-Sleep -Milliseconds 60 }} }} finally {{ [Desktop]::KeyUp(0x12) }}\n{}",
+        count,
+        WAIT_FOREGROUND.replace("__TIMEOUT__", "1000")
+    ))?;
+    guard.armed = false;
+    Ok(report_wait("alt_tab", &out))
+}
+
+// Presses or releases one mouse button at a screen point (MOUSEEVENTF_*DOWN / *UP); the pairing is
+// tracked by the caller. Moving to a new point before the release drags with the button held.
+pub fn mouse_button(button: &str, down: bool, x: i32, y: i32) -> Result<(), String> {
+    let flags = match (button, down) {
+        ("left", true) => 0x0002,
+        ("left", false) => 0x0004,
+        ("right", true) => 0x0008,
+        ("right", false) => 0x0010,
+        ("middle", true) => 0x0020,
+        ("middle", false) => 0x0040,
+        _ => return Err(format!("Unknown mouse button: {}", button)),
+    };
+    run_ps(&format!("[Desktop]::Mouse({}, {}, {})", x, y, flags)).map(|_| ())
+}
+
+// Shows a translucent red box over the screen rectangle for `ms`, then removes it. The window never
+// takes focus, and it is gone before this returns, so it can't end up in a screenshot.
+pub fn flash_rect(x: i32, y: i32, width: i32, height: i32, ms: u64) -> Result<(), String> {
+    run_ps(&format!(
+        "Add-Type -AssemblyName System.Windows.Forms\n$f = New-Object System.Windows.Forms.Form\n$f.FormBorderStyle = 'None'; $f.ShowInTaskbar = $false; $f.BackColor = 'Red'; $f.Opacity = 0.4\n[Desktop]::ShowOverlay($f.Handle, {}, {}, {}, {})\n[System.Windows.Forms.Application]::DoEvents()\nStart-Sleep -Milliseconds {}\n$f.Dispose()",
+        x, y, width.max(1), height.max(1), ms
+    )).map(|_| ())
+}
+
+// PowerShell expression for the window an action targets
+fn window_expr(title: Option<&str>) -> String {
+    match title.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(t) => format!("[Desktop]::Find({})", ps_str(t)),
+        None => "[Desktop]::GetForegroundWindow()".to_string(),
+    }
+}
+
+fn place(title: Option<&str>, call: &str) -> Result<String, String> {
+    let out = run_ps(&format!("$h = {}\nif ($h -eq [IntPtr]::Zero) {{ 'ERR:no matching window' }} else {{ {} }}", window_expr(title), call))?;
+    match out.strip_prefix("ERR:") {
+        Some(e) => Err(e.to_string()),
+        None => Ok(out),
+    }
+}
+
+pub fn move_window(title: Option<&str>, x: i64, y: i64) -> Result<String, String> {
+    place(title, &format!("[Desktop]::Move($h, {}, {})", x, y))
+}
+
+pub fn resize_window(title: Option<&str>, width: i64, height: i64) -> Result<String, String> {
+    place(title, &format!("[Desktop]::Resize($h, {}, {})", width, height))
+}
+
+pub fn snap_window(title: Option<&str>, side: &str) -> Result<String, String> {
+    if !matches!(side, "left" | "right" | "maximize") {
+        return Err(format!("snap_window side must be left, right or maximize, not \"{}\"", side));
+    }
+    place(title, &format!("[Desktop]::Snap($h, {})", ps_str(side)))
+}
+
+pub fn zoom_window(title: Option<&str>, direction: &str, steps: u32) -> Result<String, String> {
+    let delta = match direction {
+        "in" => 120 * steps.clamp(1, 10) as i32,
+        "out" => -120 * steps.clamp(1, 10) as i32,
+        "reset" => 0,
+        other => return Err(format!("zoom direction must be in, out or reset, not \"{}\"", other)),
+    };
+    let mut guard = ModifierGuard::new();
+    let out = place(title, &format!("[Desktop]::Zoom($h, {})", delta));
+    guard.armed = false;
+    out
+}
+
+// Prints OK:<value>, so an empty value still reads as success
+const READ_PROPERTY: &str = r#"
+Add-Type -AssemblyName UIAutomationClient
+Add-Type -AssemblyName UIAutomationTypes
+$A = [System.Windows.Automation.AutomationElement]
+$h = __WINDOW__
+if ($h -eq [IntPtr]::Zero) { 'ERR:no matching window'; return }
+$key = __ELEMENT__
+$cond = __CONDITION__
+$el = $A::FromHandle($h).FindFirst([System.Windows.Automation.TreeScope]::Descendants, $cond)
+if ($null -eq $el) { "ERR:no element named or with AutomationId $key in " + [Desktop]::Title($h); return }
+$v = $el.GetCurrentPropertyValue(__PROPERTY__)
+if ($v -eq $A::NotSupported) { "ERR:$key (" + $el.Current.ControlType.ProgrammaticName + ") does not support __PATTERN__"; return }
+'OK:' + $v
+"#;
+
+// UI Automation condition for a control target: its AutomationId or Name, or with "role:Button|name:Save"
+// a control of that ControlType and exactly that Name, for names several controls share
+fn find_node_by_role_and_name(element: &str) -> Result<String, String> {
+    let prop = |p: &str, v: &str| format!("(New-Object System.Windows.Automation.PropertyCondition($A::{}, {}))", p, v);
+    if !element.starts_with("role:") {
+        return Ok(format!("New-Object System.Windows.Automation.OrCondition({}, {})", prop("AutomationIdProperty", "$key"), prop("NameProperty", "$key")));
+    }
+    let (role, name) = crate::automation::chrome_cdp::parse_role_target(element).ok_or(format!("Role target must look like role:Button|name:Save, got \"{}\"", element))?;
+    if !role.chars().all(|c| c.is_ascii_alphanumeric()) { return Err(format!("\"{}\" is not a UI Automation control type", role)); }
+    // ControlType names are PascalCase (Button, CheckBox); PowerShell resolves the static member case-insensitively
+    Ok(format!(
+        "New-Object System.Windows.Automation.AndCondition({}, {})",
+        prop("ControlTypeProperty", &format!("[System.Windows.Automation.ControlType]::{}", role)),
+        prop("NameProperty", &ps_str(name))
+    ))
+}
+
+fn read_property(title: Option<&str>, element: &str, pattern: &str, property: &str) -> Result<String, String> {
+    if element.trim().is_empty() { return Err("target must be the control's AutomationId or name".to_string()); }
+    let script = READ_PROPERTY
+        .replace("__WINDOW__", &window_expr(title))
+        .replace("__CONDITION__", &find_node_by_role_and_name(element.trim())?)
+        .replace("__ELEMENT__", &ps_str(element.trim()))
+        .replace("__PROPERTY__", &format!("[System.Windows.Automation.{}]::{}", pattern, property))
+        .replace("__PATTERN__", pattern);
+    property_value(&run_ps(&script)?)
+}
+
+// READ_PROPERTY's output: the value after OK:, or the reason after ERR:
+fn property_value(out: &str) -> Result<String, String> {
+    match (out.strip_prefix("OK:"), out.strip_prefix("ERR:")) {
+        (Some(value), _) => Ok(value.to_string()),
+        (None, Some(e)) => Err(e.to_string()),
+        (None, None) => Err(format!("unexpected UI Automation output: {}", out)),
+    }
+}
+
+// ToggleState prints as On, Off or Indeterminate
+fn toggle_state(value: &str) -> Result<String, String> {
+    match value.trim().to_lowercase().as_str() {
+        state @ ("on" | "off" | "indeterminate") => Ok(state.to_string()),
+        other => Err(format!("unexpected toggle state \"{}\"", other)),
+    }
+}
+
+fn selected(value: &str) -> Result<String, String> {
+    match value.trim().to_lowercase().as_str() {
+        state @ ("true" | "false") => Ok(state.to_string()),
+        other => Err(format!("unexpected selection state \"{}\"", other)),
+    }
+}
+
+pub fn get_value(title: Option<&str>, element: &str) -> Result<String, String> {
+    read_property(title, element, "ValuePattern", "ValueProperty")
+}
+
+pub fn get_toggle_state(title: Option<&str>, element: &str) -> Result<String, String> {
+    toggle_state(&read_property(title, element, "TogglePattern", "ToggleStateProperty")?)
+}
+
+pub fn is_selected(title: Option<&str>, element: &str) -> Result<String, String> {
+    selected(&read_property(title, element, "SelectionItemPattern", "IsSelectedProperty")?)
+}
+
+// Document controls give their TextPattern text, Text controls their name; one per line, in tree order
+const WINDOW_TEXT: &str = r#"
+Add-Type -AssemblyName UIAutomationClient
+Add-Type -AssemblyName UIAutomationTypes
+$A = [System.Windows.Automation.AutomationElement]
+$T = [System.Windows.Automation.ControlType]
+$h = __WINDOW__
+if ($h -eq [IntPtr]::Zero) { 'ERR:no matching window'; return }
+$cond = New-Object System.Windows.Automation.OrCondition(
+    (New-Object System.Windows.Automation.PropertyCondition($A::ControlTypeProperty, $T::Document)),
+    (New-Object System.Windows.Automation.PropertyCondition($A::ControlTypeProperty, $T::Text)))
+$lines = foreach ($el in $A::FromHandle($h).FindAll([System.Windows.Automation.TreeScope]::Descendants, $cond)) {
+    $p = $null
+    if ($el.Current.ControlType -eq $T::Document -and $el.TryGetCurrentPattern([System.Windows.Automation.TextPattern]::Pattern, [ref]$p)) {
+        $p.DocumentRange.GetText(__MAX_CHARS__)
+    } else { $el.Current.Name }
+}
+'OK:' + (($lines | Where-Object { $_ -and $_.Trim() }) -join "`n")
+"#;
+
+// Visible text of a desktop window, the desktop side of get_page_text
+pub fn window_text(title: Option<&str>, max_chars: usize) -> Result<String, String> {
+    let out = run_ps(&WINDOW_TEXT.replace("__WINDOW__", &window_expr(title)).replace("__MAX_CHARS__", &max_chars.to_string()))?;
+    match (out.strip_prefix("OK:"), out.strip_prefix("ERR:")) {
+        (Some(text), _) => Ok(text.to_string()),
+        (None, Some(e)) => Err(e.to_string()),
+        (None, None) => Err(format!("unexpected UI Automation output: {}", out)),
+    }
+}
+
+// Runs one of ACTIONS; blocking, so call it off the async runtime. Returns a line for the model.
+pub fn execute(action: &ActionCommand) -> Result<String, String> {
+    let params = action.params.clone().unwrap_or_default();
+    let num = |k: &str| params[k].as_i64().ok_or_else(|| format!("{} needs params.{}", action.action_type, k));
+    let title = params["title"].as_str();
+    let element = action.target.as_str().unwrap_or("");
+    let window = |t: String| format!("Window: {}", t);
+    match action.action_type.as_str() {
+        "alt_tab" => alt_tab(params["count"].as_u64().unwrap_or(1) as u32).map(|t| format!("Foreground window: {}", t)),
+        "switch_to_app" => switch_to_app(title.or(action.target.as_str()).unwrap_or("")).map(|t| format!("Foreground window: {}", t)),
+        "move_window" => move_window(title, num("x")?, num("y")?).map(window),
+        "resize_window" => resize_window(title, num("width")?, num("height")?).map(window),
+        "snap_window" => snap_window(title, params["side"].as_str().unwrap_or("")).map(window),
+        "get_value" => get_value(title, element).map(|v| format!("{} value: {}", element, v)),
+        "get_toggle_state" => get_toggle_state(title, element).map(|v| format!("{} toggle state: {}", element, v)),
+        "is_selected" => is_selected(title, element).map(|v| format!("{} selected: {}", element, v)),
+        other => Err(format!("{} is not a desktop action", other)),
+    }
+}
+
+// Brings the first visible window whose title contains `title` (case-insensitive) to the front
+pub fn switch_to_app(title: &str) -> Result<String, String> {
+    if title.trim().is_empty() { return Err("switch_to_app needs a window title".to_string()); }
+    let out = run_ps(&format!(
+        "$h = [Desktop]::Find({})\nif ($h -eq [IntPtr]::Zero) {{ 'NOT_FOUND' }} elseif ([Desktop]::Focus($h)) {{ [Desktop]::Title($h) }} else {{ 'NO_FOCUS:' + [Desktop]::Title($h) }}",
+        ps_str(title.trim())
+    ))?;
+    if out == "NOT_FOUND" { return Err(format!("No window title contains \"{}\"", title)); }
+    if let Some(found) = out.strip_prefix("NO_FOCUS:") { return Err(format!("Windows refused to focus \"{}\"", found)); }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_reads_parse_the_script_output() {
+        assert_eq!(property_value("OK:42 kg"), Ok("42 kg".to_string()));
+        // An empty field is still a value
+        assert_eq!(property_value("OK:"), Ok(String::new()));
+        assert_eq!(property_value("ERR:no matching window"), Err("no matching window".to_string()));
+        assert_eq!(property_value("Exception calling FromHandle"), Err("unexpected UI Automation output: Exception calling FromHandle".to_string()));
+    }
+
+    #[test]
+    fn toggle_and_selection_states_are_lowercase_words() {
+        for (raw, state) in [("On", "on"), ("Off", "off"), ("Indeterminate", "indeterminate")] {
+            assert_eq!(toggle_state(raw).as_deref(), Ok(state));
+        }
+        assert!(toggle_state("2").is_err());
+        assert_eq!(selected("True").as_deref(), Ok("true"));
+        assert_eq!(selected("False").as_deref(), Ok("false"));
+        assert!(selected("").is_err());
+    }
+
+    #[test]
+    fn control_targets_match_by_id_or_name_or_by_role_and_name() {
+        let plain = find_node_by_role_and_name("txtAmount").unwrap();
+        assert!(plain.starts_with("New-Object System.Windows.Automation.OrCondition("));
+        assert!(plain.contains("($A::AutomationIdProperty, $key)") && plain.contains("($A::NameProperty, $key)"));
+
+        let role = find_node_by_role_and_name("role:CheckBox|name:Don't ask again").unwrap();
+        assert!(role.starts_with("New-Object System.Windows.Automation.AndCondition("));
+        assert!(role.contains("($A::ControlTypeProperty, [System.Windows.Automation.ControlType]::CheckBox)"));
+        assert!(role.contains("($A::NameProperty, 'Don''t ask again')"));
+
+        assert!(find_node_by_role_and_name("role:CheckBox").is_err());
+        assert_eq!(find_node_by_role_and_name("role:Check Box|name:x").unwrap_err(), "\"Check Box\" is not a UI Automation control type");
+    }
+
+    // Stands in for a script that fails between pressing and releasing a modifier
+    fn press_modifier(released: &std::cell::Cell<u32>, fail: bool) -> Result<(), String> {
+        let mut guard = ModifierGuard::with_release(|| released.set(released.get() + 1));
+        if fail { return Err("PowerShell exited before KeyUp".to_string()); }
+        guard.armed = false;
+        Ok(())
+    }
+
+    #[test]
+    fn an_error_between_key_down_and_key_up_still_releases_modifiers() {
+        let released = std::cell::Cell::new(0);
+        assert!(press_modifier(&released, true).is_err());
+        assert_eq!(released.get(), 1);
+        // A clean run released its own keys, so the guard stays quiet
+        press_modifier(&released, false).unwrap();
+        assert_eq!(released.get(), 1);
+    }
+}
@@ -1,2 +1,5 @@
 ﻿pub mod chrome_cdp;
-pub mod windows_ui;
+pub mod desktop;
+pub mod ocr;
+pub mod screen;
+pub mod windows_ui;
\ No newline at end of file
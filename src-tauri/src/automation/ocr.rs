@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use crate::automation::chrome_cdp::{screenshot_to_css, AXNode, Bounds};
+
+// Tells apart the temp files of concurrent scans in one process
+static SCAN_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+// Runs the screenshot through the tesseract CLI and turns each recognised text line into a
+// synthetic "Text" node, so canvas-heavy pages still give the agent something to target.
+// Bounds come back in CSS pixels like the rest of the tree, using the screenshot's device pixel ratio.
+pub fn ocr_nodes(png_base64: &str, device_pixel_ratio: f64) -> Result<Vec<AXNode>, Box<dyn std::error::Error + Send + Sync>> {
+    let seq = SCAN_SEQ.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("pc-agent-ocr-{}-{}.png", std::process::id(), seq));
+    std::fs::write(&path, STANDARD.decode(png_base64)?)?;
+    let output = Command::new("tesseract").arg(&path).args(["stdout", "tsv"]).output();
+    let _ = std::fs::remove_file(&path);
+    let output = output.map_err(|e| format!("tesseract not available: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).to_string().into());
+    }
+    Ok(parse_tsv(&String::from_utf8_lossy(&output.stdout), device_pixel_ratio))
+}
+
+// TSV columns: level page block par line word left top width height conf text
+fn parse_tsv(tsv: &str, device_pixel_ratio: f64) -> Vec<AXNode> {
+    let mut lines: BTreeMap<(u32, u32, u32), (Vec<String>, f64, f64, f64, f64)> = BTreeMap::new();
+    for row in tsv.lines().skip(1) {
+        let cols: Vec<&str> = row.split('\t').collect();
+        if cols.len() < 12 || cols[0] != "5" { continue; }
+        let text = cols[11].trim();
+        let conf = cols[10].parse::<f64>().unwrap_or(-1.0);
+        if text.is_empty() || conf < 30.0 { continue; }
+        let key = (cols[2].parse().unwrap_or(0), cols[3].parse().unwrap_or(0), cols[4].parse().unwrap_or(0));
+        let (l, t, w, h) = (cols[6].parse().unwrap_or(0.0), cols[7].parse().unwrap_or(0.0), cols[8].parse().unwrap_or(0.0), cols[9].parse().unwrap_or(0.0));
+        let entry = lines.entry(key).or_insert((Vec::new(), l, t, l + w, t + h));
+        entry.0.push(text.to_string());
+        entry.1 = entry.1.min(l);
+        entry.2 = entry.2.min(t);
+        entry.3 = entry.3.max(l + w);
+        entry.4 = entry.4.max(t + h);
+    }
+    lines.into_values().enumerate().map(|(i, (words, x1, y1, x2, y2))| {
+        let ((x1, y1), (x2, y2)) = (screenshot_to_css((x1, y1), device_pixel_ratio), screenshot_to_css((x2, y2), device_pixel_ratio));
+        AXNode {
+            node_id: format!("ocr:{}", i),
+            role: "Text".to_string(),
+            name: Some(words.join(" ")),
+            value: None,
+            bounds: Some(Bounds { x: x1, y: y1, width: x2 - x1, height: y2 - y1 }),
+            focusable: false,
+            enabled: true,
+            offscreen: false,
+            context: None,
+            coords: None,
+            frame_id: None,
+        }
+    }).collect()
+}
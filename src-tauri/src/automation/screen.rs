@@ -0,0 +1,133 @@
+// OS-level screen capture (xcap) for the clipboard and the MCP take_screenshot tool. Page state
+// comes from CDP screenshots; this is for everything outside the browser.
+use image::RgbaImage;
+use serde::Serialize;
+use xcap::Monitor;
+
+// Which part of the desktop to grab
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    // Position in list_monitors()
+    Monitor(usize),
+    // The monitor the foreground window is on, else the primary one
+    ActiveWindow,
+    // Every monitor, composited at its desktop position
+    All,
+}
+
+impl Capture {
+    pub fn from_params(monitor_index: Option<usize>, all_monitors: bool) -> Self {
+        match (all_monitors, monitor_index) {
+            (true, _) => Capture::All,
+            (false, Some(i)) => Capture::Monitor(i),
+            (false, None) => Capture::ActiveWindow,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorInfo {
+    pub index: usize,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+pub fn list_monitors() -> Result<Vec<MonitorInfo>, String> {
+    Ok(describe(&Monitor::all().map_err(|e| e.to_string())?))
+}
+
+fn describe(monitors: &[Monitor]) -> Vec<MonitorInfo> {
+    monitors.iter().enumerate().map(|(index, m)| MonitorInfo {
+        index,
+        name: m.name().to_string(),
+        x: m.x(),
+        y: m.y(),
+        width: m.width(),
+        height: m.height(),
+        primary: m.is_primary(),
+    }).collect()
+}
+
+pub fn capture(which: Capture) -> Result<RgbaImage, String> {
+    let monitors = Monitor::all().map_err(|e| e.to_string())?;
+    if monitors.is_empty() { return Err("No monitor found".to_string()); }
+    let point = match which {
+        Capture::All => return stitch(&monitors),
+        Capture::Monitor(_) => None,
+        Capture::ActiveWindow => match crate::automation::desktop::foreground_center() {
+            Ok(p) => Some(p),
+            Err(e) => {
+                crate::log_warn!("screen", "foreground window not located, using the primary monitor: {}", e);
+                None
+            }
+        },
+    };
+    let i = pick_monitor(which, &describe(&monitors), point)?;
+    monitors[i].capture_image().map_err(|e| e.to_string())
+}
+
+// Index of the one monitor a capture grabs. `point` is the foreground window's centre; a window that
+// isn't on any monitor, or an unknown one, falls back to the primary monitor.
+fn pick_monitor(which: Capture, monitors: &[MonitorInfo], point: Option<(i32, i32)>) -> Result<usize, String> {
+    if monitors.is_empty() { return Err("No monitor found".to_string()); }
+    match which {
+        Capture::Monitor(i) if i < monitors.len() => Ok(i),
+        Capture::Monitor(i) => Err(format!("No monitor {} ({} found)", i, monitors.len())),
+        Capture::ActiveWindow | Capture::All => {
+            let contains = |m: &&MonitorInfo, (x, y): (i32, i32)| x >= m.x && y >= m.y && x < m.x + m.width as i32 && y < m.y + m.height as i32;
+            let active = point.and_then(|p| monitors.iter().find(|m| contains(m, p)));
+            Ok(active.or_else(|| monitors.iter().find(|m| m.primary)).map_or(0, |m| m.index))
+        }
+    }
+}
+
+// Monitors can sit left of or above the primary one, so positions are offset from the top-left-most edge.
+// Each capture's own size is used, which keeps a scaled monitor from overlapping its neighbour.
+fn stitch(monitors: &[Monitor]) -> Result<RgbaImage, String> {
+    let shots = monitors.iter().map(|m| m.capture_image().map(|img| (m.x(), m.y(), img))).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+    let left = shots.iter().map(|(x, _, _)| *x).min().unwrap_or(0);
+    let top = shots.iter().map(|(_, y, _)| *y).min().unwrap_or(0);
+    let right = shots.iter().map(|(x, _, img)| x + img.width() as i32).max().unwrap_or(0);
+    let bottom = shots.iter().map(|(_, y, img)| y + img.height() as i32).max().unwrap_or(0);
+    let mut canvas = RgbaImage::new((right - left) as u32, (bottom - top) as u32);
+    for (x, y, img) in &shots {
+        image::imageops::replace(&mut canvas, img, (x - left) as i64, (y - top) as i64);
+    }
+    Ok(canvas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1920x1080 primary monitor with a 2560x1440 one to its left, as Windows reports them
+    fn two_monitors() -> Vec<MonitorInfo> {
+        let monitor = |index: usize, x: i32, width: u32, height: u32, primary: bool| MonitorInfo { index, name: format!("DISPLAY{}", index + 1), x, y: 0, width, height, primary };
+        vec![monitor(0, -2560, 2560, 1440, false), monitor(1, 0, 1920, 1080, true)]
+    }
+
+    #[test]
+    fn a_monitor_index_selects_that_monitor() {
+        assert!(matches!(Capture::from_params(Some(0), false), Capture::Monitor(0)));
+        assert!(matches!(Capture::from_params(Some(1), true), Capture::All));
+        assert!(matches!(Capture::from_params(None, false), Capture::ActiveWindow));
+        let monitors = two_monitors();
+        assert_eq!(pick_monitor(Capture::Monitor(0), &monitors, Some((100, 100))), Ok(0));
+        assert_eq!(pick_monitor(Capture::Monitor(2), &monitors, None), Err("No monitor 2 (2 found)".to_string()));
+    }
+
+    #[test]
+    fn the_active_window_picks_the_monitor_it_is_on() {
+        let monitors = two_monitors();
+        assert_eq!(pick_monitor(Capture::ActiveWindow, &monitors, Some((-1200, 700))), Ok(0));
+        assert_eq!(pick_monitor(Capture::ActiveWindow, &monitors, Some((960, 540))), Ok(1));
+        // Off every monitor, or not located at all: the primary one
+        assert_eq!(pick_monitor(Capture::ActiveWindow, &monitors, Some((5000, 0))), Ok(1));
+        assert_eq!(pick_monitor(Capture::ActiveWindow, &monitors, None), Ok(1));
+        assert!(pick_monitor(Capture::ActiveWindow, &[], None).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+// Headless mode: `pc-automation-agent --goal "..." [--api-key KEY] [--model NAME] [--max-steps N]
+// [--cdp-host HOST] [--cdp-port PORT]` runs one goal to completion without the GUI, auto-approving every step, and prints the outcome
+// as JSON on stdout. Exit code 0 = completed, 1 = failed, 2 = stopped at max-steps.
+// The key falls back to the active profile, then ANTHROPIC_API_KEY, like the GUI. Chrome is launched
+// unless --cdp-host names a remote debugger.
+// Windows release builds have no console attached, so redirect stdout to capture the result.
+use serde::Serialize;
+use crate::{AppState, Emitter, HistoryEntry};
+
+const DEFAULT_MAX_STEPS: u32 = 25;
+
+#[derive(Serialize)]
+struct RunResult {
+    goal: String,
+    success: bool,
+    completed: bool,
+    steps: usize,
+    error: Option<String>,
+    url: Option<String>,
+    last: Option<HistoryEntry>,
+    result: Option<serde_json::Value>,
+}
+
+fn arg(args: &[String], name: &str) -> Option<String> {
+    args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).cloned()
+}
+
+// None when the process wasn't started with --goal, so the GUI should run
+pub fn run() -> Option<i32> {
+    let args: Vec<String> = std::env::args().collect();
+    let goal = arg(&args, "--goal")?;
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            eprintln!("Cannot start async runtime: {}", e);
+            return Some(1);
+        }
+    };
+    Some(runtime.block_on(run_goal(goal, &args)))
+}
+
+async fn run_goal(goal: String, args: &[String]) -> i32 {
+    let state = AppState::load();
+    if let Some(key) = arg(args, "--api-key") {
+        crate::logging::register_secret(&key);
+        *state.api_key.lock().unwrap() = Some(key);
+    }
+    if let Some(model) = arg(args, "--model") { *state.model.lock().unwrap() = model; }
+    {
+        let mut config = state.execution_config.lock().unwrap();
+        config.approval_mode = "none".to_string();
+        config.max_steps = arg(args, "--max-steps").and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_MAX_STEPS);
+    }
+    // Nobody is around to take over
+    state.handoff.lock().unwrap().enabled = false;
+
+    let endpoint = {
+        let mut endpoint = state.cdp_endpoint.lock().unwrap();
+        if let Some(host) = arg(args, "--cdp-host") { endpoint.host = host; }
+        if let Some(port) = arg(args, "--cdp-port").and_then(|p| p.parse().ok()) { endpoint.port = port; }
+        endpoint.clone()
+    };
+    if endpoint.is_local() {
+        let opts = state.launch_options.lock().unwrap().clone();
+        let launched = tokio::task::spawn_blocking(move || crate::automation::chrome_cdp::launch_chrome_with_debugging(endpoint.port, &opts).map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+        if let Err(e) = launched { log_error!("chrome", "Chrome launch: {}", e); }
+    }
+
+    let events = Emitter(None);
+    let outcome = match crate::start_goal(&events, &state, goal.clone()).await {
+        Ok(_) => crate::run_approved(&events, &state, true).await,
+        Err(e) => Err(e),
+    };
+
+    let last = state.history.lock().unwrap().last().cloned().map(|h| HistoryEntry { screenshot_base64: None, target_screenshot_base64: None, ..h });
+    let completed = last.as_ref().is_some_and(|h| h.success && h.action.action_type == "complete");
+    let result = RunResult {
+        goal,
+        success: outcome.is_ok(),
+        completed,
+        steps: crate::history_len(&state),
+        error: outcome.as_ref().err().cloned(),
+        url: outcome.as_ref().ok().and_then(|s| s.url.clone()),
+        last,
+        result: state.last_result.lock().unwrap().clone(),
+    };
+    println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+    match (outcome.is_ok(), completed) {
+        (true, true) => 0,
+        (true, false) => 2,
+        _ => 1,
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+// Recent entries kept for the UI, adjustable with set_log_buffer_size
+const DEFAULT_LOG_BUFFER_SIZE: usize = 100;
+pub const MAX_LOG_BUFFER_SIZE: usize = 10_000;
+// Daily files kept on disk; older ones are pruned at startup
+const MAX_LOG_FILES: usize = 7;
+// Live entries are batched so a fast scan can't flood the event channel
+const LOG_EVENT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub category: String,
+    pub message: String,
+    #[serde(default)]
+    pub session: String,
+}
+
+static LOG_BUFFER: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+static LOG_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_LOG_BUFFER_SIZE);
+static JSON_CONSOLE: AtomicBool = AtomicBool::new(false);
+// Values scrubbed from every message before it goes anywhere (API keys, stored credentials)
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static SINK: OnceLock<(PathBuf, String)> = OnceLock::new();
+// Entries waiting for the next "log" event; only filled once attach() has run
+static PENDING: Mutex<Vec<LogEntry>> = Mutex::new(Vec::new());
+static APP: OnceLock<AppHandle> = OnceLock::new();
+
+// Enables the file sink for this session; without it entries only reach the console and buffer
+pub fn init(dir: PathBuf, session: &str) {
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("Log directory unavailable, file logging disabled: {}", e);
+        return;
+    }
+    prune(&dir);
+    let _ = SINK.set((dir, session.to_string()));
+}
+
+// Starts streaming entries to the frontend as "log" events, each carrying a batch (array) of entries
+pub fn attach(app: AppHandle) {
+    if APP.set(app).is_err() { return; }
+    std::thread::spawn(|| loop {
+        std::thread::sleep(LOG_EVENT_INTERVAL);
+        let batch = std::mem::take(&mut *PENDING.lock().unwrap());
+        if batch.is_empty() { continue; }
+        if let Some(app) = APP.get() {
+            let _ = app.emit_all("log", batch);
+        }
+    });
+}
+
+fn prune(dir: &PathBuf) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut files: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).filter(|p| p.extension().map_or(false, |x| x == "jsonl")).collect();
+    // Names embed the date, so lexical order is chronological
+    files.sort();
+    if files.len() > MAX_LOG_FILES {
+        for old in &files[..files.len() - MAX_LOG_FILES] {
+            let _ = std::fs::remove_file(old);
+        }
+    }
+}
+
+fn file_for_today() -> Option<PathBuf> {
+    SINK.get().map(|(dir, _)| dir.join(format!("agent-{}.jsonl", chrono::Local::now().format("%Y-%m-%d"))))
+}
+
+pub fn set_json_console(json: bool) {
+    JSON_CONSOLE.store(json, Ordering::Relaxed);
+}
+
+pub fn register_secret(secret: &str) {
+    // Very short values would redact half of every message
+    if secret.len() < 4 { return; }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|s| s == secret) { secrets.push(secret.to_string()); }
+}
+
+pub fn redact(message: &str) -> String {
+    let mut out = message.to_string();
+    for secret in SECRETS.lock().unwrap().iter() {
+        out = out.replace(secret.as_str(), "[redacted]");
+    }
+    // Catch Anthropic keys that were never registered (e.g. pasted into a goal)
+    while let Some(start) = out.find("sk-ant-") {
+        let end = out[start..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_')).map_or(out.len(), |i| start + i);
+        out.replace_range(start..end, "[redacted]");
+    }
+    out
+}
+
+pub fn log_action(level: &str, category: &str, message: impl AsRef<str>) {
+    let entry = LogEntry {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        level: level.to_string(),
+        category: category.to_string(),
+        message: redact(message.as_ref()),
+        session: SINK.get().map(|(_, s)| s.clone()).unwrap_or_default(),
+    };
+    let json = serde_json::to_string(&entry).unwrap_or_default();
+
+    if JSON_CONSOLE.load(Ordering::Relaxed) {
+        eprintln!("{}", json);
+    } else {
+        eprintln!("{} [{}] {}: {}", entry.timestamp, entry.level, entry.category, entry.message);
+    }
+
+    if let Some(path) = file_for_today() {
+        if let Ok(mut f) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            let _ = writeln!(f, "{}", json);
+        }
+    }
+
+    if APP.get().is_some() {
+        PENDING.lock().unwrap().push(entry.clone());
+    }
+
+    let mut buffer = LOG_BUFFER.lock().unwrap();
+    buffer.push_back(entry);
+    let cap = LOG_BUFFER_SIZE.load(Ordering::Relaxed);
+    while buffer.len() > cap { buffer.pop_front(); }
+}
+
+// Shrinking drops the oldest entries immediately
+pub fn set_buffer_size(size: usize) {
+    LOG_BUFFER_SIZE.store(size, Ordering::Relaxed);
+    let mut buffer = LOG_BUFFER.lock().unwrap();
+    while buffer.len() > size { buffer.pop_front(); }
+}
+
+pub fn buffered() -> Vec<LogEntry> {
+    LOG_BUFFER.lock().unwrap().iter().cloned().collect()
+}
+
+fn level_rank(level: &str) -> u8 {
+    match level { "debug" => 0, "info" => 1, "warn" => 2, "error" => 3, _ => 1 }
+}
+
+// Keeps entries at or above min_level and in category, then the newest `limit`
+pub fn filter(entries: Vec<LogEntry>, limit: Option<usize>, min_level: Option<&str>, category: Option<&str>) -> Vec<LogEntry> {
+    let min = min_level.map_or(0, level_rank);
+    let mut out: Vec<LogEntry> = entries.into_iter()
+        .filter(|e| level_rank(&e.level) >= min && category.map_or(true, |c| e.category == c))
+        .collect();
+    if let Some(limit) = limit {
+        out.drain(..out.len().saturating_sub(limit));
+    }
+    out
+}
+
+// Every entry this session wrote to disk, across day rollovers
+pub fn session_from_disk() -> Result<Vec<LogEntry>, String> {
+    let Some((dir, session)) = SINK.get() else { return Err("File logging is not enabled".to_string()) };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir).map_err(|e| e.to_string())?.filter_map(|e| e.ok().map(|e| e.path())).filter(|p| p.extension().map_or(false, |x| x == "jsonl")).collect();
+    files.sort();
+    let mut out = Vec::new();
+    for file in files {
+        let text = std::fs::read_to_string(&file).map_err(|e| e.to_string())?;
+        out.extend(text.lines().filter_map(|l| serde_json::from_str::<LogEntry>(l).ok()).filter(|e| &e.session == session));
+    }
+    Ok(out)
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($cat:expr, $($arg:tt)*) => { $crate::logging::log_action("info", $cat, format!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($cat:expr, $($arg:tt)*) => { $crate::logging::log_action("warn", $cat, format!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($cat:expr, $($arg:tt)*) => { $crate::logging::log_action("error", $cat, format!($($arg)*)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(level: &str, category: &str, message: &str) -> LogEntry {
+        LogEntry { timestamp: String::new(), level: level.to_string(), category: category.to_string(), message: message.to_string(), session: String::new() }
+    }
+
+    #[test]
+    fn buffer_never_exceeds_the_configured_size() {
+        set_buffer_size(5);
+        for i in 0..20 {
+            log_action("info", "buffer-test", format!("entry {}", i));
+            assert!(buffered().len() <= 5);
+        }
+        let ours: Vec<String> = buffered().into_iter().filter(|e| e.category == "buffer-test").map(|e| e.message).collect();
+        assert_eq!(ours.last().map(String::as_str), Some("entry 19"));
+        set_buffer_size(2);
+        assert!(buffered().len() <= 2);
+        set_buffer_size(DEFAULT_LOG_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn filter_keeps_the_newest_entries_at_or_above_the_level() {
+        let entries = vec![entry("debug", "cdp", "a"), entry("warn", "cdp", "b"), entry("error", "llm", "c"), entry("info", "cdp", "d")];
+        let messages = |out: Vec<LogEntry>| out.into_iter().map(|e| e.message).collect::<Vec<_>>();
+        assert_eq!(messages(filter(entries.clone(), None, Some("info"), Some("cdp"))), vec!["b", "d"]);
+        assert_eq!(messages(filter(entries.clone(), Some(2), None, None)), vec!["c", "d"]);
+        assert_eq!(messages(filter(entries, None, Some("error"), None)), vec!["c"]);
+    }
+}
@@ -1,17 +1,49 @@
 ﻿#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+#[macro_use]
+mod logging;
+mod api;
 mod automation;
 mod ai;
+mod cli;
+mod mcp;
+mod narration;
+mod playwright;
+mod plugins;
+mod safety;
+mod secrets;
+mod skills;
+mod timing;
+mod undo;
 
-use std::sync::Mutex;
-use tauri::{Manager, State};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, GlobalShortcutManager, Manager, RunEvent, State, Window};
 use serde::{Deserialize, Serialize};
+use safety::{ActionFilter, SafetyPolicy};
+use secrets::{Profile, ProfileStore, Secret};
+use ai::claude::{LLMError, LlmConfig};
+use automation::chrome_cdp::{BannerConfig, BrowserSession, CdpEndpoint, ChromeConnection, EmulationConfig, HandoffConfig, LaunchOptions, LocaleConfig, NetworkConditions, PdfOptions, PickedTarget, SettleConfig, TabInfo};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ActionCommand { 
     pub action_type: String, 
     pub target: serde_json::Value, 
     pub params: Option<serde_json::Value>, 
-    pub reasoning: Option<String> 
+    pub reasoning: Option<String>,
+    // Filled in by the client after parsing, never by the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm_cost: Option<ai::claude::LlmCost>,
+    // Planning mode: the model sets this on the action that finishes the current plan step
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub step_done: bool,
+    // Model's own 0-1 estimate; None when it didn't say
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    // Other candidates the model considered, offered to the user when it is unsure
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<ActionCommand>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,7 +53,12 @@ pub struct ExecutionState {
     pub active_window: String, 
     pub url: Option<String>, 
     pub success: bool, 
-    pub error: Option<String> 
+    pub error: Option<String>,
+    // Open tabs in /json order and the index of the one actions go to
+    #[serde(default)]
+    pub tabs: Vec<TabInfo>,
+    #[serde(default)]
+    pub active_tab: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,142 +68,2210 @@ pub struct HistoryEntry {
     pub llm_reasoning: String, 
     pub action: ActionCommand, 
     pub success: bool, 
-    pub error: Option<String> 
+    pub error: Option<String>,
+    #[serde(default)]
+    pub screenshot_base64: Option<String>,
+    #[serde(default)]
+    pub screenshot_path: Option<String>,
+    #[serde(default)]
+    pub output: Option<String>,
+    // Crop around the failed action's target, captured when a step gives up
+    #[serde(default)]
+    pub target_screenshot_base64: Option<String>,
+    #[serde(default)]
+    pub confidence: Option<f32>,
+}
+
+// max_steps = 1 keeps the classic "approve one action" flow; raise it to let the agent continue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionConfig {
+    pub approval_mode: String,
+    pub max_steps: u32,
+    // Outline the goal with one LLM call before the first action
+    #[serde(default)]
+    pub planning_mode: bool,
+    // Keep working through the goal queue when a goal fails after all retries
+    #[serde(default)]
+    pub continue_on_error: bool,
+    // Actions the model rates below this pause for the user to confirm or pick an alternative.
+    // 0 never pauses, and neither does an action without a confidence.
+    #[serde(default)]
+    pub confidence_threshold: f32,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self { approval_mode: "first".to_string(), max_steps: 1, planning_mode: false, continue_on_error: false, confidence_threshold: 0.0 }
+    }
+}
+
+// steps[current] is the step being worked on. reactive is set once a step failed for good;
+// from then on the plan is kept for reference but no longer steers prompts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub steps: Vec<String>,
+    pub current: usize,
+    pub reactive: bool,
+}
+
+// A mouse_down still waiting for its mouse_up; screen is true for OS-level (screen:x,y) presses
+#[derive(Debug, Clone)]
+pub struct HeldMouse {
+    pub button: String,
+    pub x: f64,
+    pub y: f64,
+    pub screen: bool,
+}
+
+impl ExecutionConfig {
+    pub fn requires_approval(&self, action: &ActionCommand) -> bool {
+        match self.approval_mode.as_str() {
+            "every" => true,
+            "destructive" => safety::DESTRUCTIVE_ACTIONS.contains(&action.action_type.as_str()),
+            _ => false,
+        }
+    }
+
+    pub fn is_unsure(&self, action: &ActionCommand) -> bool {
+        self.approval_mode != "none" && action.confidence.is_some_and(|c| c < self.confidence_threshold)
+    }
+}
+
+// What the user decided for a step paused in await_step_approval
+#[derive(Debug, Clone, Copy)]
+pub enum StepDecision {
+    Approve,
+    Reject,
+    // Index into the paused action's alternatives
+    Alternative(usize),
+}
+
+// Where loop events go: the webview, or nowhere when running headless
+pub struct Emitter(Option<Window>);
+
+impl Emitter {
+    pub fn emit<S: Serialize + Clone>(&self, event: &str, payload: S) {
+        if let Some(window) = &self.0 { let _ = window.emit(event, payload); }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub step: u32,
+    pub attempt: u32,
+    pub action: ActionCommand,
+    pub success: bool,
+    pub error: Option<String>,
+    pub active_window: Option<String>,
+    pub url: Option<String>,
+    pub screenshot_base64: Option<String>,
+    pub goal: Option<String>,
+    // params.result of a successful complete step
+    pub result: Option<serde_json::Value>,
 }
 
 pub struct AppState {
     pub api_key: Mutex<Option<String>>,
+    pub model: Mutex<String>,
+    pub max_tokens: Mutex<u32>,
     pub history: Mutex<Vec<HistoryEntry>>,
+    // Entries kept in memory; older ones move to the session's history.jsonl (see push_history)
+    pub history_limit: Mutex<usize>,
+    pub archived_history: Mutex<ArchivedHistory>,
     pub pending_action: Mutex<Option<ActionCommand>>,
     pub current_goal: Mutex<Option<String>>,
+    pub last_progress_image: Mutex<Option<Instant>>,
+    pub ocr_fallback: Mutex<bool>,
+    pub full_page_screenshots: Mutex<bool>,
+    pub banners: Mutex<BannerConfig>,
+    pub handoff: Mutex<HandoffConfig>,
+    pub human_handoff: Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
+    // Set by pause_execution; the loop parks on resume_signal before its next LLM call
+    pub paused: Mutex<bool>,
+    // From send_hint; goes into the next prompt only
+    pub pending_hint: Mutex<Option<String>>,
+    // Hints already used, for get_hint_log
+    pub hint_log: Mutex<Vec<HintRecord>>,
+    // OS foreground window after the last step, to spot focus stolen by a popup (Windows only)
+    pub last_foreground: Mutex<Option<String>>,
+    // params.result of the current goal's complete step, for get_last_result
+    pub last_result: Mutex<Option<serde_json::Value>>,
+    // Tabs the last action opened, noted in the next prompt only
+    pub opened_tabs: Mutex<Vec<TabInfo>>,
+    // Follow a tab opened by an action instead of only reporting it
+    pub auto_switch_tabs: Mutex<bool>,
+    // Debug aid: outline each click point just before pressing
+    pub highlight_actions: Mutex<bool>,
+    // Longest a single action may run before it counts as failed
+    pub action_timeout_secs: Mutex<u64>,
+    // Pause before a failed action's retry, multiplied by the attempt number (see retry_delay)
+    pub retry_base_delay_ms: Mutex<u64>,
+    pub resume_signal: tokio::sync::Notify,
+    // label -> login; values only ever flow into fill_credential, never into prompts or history
+    pub credentials: Mutex<HashMap<String, Secret>>,
+    pub profiles: Mutex<ProfileStore>,
+    pub store_step_screenshots: Mutex<bool>,
+    // Numbers step screenshot files so none is overwritten within a session
+    pub screenshot_seq: AtomicUsize,
+    pub session_id: String,
+    // Shared with each ChromeConnection so typing can stop mid-string
+    pub cancel_requested: Arc<AtomicBool>,
+    pub panic_hotkey: Mutex<String>,
+    pub safety: Mutex<SafetyPolicy>,
+    pub action_filter: Mutex<ActionFilter>,
+    // Left out of goal keywords when ranking the a11y tree for a prompt
+    pub stop_words: Mutex<Vec<String>>,
+    pub execution_config: Mutex<ExecutionConfig>,
+    pub step_approval: Mutex<Option<tokio::sync::oneshot::Sender<StepDecision>>>,
+    pub consecutive_rejections: Mutex<u32>,
+    pub history_summary: Mutex<Option<HistorySummary>>,
+    pub summarize_after: Mutex<usize>,
+    pub emulation: Mutex<Option<EmulationConfig>>,
+    pub network_conditions: Mutex<Option<NetworkConditions>>,
+    pub locale_override: Mutex<Option<LocaleConfig>>,
+    // Requests matching these are failed while the agent is connected; empty = no interception
+    pub blocked_url_patterns: Mutex<Vec<String>>,
+    // Milliseconds between typed characters; 0 inserts text in one go
+    pub typing_delay_ms: Mutex<u64>,
+    // get_page_text keeps this many characters
+    pub page_text_chars: Mutex<usize>,
+    // Page zoom set by the zoom action, re-applied on every connection since navigation resets it
+    pub zoom_level: Mutex<f64>,
+    // Send screenshots to the LLM where a call supports them (currently retries)
+    pub vision_mode: Mutex<bool>,
+    pub ui_settle: Mutex<SettleConfig>,
+    // Speak each step aloud (see narration)
+    pub narration: Mutex<bool>,
+    // Benchmark mode: finished steps of the current run, and the one being timed
+    pub benchmark_mode: Mutex<bool>,
+    pub step_timings: Mutex<Vec<timing::StepTiming>>,
+    pub open_timing: Mutex<timing::StepTiming>,
+    pub launch_options: Mutex<LaunchOptions>,
+    pub plan: Mutex<Option<Plan>>,
+    // Goals started automatically, in order, each time the running goal completes
+    pub goal_queue: Mutex<VecDeque<String>>,
+    // History index where the current queued goal began; prompts only see entries from here on
+    pub history_checkpoint: Mutex<usize>,
+    // Port of the local HTTP API once started
+    pub api_port: Mutex<Option<u16>>,
+    pub completion_webhook: Mutex<Option<String>>,
+    // Custom actions, checked before the built-in ones
+    pub actions: Mutex<plugins::ActionRegistry>,
+    pub held_mouse: Mutex<Option<HeldMouse>>,
+    // WebSocket URL of the tab connect_browser attaches to, and when it was looked up
+    pub cdp_target: Mutex<Option<(String, Instant)>>,
+    // Target id of the tab actions go to; None follows the first tab
+    pub active_tab: Mutex<Option<String>>,
+    pub cdp_endpoint: Mutex<CdpEndpoint>,
+}
+
+// Shared by the GUI and headless runs: starts the session log and loads keychain profiles and credentials
+impl AppState {
+    pub fn load() -> Self {
+        let session_id = chrono::Utc::now().format("%Y%m%d-%H%M%S").to_string();
+        if let Ok(dir) = app_dir() { logging::init(dir.join("logs"), &session_id); }
+        let profiles = secrets::load_profiles();
+        let credentials = secrets::load_credentials();
+        // Registered before anything can log them
+        for p in &profiles.profiles { logging::register_secret(&p.api_key); }
+        for c in credentials.values() {
+            logging::register_secret(&c.username);
+            logging::register_secret(&c.password);
+        }
+        let mut actions = plugins::ActionRegistry::default();
+        if let Ok(dir) = app_dir() { plugins::register_actions(&mut actions, &dir); }
+        Self::new(session_id, profiles, credentials, actions)
+    }
+
+    // Everything at its default, without load's logging, keyring and plugin side effects
+    fn new(session_id: String, profiles: ProfileStore, credentials: HashMap<String, Secret>, actions: plugins::ActionRegistry) -> Self {
+        let active = profiles.active().cloned();
+        AppState {
+            api_key: Mutex::new(None),
+            model: Mutex::new(active.as_ref().map_or(ai::claude::DEFAULT_MODEL.to_string(), |p| p.model.clone())),
+            max_tokens: Mutex::new(active.as_ref().map_or(ai::claude::DEFAULT_MAX_TOKENS, |p| p.max_tokens)),
+            history: Mutex::new(Vec::new()),
+            pending_action: Mutex::new(None),
+            current_goal: Mutex::new(None),
+            last_progress_image: Mutex::new(None),
+            ocr_fallback: Mutex::new(false),
+            full_page_screenshots: Mutex::new(false),
+            banners: Mutex::new(BannerConfig::default()),
+            handoff: Mutex::new(HandoffConfig::default()),
+            human_handoff: Mutex::new(None),
+            paused: Mutex::new(false),
+            pending_hint: Mutex::new(None),
+            hint_log: Mutex::new(Vec::new()),
+            last_foreground: Mutex::new(None),
+            last_result: Mutex::new(None),
+            opened_tabs: Mutex::new(Vec::new()),
+            auto_switch_tabs: Mutex::new(false),
+            highlight_actions: Mutex::new(false),
+            action_timeout_secs: Mutex::new(DEFAULT_ACTION_TIMEOUT_SECS),
+            retry_base_delay_ms: Mutex::new(DEFAULT_RETRY_BASE_DELAY_MS),
+            resume_signal: tokio::sync::Notify::new(),
+            credentials: Mutex::new(credentials),
+            profiles: Mutex::new(profiles),
+            store_step_screenshots: Mutex::new(false),
+            screenshot_seq: AtomicUsize::new(0),
+            session_id,
+            cancel_requested: Arc::new(AtomicBool::new(false)),
+            panic_hotkey: Mutex::new(DEFAULT_PANIC_HOTKEY.to_string()),
+            safety: Mutex::new(SafetyPolicy::default()),
+            action_filter: Mutex::new(ActionFilter::default()),
+            stop_words: Mutex::new(ai::claude::DEFAULT_STOP_WORDS.iter().map(|w| w.to_string()).collect()),
+            execution_config: Mutex::new(ExecutionConfig::default()),
+            step_approval: Mutex::new(None),
+            consecutive_rejections: Mutex::new(0),
+            history_summary: Mutex::new(None),
+            history_limit: Mutex::new(DEFAULT_HISTORY_LIMIT),
+            archived_history: Mutex::new(ArchivedHistory::default()),
+            summarize_after: Mutex::new(DEFAULT_SUMMARIZE_AFTER),
+            emulation: Mutex::new(None),
+            network_conditions: Mutex::new(None),
+            locale_override: Mutex::new(None),
+            blocked_url_patterns: Mutex::new(Vec::new()),
+            typing_delay_ms: Mutex::new(0),
+            page_text_chars: Mutex::new(DEFAULT_PAGE_TEXT_CHARS),
+            zoom_level: Mutex::new(1.0),
+            vision_mode: Mutex::new(false),
+            ui_settle: Mutex::new(SettleConfig::default()),
+            narration: Mutex::new(false),
+            benchmark_mode: Mutex::new(false),
+            step_timings: Mutex::new(Vec::new()),
+            open_timing: Mutex::new(timing::StepTiming::default()),
+            launch_options: Mutex::new(LaunchOptions::default()),
+            plan: Mutex::new(None),
+            goal_queue: Mutex::new(VecDeque::new()),
+            history_checkpoint: Mutex::new(0),
+            api_port: Mutex::new(None),
+            completion_webhook: Mutex::new(None),
+            actions: Mutex::new(actions),
+            held_mouse: Mutex::new(None),
+            cdp_target: Mutex::new(None),
+            active_tab: Mutex::new(None),
+            cdp_endpoint: Mutex::new(CdpEndpoint::default()),
+        }
+    }
+}
+
+// A send_hint note as it was used. Kept apart from history, which only holds executed actions.
+#[derive(Debug, Clone, Serialize)]
+pub struct HintRecord {
+    pub timestamp: String,
+    pub goal: Option<String>,
+    // 1-based step the hint went into the prompt for, counted like get_step_screenshot
+    pub step: usize,
+    pub text: String,
+}
+
+// History entries moved out of memory by the cap. They were the oldest, so step n (1-based) sits at
+// index n - 1 - count of the in-memory history; indexes like history_checkpoint count them too.
+#[derive(Debug, Clone, Default)]
+pub struct ArchivedHistory {
+    pub count: usize,
+    pub path: Option<String>,
+}
+
+// Cached condensation of the history entries older than the recent window
+#[derive(Debug, Clone)]
+pub struct HistorySummary {
+    pub covered: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionStats {
+    pub action_type: String,
+    pub total: usize,
+    pub succeeded: usize,
+    pub success_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeCost {
+    pub mode: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CostlyStep {
+    pub step: usize,
+    pub action_type: String,
+    pub cost_usd: f64,
+}
+
+// A history entry with its 1-based step number, as search and filter results
+#[derive(Debug, Clone, Serialize)]
+pub struct StepEntry {
+    pub step: usize,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    // All steps this session; the fields below cover only those after archived_steps
+    pub steps: usize,
+    pub archived_steps: usize,
+    pub succeeded: usize,
+    pub per_action: Vec<ActionStats>,
+    // First to last history timestamp
+    pub duration_secs: f64,
+    pub avg_step_gap_secs: f64,
+    pub per_mode: Vec<ModeCost>,
+    // 1-based, like get_step_screenshot
+    pub most_expensive_step: Option<CostlyStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", content = "data", rename_all = "snake_case")]
+pub enum ApprovalOutcome {
+    Executed(ExecutionState),
+    Revised(ActionCommand),
+}
+
+// Progress screenshots are downscaled and throttled to keep the event channel light
+const PROGRESS_IMAGE_WIDTH: u32 = 640;
+const PROGRESS_IMAGE_INTERVAL: Duration = Duration::from_millis(1500);
+
+// Below this many a11y nodes the page is treated as opaque (canvas, remote desktop) and OCR kicks in
+const OCR_MIN_NODES: usize = 3;
+
+// Step screenshots older than the last few are written to disk instead of kept in memory
+const INLINE_STEP_SCREENSHOTS: usize = 5;
+
+// Entries shown verbatim in the prompt; older ones are summarized once history passes summarize_after
+const RECENT_HISTORY: usize = 10;
+const DEFAULT_SUMMARIZE_AFTER: usize = 20;
+// Re-summarize once this many more entries have aged out of the recent window
+const SUMMARY_REFRESH: usize = 5;
+// History entries kept in memory before the oldest are archived to disk
+const DEFAULT_HISTORY_LIMIT: usize = 500;
+const MIN_HISTORY_LIMIT: usize = 2 * RECENT_HISTORY;
+
+// Stop re-proposing after this many rejections in a row so feedback can't loop forever
+const MAX_CONSECUTIVE_REJECTIONS: u32 = 3;
+
+const DEFAULT_PANIC_HOTKEY: &str = "CmdOrCtrl+Alt+Escape";
+
+// Keychain entry that held the key before profiles existed; migrated into the default profile
+const LEGACY_API_KEY_ENTRY: &str = "api_key";
+
+// Per attempt; a failed delivery is retried once
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// `--serve[=port]` starts the HTTP API at launch, with the token taken from this variable
+const DEFAULT_API_PORT: u16 = 7878;
+const API_TOKEN_ENV: &str = "AGENT_API_TOKEN";
+
+// How long connect_browser reuses a tab lookup before asking /json again; short, since the user may switch tabs
+const CDP_TARGET_TTL: Duration = Duration::from_secs(5);
+// Actions meant to bring another window or tab to the front
+const FOCUS_ACTIONS: &[&str] = &["alt_tab", "switch_to_app", "focus_window", "switch_tab", "new_tab", "close_tab"];
+const DEFAULT_PAGE_TEXT_CHARS: usize = 8_000;
+const MAX_PAGE_TEXT_CHARS: usize = 100_000;
+// Grace period before re-reading a freshly opened tab that has no title yet
+const NEW_TAB_TITLE_WAIT_MS: u64 = 500;
+
+// A longer settle would stall every step on pages that animate forever
+const MAX_UI_SETTLE_MS: u64 = 30_000;
+
+const DEFAULT_ACTION_TIMEOUT_SECS: u64 = 20;
+const MAX_ACTION_TIMEOUT_SECS: u64 = 600;
+
+// Model calls per request, counting the first; see ask_llm
+const MAX_LLM_ATTEMPTS: u32 = 3;
+const LLM_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_LLM_BACKOFF: Duration = Duration::from_secs(60);
+
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 300;
+// No retry waits longer than this, jitter included
+const MAX_RETRY_DELAY_MS: u64 = 3_000;
+
+const DEFAULT_PICK_SECS: u64 = 30;
+const MAX_PICK_SECS: u64 = 120;
+
+const ZOOM_STEP: f64 = 1.25;
+const MIN_ZOOM: f64 = 0.25;
+const MAX_ZOOM: f64 = 5.0;
+
+fn app_dir() -> Result<std::path::PathBuf, String> {
+    Ok(dirs::config_dir().ok_or("No config dir")?.join("pc-automation-agent"))
 }
 
+// Single-key API: stores the key in the "default" profile and makes it active
 #[tauri::command]
 async fn save_api_key(key: String, state: State<'_, AppState>) -> Result<(), String> {
-    *state.api_key.lock().unwrap() = Some(key.clone());
-    let config_dir = dirs::config_dir().ok_or("No config dir")?.join("pc-automation-agent");
-    std::fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
-    std::fs::write(config_dir.join("config.json"), serde_json::json!({"api_key": key}).to_string()).map_err(|e| e.to_string())?;
-    Ok(())
+    let profile = Profile {
+        name: secrets::DEFAULT_PROFILE.to_string(),
+        api_key: key,
+        model: state.model.lock().unwrap().clone(),
+        max_tokens: *state.max_tokens.lock().unwrap(),
+    };
+    store_profile(&state, profile, true)
+}
+
+// Persists a profile, and applies it to the live settings when it is (or becomes) the active one
+fn store_profile(state: &AppState, profile: Profile, activate: bool) -> Result<(), String> {
+    let mut store = state.profiles.lock().unwrap();
+    if activate { store.active = Some(profile.name.clone()); }
+    if store.active.as_deref() == Some(profile.name.as_str()) {
+        apply_profile(state, &profile);
+    }
+    store.upsert(profile);
+    secrets::save_profiles(&store)
+}
+
+fn apply_profile(state: &AppState, profile: &Profile) {
+    logging::register_secret(&profile.api_key);
+    *state.api_key.lock().unwrap() = Some(profile.api_key.clone());
+    *state.model.lock().unwrap() = profile.model.clone();
+    *state.max_tokens.lock().unwrap() = profile.max_tokens;
+}
+
+#[tauri::command]
+async fn save_profile(name: String, api_key: String, model: String, max_tokens: u32, state: State<'_, AppState>) -> Result<(), String> {
+    let name = name.trim().to_string();
+    let model = model.trim().to_string();
+    if name.is_empty() { return Err("Profile name cannot be empty".to_string()); }
+    if model.is_empty() { return Err("Model name cannot be empty".to_string()); }
+    if !(1..=ai::claude::MAX_TOKENS_LIMIT).contains(&max_tokens) {
+        return Err(format!("max_tokens must be between 1 and {}", ai::claude::MAX_TOKENS_LIMIT));
+    }
+    store_profile(&state, Profile { name, api_key, model, max_tokens }, false)
+}
+
+// A run already in progress keeps the LlmConfig it started with; the switch applies from the next command
+#[tauri::command]
+async fn activate_profile(name: String, state: State<'_, AppState>) -> Result<(), String> {
+    let profile = state.profiles.lock().unwrap().get(&name).cloned().ok_or_else(|| format!("No profile named {}", name))?;
+    store_profile(&state, profile, true)
+}
+
+#[derive(Serialize)]
+struct ProfileInfo { name: String, model: String, max_tokens: u32, active: bool }
+
+// Keys are never sent back to the UI
+#[tauri::command]
+async fn list_profiles(state: State<'_, AppState>) -> Result<Vec<ProfileInfo>, String> {
+    let store = state.profiles.lock().unwrap();
+    Ok(store.profiles.iter().map(|p| ProfileInfo {
+        name: p.name.clone(),
+        model: p.model.clone(),
+        max_tokens: p.max_tokens,
+        active: store.active.as_deref() == Some(p.name.as_str()),
+    }).collect())
 }
 
 #[tauri::command]
 async fn load_api_key(state: State<'_, AppState>) -> Result<Option<String>, String> {
+    resolve_api_key(&state)
+}
+
+// Precedence: in-memory (saved this session) > active profile > a legacy key (keychain entry or
+// plaintext config.json, migrated into the default profile) > ANTHROPIC_API_KEY.
+// The env key is cached in memory only, never persisted.
+fn resolve_api_key(state: &AppState) -> Result<Option<String>, String> {
     if let Some(k) = state.api_key.lock().unwrap().clone() { return Ok(Some(k)); }
-    let p = dirs::config_dir().ok_or("No config dir")?.join("pc-automation-agent").join("config.json");
-    if p.exists() {
-        let c: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&p).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
-        if let Some(k) = c["api_key"].as_str() { 
-            *state.api_key.lock().unwrap() = Some(k.to_string()); 
-            return Ok(Some(k.to_string())); 
+    let active = state.profiles.lock().unwrap().active().cloned();
+    if let Some(profile) = active {
+        apply_profile(state, &profile);
+        return Ok(Some(profile.api_key));
+    }
+    if let Some(k) = read_legacy_api_key()? {
+        let profile = Profile {
+            name: secrets::DEFAULT_PROFILE.to_string(),
+            api_key: k.clone(),
+            model: state.model.lock().unwrap().clone(),
+            max_tokens: *state.max_tokens.lock().unwrap(),
+        };
+        store_profile(state, profile, true)?;
+        // Only scrub the old copies once the profile is safely stored
+        remove_legacy_api_key()?;
+        return Ok(Some(k));
+    }
+    let key = std::env::var("ANTHROPIC_API_KEY").ok().filter(|k| !k.trim().is_empty());
+    if let Some(k) = &key {
+        logging::register_secret(k);
+        *state.api_key.lock().unwrap() = Some(k.clone());
+    }
+    Ok(key)
+}
+
+// Older builds kept the key in its own keychain entry or, before that, in plaintext config.json
+fn read_legacy_api_key() -> Result<Option<String>, String> {
+    if let Some(k) = secrets::read(LEGACY_API_KEY_ENTRY)? {
+        return Ok(Some(k));
+    }
+    let p = app_dir()?.join("config.json");
+    if !p.exists() { return Ok(None); }
+    let c: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&p).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    Ok(c["api_key"].as_str().map(|k| k.to_string()))
+}
+
+fn remove_legacy_api_key() -> Result<(), String> {
+    secrets::delete(LEGACY_API_KEY_ENTRY)?;
+    let p = app_dir()?.join("config.json");
+    if !p.exists() { return Ok(()); }
+    let mut c: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&p).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    if let Some(obj) = c.as_object_mut() { obj.remove("api_key"); }
+    if c.as_object().map_or(true, |o| o.is_empty()) {
+        std::fs::remove_file(&p).map_err(|e| e.to_string())?;
+    } else {
+        std::fs::write(&p, c.to_string()).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn llm_config(state: &AppState) -> Result<LlmConfig, String> {
+    Ok(LlmConfig {
+        api_key: resolve_api_key(state)?.ok_or("API key not set")?,
+        model: state.model.lock().unwrap().clone(),
+        max_tokens: *state.max_tokens.lock().unwrap(),
+        extra_actions: state.actions.lock().unwrap().describe(),
+        vision: *state.vision_mode.lock().unwrap(),
+        action_filter: state.action_filter.lock().unwrap().clone(),
+        stop_words: state.stop_words.lock().unwrap().clone(),
+    })
+}
+
+// After each action the UI is polled every interval_ms until it stops changing, for at most timeout_ms
+#[tauri::command]
+async fn set_ui_settle(interval_ms: u64, timeout_ms: u64, state: State<'_, AppState>) -> Result<SettleConfig, String> {
+    if interval_ms == 0 || interval_ms > timeout_ms { return Err("interval_ms must be between 1 and timeout_ms".to_string()); }
+    if timeout_ms > MAX_UI_SETTLE_MS { return Err(format!("timeout_ms must be at most {}", MAX_UI_SETTLE_MS)); }
+    let cfg = SettleConfig { interval_ms, timeout_ms };
+    *state.ui_settle.lock().unwrap() = cfg.clone();
+    Ok(cfg)
+}
+
+#[tauri::command]
+async fn set_highlight_actions(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.highlight_actions.lock().unwrap() = enabled;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_auto_switch_tabs(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.auto_switch_tabs.lock().unwrap() = enabled;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_narration(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.narration.lock().unwrap() = enabled;
+    Ok(())
+}
+
+fn narrate(state: &AppState, text: &str) {
+    if *state.narration.lock().unwrap() { narration::say(text); }
+}
+
+// Screenshots cost input tokens, so vision is opt-in
+#[tauri::command]
+async fn set_vision_mode(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.vision_mode.lock().unwrap() = enabled;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_model(name: String, state: State<'_, AppState>) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() { return Err("Model name cannot be empty".to_string()); }
+    *state.model.lock().unwrap() = name.to_string();
+    update_active_profile(&state, |p| p.model = name.to_string())
+}
+
+// Keeps the active profile in step with the single-setting commands
+fn update_active_profile(state: &AppState, update: impl FnOnce(&mut Profile)) -> Result<(), String> {
+    let mut store = state.profiles.lock().unwrap();
+    let Some(active) = store.active.clone() else { return Ok(()); };
+    let Some(profile) = store.profiles.iter_mut().find(|p| p.name == active) else { return Ok(()); };
+    update(profile);
+    secrets::save_profiles(&store)
+}
+
+#[tauri::command]
+async fn set_max_tokens(n: u32, state: State<'_, AppState>) -> Result<(), String> {
+    if !(1..=ai::claude::MAX_TOKENS_LIMIT).contains(&n) {
+        return Err(format!("max_tokens must be between 1 and {}", ai::claude::MAX_TOKENS_LIMIT));
+    }
+    *state.max_tokens.lock().unwrap() = n;
+    update_active_profile(&state, |p| p.max_tokens = n)
+}
+
+#[tauri::command]
+async fn get_current_state(state: State<'_, AppState>) -> Result<ExecutionState, String> {
+    get_browser_state(&state).await
+}
+
+#[tauri::command]
+async fn set_ocr_fallback(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.ocr_fallback.lock().unwrap() = enabled;
+    Ok(())
+}
+
+// Step state captures the whole page instead of the viewport; applies to previews and stored screenshots
+#[tauri::command]
+async fn get_banner_config(state: State<'_, AppState>) -> Result<BannerConfig, String> {
+    Ok(state.banners.lock().unwrap().clone())
+}
+
+// Enables cookie/consent auto-dismissal after navigate and/or replaces the phrase list
+#[tauri::command]
+async fn set_banner_config(config: BannerConfig, state: State<'_, AppState>) -> Result<(), String> {
+    *state.banners.lock().unwrap() = config;
+    Ok(())
+}
+
+// "text" (default) or "json" lines on the console; the file sink is always JSON
+#[tauri::command]
+async fn set_log_format(format: String) -> Result<(), String> {
+    match format.as_str() {
+        "text" => logging::set_json_console(false),
+        "json" => logging::set_json_console(true),
+        other => return Err(format!("Unknown log format: {}", other)),
+    }
+    Ok(())
+}
+
+// Recent in-memory entries, or with from_disk everything this session wrote to the log files.
+// level_filter is a minimum ("warn" returns warn and error); limit keeps the newest entries.
+#[tauri::command]
+async fn get_logs(limit: Option<usize>, level_filter: Option<String>, category: Option<String>, from_disk: Option<bool>) -> Result<Vec<logging::LogEntry>, String> {
+    let entries = if from_disk.unwrap_or(false) { logging::session_from_disk()? } else { logging::buffered() };
+    Ok(logging::filter(entries, limit, level_filter.as_deref(), category.as_deref()))
+}
+
+#[tauri::command]
+async fn set_log_buffer_size(n: usize) -> Result<(), String> {
+    if !(1..=logging::MAX_LOG_BUFFER_SIZE).contains(&n) {
+        return Err(format!("Log buffer size must be between 1 and {}", logging::MAX_LOG_BUFFER_SIZE));
+    }
+    logging::set_buffer_size(n);
+    Ok(())
+}
+
+// Timings are kept per run: starting a goal clears them
+#[tauri::command]
+async fn set_benchmark_mode(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.benchmark_mode.lock().unwrap() = enabled;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_timing_report(state: State<'_, AppState>) -> Result<timing::TimingReport, String> {
+    Ok(timing::report(&state.step_timings.lock().unwrap()))
+}
+
+#[tauri::command]
+async fn set_full_page_screenshots(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.full_page_screenshots.lock().unwrap() = enabled;
+    Ok(())
+}
+
+#[tauri::command]
+async fn capture_element_screenshot(target: String, state: State<'_, AppState>) -> Result<String, String> {
+    let conn = connect_browser(&state).await?;
+    conn.screenshot_element(&target).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn capture_full_page_screenshot(state: State<'_, AppState>) -> Result<String, String> {
+    let conn = connect_browser(&state).await?;
+    conn.screenshot_full_page().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn save_page_pdf(path: String, options: Option<PdfOptions>, state: State<'_, AppState>) -> Result<String, String> {
+    let conn = connect_browser(&state).await?;
+    let path = std::path::PathBuf::from(path.trim());
+    conn.save_pdf(&path, &options.unwrap_or_default()).await.map_err(|e| format!("PDF export failed: {}", e))?;
+    Ok(path.display().to_string())
+}
+
+// Writes all cookies and the open tab URLs to a JSON file, to skip logging in on later runs. The file
+// holds live sessions, httpOnly cookies included, so it should be kept as private as a password.
+#[tauri::command]
+async fn save_browser_session(path: String, state: State<'_, AppState>) -> Result<String, String> {
+    let conn = connect_browser(&state).await?;
+    let cookies = conn.all_cookies().await.map_err(|e| format!("Reading cookies failed: {}", e))?;
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    let tabs = automation::chrome_cdp::get_tabs(&endpoint).await.map_err(|e| e.to_string())?;
+    let session = BrowserSession { cookies, tab_urls: tabs.into_iter().map(|t| t.url).filter(|u| u.starts_with("http")).collect() };
+    let path = std::path::PathBuf::from(path.trim());
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) { std::fs::create_dir_all(dir).map_err(|e| e.to_string())?; }
+    std::fs::write(&path, serde_json::to_string_pretty(&session).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    log_info!("session", "saved {} cookies and {} tabs to {}", session.cookies.len(), session.tab_urls.len(), path.display());
+    Ok(path.display().to_string())
+}
+
+// Sets the saved cookies first, then opens each saved URL that isn't already open, so the pages load
+// signed in. Returns a one-line summary.
+#[tauri::command]
+async fn restore_browser_session(path: String, state: State<'_, AppState>) -> Result<String, String> {
+    let text = std::fs::read_to_string(path.trim()).map_err(|e| format!("Could not read {}: {}", path.trim(), e))?;
+    let session: BrowserSession = serde_json::from_str(&text).map_err(|e| format!("Not a saved browser session: {}", e))?;
+    let conn = connect_browser(&state).await?;
+    let cookies = conn.set_cookies(&session.cookies).await.map_err(|e| format!("Setting cookies failed: {}", e))?;
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    let open: Vec<String> = automation::chrome_cdp::get_tabs(&endpoint).await.map_err(|e| e.to_string())?.into_iter().map(|t| t.url).collect();
+    let mut opened = 0;
+    for url in session.tab_urls.iter().filter(|u| !open.contains(u)) {
+        conn.new_tab(url).await.map_err(|e| format!("Opening {} failed: {}", url, e))?;
+        opened += 1;
+    }
+    let skipped = session.cookies.len() - cookies;
+    log_info!("session", "restored {} cookies ({} expired or incomplete) and opened {} tabs", cookies, skipped, opened);
+    Ok(format!("Restored {} cookies ({} skipped as expired or incomplete) and opened {} tabs", cookies, skipped, opened))
+}
+
+// Pass a preset name ("iphone", "pixel", "ipad", "desktop") or an explicit config; neither clears emulation
+#[tauri::command]
+async fn configure_emulation(config: Option<EmulationConfig>, preset: Option<String>, state: State<'_, AppState>) -> Result<Option<EmulationConfig>, String> {
+    let cfg = match (preset, config) {
+        (Some(p), _) => Some(EmulationConfig::preset(&p).ok_or(format!("Unknown emulation preset: {}", p))?),
+        (None, cfg) => cfg,
+    };
+    *state.emulation.lock().unwrap() = cfg.clone();
+    Ok(cfg)
+}
+
+// Pass a preset ("slow 3g", "fast 3g", "offline") or explicit conditions; neither turns throttling off.
+// Applied on every connection, so it holds across navigations until changed.
+#[tauri::command]
+async fn configure_network(conditions: Option<NetworkConditions>, preset: Option<String>, state: State<'_, AppState>) -> Result<Option<NetworkConditions>, String> {
+    let conditions = match (preset, conditions) {
+        (Some(p), _) => Some(NetworkConditions::preset(&p).ok_or(format!("Unknown network preset: {}", p))?),
+        (None, c) => c,
+    };
+    *state.network_conditions.lock().unwrap() = conditions.clone();
+    Ok(conditions)
+}
+
+// Fakes geolocation (needs both latitude and longitude), timezone and locale for location-aware sites.
+// None clears all three; they then reset with the next connection.
+#[tauri::command]
+async fn configure_locale(config: Option<LocaleConfig>, state: State<'_, AppState>) -> Result<Option<LocaleConfig>, String> {
+    if let Some(cfg) = &config {
+        if cfg.latitude.is_some() != cfg.longitude.is_some() { return Err("Geolocation needs both latitude and longitude".to_string()); }
+        if cfg.latitude.is_some_and(|l| !(-90.0..=90.0).contains(&l)) || cfg.longitude.is_some_and(|l| !(-180.0..=180.0).contains(&l)) {
+            return Err("Latitude must be within ±90 and longitude within ±180".to_string());
         }
     }
-    Ok(None)
+    *state.locale_override.lock().unwrap() = config.clone();
+    Ok(config)
+}
+
+// Opt-in request blocking: patterns use `*`/`?` wildcards over the whole URL ("*://*.ads.com/*"), and
+// include_ad_list adds the bundled ad/tracker hosts. An empty list turns interception off.
+#[tauri::command]
+async fn set_blocked_url_patterns(patterns: Vec<String>, include_ad_list: Option<bool>, state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let mut all: Vec<String> = patterns.into_iter().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+    if include_ad_list.unwrap_or(false) {
+        all.extend(automation::chrome_cdp::AD_BLOCKLIST.iter().map(|p| p.to_string()));
+    }
+    all.sort();
+    all.dedup();
+    *state.blocked_url_patterns.lock().unwrap() = all.clone();
+    Ok(all)
+}
+
+// Types character by character with real key events, for fields with autocomplete, debouncing or
+// per-keystroke validation that never see a single insertText. Slower: 0 (the default) turns it off.
+#[tauri::command]
+async fn set_typing_delay_ms(ms: u64, state: State<'_, AppState>) -> Result<(), String> {
+    if ms > automation::chrome_cdp::MAX_TYPING_DELAY_MS { return Err(format!("Typing delay must be at most {} ms", automation::chrome_cdp::MAX_TYPING_DELAY_MS)); }
+    *state.typing_delay_ms.lock().unwrap() = ms;
+    Ok(())
+}
+
+// How much of a page get_page_text hands the model; the text goes into one prompt, so keep it modest
+#[tauri::command]
+async fn set_page_text_limit(chars: usize, state: State<'_, AppState>) -> Result<(), String> {
+    if chars == 0 || chars > MAX_PAGE_TEXT_CHARS { return Err(format!("Page text limit must be between 1 and {} characters", MAX_PAGE_TEXT_CHARS)); }
+    *state.page_text_chars.lock().unwrap() = chars;
+    Ok(())
+}
+
+// Stores the launch options and starts Chrome with them
+#[tauri::command]
+async fn set_headless(headless: bool, user_data_dir: Option<String>, ready_timeout_secs: Option<u64>, state: State<'_, AppState>) -> Result<(), String> {
+    let opts = LaunchOptions { headless, user_data_dir: user_data_dir.filter(|d| !d.trim().is_empty()), ready_timeout_secs };
+    *state.launch_options.lock().unwrap() = opts.clone();
+    // A relaunched Chrome has new tab ids
+    *state.cdp_target.lock().unwrap() = None;
+    *state.active_tab.lock().unwrap() = None;
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    if !endpoint.is_local() {
+        return Err(format!("Chrome at {} is remote; launch options only apply to a local Chrome", endpoint.host));
+    }
+    tokio::task::spawn_blocking(move || automation::chrome_cdp::launch_chrome_with_debugging(endpoint.port, &opts).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())?
 }
 
+// Points the agent at another debugger, e.g. Chrome in a container. Returns its tabs so a wrong
+// host or port fails here rather than on the first action; the endpoint is kept either way.
 #[tauri::command]
-async fn get_current_state() -> Result<ExecutionState, String> {
-    get_browser_state().await
+async fn set_cdp_endpoint(host: String, port: u16, state: State<'_, AppState>) -> Result<Vec<TabInfo>, String> {
+    let host = host.trim().to_string();
+    if host.is_empty() || host.contains('/') { return Err(format!("Invalid host: \"{}\"", host)); }
+    if port == 0 { return Err("Port must be between 1 and 65535".to_string()); }
+    let endpoint = CdpEndpoint { host, port };
+    *state.cdp_endpoint.lock().unwrap() = endpoint.clone();
+    *state.cdp_target.lock().unwrap() = None;
+    *state.active_tab.lock().unwrap() = None;
+    log_info!("cdp", "endpoint set to {}:{}", endpoint.host, endpoint.port);
+    automation::chrome_cdp::get_tabs(&endpoint).await.map_err(|e| format!("No Chrome debugger at {}:{}: {}", endpoint.host, endpoint.port, e))
 }
 
 #[tauri::command]
-async fn execute_user_command(command: String, state: State<'_, AppState>) -> Result<ActionCommand, String> {
+async fn execute_user_command(command: String, window: Window, state: State<'_, AppState>) -> Result<ActionCommand, String> {
+    start_goal(&Emitter(Some(window)), &state, command).await
+}
+
+async fn start_goal(events: &Emitter, state: &AppState, command: String) -> Result<ActionCommand, String> {
     *state.current_goal.lock().unwrap() = Some(command.clone());
+    state.cancel_requested.store(false, Ordering::SeqCst);
+    *state.consecutive_rejections.lock().unwrap() = 0;
+    *state.plan.lock().unwrap() = None;
+    *state.paused.lock().unwrap() = false;
+    release_held_mouse(state).await;
+    state.step_timings.lock().unwrap().clear();
+    *state.open_timing.lock().unwrap() = timing::StepTiming::default();
+    *state.last_foreground.lock().unwrap() = foreground_window().await;
+    *state.last_result.lock().unwrap() = None;
     
-    let cs = get_browser_state().await?;
+    let cs = timed(state, timing::Phase::Capture, get_browser_state(state)).await?;
     
     // Get history without holding the lock across await
-    let recent: Vec<HistoryEntry> = {
-        let h = state.history.lock().unwrap();
-        h.iter().rev().take(10).cloned().collect()
-    };
+    let recent = recent_history(state);
     
-    let api_key = state.api_key.lock().unwrap().clone().ok_or("API key not set")?;
+    let llm = llm_config(state)?;
+    if state.execution_config.lock().unwrap().planning_mode {
+        // A failed planning call just means running reactively
+        match ai::claude::make_plan(&llm, &command, &cs).await {
+            Ok(steps) => set_plan(events, state, Plan { steps, current: 0, reactive: false }),
+            Err(e) => log_warn!("plan", "planning failed, running reactively: {}", e),
+        }
+    }
+    let notes = step_notes(state, &llm.api_key, &command).await;
     
-    let action = ai::claude::get_next_action(&api_key, &command, &cs, &recent)
-        .await
-        .map_err(|e| e.to_string())?;
+    let action = timed(state, timing::Phase::Llm, ask_llm(state, || ai::claude::get_next_action(&llm, &command, &cs, &recent, &notes))).await?;
     
     *state.pending_action.lock().unwrap() = Some(action.clone());
     Ok(action)
 }
 
 #[tauri::command]
-async fn approve_action(approved: bool, state: State<'_, AppState>) -> Result<ExecutionState, String> {
+async fn approve_action(approved: bool, window: Window, state: State<'_, AppState>) -> Result<ExecutionState, String> {
+    run_approved(&Emitter(Some(window)), &state, approved).await
+}
+
+// The approve loop; events go to the webview, or nowhere in a headless run
+async fn run_approved(events: &Emitter, state: &AppState, approved: bool) -> Result<ExecutionState, String> {
     if !approved { 
         *state.pending_action.lock().unwrap() = None; 
         return Err("Rejected".to_string()); 
     }
-    
+    let first_entry = history_len(state);
+    let result = approve_loop(events, state).await;
+    // A run that stopped can't send the mouse_up any more
+    if result.is_err() { release_held_mouse(state).await; }
+    // A completed goal was already announced by its complete step
+    let completed = state.history.lock().unwrap().last().is_some_and(|h| h.success && h.action.action_type == "complete");
+    match &result {
+        Ok(_) if !completed => narrate(state, "Stopped at the step limit"),
+        Err(e) if e == "Cancelled" => narrate(state, "Cancelled"),
+        Err(_) => narrate(state, "The run failed"),
+        Ok(_) => {}
+    }
+    notify_completion(state, first_entry, &result).await;
+    result
+}
+
+async fn approve_loop(events: &Emitter, state: &AppState) -> Result<ExecutionState, String> {
     let action = state.pending_action.lock().unwrap().clone().ok_or("No pending action")?;
-    let goal = state.current_goal.lock().unwrap().clone();
-    let api_key = state.api_key.lock().unwrap().clone().ok_or("No API key")?;
+    let mut goal = state.current_goal.lock().unwrap().clone();
+    let llm = llm_config(state)?;
+    let config = state.execution_config.lock().unwrap().clone();
     
-    let mut attempts = 0;
-    let mut current_action = action.clone();
+    let mut step = 0;
+    // max_steps applies per goal, so it restarts when a queued goal begins
+    let mut goal_steps = 0;
+    let mut current_action = action;
     
     loop {
-        attempts += 1;
-        match execute_browser_action(&current_action).await {
-            Ok(new_state) => {
-                let entry = HistoryEntry { 
-                    timestamp: chrono::Utc::now().to_rfc3339(), 
-                    user_input: goal.clone(), 
-                    llm_reasoning: current_action.reasoning.clone().unwrap_or_default(), 
-                    action: current_action.clone(), 
-                    success: true, 
-                    error: None 
-                };
-                state.history.lock().unwrap().push(entry);
-                *state.pending_action.lock().unwrap() = None;
-                return Ok(new_state);
+        step += 1;
+        goal_steps += 1;
+        // Safe mode wants a person to approve each sensitive action, whatever the approval_mode
+        let sensitive = state.safety.lock().unwrap().safe_mode && SafetyPolicy::is_sensitive(&current_action);
+        if sensitive && config.approval_mode == "none" {
+            *state.pending_action.lock().unwrap() = None;
+            return Err(format!("Safe mode: {} needs explicit approval, and this run takes no approvals", current_action.action_type));
+        }
+        // The first action was approved by the caller; later ones are gated per approval_mode
+        if step > 1 && (sensitive || config.requires_approval(&current_action) || config.is_unsure(&current_action)) {
+            match await_step_approval(events, state, &current_action).await? {
+                StepDecision::Approve => {}
+                StepDecision::Reject => {
+                    *state.pending_action.lock().unwrap() = None;
+                    return Err("Step rejected".to_string());
+                }
+                StepDecision::Alternative(i) => {
+                    current_action = current_action.alternatives.get(i).cloned().ok_or(format!("No alternative {}", i))?;
+                }
             }
-            Err(e) if attempts < 3 => {
-                let failure_state = get_browser_state().await?;
-                let recent: Vec<HistoryEntry> = {
-                    let h = state.history.lock().unwrap();
-                    h.iter().rev().take(10).cloned().collect()
-                };
-                current_action = ai::claude::get_retry_action(&api_key, &current_action, &e, &failure_state, &recent)
-                    .await
-                    .map_err(|e| e.to_string())?;
+        }
+
+        let (mut new_state, executed) = match execute_with_retries(events, state, &llm, &goal, step, current_action).await {
+            Ok((s, a)) => (s, Some(a)),
+            // A plan step that keeps failing drops the plan and lets the model find its own way
+            Err(e) if e.starts_with("Failed after") && goal_steps < config.max_steps && abandon_plan(events, state, &e) => {
+                // Nothing ran, but the goal goes on
+                (get_browser_state(state).await?, Some(ActionCommand::default()))
             }
-            Err(e) => {
-                let entry = HistoryEntry { 
-                    timestamp: chrono::Utc::now().to_rfc3339(), 
-                    user_input: goal.clone(), 
-                    llm_reasoning: format!("Failed after {} attempts", attempts), 
-                    action: current_action.clone(), 
-                    success: false, 
-                    error: Some(e.clone()) 
-                };
-                state.history.lock().unwrap().push(entry);
+            Err(e) if e.starts_with("Failed after") && config.continue_on_error && !state.goal_queue.lock().unwrap().is_empty() => {
+                log_warn!("queue", "goal {:?} failed, moving on: {}", goal, e);
+                (get_browser_state(state).await?, None)
+            }
+            Err(e) => return Err(e),
+        };
+        let focus_note = match &executed {
+            Some(a) => unexpected_focus_change(state, a, &new_state).await,
+            None => None,
+        };
+        // executed is None only when the goal was given up on
+        let goal_over = match &executed { Some(a) => a.action_type == "complete", None => true };
+        if goal_over {
+            let Some(next) = start_next_goal(events, state, step) else {
                 *state.pending_action.lock().unwrap() = None;
-                return Err(format!("Failed after {} attempts: {}", attempts, e));
+                return Ok(new_state);
+            };
+            goal = Some(next);
+            goal_steps = 0;
+            if config.planning_mode {
+                match ai::claude::make_plan(&llm, goal.as_deref().unwrap_or(""), &new_state).await {
+                    Ok(steps) => set_plan(events, state, Plan { steps, current: 0, reactive: false }),
+                    Err(e) => log_warn!("plan", "planning failed, running reactively: {}", e),
+                }
             }
+        } else if goal_steps >= config.max_steps {
+            *state.pending_action.lock().unwrap() = None;
+            return Ok(new_state);
         }
+        if executed.is_some_and(|a| a.step_done) { advance_plan(events, state); }
+
+        if wait_if_paused(events, state, step).await? {
+            new_state = get_browser_state(state).await?;
+        }
+        let mut notes = step_notes(state, &llm.api_key, goal.as_deref().unwrap_or("")).await;
+        notes.extend(focus_note);
+        if let Some(reason) = detect_handoff(state).await {
+            await_human(events, state, step, &reason).await?;
+            new_state = get_browser_state(state).await?;
+            notes.push(format!("HANDOFF: execution paused ({}) and a person has since dealt with it. Continue from the current page.", reason));
+        }
+
+        let recent = recent_history(state);
+        current_action = timed(state, timing::Phase::Llm, ask_llm(state, || ai::claude::get_next_action(&llm, goal.as_deref().unwrap_or(""), &new_state, &recent, &notes))).await?;
+        *state.pending_action.lock().unwrap() = Some(current_action.clone());
+    }
+}
+
+// Title of the OS foreground window; None off Windows or when it can't be read
+async fn foreground_window() -> Option<String> {
+    if !cfg!(windows) { return None; }
+    tokio::task::spawn_blocking(automation::desktop::foreground_title).await.ok()?.ok()
+}
+
+// Compares the foreground window with the one after the previous step, so a UAC prompt or a popup that
+// stole focus is pointed out instead of acted through. Chrome's window title follows the page, so a
+// foreground title containing the page title still counts as the browser.
+async fn unexpected_focus_change(state: &AppState, action: &ActionCommand, new_state: &ExecutionState) -> Option<String> {
+    focus_note(state, action, new_state, foreground_window().await?)
+}
+
+// The check itself, given the title now in front; it becomes the one the next step compares with
+fn focus_note(state: &AppState, action: &ActionCommand, new_state: &ExecutionState, now: String) -> Option<String> {
+    let before = state.last_foreground.lock().unwrap().replace(now.clone())?;
+    if now == before || FOCUS_ACTIONS.contains(&action.action_type.as_str()) { return None; }
+    if !new_state.active_window.is_empty() && now.contains(&new_state.active_window) { return None; }
+    log_warn!("focus", "{} moved focus from \"{}\" to \"{}\"", action.action_type, before, now);
+    Some(format!(
+        "UNEXPECTED FOCUS CHANGE: after {} the foreground window changed from \"{}\" to \"{}\". It may be a system prompt or popup (e.g. User Account Control, a notification); dismiss it or bring the right window back (switch_to_app) before continuing.",
+        action.action_type, before, now
+    ))
+}
+
+#[tauri::command]
+async fn approve_action_with_feedback(approved: bool, feedback: Option<String>, window: Window, state: State<'_, AppState>) -> Result<ApprovalOutcome, String> {
+    let feedback = feedback.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+    let Some(feedback) = feedback.filter(|_| !approved) else {
+        *state.consecutive_rejections.lock().unwrap() = 0;
+        return approve_action(approved, window, state).await.map(ApprovalOutcome::Executed);
+    };
+
+    let rejected = state.pending_action.lock().unwrap().take().ok_or("No pending action")?;
+    let rejections = {
+        let mut r = state.consecutive_rejections.lock().unwrap();
+        *r += 1;
+        *r
+    };
+    let goal = state.current_goal.lock().unwrap().clone();
+    push_history(&state, HistoryEntry {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        user_input: goal.clone(),
+        llm_reasoning: format!("user rejected: {}", feedback),
+        action: rejected.clone(),
+        success: false,
+        error: Some(format!("user rejected: {}", feedback)),
+        screenshot_base64: None,
+        screenshot_path: None,
+        output: None,
+        target_screenshot_base64: None,
+        confidence: rejected.confidence,
+    });
+    if rejections > MAX_CONSECUTIVE_REJECTIONS {
+        return Err(format!("Rejected {} times in a row, stopping", rejections));
+    }
+
+    let llm = llm_config(&state)?;
+    let cs = get_browser_state(&state).await?;
+    let recent = recent_history(&state);
+    let mut notes = step_notes(&state, &llm.api_key, goal.as_deref().unwrap_or("")).await;
+    notes.push(format!("USER FEEDBACK: the user rejected your proposed {} on {}: \"{}\". Propose a different action.", rejected.action_type, rejected.target, feedback));
+    let action = ask_llm(&state, || ai::claude::get_next_action(&llm, goal.as_deref().unwrap_or(""), &cs, &recent, &notes)).await?;
+    *state.pending_action.lock().unwrap() = Some(action.clone());
+    Ok(ApprovalOutcome::Revised(action))
+}
+
+// Runs one step, re-prompting the LLM on failure. Returns the resulting state and the action that actually ran.
+async fn execute_with_retries(events: &Emitter, state: &AppState, llm: &LlmConfig, goal: &Option<String>, step: u32, action: ActionCommand) -> Result<(ExecutionState, ActionCommand), String> {
+    let mut attempts = 0;
+    let mut current_action = action;
+    
+    loop {
+        if state.cancel_requested.load(Ordering::SeqCst) {
+            *state.pending_action.lock().unwrap() = None;
+            return Err("Cancelled".to_string());
+        }
+        attempts += 1;
+        narrate(state, &narration::describe(&current_action));
+        match execute_browser_action(&current_action, state).await {
+            Ok((new_state, output)) => {
+                record_result(state, &current_action);
+                emit_progress(events, state, step, attempts, &current_action, Some(&new_state), None);
+                let entry = HistoryEntry { 
+                    timestamp: chrono::Utc::now().to_rfc3339(), 
+                    user_input: goal.clone(), 
+                    llm_reasoning: current_action.reasoning.clone().unwrap_or_default(), 
+                    action: current_action.clone(), 
+                    success: true, 
+                    error: None,
+                    screenshot_base64: if *state.store_step_screenshots.lock().unwrap() { Some(new_state.screenshot_base64.clone()) } else { None },
+                    screenshot_path: None,
+                    output,
+                    target_screenshot_base64: None,
+                    confidence: current_action.confidence,
+                };
+                push_history(state, entry);
+                close_timing(state, &current_action.action_type);
+                return Ok((new_state, current_action));
+            }
+            // Blocked the same way on every attempt, and already in history
+            Err(e) if e.starts_with(BLOCKED_PREFIX) => {
+                emit_progress(events, state, step, attempts, &current_action, None, Some(&e));
+                *state.pending_action.lock().unwrap() = None;
+                narrate(state, &e);
+                return Err(e);
+            }
+            Err(e) if attempts < 3 => {
+                // An element that isn't ready yet usually is a moment later
+                let base_ms = *state.retry_base_delay_ms.lock().unwrap();
+                tokio::time::sleep(retry_delay(base_ms, attempts)).await;
+                let failure_state = get_browser_state(state).await?;
+                emit_progress(events, state, step, attempts, &current_action, Some(&failure_state), Some(&e));
+                let recent = recent_history(state);
+                let screenshot = if llm.vision { retry_screenshot(state, &current_action, &failure_state).await } else { None };
+                current_action = timed(state, timing::Phase::Llm, ask_llm(state, || ai::claude::get_retry_action(llm, &current_action, &e, &failure_state, &recent, screenshot.as_deref()))).await?;
+
+                // In safe mode a sensitive retry is never auto-executed; hand it back for approval
+                if state.safety.lock().unwrap().safe_mode && SafetyPolicy::is_sensitive(&current_action) {
+                    *state.pending_action.lock().unwrap() = Some(current_action.clone());
+                    return Err(format!("Retry proposes {} which requires approval", current_action.action_type));
+                }
+            }
+            Err(e) => return Err(record_failure(events, state, goal, step, attempts, &current_action, &e).await),
+        }
+    }
+}
+
+// Out of attempts: the failed step goes into history with what evidence is left, and ends the run
+async fn record_failure(events: &Emitter, state: &AppState, goal: &Option<String>, step: u32, attempts: u32, action: &ActionCommand, e: &str) -> String {
+    emit_progress(events, state, step, attempts, action, None, Some(e));
+    let target_screenshot_base64 = capture_target(state, action).await;
+    let screenshot_base64 = if *state.store_step_screenshots.lock().unwrap() { failure_screenshot(state, action).await } else { None };
+    let entry = HistoryEntry { 
+        timestamp: chrono::Utc::now().to_rfc3339(), 
+        user_input: goal.clone(), 
+        llm_reasoning: format!("Failed after {} attempts", attempts), 
+        action: action.clone(), 
+        success: false, 
+        error: Some(e.to_string()),
+        screenshot_base64,
+        screenshot_path: None,
+        output: None,
+        target_screenshot_base64,
+        confidence: action.confidence,
+    };
+    push_history(state, entry);
+    *state.pending_action.lock().unwrap() = None;
+    narrate(state, &format!("Step failed after {} attempts", attempts));
+    format!("Failed after {} attempts: {}", attempts, e)
+}
+
+// An empty url turns the webhook off
+#[tauri::command]
+async fn set_completion_webhook(url: String, state: State<'_, AppState>) -> Result<(), String> {
+    let url = url.trim().to_string();
+    if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err("Webhook URL must be http(s)".to_string());
+    }
+    *state.completion_webhook.lock().unwrap() = Some(url).filter(|u| !u.is_empty());
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionPayload {
+    pub goal: Option<String>,
+    // "complete", "max_steps" or "failed"
+    pub outcome: String,
+    pub success: bool,
+    pub step_count: usize,
+    pub total_cost: f64,
+    pub active_window: Option<String>,
+    pub url: Option<String>,
+    pub error: Option<String>,
+    pub result: Option<serde_json::Value>,
+}
+
+// Reports how a run ended to the configured webhook. Delivery problems are only logged:
+// the run's own result never depends on the receiver.
+async fn notify_completion(state: &AppState, first_entry: usize, result: &Result<ExecutionState, String>) {
+    let Some(url) = state.completion_webhook.lock().unwrap().clone() else { return };
+    let payload = completion_payload(state, first_entry, result);
+
+    let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(c) => c,
+        Err(e) => {
+            log_warn!("webhook", "client setup failed: {}", e);
+            return;
+        }
+    };
+    for attempt in 1..=2 {
+        match client.post(&url).json(&payload).send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => {
+                log_info!("webhook", "delivered {} for {:?}", payload.outcome, payload.goal);
+                return;
+            }
+            Err(e) => log_warn!("webhook", "delivery attempt {} failed: {}", attempt, e),
+        }
+    }
+}
+
+// The run's steps are the history entries from first_entry on
+fn completion_payload(state: &AppState, first_entry: usize, result: &Result<ExecutionState, String>) -> CompletionPayload {
+    let entries: Vec<HistoryEntry> = {
+        let h = state.history.lock().unwrap();
+        let skip = first_entry.saturating_sub(state.archived_history.lock().unwrap().count);
+        h.iter().skip(skip).cloned().collect()
+    };
+    let completed = entries.last().is_some_and(|h| h.success && h.action.action_type == "complete");
+    let outcome = match (result, completed) {
+        (Ok(_), true) => "complete",
+        (Ok(_), false) => "max_steps",
+        (Err(_), _) => "failed",
+    };
+    CompletionPayload {
+        goal: state.current_goal.lock().unwrap().clone(),
+        outcome: outcome.to_string(),
+        success: outcome == "complete",
+        step_count: entries.len(),
+        total_cost: entries.iter().filter_map(|h| h.action.llm_cost.as_ref()).map(|c| c.cost_usd).sum(),
+        active_window: result.as_ref().ok().map(|s| s.active_window.clone()),
+        url: result.as_ref().ok().and_then(|s| s.url.clone()),
+        error: result.as_ref().err().cloned(),
+        result: state.last_result.lock().unwrap().clone(),
+    }
+}
+
+// Pops the queue into current_goal and checkpoints history so the new goal starts with a clean prompt
+fn start_next_goal(events: &Emitter, state: &AppState, step: u32) -> Option<String> {
+    let next = state.goal_queue.lock().unwrap().pop_front()?;
+    *state.current_goal.lock().unwrap() = Some(next.clone());
+    *state.history_checkpoint.lock().unwrap() = history_len(state);
+    *state.history_summary.lock().unwrap() = None;
+    *state.plan.lock().unwrap() = None;
+    *state.last_result.lock().unwrap() = None;
+    log_info!("queue", "starting next goal: {}", next);
+    let marker = ActionCommand { action_type: "next_goal".to_string(), target: serde_json::json!(next), ..Default::default() };
+    emit_progress(events, state, step, 0, &marker, None, None);
+    Some(next)
+}
+
+// Goals wait until the running goal completes; the first goal is still started with execute_user_command
+#[tauri::command]
+async fn enqueue_goals(goals: Vec<String>, state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let mut queue = state.goal_queue.lock().unwrap();
+    queue.extend(goals.into_iter().map(|g| g.trim().to_string()).filter(|g| !g.is_empty()));
+    Ok(queue.iter().cloned().collect())
+}
+
+#[tauri::command]
+async fn get_queue(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(state.goal_queue.lock().unwrap().iter().cloned().collect())
+}
+
+#[tauri::command]
+async fn clear_queue(state: State<'_, AppState>) -> Result<(), String> {
+    state.goal_queue.lock().unwrap().clear();
+    Ok(())
+}
+
+fn set_plan(events: &Emitter, state: &AppState, plan: Plan) {
+    log_info!("plan", "{} steps: {}", plan.steps.len(), plan.steps.join(" | "));
+    events.emit("plan", plan.clone());
+    *state.plan.lock().unwrap() = Some(plan);
+}
+
+fn advance_plan(events: &Emitter, state: &AppState) {
+    let plan = {
+        let mut guard = state.plan.lock().unwrap();
+        let Some(plan) = guard.as_mut().filter(|p| !p.reactive) else { return };
+        plan.current = (plan.current + 1).min(plan.steps.len());
+        plan.clone()
+    };
+    events.emit("plan", plan);
+}
+
+// Returns false when there is no active plan to fall back from
+fn abandon_plan(events: &Emitter, state: &AppState, error: &str) -> bool {
+    let plan = {
+        let mut guard = state.plan.lock().unwrap();
+        let Some(plan) = guard.as_mut().filter(|p| !p.reactive) else { return false };
+        plan.reactive = true;
+        plan.clone()
+    };
+    log_warn!("plan", "step {} failed ({}), switching to reactive mode", plan.current + 1, error);
+    events.emit("plan", plan);
+    true
+}
+
+fn plan_note(state: &AppState) -> Option<String> {
+    let plan = state.plan.lock().unwrap().clone().filter(|p| !p.reactive && p.current < p.steps.len())?;
+    let steps = plan.steps.iter().enumerate().map(|(i, s)| {
+        let mark = if i < plan.current { " (done)" } else if i == plan.current { " <- CURRENT" } else { "" };
+        format!("{}. {}{}", i + 1, s, mark)
+    }).collect::<Vec<_>>().join("\n");
+    Some(format!("PLAN (work only on the CURRENT step; add \"step_done\": true to the action that finishes it):\n{}", steps))
+}
+
+// Structured data the last complete step returned, if the goal asked for any
+#[tauri::command]
+async fn get_last_result(state: State<'_, AppState>) -> Result<Option<serde_json::Value>, String> {
+    Ok(state.last_result.lock().unwrap().clone())
+}
+
+#[tauri::command]
+async fn get_plan(state: State<'_, AppState>) -> Result<Option<Plan>, String> {
+    Ok(state.plan.lock().unwrap().clone())
+}
+
+// Replaces the remaining steps; execution continues from the first of them
+#[tauri::command]
+async fn revise_plan(steps: Vec<String>, window: Window, state: State<'_, AppState>) -> Result<Plan, String> {
+    let steps: Vec<String> = steps.into_iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    if steps.is_empty() { return Err("A plan needs at least one step".to_string()); }
+    let plan = Plan { steps, current: 0, reactive: false };
+    set_plan(&Emitter(Some(window)), &state, plan.clone());
+    Ok(plan)
+}
+
+// The failure screenshot with a box where the failed action aimed. The box is left out when the target
+// can't be located, or with full-page screenshots, whose origin isn't the viewport's.
+async fn retry_screenshot(state: &AppState, action: &ActionCommand, failure_state: &ExecutionState) -> Option<String> {
+    let png = Some(failure_state.screenshot_base64.clone()).filter(|s| !s.is_empty())?;
+    let target = action.target.as_str().filter(|t| !t.is_empty());
+    let Some(target) = target.filter(|_| !*state.full_page_screenshots.lock().unwrap()) else { return Some(png) };
+    let Ok(conn) = connect_browser(state).await else { return Some(png) };
+    let marked = match conn.target_center(target).await {
+        Ok((x, y)) => {
+            let dpr = conn.device_pixel_ratio().await;
+            mark_point(&png, x * dpr, y * dpr)
+        }
+        Err(_) => None,
+    };
+    Some(marked.unwrap_or(png))
+}
+
+// Red square outline centred on a screenshot pixel
+fn mark_point(png_base64: &str, x: f64, y: f64) -> Option<String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    const HALF: i64 = 24;
+    const THICKNESS: i64 = 3;
+    let bytes = STANDARD.decode(png_base64).ok()?;
+    let mut img = image::load_from_memory(&bytes).ok()?.to_rgba8();
+    let (cx, cy) = (x.round() as i64, y.round() as i64);
+    for py in cy - HALF..=cy + HALF {
+        for px in cx - HALF..=cx + HALF {
+            let edge = (px - cx).abs() > HALF - THICKNESS || (py - cy).abs() > HALF - THICKNESS;
+            if edge && px >= 0 && py >= 0 && (px as u32) < img.width() && (py as u32) < img.height() {
+                img.put_pixel(px as u32, py as u32, image::Rgba([255, 0, 0, 255]));
+            }
+        }
+    }
+    let mut out = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(img).write_to(&mut out, image::ImageOutputFormat::Png).ok()?;
+    Some(STANDARD.encode(out.into_inner()))
+}
+
+// What the screen looked like when a step gave up, from the same backend the action ran on
+async fn failure_screenshot(state: &AppState, action: &ActionCommand) -> Option<String> {
+    let captured = if automation::desktop::ACTIONS.contains(&action.action_type.as_str()) {
+        get_desktop_state().await
+    } else {
+        get_browser_state(state).await
+    };
+    captured.ok().map(|s| s.screenshot_base64).filter(|b64| !b64.is_empty())
+}
+
+// Best-effort visual evidence for a failed step; the element may well be gone
+async fn capture_target(state: &AppState, action: &ActionCommand) -> Option<String> {
+    let target = action.target.as_str().filter(|t| !t.is_empty())?;
+    let conn = connect_browser(state).await.ok()?;
+    conn.screenshot_element(target).await.ok()
+}
+
+async fn detect_handoff(state: &AppState) -> Option<String> {
+    let cfg = state.handoff.lock().unwrap().clone();
+    if !cfg.enabled { return None; }
+    let conn = connect_browser(state).await.ok()?;
+    let reason = conn.detect_handoff(&cfg).await.unwrap_or_else(|e| { log_warn!("handoff", "Handoff detection failed: {}", e); None })?;
+    // A login form is only a wall when the agent has nothing to fill it with
+    if reason == "Login form" && !state.credentials.lock().unwrap().is_empty() { return None; }
+    Some(reason)
+}
+
+// Parks the loop until a person solves the challenge and calls resume_execution (or cancels)
+async fn await_human(events: &Emitter, state: &AppState, step: u32, reason: &str) -> Result<(), String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    *state.human_handoff.lock().unwrap() = Some(tx);
+    events.emit("awaiting_human", serde_json::json!({ "step": step, "reason": reason }));
+    rx.await.map_err(|_| "Cancelled while waiting for a human".to_string())
+}
+
+// Lets the user point at an element the agent can't find: the next click on the page is captured instead
+// of acting, and resolved to a target. Unless as_hint is false, it is also queued as the next hint.
+#[tauri::command]
+async fn capture_click_target(timeout_secs: Option<u64>, as_hint: Option<bool>, state: State<'_, AppState>) -> Result<PickedTarget, String> {
+    let timeout_ms = timeout_secs.unwrap_or(DEFAULT_PICK_SECS).clamp(1, MAX_PICK_SECS) * 1000;
+    let conn = connect_browser(&state).await?;
+    let picked = conn.pick_element(timeout_ms).await.map_err(|e| e.to_string())?.ok_or("No click within the time limit")?;
+    if as_hint.unwrap_or(true) {
+        let what = match (&picked.role, &picked.name) {
+            (Some(role), Some(name)) => format!(" ({} \"{}\")", role, name),
+            (Some(role), None) => format!(" ({})", role),
+            _ => String::new(),
+        };
+        *state.pending_hint.lock().unwrap() = Some(format!("The user pointed out the element to use: target {}{} at coords:{:.0},{:.0}", picked.target, what, picked.x, picked.y));
+    }
+    Ok(picked)
+}
+
+#[tauri::command]
+async fn set_action_timeout(secs: u64, state: State<'_, AppState>) -> Result<(), String> {
+    if !(1..=MAX_ACTION_TIMEOUT_SECS).contains(&secs) { return Err(format!("Action timeout must be between 1 and {} seconds", MAX_ACTION_TIMEOUT_SECS)); }
+    *state.action_timeout_secs.lock().unwrap() = secs;
+    Ok(())
+}
+
+// 0 retries straight away
+#[tauri::command]
+async fn set_retry_delay(base_ms: u64, state: State<'_, AppState>) -> Result<(), String> {
+    if base_ms > MAX_RETRY_DELAY_MS { return Err(format!("Retry delay must be at most {} ms", MAX_RETRY_DELAY_MS)); }
+    *state.retry_base_delay_ms.lock().unwrap() = base_ms;
+    Ok(())
+}
+
+// base x attempt plus up to a quarter of that again, capped. The jitter keeps retries from
+// landing in lockstep with a page's own polling timers.
+fn retry_delay(base_ms: u64, attempt: u32) -> Duration {
+    let delay = base_ms.saturating_mul(attempt as u64);
+    let seed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.subsec_nanos() as u64);
+    let jitter = if delay >= 4 { seed % (delay / 4) } else { 0 };
+    Duration::from_millis((delay + jitter).min(MAX_RETRY_DELAY_MS))
+}
+
+// Steers a live run: the hint is added to the next prompt only. A second hint before then replaces the first.
+#[tauri::command]
+async fn send_hint(text: String, state: State<'_, AppState>) -> Result<(), String> {
+    let text = text.trim();
+    if text.is_empty() { return Err("Hint cannot be empty".to_string()); }
+    *state.pending_hint.lock().unwrap() = Some(text.to_string());
+    Ok(())
+}
+
+// Unlike cancel, the run keeps its goal, history and step count; it just stops before the next LLM call
+#[tauri::command]
+async fn pause_execution(state: State<'_, AppState>) -> Result<(), String> {
+    *state.paused.lock().unwrap() = true;
+    Ok(())
+}
+
+// Ends a pause, or a handoff wait for a human
+#[tauri::command]
+async fn resume_execution(state: State<'_, AppState>) -> Result<(), String> {
+    if std::mem::take(&mut *state.paused.lock().unwrap()) {
+        state.resume_signal.notify_waiters();
+        return Ok(());
+    }
+    let tx = state.human_handoff.lock().unwrap().take().ok_or("Execution is not paused or waiting for a human")?;
+    tx.send(()).map_err(|_| "Execution is no longer waiting".to_string())
+}
+
+// Parks while paused. Returns whether it did, so the caller can re-read the page the user may have changed.
+async fn wait_if_paused(events: &Emitter, state: &AppState, step: u32) -> Result<bool, String> {
+    let mut parked = false;
+    loop {
+        // Registered before the check, so a resume in between isn't missed
+        let resumed = state.resume_signal.notified();
+        if state.cancel_requested.load(Ordering::SeqCst) {
+            *state.pending_action.lock().unwrap() = None;
+            return Err("Cancelled".to_string());
+        }
+        if !*state.paused.lock().unwrap() { break; }
+        if !parked {
+            parked = true;
+            events.emit("paused", serde_json::json!({ "step": step }));
+        }
+        resumed.await;
+    }
+    if parked { events.emit("resumed", serde_json::json!({ "step": step })); }
+    Ok(parked)
+}
+
+#[tauri::command]
+async fn store_credential(label: String, username: String, password: String, state: State<'_, AppState>) -> Result<(), String> {
+    logging::register_secret(&username);
+    logging::register_secret(&password);
+    let mut credentials = state.credentials.lock().unwrap();
+    credentials.insert(label, Secret { username, password });
+    secrets::save_credentials(&credentials)
+}
+
+// Labels only; stored values are never sent back to the UI
+#[tauri::command]
+async fn list_credentials(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let mut labels: Vec<String> = state.credentials.lock().unwrap().keys().cloned().collect();
+    labels.sort();
+    Ok(labels)
+}
+
+#[tauri::command]
+async fn delete_credential(label: String, state: State<'_, AppState>) -> Result<(), String> {
+    let mut credentials = state.credentials.lock().unwrap();
+    credentials.remove(&label);
+    secrets::save_credentials(&credentials)
+}
+
+#[tauri::command]
+async fn get_handoff_config(state: State<'_, AppState>) -> Result<HandoffConfig, String> {
+    Ok(state.handoff.lock().unwrap().clone())
+}
+
+#[tauri::command]
+async fn set_handoff_config(config: HandoffConfig, state: State<'_, AppState>) -> Result<(), String> {
+    *state.handoff.lock().unwrap() = config;
+    Ok(())
+}
+
+// Parks the loop until approve_step (or cancel) answers
+async fn await_step_approval(events: &Emitter, state: &AppState, action: &ActionCommand) -> Result<StepDecision, String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    *state.step_approval.lock().unwrap() = Some(tx);
+    *state.pending_action.lock().unwrap() = Some(action.clone());
+    events.emit("awaiting_approval", action.clone());
+    rx.await.map_err(|_| "Approval was abandoned".to_string())
+}
+
+#[tauri::command]
+async fn approve_step(approved: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let tx = state.step_approval.lock().unwrap().take().ok_or("No step awaiting approval")?;
+    let decision = if approved { StepDecision::Approve } else { StepDecision::Reject };
+    tx.send(decision).map_err(|_| "Execution is no longer waiting".to_string())
+}
+
+// Runs alternative `index` instead of the paused step. With no step paused it swaps the pending
+// first action instead, to be run by approve_action as usual.
+#[tauri::command]
+async fn choose_alternative(index: usize, state: State<'_, AppState>) -> Result<(), String> {
+    if let Some(tx) = state.step_approval.lock().unwrap().take() {
+        return tx.send(StepDecision::Alternative(index)).map_err(|_| "Execution is no longer waiting".to_string());
+    }
+    let mut pending = state.pending_action.lock().unwrap();
+    let action = pending.as_ref().ok_or("No pending action")?;
+    let alternative = action.alternatives.get(index).cloned().ok_or(format!("No alternative {}", index))?;
+    *pending = Some(alternative);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_execution_config(state: State<'_, AppState>) -> Result<ExecutionConfig, String> {
+    Ok(state.execution_config.lock().unwrap().clone())
+}
+
+#[tauri::command]
+async fn set_execution_config(config: ExecutionConfig, state: State<'_, AppState>) -> Result<(), String> {
+    if !["first", "every", "destructive"].contains(&config.approval_mode.as_str()) {
+        return Err(format!("Unknown approval_mode: {}", config.approval_mode));
+    }
+    if config.max_steps == 0 { return Err("max_steps must be at least 1".to_string()); }
+    *state.execution_config.lock().unwrap() = config;
+    Ok(())
+}
+
+fn recent_history(state: &AppState) -> Vec<HistoryEntry> {
+    let h = state.history.lock().unwrap();
+    let start = goal_start(state, h.len());
+    h[start..].iter().rev().take(RECENT_HISTORY).cloned().collect()
+}
+
+// Steps recorded this session, archived ones included
+fn history_len(state: &AppState) -> usize {
+    let h = state.history.lock().unwrap();
+    h.len() + state.archived_history.lock().unwrap().count
+}
+
+// In-memory index where the current goal's entries begin; 0 once its first entries were archived
+fn goal_start(state: &AppState, len: usize) -> usize {
+    let archived = state.archived_history.lock().unwrap().count;
+    (*state.history_checkpoint.lock().unwrap()).saturating_sub(archived).min(len)
+}
+
+// The entry for a 1-based step number, or why there is none
+fn history_step(state: &AppState, step: usize) -> Result<HistoryEntry, String> {
+    let h = state.history.lock().unwrap();
+    let archived = state.archived_history.lock().unwrap().clone();
+    if step >= 1 && step <= archived.count {
+        return Err(format!("Step {} was archived to {}", step, archived.path.as_deref().unwrap_or("disk")));
+    }
+    h.get(step.wrapping_sub(1 + archived.count)).cloned().ok_or(format!("No step {}", step))
+}
+
+// Notes prepended to every next-action prompt
+async fn step_notes(state: &AppState, api_key: &str, goal: &str) -> Vec<String> {
+    let mut notes: Vec<String> = progress_summary(state, api_key, goal).await.into_iter().collect();
+    notes.extend(plan_note(state));
+    let mut labels: Vec<String> = state.credentials.lock().unwrap().keys().cloned().collect();
+    if !labels.is_empty() {
+        labels.sort();
+        notes.push(format!("STORED CREDENTIALS (use fill_credential, never type them yourself): {}", labels.join(", ")));
+    }
+    let skills = app_dir().and_then(|d| skills::load(&d)).unwrap_or_default();
+    if !skills.is_empty() {
+        let list = skills.iter().map(|s| {
+            let args = s.placeholders().into_iter().collect::<Vec<_>>();
+            if args.is_empty() { format!("- {}: {}", s.name, s.description) } else { format!("- {}({}): {}", s.name, args.join(", "), s.description) }
+        }).collect::<Vec<_>>().join("\n");
+        notes.push(format!("SAVED SKILLS (replay one with run_skill):\n{}", list));
+    }
+    if let Some(hint) = take_hint(state) { notes.push(format!("USER HINT (from the person watching, follow it): {}", hint)); }
+    let opened = std::mem::take(&mut *state.opened_tabs.lock().unwrap());
+    let following = *state.auto_switch_tabs.lock().unwrap();
+    for tab in opened {
+        let next = if following { "actions now go to it" } else { "use switch_tab to work in it" };
+        notes.push(format!("NEW TAB OPENED by the last action: {} ({}); {}", if tab.title.is_empty() {"untitled"} else {&tab.title}, tab.url, next));
+    }
+    notes
+}
+
+// Consumed by the next prompt, and logged at that point so the record shows which step it steered
+fn take_hint(state: &AppState) -> Option<String> {
+    let hint = state.pending_hint.lock().unwrap().take()?;
+    let record = HintRecord {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        goal: state.current_goal.lock().unwrap().clone(),
+        step: history_len(state) + 1,
+        text: hint.clone(),
+    };
+    state.hint_log.lock().unwrap().push(record);
+    Some(hint)
+}
+
+#[tauri::command]
+async fn get_hint_log(state: State<'_, AppState>) -> Result<Vec<HintRecord>, String> {
+    Ok(state.hint_log.lock().unwrap().clone())
+}
+
+// "PROGRESS SO FAR" note covering everything older than the recent window, cached on AppState
+async fn progress_summary(state: &AppState, api_key: &str, goal: &str) -> Option<String> {
+    // Archived entries of this goal count as covered, but only the ones still in memory get summarized
+    let (older, archived): (Vec<HistoryEntry>, usize) = {
+        let h = state.history.lock().unwrap();
+        let start = goal_start(state, h.len());
+        let archived = state.archived_history.lock().unwrap().count.saturating_sub(*state.history_checkpoint.lock().unwrap());
+        let h = &h[start..];
+        if archived + h.len() <= (*state.summarize_after.lock().unwrap()).max(RECENT_HISTORY) { return None; }
+        (h[..h.len().saturating_sub(RECENT_HISTORY)].to_vec(), archived)
+    };
+    let covered = archived + older.len();
+    let cached = state.history_summary.lock().unwrap().clone();
+    if let Some(c) = cached.filter(|c| covered < c.covered + SUMMARY_REFRESH) {
+        return Some(format!("PROGRESS SO FAR (first {} actions):\n{}", c.covered, c.text));
+    }
+    let mut text = match ai::claude::summarize_history(api_key, goal, &older).await {
+        Ok(t) => t,
+        Err(_) => ai::claude::local_history_summary(&older),
+    };
+    if archived > 0 { text = format!("({} earliest actions are no longer listed.) {}", archived, text); }
+    *state.history_summary.lock().unwrap() = Some(HistorySummary { covered, text: text.clone() });
+    Some(format!("PROGRESS SO FAR (first {} actions):\n{}", covered, text))
+}
+
+// Caps the entries held in memory; the oldest beyond it are appended to the session's history.jsonl
+// and dropped, and the step summary keeps accounting for them
+#[tauri::command]
+async fn set_history_limit(entries: usize, state: State<'_, AppState>) -> Result<(), String> {
+    if entries < MIN_HISTORY_LIMIT { return Err(format!("History limit must be at least {} entries", MIN_HISTORY_LIMIT)); }
+    *state.history_limit.lock().unwrap() = entries;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_summarize_after(entries: usize, state: State<'_, AppState>) -> Result<(), String> {
+    *state.summarize_after.lock().unwrap() = entries;
+    *state.history_summary.lock().unwrap() = None;
+    Ok(())
+}
+
+fn push_history(state: &AppState, entry: HistoryEntry) {
+    let dir = app_dir().ok().map(|d| d.join("sessions").join(&state.session_id));
+    push_history_in(state, entry, dir);
+}
+
+// The cap applies even without a session folder; only the archive and the screenshot files need one
+fn push_history_in(state: &AppState, entry: HistoryEntry, dir: Option<std::path::PathBuf>) {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    // A new failure changes the story, so the cached summary is stale
+    if !entry.success { *state.history_summary.lock().unwrap() = None; }
+
+    // Keep only the newest screenshots inline; older ones are copied out here and written below
+    let spill: Vec<(std::path::PathBuf, String)> = {
+        let mut history = state.history.lock().unwrap();
+        history.push(entry);
+        let limit = *state.history_limit.lock().unwrap();
+        if history.len() > limit {
+            let excess = history.len() - limit;
+            let old: Vec<HistoryEntry> = history.drain(..excess).collect();
+            archive_history(state, dir.as_ref(), &old);
+        }
+        let Some(dir) = &dir else { return };
+        let archived = state.archived_history.lock().unwrap().count;
+        let inline: Vec<usize> = history.iter().enumerate().filter(|(_, h)| h.screenshot_base64.is_some()).map(|(i, _)| i).collect();
+        if inline.len() <= INLINE_STEP_SCREENSHOTS { return; }
+        inline[..inline.len() - INLINE_STEP_SCREENSHOTS].iter().filter_map(|&i| {
+            // Step numbers restart after clear_history, so the sequence number keeps older files intact
+            let seq = state.screenshot_seq.fetch_add(1, Ordering::SeqCst);
+            let path = dir.join(format!("step_{}_{}.png", archived + i + 1, seq));
+            history[i].screenshot_base64.clone().map(|b64| (path, b64))
+        }).collect()
+    };
+
+    // Disk writes happen without the history lock; an entry only swaps its image for the path once the
+    // file exists, and stays inline when the write fails
+    let Some(dir) = dir else { return };
+    if std::fs::create_dir_all(&dir).is_err() { return; }
+    for (path, b64) in spill {
+        let Ok(bytes) = STANDARD.decode(&b64) else { continue; };
+        if let Err(e) = std::fs::write(&path, bytes) {
+            log_warn!("history", "could not write {}: {}", path.display(), e);
+            continue;
+        }
+        let swapped = match state.history.lock().unwrap().iter_mut().find(|h| h.screenshot_base64.as_ref() == Some(&b64)) {
+            Some(entry) => {
+                entry.screenshot_base64 = None;
+                entry.screenshot_path = Some(path.display().to_string());
+                true
+            }
+            None => false,
+        };
+        // The entry was archived or cleared in the meantime
+        if !swapped { let _ = std::fs::remove_file(&path); }
+    }
+}
+
+// Appends entries dropped by the history cap to <session>/history.jsonl, one per line and without inline
+// images (screenshots older than the inline few are already files). Memory stays bounded even when the
+// write fails; that is only logged.
+fn archive_history(state: &AppState, dir: Option<&std::path::PathBuf>, entries: &[HistoryEntry]) {
+    use std::io::Write;
+
+    let mut archived = state.archived_history.lock().unwrap();
+    archived.count += entries.len();
+    let Some(dir) = dir else {
+        log_warn!("history", "dropped {} entries: no session folder", entries.len());
+        return;
+    };
+    let path = dir.join("history.jsonl");
+    let lines: String = entries.iter()
+        .map(|h| HistoryEntry { screenshot_base64: None, target_screenshot_base64: None, ..h.clone() })
+        .filter_map(|h| serde_json::to_string(&h).ok())
+        .map(|line| line + "\n")
+        .collect();
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::OpenOptions::new().create(true).append(true).open(&path))
+        .and_then(|mut f| f.write_all(lines.as_bytes()));
+    match written {
+        Ok(()) => archived.path = Some(path.display().to_string()),
+        Err(e) => log_warn!("history", "dropped {} entries, archiving failed: {}", entries.len(), e),
+    }
+}
+
+fn emit_progress(events: &Emitter, state: &AppState, step: u32, attempt: u32, action: &ActionCommand, exec_state: Option<&ExecutionState>, error: Option<&str>) {
+    // Only attach a screenshot if the last one went out long enough ago
+    let screenshot_base64 = exec_state.and_then(|s| {
+        let mut last = state.last_progress_image.lock().unwrap();
+        if last.map_or(false, |t| t.elapsed() < PROGRESS_IMAGE_INTERVAL) { return None; }
+        let img = downscale_screenshot(&s.screenshot_base64, PROGRESS_IMAGE_WIDTH)?;
+        *last = Some(Instant::now());
+        Some(img)
+    });
+    events.emit("progress", ProgressEvent {
+        step,
+        attempt,
+        action: action.clone(),
+        success: error.is_none(),
+        error: error.map(|e| e.to_string()),
+        active_window: exec_state.map(|s| s.active_window.clone()),
+        url: exec_state.and_then(|s| s.url.clone()),
+        screenshot_base64,
+        goal: state.current_goal.lock().unwrap().clone(),
+        result: if error.is_none() { goal_result(action) } else { None },
+    });
+}
+
+// A complete action may carry what the goal asked for (a price, a list of links) as params.result
+fn goal_result(action: &ActionCommand) -> Option<serde_json::Value> {
+    if action.action_type != "complete" { return None; }
+    action.params.as_ref().map(|p| p["result"].clone()).filter(|r| !r.is_null())
+}
+
+// Kept for get_last_result once the complete action has run
+fn record_result(state: &AppState, action: &ActionCommand) {
+    if let Some(result) = goal_result(action) { *state.last_result.lock().unwrap() = Some(result); }
+}
+
+fn downscale_screenshot(png_base64: &str, max_width: u32) -> Option<String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let bytes = STANDARD.decode(png_base64).ok()?;
+    let img = image::load_from_memory(&bytes).ok()?;
+    let img = if img.width() > max_width { img.resize(max_width, u32::MAX, image::imageops::FilterType::Triangle) } else { img };
+    let mut out = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageOutputFormat::Png).ok()?;
+    Some(STANDARD.encode(out.into_inner()))
+}
+
+#[tauri::command]
+async fn get_safety_policy(state: State<'_, AppState>) -> Result<SafetyPolicy, String> {
+    Ok(state.safety.lock().unwrap().clone())
+}
+
+#[tauri::command]
+async fn set_safety_policy(policy: SafetyPolicy, state: State<'_, AppState>) -> Result<(), String> {
+    *state.safety.lock().unwrap() = policy;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_action_filter(state: State<'_, AppState>) -> Result<ActionFilter, String> {
+    Ok(state.action_filter.lock().unwrap().clone())
+}
+
+// Only these actions may run (and only they are offered to the model); an empty list lifts the restriction
+#[tauri::command]
+async fn set_allowed_actions(actions: Vec<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let actions = known_actions(&state, actions)?;
+    state.action_filter.lock().unwrap().allowed = actions;
+    Ok(())
+}
+
+// These actions never run, whatever the allowlist says
+#[tauri::command]
+async fn set_blocked_actions(actions: Vec<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let actions = known_actions(&state, actions)?;
+    state.action_filter.lock().unwrap().blocked = actions;
+    Ok(())
+}
+
+// A misspelled name would silently restrict nothing, so unknown ones are refused
+fn known_actions(state: &AppState, actions: Vec<String>) -> Result<Vec<String>, String> {
+    let actions: Vec<String> = actions.into_iter().map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect();
+    let registry = state.actions.lock().unwrap();
+    let unknown: Vec<&str> = actions.iter().map(|a| a.as_str()).filter(|a| !ai::claude::is_builtin_action(a) && registry.get(a).is_none()).collect();
+    if !unknown.is_empty() { return Err(format!("Unknown actions: {}", unknown.join(", "))); }
+    Ok(actions)
+}
+
+// Starts every error for an action safe mode or the filter stopped, so the retry loop can tell it from a failure
+const BLOCKED_PREFIX: &str = "Blocked: ";
+
+// Leaves a trace of an action safe mode or the filter stopped; nothing was sent to the browser or desktop.
+// Returns the error to hand back.
+fn record_blocked(state: &AppState, action: &ActionCommand, reason: &str) -> String {
+    log_warn!("safety", "blocked_action {}: {}", action.action_type, reason);
+    push_history(state, HistoryEntry {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        user_input: state.current_goal.lock().unwrap().clone(),
+        llm_reasoning: "blocked_action".to_string(),
+        action: action.clone(),
+        success: false,
+        error: Some(reason.to_string()),
+        screenshot_base64: None,
+        screenshot_path: None,
+        output: None,
+        target_screenshot_base64: None,
+        confidence: action.confidence,
+    });
+    format!("{}{}", BLOCKED_PREFIX, reason)
+}
+
+#[tauri::command]
+async fn get_stop_words(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(state.stop_words.lock().unwrap().clone())
+}
+
+// Replaces the list; None restores the defaults and an empty list keeps every goal word
+#[tauri::command]
+async fn set_stop_words(words: Option<Vec<String>>, state: State<'_, AppState>) -> Result<(), String> {
+    let words = match words {
+        Some(words) => words.into_iter().map(|w| w.trim().to_lowercase()).filter(|w| !w.is_empty()).collect(),
+        None => ai::claude::DEFAULT_STOP_WORDS.iter().map(|w| w.to_string()).collect(),
+    };
+    *state.stop_words.lock().unwrap() = words;
+    Ok(())
+}
+
+#[tauri::command]
+async fn cancel_execution(state: State<'_, AppState>) -> Result<(), String> {
+    signal_cancel(&state);
+    release_inputs(&state).await;
+    Ok(())
+}
+
+// Stops the run wherever it waits: between attempts, at a step approval, a handoff or a pause,
+// and mid-string while typing. Shared by cancel_execution and the panic hotkey.
+fn signal_cancel(state: &AppState) {
+    state.cancel_requested.store(true, Ordering::SeqCst);
+    if let Some(tx) = state.step_approval.lock().unwrap().take() { let _ = tx.send(StepDecision::Reject); }
+    // Dropping the sender wakes await_human with an error
+    state.human_handoff.lock().unwrap().take();
+    *state.paused.lock().unwrap() = false;
+    state.resume_signal.notify_waiters();
+}
+
+// Lets go of a held mouse button and any modifier keys a stopped action left down
+async fn release_inputs(state: &AppState) {
+    release_held_mouse(state).await;
+    release_modifiers().await;
+}
+
+async fn release_modifiers() {
+    if !cfg!(windows) { return; }
+    match tokio::task::spawn_blocking(automation::desktop::release_all_modifiers).await {
+        Ok(Err(e)) => log_warn!("desktop", "could not release modifiers: {}", e),
+        Err(e) => log_warn!("desktop", "could not release modifiers: {}", e),
+        Ok(Ok(_)) => {}
+    }
+}
+
+#[tauri::command]
+async fn set_panic_hotkey(accelerator: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let previous = state.panic_hotkey.lock().unwrap().clone();
+    let mut manager = app.global_shortcut_manager();
+    let _ = manager.unregister(&previous);
+    if let Err(e) = register_panic_hotkey(&app, &accelerator) {
+        // Keep the old binding rather than leaving the user without an emergency stop
+        let _ = register_panic_hotkey(&app, &previous);
+        return Err(e);
+    }
+    *state.panic_hotkey.lock().unwrap() = accelerator;
+    Ok(())
+}
+
+// The global shortcut fires on the main thread, independently of any running action
+fn register_panic_hotkey(app: &AppHandle, accelerator: &str) -> Result<(), String> {
+    let handle = app.clone();
+    app.global_shortcut_manager().register(accelerator, move || {
+        signal_cancel(&handle.state::<AppState>());
+        let _ = handle.emit_all("emergency_stop", ());
+        let releasing = handle.clone();
+        tauri::async_runtime::spawn(async move { release_inputs(&releasing.state::<AppState>()).await });
+    }).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_history(state: State<'_, AppState>) -> Result<Vec<HistoryEntry>, String> { 
+    Ok(state.history.lock().unwrap().clone()) 
+}
+
+#[tauri::command]
+async fn get_session_summary(state: State<'_, AppState>) -> Result<SessionSummary, String> {
+    let history = state.history.lock().unwrap().clone();
+    let archived = state.archived_history.lock().unwrap().count;
+    Ok(session_summary(&history, archived))
+}
+
+// Which backend a step ran on: window actions and the titled zoom/get_page_text variants are desktop
+// steps, everything else goes through the browser
+fn step_mode(action: &ActionCommand) -> &'static str {
+    let titled = matches!(action.action_type.as_str(), "zoom" | "get_page_text")
+        && action.params.as_ref().map_or(false, |p| p["title"].is_string());
+    if titled || automation::desktop::ACTIONS.contains(&action.action_type.as_str()) { "desktop" } else { "browser" }
+}
+
+// Entries without llm_cost (user rejections, older sessions) count as free. The first `archived` steps
+// were moved to disk by the history cap; they count towards steps but not the breakdowns.
+fn session_summary(history: &[HistoryEntry], archived: usize) -> SessionSummary {
+    let mut per_action: Vec<ActionStats> = Vec::new();
+    for h in history {
+        let stats = match per_action.iter_mut().find(|s| s.action_type == h.action.action_type) {
+            Some(s) => s,
+            None => {
+                per_action.push(ActionStats { action_type: h.action.action_type.clone(), total: 0, succeeded: 0, success_rate: 0.0 });
+                per_action.last_mut().unwrap()
+            }
+        };
+        stats.total += 1;
+        if h.success { stats.succeeded += 1; }
+    }
+    for s in &mut per_action { s.success_rate = s.succeeded as f64 / s.total as f64; }
+
+    let times: Vec<chrono::DateTime<chrono::FixedOffset>> = history.iter().filter_map(|h| chrono::DateTime::parse_from_rfc3339(&h.timestamp).ok()).collect();
+    let duration_secs = match (times.first(), times.last()) {
+        (Some(first), Some(last)) => (*last - *first).num_milliseconds() as f64 / 1000.0,
+        _ => 0.0,
+    };
+    let avg_step_gap_secs = if times.len() > 1 { duration_secs / (times.len() - 1) as f64 } else { 0.0 };
+
+    // One bucket per mode that actually ran, in order of first use
+    let mut per_mode: Vec<ModeCost> = Vec::new();
+    for h in history {
+        let mode = step_mode(&h.action);
+        let bucket = match per_mode.iter_mut().find(|m| m.mode == mode) {
+            Some(m) => m,
+            None => {
+                per_mode.push(ModeCost { mode: mode.to_string(), input_tokens: 0, output_tokens: 0, cost_usd: 0.0 });
+                per_mode.last_mut().unwrap()
+            }
+        };
+        if let Some(c) = &h.action.llm_cost {
+            bucket.input_tokens += c.input_tokens as u64;
+            bucket.output_tokens += c.output_tokens as u64;
+            bucket.cost_usd += c.cost_usd;
+        }
+    }
+    let most_expensive_step = history.iter().enumerate()
+        .filter_map(|(i, h)| h.action.llm_cost.as_ref().map(|c| CostlyStep { step: archived + i + 1, action_type: h.action.action_type.clone(), cost_usd: c.cost_usd }))
+        .max_by(|a, b| a.cost_usd.total_cmp(&b.cost_usd));
+
+    SessionSummary {
+        steps: archived + history.len(),
+        archived_steps: archived,
+        succeeded: history.iter().filter(|h| h.success).count(),
+        per_action,
+        duration_secs,
+        avg_step_gap_secs,
+        per_mode,
+        most_expensive_step,
+    }
+}
+
+// Case-insensitive match on goal, reasoning, action type, target and error; history order is kept
+#[tauri::command]
+async fn search_history(query: String, state: State<'_, AppState>) -> Result<Vec<StepEntry>, String> {
+    Ok(numbered_history(&state, |h| history_contains(h, &query)))
+}
+
+fn history_contains(h: &HistoryEntry, query: &str) -> bool {
+    let q = query.trim().to_lowercase();
+    let target = h.action.target.as_str().map(|t| t.to_string()).unwrap_or_else(|| h.action.target.to_string());
+    [h.user_input.as_deref().unwrap_or(""), &h.llm_reasoning, &h.action.action_type, &target, h.error.as_deref().unwrap_or("")]
+        .iter()
+        .any(|field| field.to_lowercase().contains(&q))
+}
+
+// All filters are optional and combine with AND; mode is "browser" or "desktop", as in step_mode
+#[tauri::command]
+async fn filter_history(success: Option<bool>, mode: Option<String>, action_type: Option<String>, state: State<'_, AppState>) -> Result<Vec<StepEntry>, String> {
+    filtered_history(&state, success, mode.as_deref(), action_type.as_deref())
+}
+
+fn filtered_history(state: &AppState, success: Option<bool>, mode: Option<&str>, action_type: Option<&str>) -> Result<Vec<StepEntry>, String> {
+    if let Some(m) = mode.filter(|m| !matches!(*m, "browser" | "desktop")) {
+        return Err(format!("Unknown mode \"{}\"; use browser or desktop", m));
+    }
+    Ok(numbered_history(state, |h| history_matches(h, success, mode, action_type)))
+}
+
+// Matching in-memory entries with their 1-based step numbers; archived steps are on disk and not searched
+fn numbered_history(state: &AppState, keep: impl Fn(&HistoryEntry) -> bool) -> Vec<StepEntry> {
+    let h = state.history.lock().unwrap();
+    let archived = state.archived_history.lock().unwrap().count;
+    h.iter().enumerate()
+        .filter(|(_, entry)| keep(entry))
+        .map(|(i, entry)| StepEntry { step: archived + i + 1, entry: entry.clone() })
+        .collect()
+}
+
+fn history_matches(h: &HistoryEntry, success: Option<bool>, mode: Option<&str>, action_type: Option<&str>) -> bool {
+    success.map_or(true, |s| h.success == s)
+        && mode.map_or(true, |m| step_mode(&h.action) == m)
+        && action_type.map_or(true, |a| h.action.action_type == a)
+}
+
+#[tauri::command]
+async fn set_store_step_screenshots(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    *state.store_step_screenshots.lock().unwrap() = enabled;
+    Ok(())
+}
+
+// Steps are 1-based, matching the step_<n>_<seq>.png files
+#[tauri::command]
+async fn get_step_screenshot(step: usize, state: State<'_, AppState>) -> Result<String, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let entry = history_step(&state, step)?;
+    if let Some(b64) = entry.screenshot_base64 { return Ok(b64); }
+    let path = entry.screenshot_path.ok_or(format!("No screenshot stored for step {}", step))?;
+    Ok(STANDARD.encode(std::fs::read(path).map_err(|e| e.to_string())?))
+}
+
+// Re-runs one recorded action against the current page, for checking whether a step is flaky.
+// History, the pending action and the goal are left untouched; failures come back as a
+// state with success = false rather than an error.
+#[tauri::command]
+async fn replay_step(step_number: usize, state: State<'_, AppState>) -> Result<ExecutionState, String> {
+    let action = history_step(&state, step_number)?.action;
+    log_info!("replay", "replaying step {}: {} on {}", step_number, action.action_type, action.target);
+    match execute_browser_action(&action, &state).await {
+        Ok((new_state, _)) => Ok(new_state),
+        Err(e) => {
+            let mut failure_state = get_browser_state(&state).await?;
+            failure_state.success = false;
+            failure_state.error = Some(e);
+            Ok(failure_state)
+        }
+    }
+}
+
+// Performs the inverse of the last successful step (see undo::INVERSES) and records it as a step of its own
+#[tauri::command]
+async fn undo_last_action(state: State<'_, AppState>) -> Result<ExecutionState, String> {
+    let (step, last) = {
+        let h = state.history.lock().unwrap();
+        let archived = state.archived_history.lock().unwrap().count;
+        h.iter().enumerate().rev().find(|(_, e)| e.success).map(|(i, e)| (archived + i + 1, e.clone())).ok_or("Nothing to undo")?
+    };
+    let undo = undo::inverse_of(&last.action).map_err(|e| format!("Cannot undo step {}: {}", step, e))?;
+    let (new_state, output) = execute_browser_action(&undo, &state).await?;
+    push_history(&state, HistoryEntry {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        user_input: last.user_input,
+        llm_reasoning: format!("undo of step {}", step),
+        action: undo,
+        success: true,
+        error: None,
+        screenshot_base64: if *state.store_step_screenshots.lock().unwrap() { Some(new_state.screenshot_base64.clone()) } else { None },
+        screenshot_path: None,
+        output,
+        target_screenshot_base64: None,
+        confidence: None,
+    });
+    Ok(new_state)
+}
+
+// Snapshots the successful steps of the current history as a named skill, overwriting any of the same name
+#[tauri::command]
+async fn save_skill(name: String, description: String, state: State<'_, AppState>) -> Result<skills::Skill, String> {
+    let actions: Vec<ActionCommand> = state.history.lock().unwrap().iter()
+        .filter(|h| h.success && h.action.action_type != "complete")
+        .map(|h| ActionCommand { llm_cost: None, step_done: false, confidence: None, alternatives: Vec::new(), ..h.action.clone() })
+        .collect();
+    if actions.is_empty() { return Err("No successful steps to save".to_string()); }
+    let skill = skills::Skill { name, description, actions };
+    skills::upsert(&app_dir()?, skill.clone())?;
+    log_info!("skills", "saved \"{}\" with {} actions", skill.name, skill.actions.len());
+    Ok(skill)
+}
+
+#[tauri::command]
+async fn list_skills() -> Result<Vec<skills::Skill>, String> {
+    skills::load(&app_dir()?)
+}
+
+// One server per session; the token is chosen by the caller and must be sent as a bearer token
+#[tauri::command]
+async fn start_api_server(port: u16, token: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    if let Some(running) = *state.api_port.lock().unwrap() {
+        return Err(format!("API server already running on port {}", running));
     }
+    api::start(app, port, token)?;
+    *state.api_port.lock().unwrap() = Some(port);
+    Ok(())
 }
 
+// Successful steps of the current history as a Playwright .spec.ts; skipped steps are logged
 #[tauri::command]
-async fn get_history(state: State<'_, AppState>) -> Result<Vec<HistoryEntry>, String> { 
-    Ok(state.history.lock().unwrap().clone()) 
+async fn export_playwright(state: State<'_, AppState>) -> Result<String, String> {
+    let history = state.history.lock().unwrap().clone();
+    if !history.iter().any(|h| h.success) { return Err("No successful steps to export".to_string()); }
+    let title = history.iter().find_map(|h| h.user_input.clone()).unwrap_or_else(|| "recorded run".to_string());
+    let dir = app_dir()?;
+    let (file, warnings) = playwright::script(&title, &history, |action| {
+        let params = action.params.clone().unwrap_or_default();
+        let skill = skills::find(&dir, params["name"].as_str().unwrap_or(""))?;
+        skill.instantiate(&params["args"].as_object().cloned().unwrap_or_default())
+    });
+    for w in &warnings { log_warn!("export", "{}", w); }
+    Ok(file)
 }
 
 #[tauri::command]
 async fn clear_history(state: State<'_, AppState>) -> Result<(), String> {
     state.history.lock().unwrap().clear();
+    *state.archived_history.lock().unwrap() = ArchivedHistory::default();
+    *state.history_summary.lock().unwrap() = None;
+    *state.history_checkpoint.lock().unwrap() = 0;
+    state.hint_log.lock().unwrap().clear();
     Ok(())
 }
 
+// Copies the monitor the active window is on, or the one at monitor_index (see list_monitors),
+// or all of them side by side
 #[tauri::command]
-async fn take_screenshot_to_clipboard() -> Result<(), String> {
+async fn take_screenshot_to_clipboard(monitor_index: Option<usize>, all_monitors: Option<bool>) -> Result<(), String> {
     use arboard::{Clipboard, ImageData};
-    use xcap::Monitor;
 
-    let monitors = Monitor::all().map_err(|e| e.to_string())?;
-    let monitor = monitors.first().ok_or("No monitor found")?;
-    let img = monitor.capture_image().map_err(|e| e.to_string())?;
+    let which = automation::screen::Capture::from_params(monitor_index, all_monitors.unwrap_or(false));
+    let img = tokio::task::spawn_blocking(move || automation::screen::capture(which)).await.map_err(|e| e.to_string())??;
 
     let width = img.width() as usize;
     let height = img.height() as usize;
@@ -183,17 +2288,83 @@ async fn take_screenshot_to_clipboard() -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn get_screen_a11y_tree() -> Result<String, String> {
-    let ps_script = r#"
+async fn list_monitors() -> Result<Vec<automation::screen::MonitorInfo>, String> {
+    automation::screen::list_monitors()
+}
+
+// Tree depth captured after a desktop action; deeper scans are left to get_screen_a11y_tree
+const DESKTOP_STATE_DEPTH: u32 = 6;
+
+// Shared node budget for one scan, keeps multi-window output within a sane prompt size
+const A11Y_MAX_NODES: u32 = 1500;
+
+// scope "desktop" (default) walks from RootElement, "focused" from the foreground window only,
+// "windows" walks the foreground window plus every other visible top-level window (slower, opt-in)
+#[tauri::command]
+async fn get_screen_a11y_tree(depth: Option<u32>, scope: Option<String>) -> Result<String, String> {
+    let ps_script = a11y_script(depth, scope.as_deref())?;
+    let output = std::process::Command::new("powershell")
+        .args(["-ExecutionPolicy", "Bypass", "-Command", &ps_script])
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// The UI Automation walk for get_screen_a11y_tree; depth defaults to 4 and is kept within 1..=20
+fn a11y_script(depth: Option<u32>, scope: Option<&str>) -> Result<String, String> {
+    let depth = depth.unwrap_or(4).clamp(1, 20);
+    let tree = match scope.unwrap_or("desktop") {
+        "desktop" => "$tree = Get-Tree -el ([System.Windows.Automation.AutomationElement]::RootElement) -depth 0",
+        "focused" => "$tree = Get-Tree -el ([System.Windows.Automation.AutomationElement]::FromHandle([FgWindow]::GetForegroundWindow())) -depth 0",
+        "windows" => r#"
+$fg = [FgWindow]::GetForegroundWindow()
+$handles = @($fg) + @([FgWindow]::Visible() | Where-Object { $_ -ne $fg })
+$tree = @(foreach ($h in $handles) {
+    if ($script:count -ge __MAX_NODES__) { break }
+    try { $el = [System.Windows.Automation.AutomationElement]::FromHandle($h) } catch { continue }
+    $t = Get-Tree -el $el -depth 0
+    if ($t) { $t.window = $el.Current.Name; $t }
+})"#,
+        other => return Err(format!("Unknown scope: {}", other)),
+    };
+    Ok(r#"
 Add-Type -AssemblyName UIAutomationClient
 Add-Type -AssemblyName UIAutomationTypes
+Add-Type @"
+using System; using System.Runtime.InteropServices;
+using System.Collections.Generic;
+public class FgWindow {
+    [DllImport("user32.dll")] public static extern IntPtr GetForegroundWindow();
+    public delegate bool EnumProc(IntPtr hWnd, IntPtr lParam);
+    [DllImport("user32.dll")] static extern bool EnumWindows(EnumProc cb, IntPtr lParam);
+    [DllImport("user32.dll")] static extern bool IsWindowVisible(IntPtr hWnd);
+    [DllImport("user32.dll")] static extern bool GetWindowRect(IntPtr hWnd, out RECT r);
+    public struct RECT { public int Left, Top, Right, Bottom; }
+    public static List<IntPtr> Visible() {
+        var list = new List<IntPtr>();
+        EnumWindows((h, l) => {
+            RECT r;
+            if (IsWindowVisible(h) && GetWindowRect(h, out r) && r.Right > r.Left && r.Bottom > r.Top) list.Add(h);
+            return true;
+        }, IntPtr.Zero);
+        return list;
+    }
+}
+"@
 
-$auto = [System.Windows.Automation.AutomationElement]::RootElement
+$script:count = 0
 $walker = [System.Windows.Automation.TreeWalker]::ContentViewWalker
 
 function Get-Tree {
     param($el, $depth)
-    if ($depth -gt 4 -or $null -eq $el) { return $null }
+    if ($depth -gt __DEPTH__ -or $null -eq $el) { return $null }
+    if ($script:count -ge __MAX_NODES__) { return $null }
+    $script:count++
 
     $current = $el.Current
     $children = @()
@@ -214,31 +2385,98 @@ function Get-Tree {
     }
 }
 
-$tree = Get-Tree -el $auto -depth 0
-$tree | ConvertTo-Json -Depth 20 -Compress
-"#;
+__TREE__
+$tree | ConvertTo-Json -Depth __JSON_DEPTH__ -Compress
+"#
+    .replace("__TREE__", tree)
+    .replace("__DEPTH__", &depth.to_string())
+    .replace("__MAX_NODES__", &A11Y_MAX_NODES.to_string())
+    // Each tree level is an object plus its children array, plus the outer window list
+    .replace("__JSON_DEPTH__", &(depth * 2 + 3).min(100).to_string()))
+}
 
-    let output = std::process::Command::new("powershell")
-        .args(["-ExecutionPolicy", "Bypass", "-Command", ps_script])
-        .output()
-        .map_err(|e| e.to_string())?;
+// Looks the tab up over HTTP and remembers it, so back-to-back actions skip the /json round trip.
+// Falls back to the first tab when the active one is gone.
+async fn connect_active_tab(state: &AppState) -> Result<ChromeConnection, Box<dyn std::error::Error + Send + Sync>> {
+    *state.cdp_target.lock().unwrap() = None;
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    let tabs = automation::chrome_cdp::get_tabs(&endpoint).await?;
+    let active = state.active_tab.lock().unwrap().clone();
+    let tab = tabs.iter().find(|t| Some(&t.id) == active.as_ref()).or(tabs.first()).ok_or("No tabs")?;
+    let conn = ChromeConnection::connect(&tab.ws_url).await?;
+    *state.cdp_target.lock().unwrap() = Some((tab.ws_url.clone(), Instant::now()));
+    *state.active_tab.lock().unwrap() = Some(tab.id.clone());
+    Ok(conn)
+}
 
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+// Connects to the active tab and re-applies the session-scoped overrides configured on AppState
+async fn connect_browser(state: &AppState) -> Result<ChromeConnection, String> {
+    let cached = state.cdp_target.lock().unwrap().clone().filter(|(_, at)| at.elapsed() < CDP_TARGET_TTL);
+    let reused = match cached {
+        Some((ws_url, _)) => ChromeConnection::connect(&ws_url).await.ok(),
+        None => None,
+    };
+    let mut conn = match reused {
+        Some(conn) => conn,
+        None => connect_active_tab(state).await.map_err(|e| {
+            let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+            format!("Chrome connection failed: {}. Make sure Chrome is running with --remote-debugging-port={} and reachable at {}", e, endpoint.port, endpoint.host)
+        })?,
+    };
+    let emulation = state.emulation.lock().unwrap().clone();
+    if let Some(cfg) = emulation {
+        conn.apply_emulation(&cfg).await.map_err(|e| format!("Emulation failed: {}", e))?;
     }
-
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    let locale = state.locale_override.lock().unwrap().clone();
+    if let Some(cfg) = locale {
+        conn.apply_locale(&cfg).await.map_err(|e| format!("Locale override failed: {}", e))?;
+    }
+    let blocked = state.blocked_url_patterns.lock().unwrap().clone();
+    if !blocked.is_empty() {
+        conn.block_urls(&blocked).await.map_err(|e| format!("URL blocking failed: {}", e))?;
+    }
+    conn.set_typing_delay(*state.typing_delay_ms.lock().unwrap());
+    conn.set_cancel_flag(state.cancel_requested.clone());
+    conn.set_highlight(*state.highlight_actions.lock().unwrap());
+    let zoom = *state.zoom_level.lock().unwrap();
+    if zoom != 1.0 {
+        conn.set_zoom(zoom).await.map_err(|e| format!("Zoom failed: {}", e))?;
+    }
+    let network = state.network_conditions.lock().unwrap().clone();
+    if let Some(conditions) = network {
+        conn.set_network_conditions(&conditions).await.map_err(|e| format!("Network conditions failed: {}", e))?;
+    }
+    Ok(conn)
 }
 
-async fn get_browser_state() -> Result<ExecutionState, String> {
-    let conn = automation::chrome_cdp::ChromeConnection::connect_to_first_tab(9222)
-        .await
-        .map_err(|e| format!("Chrome connection failed: {}. Make sure Chrome is running with --remote-debugging-port=9222", e))?;
+async fn get_browser_state(state: &AppState) -> Result<ExecutionState, String> {
+    let conn = connect_browser(state).await?;
     
-    let browser_state = conn.get_browser_state()
+    let full_page = *state.full_page_screenshots.lock().unwrap();
+    let mut browser_state = conn.get_browser_state(full_page)
         .await
         .map_err(|e| e.to_string())?;
+
+    let ocr_enabled = *state.ocr_fallback.lock().unwrap();
+    if ocr_enabled && browser_state.accessibility_tree.len() < OCR_MIN_NODES {
+        let screenshot = browser_state.screenshot_base64.clone();
+        let dpr = browser_state.device_pixel_ratio;
+        let ocr = tokio::task::spawn_blocking(move || automation::ocr::ocr_nodes(&screenshot, dpr).map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+        match ocr {
+            Ok(nodes) => browser_state.accessibility_tree.extend(nodes),
+            Err(e) => log_warn!("ocr", "OCR fallback failed: {}", e),
+        }
+    }
     
+    // Best effort: a failed listing only costs the model the tab overview
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    browser_state.tabs = automation::chrome_cdp::get_tabs(&endpoint).await.unwrap_or_default();
+    let active = state.active_tab.lock().unwrap().clone();
+    let active_tab = browser_state.tabs.iter().position(|t| Some(&t.id) == active.as_ref());
+
     Ok(ExecutionState {
         screenshot_base64: browser_state.screenshot_base64,
         accessibility_tree: serde_json::to_value(&browser_state.accessibility_tree).unwrap_or_default(),
@@ -246,35 +2484,480 @@ async fn get_browser_state() -> Result<ExecutionState, String> {
         url: Some(browser_state.url),
         success: true,
         error: None,
+        tabs: browser_state.tabs,
+        active_tab,
     })
 }
 
-async fn execute_browser_action(action: &ActionCommand) -> Result<ExecutionState, String> {
-    let conn = automation::chrome_cdp::ChromeConnection::connect_to_first_tab(9222)
+// Types a stored secret into the target. The value goes straight to the browser: the action
+// (and so history and prompts) only ever carries the label and field name.
+async fn fill_credential(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<(), String> {
+    let param = |k: &str| action.params.as_ref().and_then(|p| p[k].as_str()).map(|s| s.to_string());
+    let label = param("label").ok_or("fill_credential needs params.label")?;
+    let field = param("field").unwrap_or_else(|| "password".to_string());
+    let value = {
+        let credentials = state.credentials.lock().unwrap();
+        let secret = credentials.get(&label).ok_or_else(|| format!("No stored credential \"{}\"", label))?;
+        secret.field(&field).ok_or_else(|| format!("Unknown credential field \"{}\"", field))?.to_string()
+    };
+    conn.execute_llm_action("type", &action.target, Some(&serde_json::json!({ "text": value })))
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| format!("fill_credential failed: {}", e))?;
+    log_info!("credentials", "filled {} for \"{}\": [redacted]", field, label);
+    Ok(())
+}
+
+async fn execute_browser_action(action: &ActionCommand, state: &AppState) -> Result<(ExecutionState, Option<String>), String> {
+    let checked = state.safety.lock().unwrap().check(action);
+    let filtered = checked.and_then(|_| state.action_filter.lock().unwrap().check(&action.action_type));
+    if let Err(e) = filtered { return Err(record_blocked(state, action, &e)); }
+
+    // Window actions don't touch the page, so they run (and report the desktop) even with Chrome closed
+    if automation::desktop::ACTIONS.contains(&action.action_type.as_str()) {
+        let output = timed(state, timing::Phase::Action, run_desktop_action(action, state)).await?;
+        return Ok((timed(state, timing::Phase::Capture, get_desktop_state()).await?, Some(output)));
+    }
+    if action.action_type == "open_url" { ensure_chrome(state).await?; }
+    let conn = connect_browser(state).await?;
+    // Opening tabs is what the tab actions are for, so only other actions are watched
+    let watch = !matches!(action.action_type.as_str(), "switch_tab" | "new_tab" | "close_tab");
+    let known = if watch {
+        conn.watch_new_tabs().await.map_err(|e| log_warn!("cdp", "tab discovery failed: {}", e)).ok()
+    } else {
+        None
+    };
     
-    conn.execute_llm_action(&action.action_type, &action.target, action.params.as_ref())
-        .await
-        .map_err(|e| e.to_string())?;
+    let output = if action.action_type == "run_skill" {
+        timed(state, timing::Phase::Action, run_skill(&conn, action, state)).await
+    } else {
+        timed(state, timing::Phase::Action, perform(&conn, action, state)).await
+    };
+    // Wait for spinners and late rendering rather than a fixed delay, so the next state isn't half-loaded
+    // A closed tab has nothing left to settle
+    if output.is_ok() && action.action_type != "close_tab" {
+        let settle = state.ui_settle.lock().unwrap().clone();
+        if let Err(e) = conn.wait_for_stable_ui(&settle).await { log_warn!("cdp", "settle check failed: {}", e); }
+    }
+    let opened = match &known {
+        Some(known) => conn.new_tabs(known).await.unwrap_or_else(|e| { log_warn!("cdp", "tab discovery failed: {}", e); Vec::new() }),
+        None => Vec::new(),
+    };
+    // Closed before the state read or a retry opens the next session
+    if let Err(e) = conn.disconnect().await { log_warn!("cdp", "disconnect failed: {}", e); }
+    let output = output?;
+    if !opened.is_empty() { note_opened_tabs(state, &opened).await; }
     
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-    get_browser_state().await
+    Ok((timed(state, timing::Phase::Capture, get_browser_state(state)).await?, output))
+}
+
+// Same time limit as perform; the PowerShell call itself can't be cut short, only stopped waiting for
+async fn run_desktop_action(action: &ActionCommand, state: &AppState) -> Result<String, String> {
+    let limit = Duration::from_secs(*state.action_timeout_secs.lock().unwrap());
+    let moved = action.clone();
+    match tokio::time::timeout(limit, tokio::task::spawn_blocking(move || automation::desktop::execute(&moved))).await {
+        Ok(result) => result.map_err(|e| e.to_string())?,
+        Err(_) => Err(format!("{} timed out after {} s", action.action_type, limit.as_secs())),
+    }
+}
+
+// What the model sees after a desktop action: the window now in front, its UI Automation tree and
+// a capture of the monitor it is on
+async fn get_desktop_state() -> Result<ExecutionState, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let tree = get_screen_a11y_tree(Some(DESKTOP_STATE_DEPTH), Some("focused".to_string())).await?;
+    let screenshot = tokio::task::spawn_blocking(|| -> Result<String, String> {
+        let img = automation::screen::capture(automation::screen::Capture::ActiveWindow)?;
+        let mut out = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut out, image::ImageOutputFormat::Png).map_err(|e| e.to_string())?;
+        Ok(STANDARD.encode(out.into_inner()))
+    }).await.map_err(|e| e.to_string())?;
+    let screenshot_base64 = screenshot.unwrap_or_else(|e| {
+        log_warn!("desktop", "screen capture failed: {}", e);
+        String::new()
+    });
+    Ok(ExecutionState {
+        screenshot_base64,
+        accessibility_tree: serde_json::from_str(tree.trim()).unwrap_or(serde_json::Value::String(tree)),
+        active_window: foreground_window().await.unwrap_or_default(),
+        url: None,
+        success: true,
+        error: None,
+        tabs: Vec::new(),
+        active_tab: None,
+    })
+}
+
+// Starts a local Chrome with the debugger when none answers yet (a running one is left alone), so
+// open_url works from the desktop as well. A remote endpoint is assumed to be up.
+async fn ensure_chrome(state: &AppState) -> Result<(), String> {
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    if !endpoint.is_local() { return Ok(()); }
+    let opts = state.launch_options.lock().unwrap().clone();
+    let launched = tokio::task::spawn_blocking(move || {
+        if automation::chrome_cdp::debugger_available(endpoint.port) { return Ok(false); }
+        log_info!("chrome", "open_url: starting Chrome on port {}", endpoint.port);
+        automation::chrome_cdp::launch_chrome_with_debugging(endpoint.port, &opts).map(|_| true).map_err(|e| e.to_string())
+    }).await.map_err(|e| e.to_string())??;
+    if !launched { return Ok(()); }
+    // Tab ids from an earlier Chrome are gone
+    *state.cdp_target.lock().unwrap() = None;
+    *state.active_tab.lock().unwrap() = None;
+    Ok(())
+}
+
+// Looks the new tabs up for the next prompt and, with auto_switch_tabs, makes the newest one active.
+// A tab announced a moment ago often has no title yet, so a blank one is read once more after a pause.
+async fn note_opened_tabs(state: &AppState, ids: &[String]) {
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    let mut tabs = Vec::new();
+    for attempt in 0..2 {
+        if attempt > 0 { tokio::time::sleep(tokio::time::Duration::from_millis(NEW_TAB_TITLE_WAIT_MS)).await; }
+        let all = automation::chrome_cdp::get_tabs(&endpoint).await.unwrap_or_default();
+        tabs = all.into_iter().filter(|t| ids.contains(&t.id)).collect::<Vec<_>>();
+        if tabs.iter().all(|t| !t.title.is_empty()) { break; }
+    }
+    for tab in &tabs { log_info!("cdp", "action opened tab {} ({})", tab.title, tab.url); }
+    if *state.auto_switch_tabs.lock().unwrap() {
+        if let Some(newest) = tabs.last() {
+            *state.active_tab.lock().unwrap() = Some(newest.id.clone());
+            *state.cdp_target.lock().unwrap() = Some((newest.ws_url.clone(), Instant::now()));
+        }
+    }
+    *state.opened_tabs.lock().unwrap() = tabs;
+}
+
+// Calls the model again when the failure may pass: after the API's retry-after, or a doubling backoff,
+// when rate limited, overloaded or unreachable, and straight away when the reply was unusable. A rejected
+// key or request ends the run at once, since asking again can't fix it.
+async fn ask_llm<F, Fut>(state: &AppState, call: F) -> Result<ActionCommand, String>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<ActionCommand, LLMError>>,
+{
+    let mut attempt: u32 = 1;
+    loop {
+        let err = match call().await {
+            Ok(action) => return Ok(action),
+            Err(e) => e,
+        };
+        let wait = match &err {
+            LLMError::Auth(_) | LLMError::Api { .. } => return Err(err.to_string()),
+            _ if attempt >= MAX_LLM_ATTEMPTS => return Err(err.to_string()),
+            LLMError::RateLimited { retry_after: Some(after) } => *after,
+            LLMError::RateLimited { retry_after: None } | LLMError::Overloaded | LLMError::Network(_) => LLM_BACKOFF * 2u32.pow(attempt - 1),
+            LLMError::Parse(_) | LLMError::EmptyContent => Duration::ZERO,
+        }.min(MAX_LLM_BACKOFF);
+        log_warn!("llm", "{} (attempt {}/{}), asking again in {} ms", err, attempt, MAX_LLM_ATTEMPTS, wait.as_millis());
+        tokio::time::sleep(wait).await;
+        if state.cancel_requested.load(Ordering::SeqCst) { return Err("Cancelled".to_string()); }
+        attempt += 1;
+    }
+}
+
+// Adds the future's run time to the step being timed, when benchmark mode is on. A replay or undo
+// between steps counts toward the next one.
+async fn timed<T>(state: &AppState, phase: timing::Phase, fut: impl std::future::Future<Output = T>) -> T {
+    let start = Instant::now();
+    let out = fut.await;
+    if *state.benchmark_mode.lock().unwrap() {
+        let ms = start.elapsed().as_millis() as u64;
+        let mut open = state.open_timing.lock().unwrap();
+        match phase {
+            timing::Phase::Capture => open.capture_ms += ms,
+            timing::Phase::Llm => open.llm_ms += ms,
+            timing::Phase::Action => open.action_ms += ms,
+        }
+    }
+    out
+}
+
+fn close_timing(state: &AppState, action_type: &str) {
+    if !*state.benchmark_mode.lock().unwrap() { return; }
+    let mut done = std::mem::take(&mut *state.open_timing.lock().unwrap());
+    let mut timings = state.step_timings.lock().unwrap();
+    done.step = timings.len() + 1;
+    done.action_type = action_type.to_string();
+    timings.push(done);
+}
+
+// Page point for a browser target, or a real screen point for "screen:x,y"
+async fn press_point(conn: &ChromeConnection, target: &str) -> Result<(f64, f64, bool), String> {
+    match target.strip_prefix("screen:") {
+        Some(c) => automation::chrome_cdp::parse_coords(c).map(|(x, y)| (x, y, true)).map_err(|e| e.to_string()),
+        None => conn.target_center(target).await.map(|(x, y)| (x, y, false)).map_err(|e| e.to_string()),
+    }
+}
+
+async fn mouse_half(conn: Option<&ChromeConnection>, held: &HeldMouse, x: f64, y: f64, down: bool) -> Result<(), String> {
+    if held.screen {
+        let button = held.button.clone();
+        return tokio::task::spawn_blocking(move || automation::desktop::mouse_button(&button, down, x as i32, y as i32)).await.map_err(|e| e.to_string())?;
+    }
+    let conn = conn.ok_or("No browser connection")?;
+    let result = if down { conn.mouse_press(x, y, &held.button).await } else { conn.mouse_release(x, y, &held.button).await };
+    result.map_err(|e| e.to_string())
+}
+
+// Browser zoom by default: params.level sets it outright, params.direction in/out steps by ZOOM_STEP and
+// reset goes back to 100%. With params.title the named desktop window is zoomed instead.
+async fn zoom(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<String, String> {
+    let params = action.params.clone().unwrap_or_default();
+    let direction = params["direction"].as_str().unwrap_or("in").to_string();
+    if let Some(title) = params["title"].as_str().map(str::to_string) {
+        let steps = params["steps"].as_u64().unwrap_or(1) as u32;
+        let dir = direction.clone();
+        let window = tokio::task::spawn_blocking(move || automation::desktop::zoom_window(Some(&title), &dir, steps)).await.map_err(|e| e.to_string())??;
+        return Ok(format!("Zoomed {} window: {}", direction, window));
+    }
+    let current = *state.zoom_level.lock().unwrap();
+    let level = match (params["level"].as_f64(), direction.as_str()) {
+        (Some(level), _) => level,
+        (None, "in") => current * ZOOM_STEP,
+        (None, "out") => current / ZOOM_STEP,
+        (None, "reset") => 1.0,
+        (None, other) => return Err(format!("zoom direction must be in, out or reset, not \"{}\"", other)),
+    };
+    let level = (level.clamp(MIN_ZOOM, MAX_ZOOM) * 100.0).round() / 100.0;
+    conn.set_zoom(level).await.map_err(|e| e.to_string())?;
+    *state.zoom_level.lock().unwrap() = level;
+    Ok(format!("Page zoom: {}%", (level * 100.0).round()))
+}
+
+// The page's readable text, or with params.title a desktop window's, cut to page_text_chars. Returned
+// as output, so the next prompt shows it as LAST_READ.
+async fn page_text(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<String, String> {
+    let limit = *state.page_text_chars.lock().unwrap();
+    let text = match action.params.as_ref().and_then(|p| p["title"].as_str()).map(str::to_string) {
+        Some(title) => tokio::task::spawn_blocking(move || automation::desktop::window_text(Some(&title), limit)).await.map_err(|e| e.to_string())??,
+        None => conn.page_text().await.map_err(|e| e.to_string())?,
+    };
+    if text.trim().is_empty() { return Err("No readable text found".to_string()); }
+    let total = text.chars().count();
+    if total <= limit { return Ok(text); }
+    Ok(format!("{}\n[truncated: showing {} of {} characters]", text.chars().take(limit).collect::<String>(), limit, total))
+}
+
+// Tab indexes follow the list shown to the model. Each branch updates active_tab and the cached
+// target together, so the next connect_browser lands on the tab the action meant.
+async fn tab_action(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<String, String> {
+    let params = action.params.clone().unwrap_or_default();
+    let endpoint = state.cdp_endpoint.lock().unwrap().clone();
+    let tabs = automation::chrome_cdp::get_tabs(&endpoint).await.map_err(|e| e.to_string())?;
+    let active = state.active_tab.lock().unwrap().clone();
+    let active_index = tabs.iter().position(|t| Some(&t.id) == active.as_ref()).unwrap_or(0);
+    let by_index = |i: u64| tabs.get(i as usize).ok_or(format!("No tab {}; there are {}", i, tabs.len()));
+
+    match action.action_type.as_str() {
+        "switch_tab" => {
+            let tab = match (params["index"].as_u64(), params["title"].as_str()) {
+                (Some(i), _) => by_index(i)?,
+                (None, Some(title)) => {
+                    let needle = title.to_lowercase();
+                    tabs.iter().find(|t| t.title.to_lowercase().contains(&needle) || t.url.to_lowercase().contains(&needle))
+                        .ok_or(format!("No tab matching \"{}\"", title))?
+                }
+                (None, None) => return Err("switch_tab needs params.index or params.title".to_string()),
+            };
+            conn.activate_tab(&tab.id).await.map_err(|e| e.to_string())?;
+            *state.active_tab.lock().unwrap() = Some(tab.id.clone());
+            *state.cdp_target.lock().unwrap() = Some((tab.ws_url.clone(), Instant::now()));
+            Ok(format!("Switched to tab: {} ({})", tab.title, tab.url))
+        }
+        "new_tab" => {
+            let url = params["url"].as_str().unwrap_or("about:blank");
+            let id = conn.new_tab(url).await.map_err(|e| e.to_string())?;
+            // The new tab's socket URL isn't known yet; connect_active_tab looks it up by id
+            *state.active_tab.lock().unwrap() = Some(id);
+            *state.cdp_target.lock().unwrap() = None;
+            Ok(format!("Opened new tab: {}", url))
+        }
+        _ => {
+            let index = params["index"].as_u64().unwrap_or(active_index as u64);
+            let tab = by_index(index)?;
+            if tabs.len() == 1 { return Err("Refusing to close the last tab".to_string()); }
+            conn.close_tab(&tab.id).await.map_err(|e| e.to_string())?;
+            if index as usize == active_index {
+                *state.active_tab.lock().unwrap() = None;
+                *state.cdp_target.lock().unwrap() = None;
+            }
+            Ok(format!("Closed tab: {} ({})", tab.title, tab.url))
+        }
+    }
+}
+
+// mouse_down and a later mouse_up form one held gesture (press-and-hold, or a drag when mouse_up has
+// another target). Only one button can be held; mouse_up without a target releases where it went down.
+async fn mouse_gesture(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<String, String> {
+    let target = action.target.as_str().unwrap_or("").trim();
+    let held = state.held_mouse.lock().unwrap().clone();
+    if action.action_type == "mouse_down" {
+        if let Some(h) = held { return Err(format!("The {} button is still held; send mouse_up first", h.button)); }
+        let button = action.params.as_ref().and_then(|p| p["button"].as_str()).unwrap_or("left").to_string();
+        if !matches!(button.as_str(), "left" | "right" | "middle") { return Err(format!("Unknown mouse button: {}", button)); }
+        let (x, y, screen) = press_point(conn, target).await?;
+        let held = HeldMouse { button, x, y, screen };
+        // Page presses are outlined by the connection itself
+        if screen && *state.highlight_actions.lock().unwrap() {
+            let (px, py) = (x as i32, y as i32);
+            let flashed = tokio::task::spawn_blocking(move || automation::desktop::flash_rect(px - 12, py - 12, 24, 24, automation::chrome_cdp::HIGHLIGHT_MS)).await.map_err(|e| e.to_string())?;
+            if let Err(e) = flashed { log_warn!("desktop", "click highlight failed: {}", e); }
+        }
+        mouse_half(Some(conn), &held, x, y, true).await?;
+        let msg = format!("Holding the {} button at {:.0},{:.0}", held.button, x, y);
+        *state.held_mouse.lock().unwrap() = Some(held);
+        return Ok(msg);
+    }
+    let held = held.ok_or("mouse_up without a mouse_down before it")?;
+    let (x, y) = if target.is_empty() {
+        (held.x, held.y)
+    } else {
+        let (x, y, screen) = press_point(conn, target).await?;
+        if screen != held.screen { return Err("mouse_up must target the same surface (page or screen:) as its mouse_down".to_string()); }
+        (x, y)
+    };
+    // Stays held if the release fails, so cleanup still lets go of it
+    mouse_half(Some(conn), &held, x, y, false).await?;
+    *state.held_mouse.lock().unwrap() = None;
+    Ok(format!("Released the {} button at {:.0},{:.0}", held.button, x, y))
+}
+
+// Lets go of a button an unfinished gesture left down, so a cancelled or failed run can't leave it stuck
+async fn release_held_mouse(state: &AppState) {
+    let Some(held) = state.held_mouse.lock().unwrap().take() else { return };
+    let conn = if held.screen { None } else { connect_browser(state).await.ok() };
+    match mouse_half(conn.as_ref(), &held, held.x, held.y, false).await {
+        Ok(()) => log_info!("mouse", "released the held {} button", held.button),
+        Err(e) => log_warn!("mouse", "could not release the held {} button: {}", held.button, e),
+    }
+}
+
+// One action on an open connection, after the safety check
+// A hung page or app fails the attempt after the action timeout, so the usual retry takes over instead of
+// the loop stalling. A wait gets its own params.timeout on top; registered handlers keep their own limits.
+async fn perform(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<Option<String>, String> {
+    let handler = state.actions.lock().unwrap().get(&action.action_type);
+    if let Some(handler) = handler {
+        return handler.execute(&action.target, action.params.as_ref()).await;
+    }
+    let waits_ms = action.params.as_ref().and_then(|p| p["timeout"].as_u64()).filter(|_| action.action_type == "wait").unwrap_or(0);
+    let limit = Duration::from_secs(*state.action_timeout_secs.lock().unwrap()) + Duration::from_millis(waits_ms);
+    match tokio::time::timeout(limit, dispatch(conn, action, state)).await {
+        Ok(result) => result,
+        Err(_) => {
+            log_warn!("action", "{} on {} timed out after {} s", action.action_type, action.target, limit.as_secs());
+            Err(format!("{} timed out after {} s", action.action_type, limit.as_secs()))
+        }
+    }
+}
+
+async fn dispatch(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<Option<String>, String> {
+    let output = if action.action_type == "fill_credential" {
+        fill_credential(conn, action, state).await?;
+        None
+    } else if matches!(action.action_type.as_str(), "mouse_down" | "mouse_up") {
+        Some(mouse_gesture(conn, action, state).await?)
+    } else if action.action_type == "zoom" {
+        Some(zoom(conn, action, state).await?)
+    } else if action.action_type == "get_page_text" {
+        Some(page_text(conn, action, state).await?)
+    } else if matches!(action.action_type.as_str(), "switch_tab" | "new_tab" | "close_tab") {
+        Some(tab_action(conn, action, state).await?)
+    } else if action.action_type == "open_url" {
+        // Chrome is up by now (see ensure_chrome), so the rest is a plain navigate
+        conn.execute_llm_action("navigate", &action.target, action.params.as_ref())
+            .await
+            .map_err(|e| e.to_string())?
+    } else if automation::desktop::ACTIONS.contains(&action.action_type.as_str()) {
+        // Only reached from skill steps; execute_browser_action runs these before connecting
+        let moved = action.clone();
+        Some(tokio::task::spawn_blocking(move || automation::desktop::execute(&moved)).await.map_err(|e| e.to_string())??)
+    } else {
+        conn.execute_llm_action(&action.action_type, &action.target, action.params.as_ref())
+            .await
+            .map_err(|e| e.to_string())?
+    };
+
+    let banners = state.banners.lock().unwrap().clone();
+    if banners.enabled && matches!(action.action_type.as_str(), "navigate" | "open_url") {
+        match conn.dismiss_banners(&banners.phrases).await {
+            Ok(Some(label)) => log_info!("banners", "dismissed \"{}\" on {}", label, conn.get_url().await.unwrap_or_default()),
+            Ok(None) => {}
+            Err(e) => log_warn!("banners", "dismissal failed: {}", e),
+        }
+    }
+    Ok(output)
+}
+
+// Splices a saved skill into the current step. Every action still goes through the safety policy;
+// skills can't nest. Returns the last output any step produced.
+async fn run_skill(conn: &ChromeConnection, action: &ActionCommand, state: &AppState) -> Result<Option<String>, String> {
+    let params = action.params.clone().unwrap_or_default();
+    let name = params["name"].as_str().ok_or("run_skill needs params.name")?;
+    let skill = skills::find(&app_dir()?, name)?;
+    let args = params["args"].as_object().cloned().unwrap_or_default();
+    let actions = skill.instantiate(&args)?;
+
+    let mut output = None;
+    for (i, step) in actions.iter().enumerate() {
+        if state.cancel_requested.load(Ordering::SeqCst) { return Err("Cancelled".to_string()); }
+        if step.action_type == "run_skill" { return Err(format!("Skill \"{}\" step {} runs another skill", name, i + 1)); }
+        state.safety.lock().unwrap().check(step)?;
+        let filtered = state.action_filter.lock().unwrap().check(&step.action_type);
+        if let Err(e) = filtered { return Err(record_blocked(state, step, &format!("skill \"{}\" step {}: {}", name, i + 1, e))); }
+        let out = perform(conn, step, state).await.map_err(|e| format!("Skill \"{}\" step {} ({}) failed: {}", name, i + 1, step.action_type, e))?;
+        output = out.or(output);
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    }
+    log_info!("skills", "ran \"{}\" ({} actions)", name, skill.actions.len());
+    Ok(output)
 }
 
 fn main() {
+    // --goal runs headless, without the Tauri event loop
+    if let Some(code) = cli::run() { std::process::exit(code); }
+
     tauri::Builder::default()
         .setup(|app| {
-            app.manage(AppState {
-                api_key: Mutex::new(None),
-                history: Mutex::new(Vec::new()),
-                pending_action: Mutex::new(None),
-                current_goal: Mutex::new(None),
-            });
+            app.manage(AppState::load());
+
+            logging::attach(app.handle());
+
+            if let Err(e) = register_panic_hotkey(&app.handle(), DEFAULT_PANIC_HOTKEY) {
+                log_error!("hotkey", "Failed to register panic hotkey: {}", e);
+            }
             
+            // MCP clients talk over stdio and have no use for the window
+            if std::env::args().any(|a| a == "--mcp") {
+                if let Some(window) = app.get_window("main") { let _ = window.hide(); }
+                mcp::start(app.handle());
+            }
+
+            let serve = std::env::args().find_map(|a| a.strip_prefix("--serve").filter(|rest| rest.is_empty() || rest.starts_with('=')).map(|rest| rest.trim_start_matches('=').to_string()));
+            if let Some(port) = serve {
+                let port = port.parse().unwrap_or(DEFAULT_API_PORT);
+                match std::env::var(API_TOKEN_ENV) {
+                    Ok(token) => {
+                        let handle = app.handle();
+                        // Binding needs the async runtime, which setup doesn't run on
+                        tauri::async_runtime::spawn(async move {
+                            match api::start(handle.clone(), port, token) {
+                                Ok(()) => *handle.state::<AppState>().api_port.lock().unwrap() = Some(port),
+                                Err(e) => log_error!("api", "{}", e),
+                            }
+                        });
+                    }
+                    Err(_) => log_error!("api", "--serve needs the API token in {}", API_TOKEN_ENV),
+                }
+            }
+
             // Try to launch Chrome with debugging
-            std::thread::spawn(|| {
-                let _ = automation::chrome_cdp::launch_chrome_with_debugging(9222);
+            let handle = app.handle();
+            std::thread::spawn(move || {
+                if let Err(e) = automation::chrome_cdp::launch_chrome_with_debugging(CdpEndpoint::default().port, &LaunchOptions::default()) {
+                    log_error!("chrome", "Chrome launch: {}", e);
+                    let _ = handle.emit_all("chrome_launch_error", e.to_string());
+                }
             });
             
             Ok(())
@@ -282,14 +2965,703 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             save_api_key,
             load_api_key,
+            save_profile,
+            activate_profile,
+            list_profiles,
+            set_model,
+            set_max_tokens,
             get_current_state,
+            set_ocr_fallback,
+            set_full_page_screenshots,
+            set_log_format,
+            get_logs,
+            set_log_buffer_size,
+            get_banner_config,
+            set_banner_config,
+            capture_full_page_screenshot,
+            capture_element_screenshot,
+            save_page_pdf,
+            save_browser_session,
+            restore_browser_session,
+            configure_emulation,
+            configure_network,
+            configure_locale,
+            set_blocked_url_patterns,
+            set_typing_delay_ms,
+            set_page_text_limit,
+            set_vision_mode,
+            set_ui_settle,
+            set_narration,
+            set_auto_switch_tabs,
+            set_highlight_actions,
+            set_headless,
+            set_cdp_endpoint,
             execute_user_command,
             approve_action,
+            approve_action_with_feedback,
+            approve_step,
+            choose_alternative,
+            resume_execution,
+            pause_execution,
+            send_hint,
+            capture_click_target,
+            set_action_timeout,
+            set_retry_delay,
+            get_handoff_config,
+            set_handoff_config,
+            store_credential,
+            list_credentials,
+            delete_credential,
+            get_execution_config,
+            set_execution_config,
             get_history,
+            get_hint_log,
+            search_history,
+            filter_history,
+            get_session_summary,
+            set_benchmark_mode,
+            get_timing_report,
+            cancel_execution,
+            get_safety_policy,
+            set_safety_policy,
+            get_action_filter,
+            set_allowed_actions,
+            set_blocked_actions,
+            get_stop_words,
+            set_stop_words,
+            set_panic_hotkey,
             clear_history,
+            set_summarize_after,
+            set_history_limit,
+            set_store_step_screenshots,
+            get_step_screenshot,
+            replay_step,
+            undo_last_action,
+            save_skill,
+            list_skills,
+            get_plan,
+            get_last_result,
+            revise_plan,
+            enqueue_goals,
+            start_api_server,
+            set_completion_webhook,
+            export_playwright,
+            get_queue,
+            clear_queue,
             take_screenshot_to_clipboard,
+            list_monitors,
             get_screen_a11y_tree
         ])
-        .run(tauri::generate_context!())
-        .expect("error running app");
-}
\ No newline at end of file
+        .build(tauri::generate_context!())
+        .expect("error building app")
+        .run(|app, event| {
+            // Don't leave keys or a mouse button pressed for the rest of the OS after we're gone
+            if let RunEvent::Exit = event {
+                if !cfg!(windows) { return; }
+                let _ = automation::desktop::release_all_modifiers();
+                let held = app.state::<AppState>().held_mouse.lock().unwrap().take();
+                if let Some(held) = held.filter(|h| h.screen) {
+                    let _ = automation::desktop::mouse_button(&held.button, false, held.x as i32, held.y as i32);
+                }
+            }
+        });
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn app_state() -> AppState {
+        AppState::new(String::new(), ProfileStore::default(), HashMap::new(), plugins::ActionRegistry::default())
+    }
+
+    #[tokio::test]
+    async fn blocked_actions_are_rejected_before_any_backend_is_reached() {
+        let state = app_state();
+        // Stands in for Chrome's debugger port; nothing may connect to it
+        let chrome = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        chrome.set_nonblocking(true).unwrap();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.local_addr().unwrap().port() };
+        let marker = std::env::temp_dir().join(format!("pc-agent-safe-mode-{}", std::process::id()));
+        let blocked = [
+            ActionCommand { action_type: "run_command".to_string(), target: json!(format!("touch {}", marker.display())), ..Default::default() },
+            ActionCommand { action_type: "launch".to_string(), target: json!("calc.exe"), ..Default::default() },
+            ActionCommand { action_type: "eval_js".to_string(), target: json!(""), params: Some(json!({"code": "fetch('https://evil.example')"})), ..Default::default() },
+        ];
+        for action in &blocked {
+            let err = execute_browser_action(action, &state).await.unwrap_err();
+            // Blocked, so the retry loop gives up at once instead of re-prompting
+            assert!(err.starts_with(&format!("{}Safe mode: ", BLOCKED_PREFIX)), "{}: {}", action.action_type, err);
+        }
+        assert!(!marker.exists());
+        assert_eq!(chrome.accept().unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+        let history = state.history.lock().unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history.iter().all(|h| !h.success && h.llm_reasoning == "blocked_action"));
+    }
+
+    #[test]
+    fn a11y_depth_is_clamped_and_unknown_scopes_are_rejected() {
+        let walks = |depth, levels: u32| a11y_script(depth, None).unwrap().contains(&format!("if ($depth -gt {} -or", levels));
+        assert!(walks(None, 4));
+        assert!(walks(Some(0), 1));
+        assert!(walks(Some(6), 6));
+        assert!(walks(Some(99), 20));
+        assert!(a11y_script(Some(99), None).unwrap().contains("ConvertTo-Json -Depth 43"));
+        assert!(a11y_script(None, Some("windows")).unwrap().contains("[FgWindow]::Visible()"));
+        assert_eq!(a11y_script(None, Some("galaxy")).unwrap_err(), "Unknown scope: galaxy");
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn a_deeper_a11y_scan_finds_more_nodes() {
+        let nodes = |tree: String| tree.matches("\"type\":").count();
+        let shallow = nodes(get_screen_a11y_tree(Some(2), None).await.unwrap());
+        let deep = nodes(get_screen_a11y_tree(Some(6), None).await.unwrap());
+        assert!(shallow > 0);
+        assert!(deep > shallow, "depth 2: {} nodes, depth 6: {}", shallow, deep);
+    }
+
+    fn entry(ts: &str, action_type: &str, params: serde_json::Value, success: bool, cost: Option<(u32, u32, f64)>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: ts.to_string(),
+            user_input: None,
+            llm_reasoning: String::new(),
+            action: ActionCommand {
+                action_type: action_type.to_string(),
+                params: Some(params),
+                llm_cost: cost.map(|(input_tokens, output_tokens, cost_usd)| ai::claude::LlmCost { input_tokens, output_tokens, cost_usd, ..Default::default() }),
+                ..Default::default()
+            },
+            success,
+            error: None,
+            screenshot_base64: None,
+            screenshot_path: None,
+            output: None,
+            target_screenshot_base64: None,
+            confidence: None,
+        }
+    }
+
+    // Two browser steps and two desktop steps, one of each failing, 30 s apart
+    fn mixed_history() -> Vec<HistoryEntry> {
+        vec![
+            entry("2024-05-01T10:00:00Z", "click", json!({}), true, Some((1000, 100, 0.01))),
+            entry("2024-05-01T10:00:30Z", "switch_to_app", json!({}), false, Some((2000, 50, 0.03))),
+            entry("2024-05-01T10:01:00Z", "click", json!({}), false, None),
+            entry("2024-05-01T10:01:30Z", "get_page_text", json!({"title": "Notepad"}), true, Some((500, 20, 0.005))),
+        ]
+    }
+
+    #[test]
+    fn step_mode_separates_window_actions_from_page_actions() {
+        let mode = |t: &str, p: serde_json::Value| step_mode(&entry("", t, p, true, None).action);
+        assert_eq!(mode("alt_tab", json!({})), "desktop");
+        assert_eq!(mode("zoom", json!({"title": "Excel"})), "desktop");
+        assert_eq!(mode("zoom", json!({"direction": "in"})), "browser");
+        assert_eq!(mode("switch_tab", json!({"title": "Inbox"})), "browser");
+    }
+
+    #[test]
+    fn summary_buckets_cost_by_mode() {
+        let summary = session_summary(&mixed_history(), 0);
+        assert_eq!(summary.steps, 4);
+        assert_eq!(summary.succeeded, 2);
+        let modes: Vec<(&str, u64, u64)> = summary.per_mode.iter().map(|m| (m.mode.as_str(), m.input_tokens, m.output_tokens)).collect();
+        assert_eq!(modes, vec![("browser", 1000, 100), ("desktop", 2500, 70)]);
+        assert!((summary.per_mode[1].cost_usd - 0.035).abs() < 1e-9);
+    }
+
+    #[test]
+    fn summary_times_and_ranks_steps() {
+        let summary = session_summary(&mixed_history(), 0);
+        assert_eq!(summary.duration_secs, 90.0);
+        assert_eq!(summary.avg_step_gap_secs, 30.0);
+        let click = summary.per_action.iter().find(|s| s.action_type == "click").unwrap();
+        assert_eq!((click.total, click.succeeded, click.success_rate), (2, 1, 0.5));
+        let costly = summary.most_expensive_step.unwrap();
+        assert_eq!((costly.step, costly.action_type.as_str()), (2, "switch_to_app"));
+    }
+
+    #[test]
+    fn empty_history_summarizes_to_zero() {
+        let summary = session_summary(&[], 0);
+        assert_eq!((summary.steps, summary.duration_secs, summary.avg_step_gap_secs), (0, 0.0, 0.0));
+        assert!(summary.per_mode.is_empty() && summary.most_expensive_step.is_none());
+    }
+
+    #[test]
+    fn archived_steps_shift_step_numbers() {
+        let summary = session_summary(&mixed_history(), 40);
+        assert_eq!((summary.steps, summary.archived_steps), (44, 40));
+        assert_eq!(summary.most_expensive_step.unwrap().step, 42);
+    }
+
+    #[test]
+    fn filters_combine_success_mode_and_action() {
+        let history = mixed_history();
+        let pick = |success, mode, action_type| -> Vec<usize> {
+            history.iter().enumerate().filter(|(_, h)| history_matches(h, success, mode, action_type)).map(|(i, _)| i).collect()
+        };
+        assert_eq!(pick(Some(false), Some("browser"), Some("click")), vec![2]);
+        assert_eq!(pick(None, Some("desktop"), None), vec![1, 3]);
+        assert_eq!(pick(Some(true), None, None), vec![0, 3]);
+        assert_eq!(pick(None, None, Some("scroll")), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn search_ignores_case_and_looks_at_target_and_error() {
+        let mut history = mixed_history();
+        history[0].user_input = Some("Write a note".to_string());
+        history[0].action.target = json!("#Search-Box");
+        history[1].error = Some("No window title contains \"Notepad\"".to_string());
+        history[2].llm_reasoning = "Open the search results".to_string();
+        history[3].action.target = json!({"x": 10, "y": 20});
+        let found = |q: &str| -> Vec<usize> { history.iter().enumerate().filter(|(_, h)| history_contains(h, q)).map(|(i, _)| i).collect() };
+        assert_eq!(found("SEARCH"), vec![0, 2]);
+        assert_eq!(found(" notepad "), vec![1]);
+        assert_eq!(found("switch_TO"), vec![1]);
+        assert_eq!(found("\"y\":20"), vec![3]);
+        assert_eq!(found("calculator"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn filter_history_numbers_steps_and_rejects_unknown_modes() {
+        let state = app_state();
+        *state.history.lock().unwrap() = mixed_history();
+        state.archived_history.lock().unwrap().count = 10;
+        let steps: Vec<usize> = filtered_history(&state, Some(false), None, None).unwrap().iter().map(|s| s.step).collect();
+        assert_eq!(steps, vec![12, 13]);
+        assert_eq!(filtered_history(&state, None, Some("mobile"), None).unwrap_err(), "Unknown mode \"mobile\"; use browser or desktop");
+    }
+
+    // A webhook receiver answering every POST with `status`; returns its URL and the JSON bodies it got
+    fn webhook_receiver(status: u16) -> (String, std::sync::Arc<Mutex<Vec<serde_json::Value>>>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let bodies = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let body = loop {
+                    let Ok(n) = stream.read(&mut buf) else { break None };
+                    if n == 0 { break None; }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else { continue };
+                    let length = head.lines().find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap_or(0))).unwrap_or(0);
+                    if body.len() >= length { break Some(body.to_string()); }
+                };
+                if let Some(body) = body { received.lock().unwrap().push(serde_json::from_str(&body).unwrap()); }
+                let _ = write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+            }
+        });
+        (url, bodies)
+    }
+
+    fn finished_page() -> ExecutionState {
+        serde_json::from_value(json!({"screenshot_base64": "", "accessibility_tree": [], "active_window": "Order placed", "url": "https://shop.example/done", "success": true, "error": null})).unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_webhook_gets_the_outcome_of_each_kind_of_run() {
+        let state = app_state();
+        let (url, bodies) = webhook_receiver(200);
+        *state.completion_webhook.lock().unwrap() = Some(url);
+        *state.current_goal.lock().unwrap() = Some("order a pizza".to_string());
+        // A step from an earlier run, then this run's two
+        state.history.lock().unwrap().extend([
+            entry("2024-05-01T09:00:00Z", "click", json!({}), true, Some((100, 10, 0.5))),
+            entry("2024-05-01T10:00:00Z", "click", json!({}), true, Some((1000, 100, 0.01))),
+            entry("2024-05-01T10:00:30Z", "complete", json!({"summary": "Ordered"}), true, Some((2000, 50, 0.03))),
+        ]);
+        notify_completion(&state, 1, &Ok(finished_page())).await;
+        state.history.lock().unwrap().pop();
+        notify_completion(&state, 1, &Ok(finished_page())).await;
+        notify_completion(&state, 1, &Err("Failed after 3 attempts: no button".to_string())).await;
+
+        let bodies = bodies.lock().unwrap();
+        let got: Vec<(&str, bool, u64, Option<&str>)> = bodies.iter().map(|b| (b["outcome"].as_str().unwrap(), b["success"].as_bool().unwrap(), b["step_count"].as_u64().unwrap(), b["error"].as_str())).collect();
+        assert_eq!(got, vec![
+            ("complete", true, 2, None),
+            ("max_steps", false, 1, None),
+            ("failed", false, 1, Some("Failed after 3 attempts: no button")),
+        ]);
+        assert!((bodies[0]["total_cost"].as_f64().unwrap() - 0.04).abs() < 1e-9);
+        assert!((bodies[2]["total_cost"].as_f64().unwrap() - 0.01).abs() < 1e-9);
+        assert_eq!((bodies[0]["goal"].as_str(), bodies[0]["url"].as_str(), bodies[0]["active_window"].as_str()), (Some("order a pizza"), Some("https://shop.example/done"), Some("Order placed")));
+        assert!(bodies[2]["url"].is_null());
+    }
+
+    #[tokio::test]
+    async fn a_failing_webhook_is_tried_twice_and_leaves_the_result_alone() {
+        let state = app_state();
+        let (url, bodies) = webhook_receiver(500);
+        *state.completion_webhook.lock().unwrap() = Some(url);
+        state.history.lock().unwrap().push(entry("2024-05-01T10:00:00Z", "complete", json!({}), true, None));
+        let result = Ok(finished_page());
+        notify_completion(&state, 0, &result).await;
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+        assert_eq!(result.map(|s| s.active_window), Ok::<_, String>("Order placed".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_held_button_is_released_where_mouse_up_points() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        let state = app_state();
+        let conn = chrome.connect().await;
+        let gesture = |action_type: &str, target: &str| ActionCommand { action_type: action_type.to_string(), target: json!(target), ..Default::default() };
+        perform(&conn, &gesture("mouse_down", "coords:40,60"), &state).await.unwrap();
+        assert!(state.held_mouse.lock().unwrap().is_some());
+        assert_eq!(perform(&conn, &gesture("mouse_down", "coords:10,10"), &state).await, Err("The left button is still held; send mouse_up first".to_string()));
+
+        // Releasing somewhere else turns the pair into a drag
+        perform(&conn, &gesture("mouse_up", "coords:100,60"), &state).await.unwrap();
+        let events: Vec<(String, serde_json::Value, serde_json::Value)> = chrome.calls("Input.dispatchMouseEvent").iter()
+            .map(|e| (e["type"].as_str().unwrap().to_string(), e["x"].clone(), e["y"].clone()))
+            .collect();
+        assert_eq!(events.first().unwrap(), &("mousePressed".to_string(), json!(40.0), json!(60.0)));
+        assert_eq!(events.last().unwrap(), &("mouseReleased".to_string(), json!(100.0), json!(60.0)));
+        assert!(state.held_mouse.lock().unwrap().is_none());
+        assert_eq!(perform(&conn, &gesture("mouse_up", ""), &state).await, Err("mouse_up without a mouse_down before it".to_string()));
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_drag_releases_the_held_button() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.port() };
+        let conn = chrome.connect().await;
+        let down = ActionCommand { action_type: "mouse_down".to_string(), target: json!("coords:40,60"), ..Default::default() };
+        dispatch(&conn, &down, &state).await.unwrap();
+        assert!(state.held_mouse.lock().unwrap().is_some());
+
+        // What cancel_execution does once the loop is told to stop; the run never sent its mouse_up
+        signal_cancel(&state);
+        release_inputs(&state).await;
+        let events: Vec<(String, serde_json::Value, serde_json::Value)> = chrome.calls("Input.dispatchMouseEvent").iter()
+            .map(|e| (e["type"].as_str().unwrap().to_string(), e["x"].clone(), e["y"].clone()))
+            .collect();
+        assert_eq!(events.first().unwrap().0, "mousePressed");
+        assert_eq!(events.last().unwrap(), &("mouseReleased".to_string(), json!(40.0), json!(60.0)));
+        assert!(state.held_mouse.lock().unwrap().is_none());
+        // The release came over a fresh session to the active tab
+        assert_eq!(chrome.calls("GET"), vec![json!("/json")]);
+        // Nothing is left to release a second time
+        release_inputs(&state).await;
+        assert_eq!(chrome.calls("Input.dispatchMouseEvent").len(), events.len());
+    }
+
+    #[tokio::test]
+    async fn back_to_back_connects_reuse_the_tab_lookup() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.port() };
+        let lookups = || chrome.calls("GET").len();
+        connect_browser(&state).await.unwrap();
+        connect_browser(&state).await.unwrap();
+        assert_eq!(lookups(), 1);
+        assert_eq!(state.active_tab.lock().unwrap().as_deref(), Some("mock"));
+
+        // What set_headless does, since a relaunched Chrome has new tab ids
+        *state.cdp_target.lock().unwrap() = None;
+        connect_browser(&state).await.unwrap();
+        assert_eq!(lookups(), 2);
+
+        // So does the TTL running out
+        if let Some((_, at)) = state.cdp_target.lock().unwrap().as_mut() { *at -= CDP_TARGET_TTL; }
+        connect_browser(&state).await.unwrap();
+        assert_eq!(lookups(), 3);
+        connect_browser(&state).await.unwrap();
+        assert_eq!(lookups(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_configured_locale_is_applied_on_every_connect() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.port() };
+        *state.locale_override.lock().unwrap() = Some(LocaleConfig { timezone: Some("America/Sao_Paulo".to_string()), locale: Some("pt-BR".to_string()), ..LocaleConfig::default() });
+        connect_browser(&state).await.unwrap();
+        connect_browser(&state).await.unwrap();
+        assert_eq!(chrome.calls("Emulation.setTimezoneOverride"), vec![json!({"timezoneId": "America/Sao_Paulo"}); 2]);
+        assert_eq!(chrome.calls("Emulation.setLocaleOverride"), vec![json!({"locale": "pt-BR"}); 2]);
+        assert!(chrome.calls("Emulation.setGeolocationOverride").is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_zoom_action_sets_and_remembers_the_page_level() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        let conn = chrome.connect().await;
+        let state = app_state();
+        let zoom_to = |params: serde_json::Value| ActionCommand { action_type: "zoom".to_string(), target: json!(""), params: Some(params), ..Default::default() };
+        assert_eq!(dispatch(&conn, &zoom_to(json!({"level": 1.5})), &state).await.unwrap().as_deref(), Some("Page zoom: 150%"));
+        assert_eq!(*state.zoom_level.lock().unwrap(), 1.5);
+        assert_eq!(chrome.calls("Runtime.evaluate")[0], json!({"expression": "document.documentElement.style.zoom = '1.5'"}));
+        dispatch(&conn, &zoom_to(json!({"level": 40})), &state).await.unwrap();
+        assert_eq!(*state.zoom_level.lock().unwrap(), MAX_ZOOM);
+        dispatch(&conn, &zoom_to(json!({"direction": "reset"})), &state).await.unwrap();
+        assert_eq!(*state.zoom_level.lock().unwrap(), 1.0);
+        assert!(dispatch(&conn, &zoom_to(json!({"direction": "sideways"})), &state).await.is_err());
+    }
+
+    #[test]
+    fn mark_point_outlines_the_attempted_point() {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(100, 80, image::Rgba([255, 255, 255, 255]))).write_to(&mut png, image::ImageOutputFormat::Png).unwrap();
+        let png = STANDARD.encode(png.into_inner());
+        let marked = mark_point(&png, 50.0, 40.0).unwrap();
+        let img = image::load_from_memory(&STANDARD.decode(marked).unwrap()).unwrap().to_rgba8();
+        let red = image::Rgba([255, 0, 0, 255]);
+        assert_eq!(*img.get_pixel(26, 40), red);
+        assert_eq!(*img.get_pixel(50, 64), red);
+        assert_ne!(*img.get_pixel(50, 40), red);
+        // A box past the edge is clipped rather than failing
+        let corner = image::load_from_memory(&STANDARD.decode(mark_point(&png, 0.0, 0.0).unwrap()).unwrap()).unwrap().to_rgba8();
+        assert_eq!(*corner.get_pixel(22, 0), red);
+        assert!(mark_point("not a png", 0.0, 0.0).is_none());
+    }
+
+    #[tokio::test]
+    async fn a_hint_goes_into_exactly_one_prompt() {
+        let state = app_state();
+        *state.current_goal.lock().unwrap() = Some("buy milk".to_string());
+        *state.pending_hint.lock().unwrap() = Some("the cart is top-right".to_string());
+        let with_hint = |notes: &[String]| notes.iter().filter(|n| n.starts_with("USER HINT") && n.ends_with("the cart is top-right")).count();
+        assert_eq!(with_hint(&step_notes(&state, "", "buy milk").await), 1);
+        state.history.lock().unwrap().push(entry("2024-01-01T00:00:00Z", "click", json!({}), true, None));
+        assert_eq!(with_hint(&step_notes(&state, "", "buy milk").await), 0);
+        assert_eq!(with_hint(&step_notes(&state, "", "buy milk").await), 0);
+        let log = state.hint_log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!((log[0].step, log[0].goal.as_deref(), log[0].text.as_str()), (1, Some("buy milk"), "the cart is top-right"));
+    }
+
+    #[tokio::test]
+    async fn a_hung_action_times_out_and_fails_the_step() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        // The key press is never answered, like a page stuck in a script
+        chrome.ignore("Input.dispatchKeyEvent");
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.port() };
+        *state.action_timeout_secs.lock().unwrap() = 1;
+        let action = ActionCommand { action_type: "press_key".to_string(), target: json!(""), params: Some(json!({"key": "Enter"})), ..Default::default() };
+
+        let started = std::time::Instant::now();
+        let err = execute_browser_action(&action, &state).await.unwrap_err();
+        assert_eq!(err, "press_key timed out after 1 s");
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(chrome.calls("Input.dispatchKeyEvent").len(), 1);
+
+        // What execute_with_retries does when the third attempt hangs as well
+        let goal = Some("submit the form".to_string());
+        let failed = record_failure(&Emitter(None), &state, &goal, 1, 3, &action, &err).await;
+        assert_eq!(failed, "Failed after 3 attempts: press_key timed out after 1 s");
+        let history = state.history.lock().unwrap();
+        assert_eq!(history.len(), 1);
+        let entry = &history[0];
+        assert!(!entry.success);
+        assert_eq!(entry.error.as_deref(), Some(err.as_str()));
+        assert_eq!(entry.llm_reasoning, "Failed after 3 attempts");
+        assert_eq!(entry.user_input, goal);
+        assert!(state.pending_action.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_tab_opened_by_an_action_is_reported() {
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|method, _| Ok(match method {
+            "Target.getTargets" => json!({"targetInfos": [{"targetId": "mock", "type": "page"}]}),
+            _ => json!({}),
+        })).await;
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.port() };
+        *state.auto_switch_tabs.lock().unwrap() = true;
+        // The blank page never settles, so the settle check polls until its timeout
+        *state.ui_settle.lock().unwrap() = automation::chrome_cdp::SettleConfig { interval_ms: 50, timeout_ms: 500 };
+        let click = ActionCommand { action_type: "click".to_string(), target: json!("coords:40,60"), ..Default::default() };
+
+        let (_, ()) = tokio::join!(execute_browser_action(&click, &state), async {
+            // The popup opens once the click has landed, while the page is still settling
+            chrome.wait_for_calls("Input.dispatchMouseEvent", 1).await;
+            chrome.open_tab("popup", "Sign in", "https://accounts.example/login");
+        });
+        let opened: Vec<(String, String)> = state.opened_tabs.lock().unwrap().iter().map(|t| (t.id.clone(), t.title.clone())).collect();
+        assert_eq!(opened, vec![("popup".to_string(), "Sign in".to_string())]);
+        assert_eq!(state.active_tab.lock().unwrap().as_deref(), Some("popup"));
+    }
+
+    #[test]
+    fn only_an_unexpected_foreground_window_gets_a_focus_note() {
+        let state = app_state();
+        let page = finished_page();
+        let click = ActionCommand { action_type: "click".to_string(), target: json!("#buy"), ..Default::default() };
+        // The first step has nothing to compare with
+        assert_eq!(focus_note(&state, &click, &page, "Order placed - Google Chrome".to_string()), None);
+
+        let note = focus_note(&state, &click, &page, "User Account Control".to_string()).unwrap();
+        assert!(note.starts_with("UNEXPECTED FOCUS CHANGE: after click the foreground window changed from \"Order placed - Google Chrome\" to \"User Account Control\""));
+
+        // Asked for, so not unexpected
+        let switch = ActionCommand { action_type: "switch_to_app".to_string(), target: json!("Notepad"), ..Default::default() };
+        assert_eq!(focus_note(&state, &switch, &page, "Untitled - Notepad".to_string()), None);
+
+        // Back in Chrome, whose window title follows the page
+        assert_eq!(focus_note(&state, &click, &page, "Order placed - Google Chrome - Profile 2".to_string()), None);
+        assert_eq!(state.last_foreground.lock().unwrap().as_deref(), Some("Order placed - Google Chrome - Profile 2"));
+    }
+
+    #[test]
+    fn history_stays_bounded_over_a_long_run() {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+        let state = app_state();
+        *state.history_limit.lock().unwrap() = 50;
+        let dir = std::env::temp_dir().join(format!("pc-agent-history-{}", std::process::id()));
+        for i in 0..200 {
+            let mut step = entry(&format!("step {}", i + 1), "click", json!({}), true, None);
+            step.screenshot_base64 = Some(STANDARD.encode(format!("png {}", i)));
+            push_history_in(&state, step, Some(dir.clone()));
+        }
+        let history = state.history.lock().unwrap().clone();
+        assert_eq!(history.len(), 50);
+        assert_eq!((history[0].timestamp.as_str(), history[49].timestamp.as_str()), ("step 151", "step 200"));
+        assert_eq!(history.iter().filter(|h| h.screenshot_base64.is_some()).count(), INLINE_STEP_SCREENSHOTS);
+        assert!(history[..50 - INLINE_STEP_SCREENSHOTS].iter().all(|h| h.screenshot_path.as_ref().is_some_and(|p| std::path::Path::new(p).exists())));
+        assert_eq!(state.archived_history.lock().unwrap().count, 150);
+        assert_eq!(history_len(&state), 200);
+        let archive = std::fs::read_to_string(dir.join("history.jsonl")).unwrap();
+        assert_eq!(archive.lines().count(), 150);
+        assert!(archive.lines().next().unwrap().contains("\"step 1\""));
+        assert!(!archive.contains("screenshot_base64\":\""));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn history_is_capped_without_a_session_folder() {
+        let state = app_state();
+        *state.history_limit.lock().unwrap() = 10;
+        for i in 0..200 {
+            push_history_in(&state, entry(&i.to_string(), "click", json!({}), true, None), None);
+        }
+        assert_eq!(state.history.lock().unwrap().len(), 10);
+        assert_eq!(history_len(&state), 200);
+    }
+
+    #[test]
+    fn a_structured_result_reaches_get_last_result() {
+        let state = app_state();
+        let done = |params: serde_json::Value| ActionCommand { action_type: "complete".to_string(), params: Some(params), ..Default::default() };
+        let result = json!({"cheapest": {"airline": "KLM", "price": 129.5, "currency": "EUR"}, "checked": ["KLM", "Transavia"]});
+        record_result(&state, &ActionCommand { action_type: "read_text".to_string(), params: Some(json!({"result": 1})), ..Default::default() });
+        assert_eq!(*state.last_result.lock().unwrap(), None);
+        record_result(&state, &done(json!({"summary": "Found it", "result": result})));
+        assert_eq!(*state.last_result.lock().unwrap(), Some(result.clone()));
+        // A complete without a result is plain text only
+        assert_eq!(goal_result(&done(json!({"summary": "Done"}))), None);
+        assert_eq!(goal_result(&ActionCommand { action_type: "complete".to_string(), ..Default::default() }), None);
+    }
+
+    #[test]
+    fn retry_delays_grow_with_jitter_up_to_the_cap() {
+        let ms = |attempt| retry_delay(DEFAULT_RETRY_BASE_DELAY_MS, attempt).as_millis() as u64;
+        let mut previous = 0;
+        for attempt in 1..=4 {
+            let base = DEFAULT_RETRY_BASE_DELAY_MS * attempt as u64;
+            let delay = ms(attempt);
+            // Jitter adds under a quarter, so each attempt still waits longer than the one before
+            assert!((base..base + base / 4).contains(&delay), "attempt {}: {}ms", attempt, delay);
+            assert!(delay > previous);
+            previous = delay;
+        }
+        for attempt in [10, 50, u32::MAX] {
+            assert!(ms(attempt) <= MAX_RETRY_DELAY_MS);
+        }
+        assert_eq!(ms(50), MAX_RETRY_DELAY_MS);
+        assert_eq!(retry_delay(0, 3), Duration::ZERO);
+    }
+
+    // Answers one /json/version probe the way a running Chrome does
+    fn fake_debugger() -> u16 {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let body = format!("{{\"webSocketDebuggerUrl\": \"ws://127.0.0.1:{}/devtools/browser/x\"}}", port);
+            let _ = write!(stream, "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn open_url_navigates_when_chrome_is_already_running() {
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: fake_debugger() };
+        *state.active_tab.lock().unwrap() = Some("tab-1".to_string());
+        ensure_chrome(&state).await.unwrap();
+        // Nothing was launched, so the tab the agent drives is kept
+        assert_eq!(state.active_tab.lock().unwrap().as_deref(), Some("tab-1"));
+
+        let chrome = automation::chrome_cdp::tests::MockChrome::start(|_, _| Ok(json!({}))).await;
+        let conn = chrome.connect().await;
+        let open = ActionCommand { action_type: "open_url".to_string(), target: json!(""), params: Some(json!({"url": "https://example.com/"})), ..Default::default() };
+        dispatch(&conn, &open, &state).await.unwrap();
+        assert_eq!(chrome.calls("Page.navigate"), vec![json!({"url": "https://example.com/"})]);
+    }
+
+    #[tokio::test]
+    async fn open_url_never_launches_chrome_for_a_remote_endpoint() {
+        let state = app_state();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "10.0.0.5".to_string(), port: 9222 };
+        *state.active_tab.lock().unwrap() = Some("tab-1".to_string());
+        ensure_chrome(&state).await.unwrap();
+        assert_eq!(state.active_tab.lock().unwrap().as_deref(), Some("tab-1"));
+    }
+
+    #[tokio::test]
+    async fn the_action_filter_decides_what_reaches_the_browser() {
+        let state = app_state();
+        // Stands in for Chrome's debugger port, as in the safe mode test above
+        let chrome = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        chrome.set_nonblocking(true).unwrap();
+        *state.cdp_endpoint.lock().unwrap() = CdpEndpoint { host: "127.0.0.1".to_string(), port: chrome.local_addr().unwrap().port() };
+        state.action_filter.lock().unwrap().blocked = known_actions(&state, vec!["close_tab".to_string()]).unwrap();
+        let close = ActionCommand { action_type: "close_tab".to_string(), target: json!(""), ..Default::default() };
+
+        let err = execute_browser_action(&close, &state).await.unwrap_err();
+        assert_eq!(err, format!("{}close_tab is blocked for this deployment", BLOCKED_PREFIX));
+        assert_eq!(chrome.accept().unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+        {
+            let history = state.history.lock().unwrap();
+            assert_eq!(history.len(), 1);
+            assert!(!history[0].success && history[0].llm_reasoning == "blocked_action" && history[0].action.action_type == "close_tab");
+        }
+
+        // With an allowlist only the listed actions go through
+        *state.action_filter.lock().unwrap() = ActionFilter { allowed: vec!["scroll".to_string()], blocked: Vec::new() };
+        let err = execute_browser_action(&close, &state).await.unwrap_err();
+        assert_eq!(err, format!("{}close_tab is not in the allowed actions", BLOCKED_PREFIX));
+        assert_eq!(chrome.accept().unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+
+        let scroll = ActionCommand { action_type: "scroll".to_string(), target: json!(""), params: Some(json!({"direction": "down"})), ..Default::default() };
+        let (result, reached) = tokio::join!(execute_browser_action(&scroll, &state), async {
+            for _ in 0..100 {
+                // Hanging up fails the tab lookup, so the action stops there
+                if chrome.accept().is_ok() { return true; }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            false
+        });
+        assert!(reached);
+        assert!(!result.unwrap_err().starts_with(BLOCKED_PREFIX));
+        assert_eq!(state.history.lock().unwrap().len(), 2);
+    }
+}
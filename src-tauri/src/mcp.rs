@@ -0,0 +1,219 @@
+// Model Context Protocol server over stdio, enabled with `--mcp`. Requests and responses are
+// newline-delimited JSON-RPC 2.0; tool calls run the same command functions as the webview
+// against the app's AppState. Logs go to stderr, so stdout carries protocol messages only.
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use crate::AppState;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "get_current_state",
+            "description": "Accessibility tree, title and URL of the active browser tab, plus a screenshot",
+            "inputSchema": { "type": "object", "properties": {} }
+        },
+        {
+            "name": "execute_user_command",
+            "description": "Start a goal; returns the first action the agent proposes, which approve_action runs",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "goal": { "type": "string", "description": "What the agent should achieve" } },
+                "required": ["goal"]
+            }
+        },
+        {
+            "name": "approve_action",
+            "description": "Approve (run) or reject the pending action; with feedback a rejection asks the agent for a different action",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "approved": { "type": "boolean" },
+                    "feedback": { "type": "string" }
+                },
+                "required": ["approved"]
+            }
+        },
+        {
+            "name": "take_screenshot",
+            "description": "PNG of the monitor the active window is on, of one monitor by index, or of all monitors stitched together",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "monitor_index": { "type": "integer", "description": "0-based monitor to capture instead of the active window's" },
+                    "all_monitors": { "type": "boolean" }
+                }
+            }
+        }
+    ])
+}
+
+pub fn start(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let mut stdout = tokio::io::stdout();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() { continue; }
+            let reply = match serde_json::from_str::<Value>(&line) {
+                Ok(request) => handle(request, |params| call_tool(&app, params)).await,
+                Err(e) => Some(json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": e.to_string() } })),
+            };
+            // Notifications get no reply
+            let Some(reply) = reply else { continue };
+            let mut out = reply.to_string();
+            out.push('\n');
+            if stdout.write_all(out.as_bytes()).await.is_err() || stdout.flush().await.is_err() { break; }
+        }
+        log_info!("mcp", "stdin closed, MCP server stopped");
+    });
+}
+
+// `call` runs a tools/call; it is passed in so the protocol handling doesn't need a running app
+async fn handle<F, Fut>(request: Value, call: F) -> Option<Value>
+where
+    F: FnOnce(Value) -> Fut,
+    Fut: std::future::Future<Output = Value>,
+{
+    let id = request.get("id").cloned()?;
+    let method = request["method"].as_str().unwrap_or("");
+    let result = match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "pc-automation-agent", "version": env!("CARGO_PKG_VERSION") }
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => Ok(call(request["params"].clone()).await),
+        _ => Err(json!({ "code": -32601, "message": format!("Unknown method {}", method) })),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    })
+}
+
+// Tool failures are reported in the result (isError) rather than as protocol errors, as MCP expects
+async fn call_tool(app: &AppHandle, params: Value) -> Value {
+    let args = &params["arguments"];
+    let content = match params["name"].as_str().unwrap_or("") {
+        "get_current_state" => crate::get_current_state(app.state::<AppState>()).await.map(|s| state_content(&s)),
+        "execute_user_command" => match (args["goal"].as_str(), window(app)) {
+            (Some(goal), Ok(w)) => crate::execute_user_command(goal.to_string(), w, app.state::<AppState>()).await.map(|a| vec![text(&a)]),
+            (None, _) => Err("goal is required".to_string()),
+            (_, Err(e)) => Err(e),
+        },
+        "approve_action" => match (args["approved"].as_bool(), window(app)) {
+            (Some(approved), Ok(w)) => {
+                let feedback = args["feedback"].as_str().map(|f| f.to_string());
+                crate::approve_action_with_feedback(approved, feedback, w, app.state::<AppState>()).await.map(|outcome| match outcome {
+                    crate::ApprovalOutcome::Executed(s) => state_content(&s),
+                    revised => vec![text(&revised)],
+                })
+            }
+            (None, _) => Err("approved is required".to_string()),
+            (_, Err(e)) => Err(e),
+        },
+        "take_screenshot" => {
+            let which = crate::automation::screen::Capture::from_params(args["monitor_index"].as_u64().map(|i| i as usize), args["all_monitors"].as_bool().unwrap_or(false));
+            screenshot(which).await.map(|png| vec![image(png)])
+        }
+        other => Err(format!("Unknown tool {}", other)),
+    };
+    match content {
+        Ok(content) => json!({ "content": content, "isError": false }),
+        Err(e) => json!({ "content": [{ "type": "text", "text": e }], "isError": true }),
+    }
+}
+
+fn window(app: &AppHandle) -> Result<tauri::Window, String> {
+    app.get_window("main").ok_or_else(|| "Main window is not open".to_string())
+}
+
+fn text<T: serde::Serialize>(value: &T) -> Value {
+    json!({ "type": "text", "text": serde_json::to_string_pretty(value).unwrap_or_default() })
+}
+
+fn image(png_base64: String) -> Value {
+    json!({ "type": "image", "data": png_base64, "mimeType": "image/png" })
+}
+
+// Everything but the screenshot as JSON text, then the screenshot as an image block
+fn state_content(state: &crate::ExecutionState) -> Vec<Value> {
+    let summary = json!({
+        "active_window": state.active_window,
+        "url": state.url,
+        "success": state.success,
+        "error": state.error,
+        "accessibility_tree": state.accessibility_tree,
+    });
+    let mut content = vec![text(&summary)];
+    if !state.screenshot_base64.is_empty() { content.push(image(state.screenshot_base64.clone())); }
+    content
+}
+
+async fn screenshot(which: crate::automation::screen::Capture) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+        let img = crate::automation::screen::capture(which)?;
+        let mut out = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut out, image::ImageOutputFormat::Png).map_err(|e| e.to_string())?;
+        Ok(STANDARD.encode(out.into_inner()))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    async fn echo(params: Value) -> Value {
+        json!({ "content": [{ "type": "text", "text": params["name"] }], "isError": false })
+    }
+
+    #[tokio::test]
+    async fn list_tools_and_call_tool_handshake() {
+        let init = handle(request(1, "initialize", json!({})), echo).await.unwrap();
+        assert_eq!(init["id"], 1);
+        assert_eq!(init["result"]["protocolVersion"], PROTOCOL_VERSION);
+        assert!(init["result"]["capabilities"]["tools"].is_object());
+
+        let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert!(handle(initialized, echo).await.is_none());
+
+        let list = handle(request(2, "tools/list", json!({})), echo).await.unwrap();
+        let tools = list["result"]["tools"].as_array().unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["get_current_state", "execute_user_command", "approve_action", "take_screenshot"]);
+        assert!(tools.iter().all(|t| t["inputSchema"]["type"] == "object"));
+        assert_eq!(tools[1]["inputSchema"]["required"], json!(["goal"]));
+
+        let params = json!({ "name": "take_screenshot", "arguments": { "monitor_index": 1 } });
+        let call = handle(request(3, "tools/call", params), echo).await.unwrap();
+        assert_eq!(call["id"], 3);
+        assert_eq!(call["result"]["content"][0]["text"], "take_screenshot");
+
+        let unknown = handle(request(4, "resources/list", json!({})), echo).await.unwrap();
+        assert_eq!(unknown["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn state_screenshot_is_an_image_block() {
+        let mut state: crate::ExecutionState = serde_json::from_value(json!({
+            "screenshot_base64": "", "accessibility_tree": [], "active_window": "Example",
+            "url": null, "success": true, "error": null
+        })).unwrap();
+        assert_eq!(state_content(&state).len(), 1);
+        state.screenshot_base64 = "iVBOR".to_string();
+        let content = state_content(&state);
+        assert_eq!(content[1], json!({ "type": "image", "data": "iVBOR", "mimeType": "image/png" }));
+    }
+}
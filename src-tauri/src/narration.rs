@@ -0,0 +1,74 @@
+// Spoken narration of the run for users who can't watch the screen. Phrases are built from the action
+// and its recorded reasoning, so narration costs no LLM calls. Speech runs on its own thread through
+// the OS voice (System.Speech via PowerShell on Windows, `say` on macOS); say() only queues, so the
+// execution loop never waits on it. When speech falls behind, stale phrases are dropped.
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use crate::ActionCommand;
+
+const MAX_REASON_CHARS: usize = 80;
+
+static QUEUE: OnceLock<Mutex<Sender<String>>> = OnceLock::new();
+
+pub fn say(text: &str) {
+    let queue = QUEUE.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            while let Ok(mut text) = rx.recv() {
+                // Only the latest phrase is still worth hearing
+                while let Ok(newer) = rx.try_recv() { text = newer; }
+                if let Err(e) = speak(&text) { log_warn!("narration", "speech failed: {}", e); }
+            }
+        });
+        Mutex::new(tx)
+    });
+    let _ = queue.lock().unwrap().send(crate::logging::redact(text));
+}
+
+fn speak(text: &str) -> Result<(), String> {
+    let mut cmd = if cfg!(windows) {
+        let mut c = std::process::Command::new("powershell");
+        // The text goes in through the environment, so it needs no quoting
+        c.args(["-NoProfile", "-Command", "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak($env:NARRATION)"]);
+        c.env("NARRATION", text);
+        c
+    } else if cfg!(target_os = "macos") {
+        let mut c = std::process::Command::new("say");
+        c.arg(text);
+        c
+    } else {
+        return Err("no speech engine on this platform".to_string());
+    };
+    let status = cmd.status().map_err(|e| e.to_string())?;
+    if status.success() { Ok(()) } else { Err(format!("speech exited with {}", status)) }
+}
+
+// "Clicking. Open the sign in form"
+pub fn describe(action: &ActionCommand) -> String {
+    let params = action.params.clone().unwrap_or_default();
+    let verb = match action.action_type.as_str() {
+        "click" | "double_click" | "right_click" => "Clicking".to_string(),
+        "type" => "Typing".to_string(),
+        "fill_credential" => format!("Filling in the {}", params["field"].as_str().unwrap_or("password")),
+        "navigate" | "open_url" => "Opening a page".to_string(),
+        "scroll" => format!("Scrolling {}", params["direction"].as_str().or(params["to"].as_str()).unwrap_or("down")),
+        "press_key" => format!("Pressing {}", params["key"].as_str().unwrap_or("a key")),
+        "go_back" => "Going back".to_string(),
+        "reload" => "Reloading".to_string(),
+        "wait" => "Waiting".to_string(),
+        "complete" => return format!("Done. {}", params["summary"].as_str().unwrap_or("")).trim().to_string(),
+        other => other.replace('_', " "),
+    };
+    match action.reasoning.as_deref().map(short_reason).filter(|r| !r.is_empty()) {
+        Some(reason) => format!("{}. {}", verb, reason),
+        None => verb,
+    }
+}
+
+// First sentence, cut at a word boundary
+fn short_reason(reasoning: &str) -> String {
+    let first = reasoning.split(['.', '\n']).next().unwrap_or("").trim();
+    if first.chars().count() <= MAX_REASON_CHARS { return first.to_string(); }
+    let cut: String = first.chars().take(MAX_REASON_CHARS).collect();
+    cut.rsplit_once(' ').map_or(cut.clone(), |(head, _)| head.to_string())
+}
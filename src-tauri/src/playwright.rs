@@ -0,0 +1,233 @@
+// Turns the successful steps of a run into a Playwright test. Targets Playwright can't express
+// (ax: node ids, coords:, pixel:) and actions without an equivalent become `// TODO` comments carrying the
+// original step, so the file still runs and shows what needs a human.
+use crate::{ActionCommand, HistoryEntry};
+
+fn js(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+// CSS passes through, xpath: and role: map to Playwright's xpath= and role= engines; anything else has no stable selector
+fn selector(target: &str) -> Option<String> {
+    if let Some((role, name)) = crate::automation::chrome_cdp::parse_role_target(target) {
+        return Some(js(&format!("role={}[name=\"{}\"]", role.to_lowercase(), name.replace('"', "\\\""))));
+    }
+    if target.is_empty() || target.starts_with("ax:") || target.starts_with("coords:") || target.starts_with("pixel:") || target.starts_with("ocr:") {
+        return None;
+    }
+    Some(match target.strip_prefix("xpath:") {
+        Some(xpath) => js(&format!("xpath={}", xpath)),
+        None => js(target),
+    })
+}
+
+// "Control+Shift+z" style chord for page.keyboard.press
+fn chord(key: &str, params: &serde_json::Value) -> String {
+    let mut parts: Vec<String> = params["modifiers"].as_array().map(|m| m.iter().filter_map(|v| v.as_str()).map(|name| match name.to_lowercase().as_str() {
+        "ctrl" | "control" => "Control".to_string(),
+        "meta" | "cmd" => "Meta".to_string(),
+        "alt" => "Alt".to_string(),
+        "shift" => "Shift".to_string(),
+        other => other.to_string(),
+    }).collect()).unwrap_or_default();
+    parts.push(key.to_string());
+    parts.join("+")
+}
+
+fn todo(action: &ActionCommand, why: &str) -> String {
+    format!("// TODO {}: {} on {} {}", why, action.action_type, action.target, action.params.as_ref().map(|p| p.to_string()).unwrap_or_default())
+}
+
+// One or more lines of test code for a step; Err is a warning for a step that was left out
+fn step(action: &ActionCommand) -> Result<String, String> {
+    let params = action.params.clone().unwrap_or_default();
+    let p = |k: &str| params[k].as_str().unwrap_or("").to_string();
+    let target = action.target.as_str().unwrap_or("");
+    let sel = selector(target);
+    let needs_sel = |code: &dyn Fn(&str) -> String| match &sel {
+        Some(s) => code(s),
+        None => todo(action, "no Playwright selector for this target"),
+    };
+    Ok(match action.action_type.as_str() {
+        "navigate" | "open_url" => format!("await page.goto({});", js(&p("url"))),
+        "click" => needs_sel(&|s| format!("await page.click({});", s)),
+        "double_click" => needs_sel(&|s| format!("await page.dblclick({});", s)),
+        "right_click" => needs_sel(&|s| format!("await page.click({}, {{ button: 'right' }});", s)),
+        "hover" => needs_sel(&|s| format!("await page.hover({});", s)),
+        "type" if target.is_empty() => format!("await page.keyboard.type({});", js(&p("text"))),
+        "type" if params["clear_first"].as_bool() == Some(false) => needs_sel(&|s| format!("await page.focus({}); await page.keyboard.type({});", s, js(&p("text")))),
+        "type" => needs_sel(&|s| format!("await page.fill({}, {});", s, js(&p("text")))),
+        "clear" => needs_sel(&|s| format!("await page.fill({}, '');", s)),
+        // Secrets never reach history, so the test reads them from the environment
+        "fill_credential" => {
+            let var = format!("CRED_{}_{}", p("label"), params["field"].as_str().unwrap_or("password")).to_uppercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+            needs_sel(&|s| format!("await page.fill({}, process.env.{} ?? '');", s, var))
+        }
+        "select" => {
+            let option = if params["label"].is_string() { format!("{{ label: {} }}", js(&p("label"))) } else { js(&p("value")) };
+            needs_sel(&|s| format!("await page.selectOption({}, {});", s, option))
+        }
+        "copy" | "cut" | "paste" | "select_all" => {
+            let key = match action.action_type.as_str() { "copy" => "c", "cut" => "x", "paste" => "v", _ => "a" };
+            let focus = sel.as_ref().map(|s| format!("await page.click({}); ", s)).unwrap_or_default();
+            // Playwright can't seed the OS clipboard, so pasting given text inserts it directly
+            match params["text"].as_str().filter(|_| key == "v") {
+                Some(text) => format!("{}await page.keyboard.insertText({});", focus, js(text)),
+                None => format!("{}await page.keyboard.press('ControlOrMeta+{}');", focus, key),
+            }
+        }
+        "press_key" => format!("await page.keyboard.press({});", js(&chord(&p("key"), &params))),
+        "scroll" => match params["to"].as_str() {
+            Some(to) => {
+                let end = matches!(to, "bottom" | "end" | "right");
+                let (x, y) = if params["axis"].as_str() == Some("x") { (if end { "document.body.scrollWidth" } else { "0" }, "window.scrollY") } else { ("window.scrollX", if end { "document.body.scrollHeight" } else { "0" }) };
+                format!("await page.evaluate(() => window.scrollTo({}, {}));", x, y)
+            }
+            None => {
+                let amount = params["amount"].as_f64().unwrap_or(300.0);
+                let horizontal = params["axis"].as_str() == Some("x");
+                let dir = params["direction"].as_str().unwrap_or(if horizontal { "right" } else { "down" });
+                let delta = if matches!(dir, "up" | "left") { -amount } else { amount };
+                if horizontal || matches!(dir, "left" | "right") { format!("await page.mouse.wheel({}, 0);", delta) } else { format!("await page.mouse.wheel(0, {});", delta) }
+            }
+        },
+        "wait" if params["until"].as_str() == Some("network_idle") => "await page.waitForLoadState('networkidle');".to_string(),
+        "wait" => {
+            let timeout = params["timeout"].as_u64().unwrap_or(5000);
+            needs_sel(&|s| format!("await page.waitForSelector({}, {{ timeout: {} }});", s, timeout))
+        }
+        "go_back" => "await page.goBack();".to_string(),
+        "go_forward" => "await page.goForward();".to_string(),
+        "reload" => "await page.reload();".to_string(),
+        "focus_window" => "await page.bringToFront();".to_string(),
+        // The fixture's page binding is reassigned so later steps follow the tab
+        "new_tab" => format!("page = await page.context().newPage(); await page.goto({});", js(params["url"].as_str().unwrap_or("about:blank"))),
+        "switch_tab" => match params["index"].as_u64() {
+            Some(i) => format!("page = page.context().pages()[{}]; await page.bringToFront();", i),
+            None => todo(action, "tab picked by title"),
+        },
+        "close_tab" => match params["index"].as_u64() {
+            Some(i) => format!("{{ const closing = page.context().pages()[{}]; await closing.close(); if (closing === page) page = page.context().pages()[0]; }}", i),
+            None => "{ const context = page.context(); await page.close(); page = context.pages()[0]; }".to_string(),
+        },
+        "zoom" if params["title"].is_string() => return Err("skipped zoom: desktop windows are out of Playwright's reach".to_string()),
+        "zoom" => match params["level"].as_f64() {
+            Some(level) => format!("await page.evaluate(() => document.documentElement.style.zoom = '{}');", level),
+            None => todo(action, "zoom steps depend on the level at recording time"),
+        },
+        "eval_js" => format!("await page.evaluate({});", js(&p("code"))),
+        "get_page_text" if params["title"].is_string() => return Err("skipped get_page_text: desktop windows are out of Playwright's reach".to_string()),
+        "get_page_text" => "console.log(await page.innerText('body'));".to_string(),
+        "read_text" => needs_sel(&|s| format!("console.log(await page.textContent({}));", s)),
+        "assert" => {
+            let sel = params["target"].as_str().and_then(selector).or(sel.clone());
+            match (sel, params["expected_text"].as_str()) {
+                (Some(s), Some(text)) => format!("await expect(page.locator({})).toContainText({});", s, js(text)),
+                (Some(s), None) => format!("await expect(page.locator({})).toBeVisible();", s),
+                _ => todo(action, &format!("check \"{}\"", p("condition"))),
+            }
+        }
+        "save_pdf" => {
+            let name = params["filename"].as_str().unwrap_or("page.pdf");
+            let format = match p("paper").to_lowercase().as_str() { "letter" => "Letter", "legal" => "Legal", "a3" => "A3", _ => "A4" };
+            format!("await page.pdf({{ path: {}, format: '{}', landscape: {}, printBackground: {} }});", js(name), format, params["landscape"].as_bool().unwrap_or(false), params["print_background"].as_bool().unwrap_or(true))
+        }
+        "complete" => format!("// Done: {}", p("summary")),
+        other => return Err(format!("skipped {}: no Playwright equivalent", other)),
+    })
+}
+
+// Skills are expanded into their steps; `skills` maps a run_skill step to its actions
+pub fn script(title: &str, entries: &[HistoryEntry], skills: impl Fn(&ActionCommand) -> Result<Vec<ActionCommand>, String>) -> (String, Vec<String>) {
+    let mut lines = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, entry) in entries.iter().enumerate().filter(|(_, e)| e.success) {
+        let actions = if entry.action.action_type == "run_skill" {
+            match skills(&entry.action) {
+                Ok(actions) => actions,
+                Err(e) => {
+                    lines.push(todo(&entry.action, &e));
+                    continue;
+                }
+            }
+        } else {
+            vec![entry.action.clone()]
+        };
+        if let Some(reason) = entry.action.reasoning.as_deref().filter(|r| !r.is_empty()) {
+            lines.push(format!("// Step {}: {}", i + 1, reason.replace('\n', " ")));
+        }
+        for action in &actions {
+            match step(action) {
+                Ok(code) => lines.push(code),
+                Err(w) => {
+                    lines.push(format!("// Step {} {}", i + 1, w));
+                    warnings.push(format!("step {}: {}", i + 1, w));
+                }
+            }
+        }
+    }
+    let body = lines.iter().map(|l| format!("  {}\n", l)).collect::<String>();
+    let file = format!("import {{ test, expect }} from '@playwright/test';\n\ntest({}, async ({{ page }}) => {{\n{}}});\n", js(title), body);
+    (file, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn action(action_type: &str, target: &str, params: serde_json::Value) -> ActionCommand {
+        ActionCommand { action_type: action_type.to_string(), target: json!(target), params: Some(params), ..Default::default() }
+    }
+
+    fn entry(action: ActionCommand, success: bool) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: String::new(),
+            user_input: None,
+            llm_reasoning: String::new(),
+            action,
+            success,
+            error: None,
+            screenshot_base64: None,
+            screenshot_path: None,
+            output: None,
+            target_screenshot_base64: None,
+            confidence: None,
+        }
+    }
+
+    #[test]
+    fn steps_map_selectors_to_playwright_engines() {
+        assert_eq!(step(&action("open_url", "", json!({"url": "https://example.com/"}))).unwrap(), "await page.goto(\"https://example.com/\");");
+        assert_eq!(step(&action("click", "#submit", json!({}))).unwrap(), "await page.click(\"#submit\");");
+        assert_eq!(step(&action("click", "xpath://button[1]", json!({}))).unwrap(), "await page.click(\"xpath=//button[1]\");");
+        assert_eq!(step(&action("click", "role:Button|name:Save \"draft\"", json!({}))).unwrap(), r#"await page.click("role=button[name=\"Save \\\"draft\\\"\"]");"#);
+        assert_eq!(step(&action("type", "#q", json!({"text": "rust"}))).unwrap(), "await page.fill(\"#q\", \"rust\");");
+        assert_eq!(step(&action("type", "#q", json!({"text": "!", "clear_first": false}))).unwrap(), "await page.focus(\"#q\"); await page.keyboard.type(\"!\");");
+        assert_eq!(step(&action("press_key", "", json!({"key": "z", "modifiers": ["ctrl", "shift"]}))).unwrap(), "await page.keyboard.press(\"Control+Shift+z\");");
+    }
+
+    #[test]
+    fn unsupported_targets_and_actions_are_flagged() {
+        let todo = step(&action("click", "ax:42", json!({}))).unwrap();
+        assert!(todo.starts_with("// TODO no Playwright selector for this target: click on \"ax:42\""));
+        assert_eq!(step(&action("fill_credential", "#pw", json!({"label": "work mail"}))).unwrap(), "await page.fill(\"#pw\", process.env.CRED_WORK_MAIL_PASSWORD ?? '');");
+        assert_eq!(step(&action("alt_tab", "", json!({}))), Err("skipped alt_tab: no Playwright equivalent".to_string()));
+        assert!(step(&action("zoom", "", json!({"title": "Notepad", "level": 1.5}))).is_err());
+    }
+
+    #[test]
+    fn scripts_keep_successful_steps_and_expand_skills() {
+        let entries = vec![
+            entry(action("navigate", "", json!({"url": "https://example.com/"})), true),
+            entry(action("click", "#missing", json!({})), false),
+            entry(action("run_skill", "", json!({"name": "search"})), true),
+            entry(action("alt_tab", "", json!({})), true),
+        ];
+        let (file, warnings) = script("search example", &entries, |_| Ok(vec![action("type", "#q", json!({"text": "rust"})), action("press_key", "", json!({"key": "Enter"}))]));
+        assert!(file.starts_with("import { test, expect } from '@playwright/test';\n\ntest(\"search example\", async ({ page }) => {\n"));
+        assert!(file.contains("  await page.goto(\"https://example.com/\");\n  await page.fill(\"#q\", \"rust\");\n  await page.keyboard.press(\"Enter\");\n"));
+        assert!(!file.contains("#missing"));
+        assert_eq!(warnings, ["step 4: skipped alt_tab: no Playwright equivalent"]);
+    }
+}
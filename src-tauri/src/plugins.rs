@@ -0,0 +1,94 @@
+// Custom actions. A handler registered here is tried before the built-in browser actions and is
+// listed in the system prompt, so the model can emit it like any other action_type.
+//
+// Handlers are registered once at startup in register_actions. Besides compiled-in handlers,
+// every entry of actions.json in the config directory becomes a CliAction, e.g.
+//   [{"name": "create_ticket", "description": "params.args=[title, body]", "program": "ticket", "args": ["new"]}]
+use std::path::Path;
+use std::sync::Arc;
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::Value;
+
+const ACTIONS_FILE: &str = "actions.json";
+const CLI_ACTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+pub trait ActionHandler: Send + Sync {
+    fn name(&self) -> &str;
+    // Parameter hint for the action table, e.g. "params.query=text to search"
+    fn description(&self) -> &str;
+    // Ok(Some(text)) is shown to the model as LAST_READ, like read_text
+    fn execute<'a>(&'a self, target: &'a Value, params: Option<&'a Value>) -> BoxFuture<'a, Result<Option<String>, String>>;
+}
+
+#[derive(Default, Clone)]
+pub struct ActionRegistry {
+    handlers: Vec<Arc<dyn ActionHandler>>,
+}
+
+impl ActionRegistry {
+    // A later handler with the same name replaces the earlier one
+    pub fn register(&mut self, handler: Arc<dyn ActionHandler>) {
+        self.handlers.retain(|h| h.name() != handler.name());
+        self.handlers.push(handler);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn ActionHandler>> {
+        self.handlers.iter().find(|h| h.name() == name).cloned()
+    }
+
+    pub fn describe(&self) -> Vec<(String, String)> {
+        self.handlers.iter().map(|h| (h.name().to_string(), h.description().to_string())).collect()
+    }
+}
+
+// Startup hook: add compiled-in handlers here
+pub fn register_actions(registry: &mut ActionRegistry, dir: &Path) {
+    for action in load_cli_actions(dir) {
+        log_info!("plugins", "registered {} -> {}", action.name, action.program);
+        registry.register(Arc::new(action));
+    }
+}
+
+// Runs a local program: the configured args, then params.args. Stdout is the action's output.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CliAction {
+    pub name: String,
+    pub description: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl ActionHandler for CliAction {
+    fn name(&self) -> &str { &self.name }
+
+    fn description(&self) -> &str { &self.description }
+
+    fn execute<'a>(&'a self, _target: &'a Value, params: Option<&'a Value>) -> BoxFuture<'a, Result<Option<String>, String>> {
+        Box::pin(async move {
+            let extra: Vec<String> = params.and_then(|p| p["args"].as_array()).map(|a| a.iter().map(|v| v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string())).collect()).unwrap_or_default();
+            let run = tokio::process::Command::new(&self.program).args(&self.args).args(&extra).kill_on_drop(true).output();
+            let output = tokio::time::timeout(CLI_ACTION_TIMEOUT, run)
+                .await
+                .map_err(|_| format!("{} timed out", self.name))?
+                .map_err(|e| format!("{} could not start {}: {}", self.name, self.program, e))?;
+            if !output.status.success() {
+                return Err(format!("{} exited with {}: {}", self.name, output.status, String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Ok(Some(stdout).filter(|s| !s.is_empty()))
+        })
+    }
+}
+
+fn load_cli_actions(dir: &Path) -> Vec<CliAction> {
+    let Ok(text) = std::fs::read_to_string(dir.join(ACTIONS_FILE)) else { return Vec::new() };
+    match serde_json::from_str(&text) {
+        Ok(actions) => actions,
+        Err(e) => {
+            log_warn!("plugins", "ignoring {}: {}", ACTIONS_FILE, e);
+            Vec::new()
+        }
+    }
+}
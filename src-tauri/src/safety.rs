@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use crate::ActionCommand;
+
+// Actions that can execute arbitrary code or start processes on the host
+pub const SENSITIVE_ACTIONS: &[&str] = &["run", "run_command", "launch", "eval_js"];
+
+// Actions gated by approval_mode "destructive"
+pub const DESTRUCTIVE_ACTIONS: &[&str] = &["close_window", "close_tab", "delete", "run", "run_command", "launch", "eval_js"];
+
+// Which actions a deployment may run at all, on top of safe mode. An empty allowlist allows
+// everything not blocked. complete always passes, or a goal could never finish.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActionFilter {
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+impl ActionFilter {
+    pub fn permits(&self, action_type: &str) -> bool {
+        self.check(action_type).is_ok()
+    }
+
+    pub fn check(&self, action_type: &str) -> Result<(), String> {
+        if action_type == "complete" { return Ok(()); }
+        if self.blocked.iter().any(|b| b == action_type) {
+            return Err(format!("{} is blocked for this deployment", action_type));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|a| a == action_type) {
+            return Err(format!("{} is not in the allowed actions", action_type));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyPolicy {
+    pub safe_mode: bool,
+    pub allowed_apps: Vec<String>,
+    pub allowed_commands: Vec<String>,
+    pub allowed_urls: Vec<String>,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self { safe_mode: true, allowed_apps: Vec::new(), allowed_commands: Vec::new(), allowed_urls: Vec::new() }
+    }
+}
+
+impl SafetyPolicy {
+    pub fn is_sensitive(action: &ActionCommand) -> bool {
+        SENSITIVE_ACTIONS.contains(&action.action_type.as_str())
+    }
+
+    // Called before an action reaches any backend, so a rejected action never runs
+    pub fn check(&self, action: &ActionCommand) -> Result<(), String> {
+        if !self.safe_mode { return Ok(()); }
+        let param = |k: &str| action.params.as_ref().and_then(|p| p[k].as_str()).unwrap_or("").trim().to_string();
+        let target = action.target.as_str().unwrap_or("").trim().to_string();
+        match action.action_type.as_str() {
+            "eval_js" => {
+                let code = param("code");
+                if !self.allowed_commands.iter().any(|c| c.trim() == code) {
+                    return Err("Safe mode: eval_js is blocked unless the exact snippet is in allowed_commands".to_string());
+                }
+            }
+            "run" | "run_command" => {
+                let cmd = if target.is_empty() { param("command") } else { target };
+                if !self.allowed_commands.iter().any(|c| c.eq_ignore_ascii_case(&cmd)) {
+                    return Err(format!("Safe mode: command '{}' is not in allowed_commands", cmd));
+                }
+            }
+            "launch" => {
+                let app = if target.is_empty() { param("app") } else { target };
+                if !self.allowed_apps.iter().any(|a| a.eq_ignore_ascii_case(&app)) && !self.url_allowed(&app) {
+                    return Err(format!("Safe mode: '{}' is not in allowed_apps", app));
+                }
+            }
+            // An empty URL allowlist means navigation is unrestricted
+            "navigate" | "open_url" if !self.allowed_urls.is_empty() => {
+                let url = param("url");
+                if !self.url_allowed(&url) {
+                    return Err(format!("Safe mode: navigation to '{}' is not in allowed_urls", url));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn url_allowed(&self, url: &str) -> bool {
+        self.allowed_urls.iter().any(|u| url_in_allowlist_entry(u, url))
+    }
+}
+
+// Compares parsed URLs rather than strings, so "https://example.com" doesn't admit
+// "https://example.com.evil.net/". An entry without a scheme (e.g. "example.com") means https.
+// Subdomains of the entry's host match; a path in the entry must prefix the URL's path.
+fn url_in_allowlist_entry(entry: &str, url: &str) -> bool {
+    let parse = |s: &str| {
+        let s = s.trim();
+        reqwest::Url::parse(s).ok().filter(|u| u.has_host()).or_else(|| reqwest::Url::parse(&format!("https://{}", s)).ok())
+    };
+    let (Some(allowed), Ok(candidate)) = (parse(entry), reqwest::Url::parse(url.trim())) else { return false };
+    let (Some(allowed_host), Some(host)) = (allowed.host_str(), candidate.host_str()) else { return false };
+    let host_ok = host.eq_ignore_ascii_case(allowed_host) || host.to_ascii_lowercase().ends_with(&format!(".{}", allowed_host.to_ascii_lowercase()));
+    let path_ok = match allowed.path().trim_end_matches('/') {
+        "" => true,
+        prefix => candidate.path() == prefix || candidate.path().starts_with(&format!("{}/", prefix)),
+    };
+    allowed.scheme() == candidate.scheme() && host_ok && allowed.port_or_known_default() == candidate.port_or_known_default() && path_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn action(action_type: &str, target: &str, params: serde_json::Value) -> ActionCommand {
+        ActionCommand { action_type: action_type.to_string(), target: json!(target), params: Some(params), ..Default::default() }
+    }
+
+    fn policy() -> SafetyPolicy {
+        SafetyPolicy {
+            safe_mode: true,
+            allowed_apps: vec!["notepad".to_string()],
+            allowed_commands: vec!["dir".to_string(), "document.title".to_string()],
+            allowed_urls: vec!["https://example.com".to_string(), "docs.rs/serde".to_string()],
+        }
+    }
+
+    #[test]
+    fn blocks_commands_and_code_outside_the_allowlist() {
+        let p = policy();
+        assert!(p.check(&action("run", "powershell -c calc", json!({}))).is_err());
+        assert!(p.check(&action("run_command", "", json!({"command": "del /q *"}))).is_err());
+        assert!(p.check(&action("eval_js", "", json!({"code": "fetch('https://evil.net')"}))).is_err());
+        assert!(p.check(&action("launch", "calc", json!({}))).is_err());
+    }
+
+    #[test]
+    fn admits_allowlisted_commands_and_apps() {
+        let p = policy();
+        assert!(p.check(&action("run", "DIR", json!({}))).is_ok());
+        assert!(p.check(&action("eval_js", "", json!({"code": " document.title "}))).is_ok());
+        assert!(p.check(&action("launch", "Notepad", json!({}))).is_ok());
+        assert!(p.check(&action("launch", "https://example.com/app", json!({}))).is_ok());
+    }
+
+    #[test]
+    fn safe_mode_off_allows_everything() {
+        let p = SafetyPolicy { safe_mode: false, ..policy() };
+        assert!(p.check(&action("run", "anything", json!({}))).is_ok());
+    }
+
+    #[test]
+    fn url_allowlist_compares_hosts_not_prefixes() {
+        let p = policy();
+        let nav = |url: &str| p.check(&action("navigate", "", json!({"url": url})));
+        assert!(nav("https://example.com/").is_ok());
+        assert!(nav("https://shop.example.com/cart").is_ok());
+        assert!(nav("https://example.com.evil.net/").is_err());
+        assert!(nav("https://evilexample.com/").is_err());
+        assert!(nav("http://example.com/").is_err());
+        assert!(nav("https://example.com:8443/").is_err());
+    }
+
+    #[test]
+    fn url_allowlist_entry_path_must_prefix_at_a_segment() {
+        assert!(url_in_allowlist_entry("docs.rs/serde", "https://docs.rs/serde/latest"));
+        assert!(url_in_allowlist_entry("docs.rs/serde", "https://docs.rs/serde"));
+        assert!(!url_in_allowlist_entry("docs.rs/serde", "https://docs.rs/serde_json"));
+        assert!(!url_in_allowlist_entry("docs.rs/serde", "not a url"));
+    }
+
+    #[test]
+    fn action_filter_applies_blocklist_over_allowlist() {
+        let filter = ActionFilter { allowed: vec!["click".to_string(), "type".to_string()], blocked: vec!["type".to_string()] };
+        assert!(filter.check("click").is_ok());
+        assert!(filter.check("type").unwrap_err().contains("blocked"));
+        assert!(filter.check("navigate").unwrap_err().contains("not in the allowed"));
+        assert!(filter.check("complete").is_ok());
+        assert!(ActionFilter::default().permits("eval_js"));
+    }
+
+    #[test]
+    fn sensitive_actions_are_flagged() {
+        assert!(SafetyPolicy::is_sensitive(&action("eval_js", "", json!({}))));
+        assert!(!SafetyPolicy::is_sensitive(&action("click", "#ok", json!({}))));
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+// Everything secret lives in the OS keychain (Credential Manager / Keychain / Secret Service) under this service
+const SERVICE: &str = "pc-automation-agent";
+const CREDENTIALS_ENTRY: &str = "credentials";
+const PROFILES_ENTRY: &str = "profiles";
+
+// Profile the single-key commands (save_api_key) read and write
+pub const DEFAULT_PROFILE: &str = "default";
+
+// A stored login. Debug is redacted so a Secret can't leak through logs or error strings
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Secret {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl Secret {
+    pub fn field(&self, field: &str) -> Option<&str> {
+        match field {
+            "username" => Some(&self.username),
+            "password" => Some(&self.password),
+            _ => None,
+        }
+    }
+}
+
+// No Debug derive: it would print the key
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub api_key: String,
+    pub model: String,
+    pub max_tokens: u32,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ProfileStore {
+    pub active: Option<String>,
+    pub profiles: Vec<Profile>,
+}
+
+impl ProfileStore {
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn active(&self) -> Option<&Profile> {
+        self.active.as_deref().and_then(|name| self.get(name))
+    }
+
+    pub fn upsert(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+}
+
+fn entry(name: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(SERVICE, name).map_err(|e| e.to_string())
+}
+
+pub fn read(name: &str) -> Result<Option<String>, String> {
+    match entry(name)?.get_password() {
+        Ok(v) => Ok(Some(v)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn write(name: &str, value: &str) -> Result<(), String> {
+    entry(name)?.set_password(value).map_err(|e| e.to_string())
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    match entry(name)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// The whole label -> Secret map is one keychain entry; an unreadable store starts empty
+pub fn load_credentials() -> HashMap<String, Secret> {
+    match read(CREDENTIALS_ENTRY) {
+        Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            crate::log_warn!("secrets", "Stored credentials are unreadable: {}", e);
+            HashMap::new()
+        }),
+        Ok(None) => HashMap::new(),
+        Err(e) => {
+            crate::log_warn!("secrets", "Keychain unavailable, credentials not loaded: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+pub fn save_credentials(credentials: &HashMap<String, Secret>) -> Result<(), String> {
+    write(CREDENTIALS_ENTRY, &serde_json::to_string(credentials).map_err(|e| e.to_string())?)
+}
+
+pub fn load_profiles() -> ProfileStore {
+    match read(PROFILES_ENTRY) {
+        Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            crate::log_warn!("secrets", "Stored profiles are unreadable: {}", e);
+            ProfileStore::default()
+        }),
+        Ok(None) => ProfileStore::default(),
+        Err(e) => {
+            crate::log_warn!("secrets", "Keychain unavailable, profiles not loaded: {}", e);
+            ProfileStore::default()
+        }
+    }
+}
+
+pub fn save_profiles(store: &ProfileStore) -> Result<(), String> {
+    write(PROFILES_ENTRY, &serde_json::to_string(store).map_err(|e| e.to_string())?)
+}
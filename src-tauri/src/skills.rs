@@ -0,0 +1,177 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::ActionCommand;
+
+const SKILLS_FILE: &str = "skills.json";
+
+// A named action sequence replayed without the LLM. String values in target/params may contain
+// {placeholders} that run_skill fills from params.args.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Skill {
+    pub name: String,
+    pub description: String,
+    pub actions: Vec<ActionCommand>,
+}
+
+pub fn load(dir: &Path) -> Result<Vec<Skill>, String> {
+    match std::fs::read_to_string(dir.join(SKILLS_FILE)) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("skills.json is corrupt: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn save(dir: &Path, skills: &[Skill]) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(skills).map_err(|e| e.to_string())?;
+    std::fs::write(dir.join(SKILLS_FILE), json).map_err(|e| e.to_string())
+}
+
+// Replaces a skill of the same name
+pub fn upsert(dir: &Path, skill: Skill) -> Result<(), String> {
+    let mut skills = load(dir)?;
+    skills.retain(|s| s.name != skill.name);
+    skills.push(skill);
+    save(dir, &skills)
+}
+
+pub fn find(dir: &Path, name: &str) -> Result<Skill, String> {
+    load(dir)?.into_iter().find(|s| s.name == name).ok_or_else(|| format!("No skill named \"{}\"", name))
+}
+
+impl Skill {
+    // Every {name} token across the actions' targets and params
+    pub fn placeholders(&self) -> BTreeSet<String> {
+        let mut out = BTreeSet::new();
+        for action in &self.actions {
+            collect_placeholders(&action.target, &mut out);
+            if let Some(p) = &action.params { collect_placeholders(p, &mut out); }
+        }
+        out
+    }
+
+    // Substituted copies of the actions, or an error naming every placeholder args doesn't cover
+    pub fn instantiate(&self, args: &serde_json::Map<String, serde_json::Value>) -> Result<Vec<ActionCommand>, String> {
+        let missing: Vec<String> = self.placeholders().into_iter().filter(|p| !args.contains_key(p)).collect();
+        if !missing.is_empty() {
+            return Err(format!("Skill \"{}\" is missing args: {}", self.name, missing.join(", ")));
+        }
+        Ok(self.actions.iter().map(|a| ActionCommand {
+            target: substitute(&a.target, args),
+            params: a.params.as_ref().map(|p| substitute(p, args)),
+            ..a.clone()
+        }).collect())
+    }
+}
+
+// Only identifier-like names count, so braces in eval_js code or CSS are not mistaken for placeholders
+fn placeholder_names(s: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = s;
+    while let Some(open) = rest.find('{') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find('}') else { break };
+        let name = &rest[..close];
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            names.push(name);
+            rest = &rest[close + 1..];
+        }
+    }
+    names
+}
+
+fn collect_placeholders(value: &serde_json::Value, out: &mut BTreeSet<String>) {
+    match value {
+        serde_json::Value::String(s) => out.extend(placeholder_names(s).into_iter().map(String::from)),
+        serde_json::Value::Array(items) => items.iter().for_each(|v| collect_placeholders(v, out)),
+        serde_json::Value::Object(map) => map.values().for_each(|v| collect_placeholders(v, out)),
+        _ => {}
+    }
+}
+
+// A string that is exactly one placeholder takes the arg's own JSON value, so {"amount": "{n}"}
+// with n = 500 becomes a number. Anywhere else args are spliced in as text.
+fn substitute(value: &serde_json::Value, args: &serde_json::Map<String, serde_json::Value>) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => {
+            let whole = s.strip_prefix('{').and_then(|r| r.strip_suffix('}')).and_then(|name| args.get(name));
+            if let Some(arg) = whole { return arg.clone(); }
+            let mut out = s.clone();
+            for (key, arg) in args {
+                let text = arg.as_str().map(|a| a.to_string()).unwrap_or_else(|| arg.to_string());
+                out = out.replace(&format!("{{{}}}", key), &text);
+            }
+            serde_json::Value::String(out)
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.iter().map(|v| substitute(v, args)).collect()),
+        serde_json::Value::Object(map) => serde_json::Value::Object(map.iter().map(|(k, v)| (k.clone(), substitute(v, args))).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn action(action_type: &str, target: &str, params: serde_json::Value) -> ActionCommand {
+        ActionCommand { action_type: action_type.to_string(), target: json!(target), params: Some(params), ..Default::default() }
+    }
+
+    fn skill(name: &str) -> Skill {
+        Skill {
+            name: name.to_string(),
+            description: "open a page and search it".to_string(),
+            actions: vec![action("navigate", "", json!({"url": "{url}"})), action("type", "#q", json!({"text": "{query}"}))],
+        }
+    }
+
+    #[test]
+    fn saved_skills_round_trip_and_run() {
+        let dir = std::env::temp_dir().join(format!("pc-agent-skills-{}", std::process::id()));
+        upsert(&dir, skill("search")).unwrap();
+        upsert(&dir, skill("other")).unwrap();
+        upsert(&dir, Skill { description: "replaced".to_string(), ..skill("search") }).unwrap();
+
+        let names: Vec<String> = load(&dir).unwrap().into_iter().map(|s| s.name + ":" + &s.description).collect();
+        assert_eq!(names, vec!["other:open a page and search it", "search:replaced"]);
+        let args = json!({"url": "https://example.com", "query": "rust"});
+        let actions = find(&dir, "search").unwrap().instantiate(args.as_object().unwrap()).unwrap();
+        assert_eq!(actions[0].params, Some(json!({"url": "https://example.com"})));
+        assert_eq!(actions[1].params, Some(json!({"text": "rust"})));
+        assert!(find(&dir, "missing").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn args(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn missing_args_are_all_named() {
+        let err = skill("search").instantiate(&args(json!({"query": "rust"}))).unwrap_err();
+        assert_eq!(err, "Skill \"search\" is missing args: url");
+        let err = skill("search").instantiate(&args(json!({}))).unwrap_err();
+        assert!(err.ends_with("query, url"));
+    }
+
+    #[test]
+    fn several_placeholders_fill_one_string() {
+        let value = json!({"text": "{first} {last} <{first}@example.com>"});
+        assert_eq!(substitute(&value, &args(json!({"first": "ada", "last": "lovelace"}))), json!({"text": "ada lovelace <ada@example.com>"}));
+    }
+
+    #[test]
+    fn a_whole_placeholder_keeps_the_arg_type() {
+        let value = json!({"amount": "{n}", "label": "pay {n}", "flags": ["{on}"], "fixed": 3});
+        let out = substitute(&value, &args(json!({"n": 500, "on": true})));
+        assert_eq!(out, json!({"amount": 500, "label": "pay 500", "flags": [true], "fixed": 3}));
+    }
+
+    #[test]
+    fn code_braces_are_not_placeholders() {
+        let s = Skill { actions: vec![action("eval_js", "", json!({"code": "if (x) { run({id}) }"}))], ..skill("js") };
+        assert_eq!(s.placeholders().into_iter().collect::<Vec<_>>(), vec!["id"]);
+    }
+}
@@ -0,0 +1,114 @@
+// Benchmark mode: where the time of a run goes. Each step records its state captures (the page read
+// before its LLM call and after its action), the LLM calls that chose it (retries included) and the
+// action itself. get_timing_report aggregates the steps of the current run.
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StepTiming {
+    // 1-based within the run
+    pub step: usize,
+    pub action_type: String,
+    pub capture_ms: u64,
+    pub llm_ms: u64,
+    pub action_ms: u64,
+}
+
+impl StepTiming {
+    pub fn total_ms(&self) -> u64 {
+        self.capture_ms + self.llm_ms + self.action_ms
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    Capture,
+    Llm,
+    Action,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseStats {
+    pub phase: String,
+    pub total_ms: u64,
+    pub avg_ms: f64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingReport {
+    pub steps: usize,
+    pub total_ms: u64,
+    // capture, llm, action, then whole steps
+    pub phases: Vec<PhaseStats>,
+    pub per_step: Vec<StepTiming>,
+}
+
+// Nearest-rank percentile of sorted values; 0 for none
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() { return 0; }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn stats(phase: &str, values: impl Iterator<Item = u64>) -> PhaseStats {
+    let mut sorted: Vec<u64> = values.collect();
+    sorted.sort_unstable();
+    let total_ms: u64 = sorted.iter().sum();
+    PhaseStats {
+        phase: phase.to_string(),
+        total_ms,
+        avg_ms: if sorted.is_empty() { 0.0 } else { total_ms as f64 / sorted.len() as f64 },
+        p50_ms: percentile(&sorted, 50.0),
+        p90_ms: percentile(&sorted, 90.0),
+        p95_ms: percentile(&sorted, 95.0),
+        max_ms: sorted.last().copied().unwrap_or(0),
+    }
+}
+
+pub fn report(steps: &[StepTiming]) -> TimingReport {
+    TimingReport {
+        steps: steps.len(),
+        total_ms: steps.iter().map(StepTiming::total_ms).sum(),
+        phases: vec![
+            stats("capture", steps.iter().map(|s| s.capture_ms)),
+            stats("llm", steps.iter().map(|s| s.llm_ms)),
+            stats("action", steps.iter().map(|s| s.action_ms)),
+            stats("step", steps.iter().map(StepTiming::total_ms)),
+        ],
+        per_step: steps.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted: Vec<u64> = (1..=10).map(|i| i * 10).collect();
+        assert_eq!(percentile(&sorted, 50.0), 50);
+        assert_eq!(percentile(&sorted, 90.0), 90);
+        assert_eq!(percentile(&sorted, 95.0), 100);
+        assert_eq!(percentile(&sorted, 0.0), 10);
+        assert_eq!(percentile(&[7], 95.0), 7);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn report_aggregates_each_phase() {
+        let steps: Vec<StepTiming> = [(100, 2000, 50), (300, 1000, 150), (200, 3000, 100), (400, 6000, 700)].iter().enumerate()
+            .map(|(i, &(capture_ms, llm_ms, action_ms))| StepTiming { step: i + 1, action_type: "click".to_string(), capture_ms, llm_ms, action_ms })
+            .collect();
+        let r = report(&steps);
+        assert_eq!((r.steps, r.total_ms), (4, 14000));
+        let names: Vec<&str> = r.phases.iter().map(|p| p.phase.as_str()).collect();
+        assert_eq!(names, ["capture", "llm", "action", "step"]);
+        let llm = &r.phases[1];
+        assert_eq!((llm.total_ms, llm.avg_ms, llm.p50_ms, llm.p90_ms, llm.max_ms), (12000, 3000.0, 2000, 6000, 6000));
+        assert_eq!(r.phases[3].p50_ms, 2150);
+        assert_eq!(report(&[]).phases[0].avg_ms, 0.0);
+    }
+}
@@ -0,0 +1,42 @@
+use serde_json::json;
+use crate::ActionCommand;
+
+// Reversible actions and how to build their inverse from the original:
+//   type       -> Ctrl+Z in the still-focused field
+//   navigate   -> go_back (open_url too)
+//   go_back    -> go_forward, and go_forward -> go_back
+//   scroll     -> the same amount in the opposite direction (edge jumps are not reversible)
+// Anything else (click, select, eval_js, fill_credential, ...) has no safe inverse.
+// Add an entry here to make another action undoable.
+type InverseFn = fn(&ActionCommand) -> Option<ActionCommand>;
+
+pub const INVERSES: &[(&str, InverseFn)] = &[
+    ("type", |_| Some(inverse("press_key", json!(""), Some(json!({"key": "z", "modifiers": ["ctrl"]}))))),
+    ("navigate", |_| Some(inverse("go_back", json!(""), None))),
+    ("open_url", |_| Some(inverse("go_back", json!(""), None))),
+    ("go_back", |_| Some(inverse("go_forward", json!(""), None))),
+    ("go_forward", |_| Some(inverse("go_back", json!(""), None))),
+    ("scroll", undo_scroll),
+];
+
+fn inverse(action_type: &str, target: serde_json::Value, params: Option<serde_json::Value>) -> ActionCommand {
+    ActionCommand { action_type: action_type.to_string(), target, params, ..Default::default() }
+}
+
+fn undo_scroll(action: &ActionCommand) -> Option<ActionCommand> {
+    let mut params = action.params.clone().unwrap_or_else(|| json!({}));
+    if params.get("to").is_some() { return None; }
+    let horizontal = params["axis"].as_str() == Some("x");
+    let dir = params["direction"].as_str().unwrap_or(if horizontal { "right" } else { "down" });
+    let opposite = match dir { "up" => "down", "down" => "up", "left" => "right", _ => "left" };
+    params["direction"] = json!(opposite);
+    Some(inverse("scroll", action.target.clone(), Some(params)))
+}
+
+pub fn inverse_of(action: &ActionCommand) -> Result<ActionCommand, String> {
+    let build = INVERSES.iter().find(|(name, _)| *name == action.action_type).map(|(_, f)| f)
+        .ok_or_else(|| format!("{} has no safe inverse", action.action_type))?;
+    let mut undo = build(action).ok_or_else(|| format!("this {} has no safe inverse", action.action_type))?;
+    undo.reasoning = Some(format!("undo {}", action.action_type));
+    Ok(undo)
+}